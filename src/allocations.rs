@@ -0,0 +1,83 @@
+//! A global allocator for the library's tests that counts what each thread allocates, so a test can
+//! hold a path to the allocations it should make. The binary keeps its own allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+struct Counting;
+
+impl Counting {
+    fn count() {
+        // A thread being torn down has no counter left; what it frees then doesn't matter to any test.
+        ALLOCATIONS.try_with(|count| count.set(count.get().saturating_add(1))).unwrap_or_default();
+    }
+}
+
+// SAFETY: every call is passed straight on to the system allocator, which upholds the contract
+// itself.
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Self::count();
+        // SAFETY: the caller's guarantees about `layout` are the ones `System::alloc` needs.
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Self::count();
+        // SAFETY: the caller's guarantees about `layout` are the ones `System::alloc_zeroed` needs.
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        Self::count();
+        // SAFETY: `ptr` came from this allocator, which is the system one, with `layout`.
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: `ptr` came from this allocator, which is the system one, with `layout`.
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+/// How many allocations, reallocations included, `run` makes on this thread.
+pub(crate) fn counted<T>(run: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = run();
+
+    (result, ALLOCATIONS.with(Cell::get).saturating_sub(before))
+}
+
+mod tests {
+    use super::*;
+    use crate::cipher::{CipherMode, RandomSource, SeededRandom};
+    use crate::config::{CHUNK_SIZE, COMPRESSION_LEVEL, KEY_LEN, NONCE_PREFIX_LEN};
+    use crate::pipeline::{Operation, Process, Task};
+    use crate::secret::Secret;
+    use crate::secure_temp::SecureBuffer;
+
+    #[test]
+    fn sealing_a_chunk_allocates_only_its_output_and_compressor() {
+        let (primary, secondary) = (Secret::new(vec![1u8; KEY_LEN]), Secret::new(vec![2u8; KEY_LEN]));
+        let process = Process::new(&primary, &secondary, Operation::Encryption, CipherMode::Dual, &[3u8; NONCE_PREFIX_LEN], COMPRESSION_LEVEL).unwrap();
+        let mut plaintext = vec![0u8; CHUNK_SIZE];
+        SeededRandom::new(5).fill(&mut plaintext).unwrap();
+
+        // The first chunk on a thread sets up its scratch buffer and Reed-Solomon encoder.
+        process.process(&Task { data: SecureBuffer::unlocked(plaintext.clone()), index: 0 }).unwrap();
+        let task = Task { data: SecureBuffer::unlocked(plaintext), index: 1 };
+        let (result, allocations) = counted(|| process.process(&task));
+        result.unwrap();
+
+        // The encoded chunk and zstd's context; every stage in between works in the thread's scratch
+        // buffer.
+        assert_eq!(allocations, 2);
+    }
+}
@@ -0,0 +1,318 @@
+//! The library's entry points: an [`Encryptor`] and [`Decryptor`] configured through
+//! [`EncryptOptions`] and [`DecryptOptions`], and the [`Header`] fields anyone can read without a
+//! password. Everything here works on whole files in memory and builds without the `cli` feature.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::cipher::{CipherMode, SystemRandom};
+use crate::config::{CHUNK_SIZE, COMPRESSION_LEVEL, MIN_CHUNK_SIZE, Settings};
+use crate::error::SweetByteError;
+use crate::header::Deserializer;
+use crate::pipeline::{NoProgress, ProgressSink, Stats};
+use crate::secret::Secret;
+
+/// How [`Encryptor`] writes a file. The defaults are the ones the command line uses.
+#[derive(Clone)]
+pub struct EncryptOptions {
+    cipher: CipherMode,
+    chunk_size: usize,
+    payload_mac: bool,
+    compression_level: i32,
+    progress: Option<Arc<dyn ProgressSink>>,
+}
+
+impl Default for EncryptOptions {
+    fn default() -> Self {
+        Self { cipher: CipherMode::default(), chunk_size: CHUNK_SIZE, payload_mac: true, compression_level: COMPRESSION_LEVEL, progress: None }
+    }
+}
+
+impl EncryptOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cipher layers each chunk is sealed with.
+    pub fn with_cipher(mut self, cipher: CipherMode) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Plaintext bytes per chunk, from 4 KiB to 256 KiB.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Whether a MAC over every chunk follows the last one, so a dropped or reordered chunk is
+    /// caught.
+    pub fn with_payload_mac(mut self, payload_mac: bool) -> Self {
+        self.payload_mac = payload_mac;
+        self
+    }
+
+    /// The zstd level each chunk is compressed at before it is sealed.
+    pub fn with_compression_level(mut self, compression_level: i32) -> Self {
+        self.compression_level = compression_level;
+        self
+    }
+
+    /// Sends [`Encryptor::encrypt`]'s progress to `progress`: key derivation, then each chunk as it
+    /// is sealed. The stream adapters don't report to it.
+    pub fn with_progress(mut self, progress: impl ProgressSink + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    pub(crate) fn cipher(&self) -> CipherMode {
+        self.cipher
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn progress(&self) -> Option<&Arc<dyn ProgressSink>> {
+        self.progress.as_ref()
+    }
+
+    pub(crate) fn settings(&self) -> Result<Settings> {
+        if !(MIN_CHUNK_SIZE..=CHUNK_SIZE).contains(&self.chunk_size) {
+            anyhow::bail!("chunk size must be between {MIN_CHUNK_SIZE} and {CHUNK_SIZE} bytes");
+        }
+        if !zstd::compression_level_range().contains(&self.compression_level) {
+            anyhow::bail!("unsupported compression level {}", self.compression_level);
+        }
+
+        Ok(Settings { chunk_size: self.chunk_size, payload_mac: self.payload_mac, compression_level: self.compression_level, ..Settings::default() })
+    }
+}
+
+/// Encrypts a whole `.swx` file in memory.
+#[derive(Clone, Default)]
+pub struct Encryptor {
+    options: EncryptOptions,
+}
+
+impl Encryptor {
+    pub fn new(options: EncryptOptions) -> Self {
+        Self { options }
+    }
+
+    /// Returns the encrypted file and a summary of the run.
+    pub fn encrypt(&self, data: &[u8], password: &[u8]) -> Result<(Vec<u8>, OperationSummary)> {
+        let settings = self.options.settings()?;
+        let started = Instant::now();
+        let stats = Arc::new(Stats::default());
+        let progress = self.options.progress.as_deref().unwrap_or(&NoProgress);
+        let encrypted = crate::memory::encrypt_counted(data, &Secret::new(password.to_vec()), self.options.cipher, &settings, &SystemRandom, Some(Arc::clone(&stats)), progress)?;
+
+        let mut warnings = Vec::new();
+        if !self.options.payload_mac {
+            warnings.push("payload MAC is off; chunks dropped from the end of the file won't be detected".to_owned());
+        }
+        let summary = OperationSummary::new(data, &encrypted, &Header::read(&encrypted)?, started, &stats, warnings)?;
+
+        Ok((encrypted, summary))
+    }
+}
+
+/// How [`Decryptor`] reads a file. By default any size the header records is accepted.
+#[derive(Clone)]
+pub struct DecryptOptions {
+    max_size: u64,
+    progress: Option<Arc<dyn ProgressSink>>,
+}
+
+impl Default for DecryptOptions {
+    fn default() -> Self {
+        Self { max_size: u64::MAX, progress: None }
+    }
+}
+
+impl DecryptOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuses a file holding more than `max_size` bytes, checked against its header before
+    /// anything is decrypted.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sends [`Decryptor::decrypt`]'s progress to `progress`: key derivation, then each chunk as it
+    /// is opened.
+    pub fn with_progress(mut self, progress: impl ProgressSink + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+}
+
+/// Decrypts a whole `.swx` file in memory and checks it against the hash in its header.
+#[derive(Clone, Default)]
+pub struct Decryptor {
+    options: DecryptOptions,
+}
+
+impl Decryptor {
+    pub fn new(options: DecryptOptions) -> Self {
+        Self { options }
+    }
+
+    /// Returns the decrypted data and a summary of the run.
+    pub fn decrypt(&self, data: &[u8], password: &[u8]) -> Result<(Vec<u8>, OperationSummary)> {
+        let started = Instant::now();
+        let stats = Arc::new(Stats::default());
+        let progress = self.options.progress.as_deref().unwrap_or(&NoProgress);
+        let decrypted = crate::memory::decrypt_bounded(data, &Secret::new(password.to_vec()), self.options.max_size, Some(Arc::clone(&stats)), progress)?;
+
+        let header = Header::read(data)?;
+        let mut warnings = Vec::new();
+        if !header.payload_mac {
+            warnings.push("file has no payload MAC; chunks dropped from its end would not have been detected".to_owned());
+        }
+        let summary = OperationSummary::new(data, &decrypted, &header, started, &stats, warnings)?;
+
+        Ok((decrypted, summary))
+    }
+}
+
+/// What an [`Encryptor`] or [`Decryptor`] run did.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OperationSummary {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub chunks: u64,
+    pub duration: Duration,
+    /// Compressed size over plaintext size; below 1.0 means compression helped.
+    pub compression_ratio: f64,
+    /// Things that didn't stop the run but are worth knowing, such as a file without a payload MAC.
+    pub warnings: Vec<String>,
+}
+
+impl OperationSummary {
+    fn new(input: &[u8], output: &[u8], header: &Header, started: Instant, stats: &Stats, warnings: Vec<String>) -> Result<Self> {
+        let duration = started.elapsed();
+
+        Ok(Self {
+            bytes_in: u64::try_from(input.len()).context("input too large")?,
+            bytes_out: u64::try_from(output.len()).context("output too large")?,
+            chunks: header.chunk_count,
+            duration,
+            compression_ratio: stats.summary(duration).compression_ratio,
+            warnings,
+        })
+    }
+}
+
+/// What a `.swx` header says in the clear. The file name, size, and hash are sealed and need the
+/// password.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Header {
+    pub cipher: CipherMode,
+    pub key_slots: usize,
+    pub chunk_count: u64,
+    /// Plaintext bytes per chunk; zero in files written before it was recorded.
+    pub chunk_size: u32,
+    pub payload_mac: bool,
+    pub padded: bool,
+    pub chunk_index: bool,
+    /// How many bytes at the start of the file the header takes up.
+    pub len: usize,
+}
+
+impl Header {
+    /// Reads the header at the start of `data`, which only has to hold that much of the file.
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let (header, len) = Deserializer::from_bytes(data).context(SweetByteError::CorruptHeader)?;
+
+        Ok(Self {
+            cipher: header.cipher_mode()?,
+            key_slots: header.slot_count(),
+            chunk_count: header.chunk_count(),
+            chunk_size: header.chunk_size(),
+            payload_mac: header.payload_mac(),
+            padded: header.padded(),
+            chunk_index: header.indexed(),
+            len,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{ProgressEvent, Stage};
+
+    fn recorder() -> (Arc<Mutex<Vec<ProgressEvent>>>, impl ProgressSink + 'static) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let events = Arc::clone(&events);
+            move |event: &ProgressEvent| events.lock().unwrap().push(*event)
+        };
+
+        (events, sink)
+    }
+
+    #[test]
+    fn options_reach_the_header() {
+        let data = vec![7u8; MIN_CHUNK_SIZE * 2 + 1];
+        let options = EncryptOptions::new()
+            .with_cipher(CipherMode::Chacha)
+            .with_chunk_size(MIN_CHUNK_SIZE)
+            .with_payload_mac(false)
+            .with_compression_level(9);
+        let (encrypted, summary) = Encryptor::new(options).encrypt(&data, b"password").unwrap();
+
+        let header = Header::read(&encrypted).unwrap();
+        assert!(header.cipher == CipherMode::Chacha && !header.payload_mac);
+        assert_eq!((header.key_slots, header.chunk_count, header.chunk_size), (1, 3, u32::try_from(MIN_CHUNK_SIZE).unwrap()));
+        assert_eq!(Decryptor::default().decrypt(&encrypted, b"password").unwrap().0, data);
+        assert_eq!((summary.bytes_in, summary.bytes_out, summary.chunks), (data.len() as u64, encrypted.len() as u64, 3));
+        assert!(summary.compression_ratio < 0.1 && summary.warnings.len() == 1);
+
+        Encryptor::new(EncryptOptions::new().with_chunk_size(1)).encrypt(&data, b"password").unwrap_err();
+        Encryptor::new(EncryptOptions::new().with_compression_level(1000)).encrypt(&data, b"password").unwrap_err();
+        assert!(Header::read(&encrypted[..header.len / 2]).is_err());
+    }
+
+    #[test]
+    fn max_size_is_checked_before_decrypting() {
+        let data = vec![7u8; 1000];
+        let (encrypted, _) = Encryptor::default().encrypt(&data, b"password").unwrap();
+
+        let (decrypted, summary) = Decryptor::new(DecryptOptions::new().with_max_size(1000)).decrypt(&encrypted, b"password").unwrap();
+        assert_eq!(decrypted, data);
+        assert_eq!((summary.bytes_in, summary.bytes_out, summary.chunks), (encrypted.len() as u64, 1000, 1));
+        assert!(summary.warnings.is_empty());
+        let error = Decryptor::new(DecryptOptions::new().with_max_size(999)).decrypt(&encrypted, b"password").unwrap_err();
+        assert!(format!("{error:#}").contains("more than the 999 allowed"));
+    }
+
+    #[test]
+    fn progress_reaches_the_callback() {
+        let data = vec![7u8; MIN_CHUNK_SIZE * 2 + 1];
+        let (encrypting, sink) = recorder();
+        let (encrypted, _) = Encryptor::new(EncryptOptions::new().with_chunk_size(MIN_CHUNK_SIZE).with_progress(sink))
+            .encrypt(&data, b"password")
+            .unwrap();
+        let (decrypting, sink) = recorder();
+        Decryptor::new(DecryptOptions::new().with_progress(sink)).decrypt(&encrypted, b"password").unwrap();
+
+        for events in [encrypting.lock().unwrap(), decrypting.lock().unwrap()] {
+            let stages: Vec<Stage> = events.iter().map(|event| event.stage).collect();
+            assert_eq!(stages, [Stage::Deriving, Stage::Derived, Stage::Started, Stage::Chunk, Stage::Chunk, Stage::Chunk, Stage::Finished]);
+            assert_eq!(events.iter().filter(|event| event.stage == Stage::Chunk).map(|event| event.chunk_plaintext).sum::<u64>(), data.len() as u64);
+            assert!(events.iter().skip(2).all(|event| event.total == data.len() as u64));
+        }
+
+        let finished = encrypting.lock().unwrap()[6];
+        assert_eq!((finished.chunk, finished.bytes_in, finished.bytes_out), (3, data.len() as u64, encrypted.len() as u64));
+        let finished = decrypting.lock().unwrap()[6];
+        assert_eq!((finished.chunk, finished.bytes_in, finished.bytes_out), (3, encrypted.len() as u64, data.len() as u64));
+    }
+}
@@ -0,0 +1,38 @@
+use std::num::NonZeroUsize;
+
+use anyhow::{Context, Result};
+
+use crate::cipher::SecondFactor;
+use crate::files::{Files, Metadata};
+use crate::header::Deserializer;
+use crate::pipeline::ProgressSink;
+use crate::secret::Secret;
+
+/// Returns the existing output's metadata when it already holds the current source. The metadata is
+/// encrypted, so this needs a password; one that does not open the output counts as a change.
+pub(crate) async fn unchanged(source: &Files, target: &Files, secret: &Secret, factor: Option<&SecondFactor>, progress: &dyn ProgressSink, threads: NonZeroUsize) -> Result<Option<Metadata>> {
+    if !target.exists() && !crate::files::spanning::is_spanned(target.path()) {
+        return Ok(None);
+    }
+
+    let mut reader = crate::files::spanning::reader(target).await.context("failed to open existing output")?;
+    let header = Deserializer::from_reader(reader.get_mut()).await.context("failed to read header of existing output")?;
+
+    let Some(keys) = super::unlock(secret, &header, None, factor, progress)? else {
+        return Ok(None);
+    };
+    if !header.verify(&keys.signer_key)? {
+        anyhow::bail!("existing output has a corrupted header");
+    }
+    let metadata = header.metadata(&keys.metadata_key)?;
+
+    if metadata.size() != source.size().await? {
+        return Ok(None);
+    }
+
+    if crate::files::hash::hash(source, threads)? != metadata.hash() {
+        return Ok(None);
+    }
+
+    Ok(Some(Metadata { name: metadata.name().to_owned(), size: metadata.size(), hash: metadata.hash().to_vec(), attributes: metadata.attributes() }))
+}
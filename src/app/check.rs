@@ -0,0 +1,29 @@
+use anyhow::Result;
+
+use crate::cipher::SecondFactor;
+use crate::error::SweetByteError;
+use crate::files::{Files, Metadata};
+use crate::pipeline::ProgressSink;
+use crate::secret::Secret;
+
+pub(crate) async fn check_password(source: &Files, secret: &Secret, factor: Option<&SecondFactor>, progress: &dyn ProgressSink) -> Result<Metadata> {
+    let (header, _) = super::read_header(source, None).await?;
+
+    let Some(keys) = super::unlock(secret, &header, None, factor, progress)? else {
+        return Err(SweetByteError::WrongPassword.into());
+    };
+    if !header.verify(&keys.signer_key)? {
+        return Err(SweetByteError::WrongPassword.into());
+    }
+
+    let metadata = header.metadata(&keys.metadata_key)?;
+    Ok(Metadata { name: metadata.name().to_owned(), size: metadata.size(), hash: metadata.hash().to_vec(), attributes: metadata.attributes() })
+}
+
+/// A printable code for the header of `source` that `decrypt --recovery-code` can use in its place.
+/// Reading it needs no password, since it holds nothing the header itself doesn't.
+pub(crate) async fn recovery_code(source: &Files) -> Result<String> {
+    let (header, _) = super::read_header(source, None).await?;
+
+    Ok(crate::recovery::encode(&header.recovery_record()?))
+}
@@ -0,0 +1,121 @@
+use std::io::ErrorKind;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::io::AsyncReadExt;
+
+use crate::config::{CHUNK_INDEX_MARKER, COMPRESSION_LEVEL, MAX_CHUNK_SIZE, ORIGINAL_COUNT, PADDING_MARKER, PAYLOAD_MAC_MARKER, RECOVERY_COUNT};
+use crate::encoding::Encoding;
+use crate::files::Files;
+use crate::header::Deserializer;
+use crate::pipeline::{Operation, Process, ProgressSink, Task};
+use crate::secret::Secret;
+use crate::secure_temp::SecureBuffer;
+
+#[derive(Serialize)]
+pub(crate) struct ChunkInfo {
+    pub(crate) index: u64,
+    pub(crate) offset: u64,
+    pub(crate) length: u32,
+    pub(crate) shards_intact: usize,
+    pub(crate) shards_total: usize,
+    pub(crate) recoverable: bool,
+    pub(crate) nonce_matches: bool,
+    pub(crate) tags_present: bool,
+    pub(crate) decrypts: Option<bool>,
+}
+
+impl ChunkInfo {
+    pub(crate) fn healthy(&self) -> bool {
+        self.recoverable && self.nonce_matches && self.tags_present && self.decrypts != Some(false)
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct ChunkLayout {
+    pub(crate) header_len: u64,
+    pub(crate) expected: u64,
+    pub(crate) trailer: bool,
+    pub(crate) problem: Option<String>,
+    pub(crate) chunks: Vec<ChunkInfo>,
+}
+
+/// Walks the payload record by record and reports what each chunk looks like on disk. Offsets are
+/// positions in the joined stream, so for split files they don't count volume headers. Only with a
+/// secret is each chunk decrypted, and even then nothing is written.
+pub(crate) async fn chunks(source: &Files, secret: Option<&Secret>, progress: &dyn ProgressSink) -> Result<ChunkLayout> {
+    let (header, mut reader) = super::read_header(source, None).await?;
+    let mode = header.cipher_mode()?;
+
+    let process = match secret {
+        Some(secret) => {
+            let (keys, metadata) = super::open_header(secret, &header, None, None, progress)?;
+            let process = Process::new(&keys.primary_key, &keys.secondary_key, Operation::Decryption, mode, header.nonce_prefix(), COMPRESSION_LEVEL)?
+                .with_padding_scheme(header.padding_scheme())
+                .with_chunk_limit(header.chunk_limit(metadata.size));
+            Some(process)
+        }
+        None => None,
+    };
+
+    let encoder = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT).context("failed to initialize encoder")?;
+    let header_len = u64::try_from(header.encoded_len()).context("header length overflow")?;
+    let mut layout = ChunkLayout { header_len, expected: header.chunk_count(), trailer: false, problem: None, chunks: Vec::new() };
+    let mut offset = header_len;
+    let mut index = 0u64;
+
+    loop {
+        let length = match reader.read_u32_le().await {
+            Ok(length) => length,
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error).context("failed to read chunk length"),
+        };
+
+        if length == PAYLOAD_MAC_MARKER && header.payload_mac() {
+            layout.trailer = true;
+            break;
+        }
+        if (length == PADDING_MARKER && header.padded()) || (length == CHUNK_INDEX_MARKER && header.indexed()) {
+            break;
+        }
+        if length > MAX_CHUNK_SIZE {
+            layout.problem = Some(format!("record at offset {offset} claims {length} bytes, more than a chunk can hold"));
+            break;
+        }
+
+        let mut data = vec![0u8; usize::try_from(length).context("chunk length overflow")?];
+        if let Err(error) = reader.read_exact(&mut data).await {
+            layout.problem = Some(format!("chunk {index} at offset {offset} is cut short: {error}"));
+            break;
+        }
+
+        layout.chunks.push(inspect(&encoder, &data, &header, process.as_ref(), index, offset, length)?);
+        offset = offset.saturating_add(u64::from(length)).saturating_add(size_of::<u32>() as u64);
+        index = index.saturating_add(1);
+    }
+
+    Ok(layout)
+}
+
+fn inspect(encoder: &Encoding, data: &[u8], header: &Deserializer, process: Option<&Process>, index: u64, offset: u64, length: u32) -> Result<ChunkInfo> {
+    let shards_intact = encoder.intact_shards(data).unwrap_or(0);
+    let sealed = if shards_intact >= encoder.original_count() { encoder.decode(data).ok() } else { None };
+
+    let framing = match &sealed {
+        Some(sealed) => Some(header.cipher_mode()?.framing(sealed, header.nonce_prefix(), index)?),
+        None => None,
+    };
+    let decrypts = process.map(|process| process.process(&Task { data: SecureBuffer::unlocked(data.to_vec()), index }).is_ok());
+
+    Ok(ChunkInfo {
+        index,
+        offset,
+        length,
+        shards_intact,
+        shards_total: encoder.total_count(),
+        recoverable: sealed.is_some(),
+        nonce_matches: framing.as_ref().is_some_and(|framing| framing.nonce_matches),
+        tags_present: framing.as_ref().is_some_and(|framing| framing.tags_present),
+        decrypts,
+    })
+}
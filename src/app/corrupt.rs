@@ -0,0 +1,112 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::cipher::RandomSource;
+use crate::config::{MAX_CHUNK_SIZE, ORIGINAL_COUNT, RECOVERY_COUNT};
+use crate::encoding::Encoding;
+use crate::error::SweetByteError;
+use crate::files::Files;
+use crate::header::Deserializer;
+use crate::secret::Secret;
+
+/// What to break in each damaged copy. Both kinds can be combined.
+#[derive(Clone, Copy)]
+pub(crate) struct Damage {
+    /// Random bits to flip anywhere in the file, header included.
+    pub(crate) flip_bits: usize,
+    /// Whole shards to zero in every payload chunk, checksum included.
+    pub(crate) wipe_shards: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Trial {
+    pub(crate) trial: u32,
+    pub(crate) recovered: bool,
+    pub(crate) error: Option<String>,
+}
+
+/// Damages `trials` copies of an encrypted file in memory and tries to decrypt each one, so the
+/// Reed-Solomon claims can be measured on real files. A copy counts as recovered only if it
+/// decrypts and matches the stored hash. The file on disk is never touched, and the same seed in
+/// `rng` damages the same bytes again.
+pub(crate) async fn corrupt(source: &Files, secret: &Secret, damage: Damage, trials: u32, rng: &dyn RandomSource, cancel: &CancellationToken) -> Result<Vec<Trial>> {
+    if damage.flip_bits == 0 && damage.wipe_shards == 0 {
+        anyhow::bail!("nothing to damage; pass --flip-bits or --wipe-shards");
+    }
+    if crate::storage::is_index(source.path()) || crate::files::spanning::is_spanned(source.path()) {
+        anyhow::bail!("only a single .swx file can be damaged, not split volumes or a chunk store");
+    }
+
+    let data = tokio::fs::read(source.path()).await.context("failed to read source file")?;
+    crate::memory::decrypt_bytes(&data, secret).context("the undamaged file does not decrypt")?;
+    let (_, header_len) = Deserializer::from_bytes(&data).context(SweetByteError::CorruptHeader)?;
+
+    let mut results = Vec::new();
+    for trial in 1..=trials {
+        if cancel.is_cancelled() {
+            return Err(SweetByteError::Cancelled.into());
+        }
+
+        let mut copy = data.clone();
+        flip_bits(&mut copy, damage.flip_bits, rng)?;
+        wipe_shards(&mut copy, header_len, damage.wipe_shards, rng)?;
+
+        let error = crate::memory::decrypt_bytes(&copy, secret).err().map(|error| format!("{error:#}"));
+        results.push(Trial { trial, recovered: error.is_none(), error });
+    }
+
+    Ok(results)
+}
+
+/// Flips `count` randomly chosen bits of `data`; the same bit may be picked twice.
+pub(crate) fn flip_bits(data: &mut [u8], count: usize, rng: &dyn RandomSource) -> Result<()> {
+    for _ in 0..count {
+        let bit = below(rng, data.len().saturating_mul(8))?;
+        if let Some(byte) = data.get_mut(bit.checked_div(8).unwrap_or(0)) {
+            *byte ^= 1u8.rotate_left(u32::try_from(bit.checked_rem(8).unwrap_or(0)).unwrap_or(0));
+        }
+    }
+
+    Ok(())
+}
+
+/// Zeroes `count` distinct, randomly chosen shards in every payload record after the header. Stops
+/// at the first marker, so trailers and footers are left alone.
+pub(crate) fn wipe_shards(data: &mut [u8], header_len: usize, count: usize, rng: &dyn RandomSource) -> Result<()> {
+    if count == 0 {
+        return Ok(());
+    }
+
+    let encoder = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT)?;
+    let mut offset = header_len;
+    while let Some(prefix) = data.get(offset..).and_then(|rest| rest.first_chunk::<4>()) {
+        let length = u32::from_le_bytes(*prefix);
+        if length > MAX_CHUNK_SIZE {
+            break;
+        }
+
+        let start = offset.saturating_add(size_of::<u32>());
+        let end = start.saturating_add(usize::try_from(length).context("chunk length overflow")?);
+        let Some(record) = data.get_mut(start..end) else {
+            break;
+        };
+
+        let mut spans = encoder.shard_spans(record.len());
+        for _ in 0..count.min(spans.len()) {
+            let span = spans.swap_remove(below(rng, spans.len())?);
+            record.get_mut(span).unwrap_or_default().fill(0);
+        }
+        offset = end;
+    }
+
+    Ok(())
+}
+
+fn below(rng: &dyn RandomSource, bound: usize) -> Result<usize> {
+    let mut bytes = [0u8; 8];
+    rng.fill(&mut bytes)?;
+    let bound = u64::try_from(bound).context("bound overflow")?;
+
+    usize::try_from(u64::from_le_bytes(bytes).checked_rem(bound).unwrap_or(0)).context("random index overflow")
+}
@@ -1,28 +1,399 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
 use anyhow::{Context, Result};
+use subtle::ConstantTimeEq;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, SeekFrom};
 
-use crate::cipher::KeyDeriver;
-use crate::files::{Files, Metadata};
+use super::hidden::Payload;
+use super::{Destination, Header, Options};
+use crate::archive::tar;
+use crate::cipher::DerivedKeys;
+use crate::config::{CHUNK_INDEX_MARKER, COMPRESSION_LEVEL, IO_BUFFER_SIZE, MAX_CHUNK_SIZE};
+use crate::error::SweetByteError;
+use crate::files::hash::StreamHash;
+use crate::files::spanning::DynReader;
+use crate::files::{Attributes, Files, Metadata, OutputLock, TempFile};
 use crate::header::Deserializer;
-use crate::pipeline::{Operation, Pipeline};
+use crate::pipeline::{ChunkIndex, INDEX_TAIL_LEN, IndexEntry, Losses, Operation, Pipeline, Process, ProgressEvent, ProgressSink, Stage, Stats, Task};
 use crate::secret::Secret;
+use crate::secure_temp::SecureBuffer;
+
+const MAX_RESTORE_ATTEMPTS: u32 = 1000;
+
+const VERIFY_LABEL: &str = "Verifying...";
+
+pub(crate) async fn decrypt(source: &Files, destination: &Destination, secret: &Secret, options: &Options) -> Result<(Metadata, PathBuf)> {
+    let (header, reader) = match super::read_any_header(source, options.recovery.as_deref()).await? {
+        (Header::Current(header), reader) => (header, reader),
+        (Header::Legacy(header), reader) => return super::legacy::decrypt(&header, reader, destination, secret, options).await,
+    };
+    super::check_policy(source.path(), &header, options)?;
+
+    let unlocking = options.progress(0, Operation::Decryption.label());
+    let (keys, metadata) = match super::open_header(secret, &header, options.session.as_ref(), options.second_factor.as_ref(), &unlocking) {
+        Err(error) if header.padded() && SweetByteError::classify(&error) == SweetByteError::WrongPassword => {
+            return match super::hidden::open(source, &header, secret, &unlocking).await? {
+                Some(payload) => decrypt_hidden(payload, destination, options).await,
+                None => Err(error),
+            };
+        }
+        result => result?,
+    };
+
+    let target = match destination {
+        Destination::File(file) => file.path().to_path_buf(),
+        Destination::Restore(dir) => restore_path(dir, &metadata.name)?,
+        Destination::Extract(dir) => dir.clone(),
+        Destination::Stdout => PathBuf::from("-"),
+    };
+
+    let _lock = match destination {
+        Destination::Stdout => None,
+        Destination::File(_) | Destination::Restore(_) | Destination::Extract(_) => {
+            let needed = options.range.as_ref().map_or(metadata.size, |range| range.end.min(metadata.size).saturating_sub(range.start));
+            super::preflight(&target, needed, options)?;
+            Some(OutputLock::acquire(&target)?)
+        }
+    };
+
+    if options.range.is_some() {
+        match destination {
+            Destination::Extract(_) => anyhow::bail!("a byte range can't be extracted as an archive"),
+            Destination::Stdout => anyhow::bail!("a byte range can't be written to stdout"),
+            Destination::File(_) | Destination::Restore(_) => {}
+        }
+        decrypt_range(source, reader, &header, &keys, &metadata, &target, options).await?;
+        return Ok((metadata, target));
+    }
+
+    let progress = Arc::new(options.progress(metadata.size, Operation::Decryption.label()));
+    let losses = options.keep_going.then(Arc::<Losses>::default);
+    let pipeline = open(&header, &keys, metadata.size, options, progress, Arc::clone(&options.stats), losses.clone())?;
+
+    if let Destination::Extract(_) = destination {
+        extract(pipeline, reader, &metadata, &target, options).await?;
+        return Ok((metadata, target));
+    }
+
+    let hash = StreamHash::default();
+    if let Destination::Stdout = destination {
+        pipeline.process(reader, hash.writer(tokio::io::stdout()), metadata.size, &options.cancel).await?;
+        let lost = settle(&hash, &metadata, losses.as_deref())?;
+        partial(&lost)?;
+        return Ok((metadata, target));
+    }
+
+    let temp = TempFile::new(&target)?;
+    let writer = Files::new(temp.path()).writer(options.settings.fsync).await.context("failed to create target file")?;
+    pipeline.process(reader, hash.writer(writer), metadata.size, &options.cancel).await?;
+    let lost = settle(&hash, &metadata, losses.as_deref())?;
+
+    temp.persist().await.context("failed to finalize target file")?;
+
+    if options.preserve_attrs {
+        metadata.attributes.apply(&target, options.preserve_special_bits).context("failed to restore file attributes")?;
+    }
+    partial(&lost)?;
+
+    Ok((metadata, target))
+}
+
+/// Writes out a payload found hidden in the padding. It was authenticated as a whole when it was
+/// opened, so there is no separate hash to check, and it has no attributes to restore.
+async fn decrypt_hidden(payload: Payload, destination: &Destination, options: &Options) -> Result<(Metadata, PathBuf)> {
+    if options.range.is_some() {
+        anyhow::bail!("a byte range can't be read from a hidden payload");
+    }
+    let metadata =
+        Metadata { name: payload.name, size: u64::try_from(payload.data.len()).context("size overflow")?, hash: blake3::hash(&payload.data).as_bytes().to_vec(), attributes: Attributes::default() };
 
-pub(crate) async fn decrypt(source: &Files, target: &Files, secret: &Secret) -> Result<Metadata> {
-    let mut reader = source.reader().await.context("failed to open source file")?;
-    let writer = target.writer().await.context("failed to create target file")?;
-    let header = Deserializer::from_reader(reader.get_mut()).await.context("failed to deserialize header")?;
+    let target = match destination {
+        Destination::File(file) => file.path().to_path_buf(),
+        Destination::Restore(dir) => restore_path(dir, &metadata.name)?,
+        Destination::Extract(_) => anyhow::bail!("a hidden payload is a single file and can't be extracted as an archive"),
+        Destination::Stdout => {
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(&payload.data).await.context("failed to write to stdout")?;
+            stdout.flush().await.context("failed to flush")?;
+            return Ok((metadata, PathBuf::from("-")));
+        }
+    };
+
+    super::preflight(&target, metadata.size, options)?;
+    let _lock = OutputLock::acquire(&target)?;
+    let temp = TempFile::new(&target)?;
+    let mut writer = Files::new(temp.path()).writer(options.settings.fsync).await.context("failed to create target file")?;
+    writer.write_all(&payload.data).await.context("failed to write target file")?;
+    writer.shutdown().await.context("failed to flush target file")?;
+    temp.persist().await.context("failed to finalize target file")?;
+
+    Ok((metadata, target))
+}
+
+/// Decrypts `source` into a hashing sink without writing any plaintext, and checks it against the
+/// header and against `expected`, the metadata encryption produced. This is the read-back `encrypt
+/// --verify-after` does so the original isn't deleted on the strength of an output that can't be
+/// decrypted.
+pub(crate) async fn read_back(source: &Files, secret: &Secret, expected: &Metadata, options: &Options) -> Result<()> {
+    let mut reader = crate::files::spanning::reader(source).await.context("failed to open encrypted file")?;
+    let header = Deserializer::from_reader(&mut reader).await.context(SweetByteError::CorruptHeader)?;
+
+    let unlocking = options.progress(0, VERIFY_LABEL);
+    let (keys, metadata) = super::open_header(secret, &header, options.session.as_ref(), options.second_factor.as_ref(), &unlocking)?;
+    if metadata.size != expected.size || !bool::from(metadata.hash.as_slice().ct_eq(&expected.hash)) {
+        anyhow::bail!("header records a different size or hash than was encrypted");
+    }
+
+    // Kept out of the run's stats, which describe the encryption.
+    let progress = Arc::new(options.progress(metadata.size, VERIFY_LABEL));
+    let pipeline = open(&header, &keys, metadata.size, options, progress, Arc::default(), None)?;
+    let hash = StreamHash::default();
+    pipeline.process(reader, hash.writer(tokio::io::sink()), metadata.size, &options.cancel).await?;
+
+    verify(&hash, &metadata)
+}
+
+/// The plaintext ranges `--keep-going` wrote as zeros. The hash can't match a file with gaps, so
+/// it's only checked when nothing was lost.
+fn settle(hash: &StreamHash, metadata: &Metadata, losses: Option<&Losses>) -> Result<Vec<Range<u64>>> {
+    let lost = losses.map(Losses::ranges).unwrap_or_default();
+    if lost.is_empty() {
+        verify(hash, metadata)?;
+    }
+
+    Ok(lost)
+}
+
+/// Fails with a partial recovery when any plaintext was lost, after the rest has been kept.
+fn partial(lost: &[Range<u64>]) -> Result<()> {
+    if lost.is_empty() {
+        return Ok(());
+    }
+
+    let lost_bytes = lost.iter().map(|range| range.end.saturating_sub(range.start)).fold(0u64, u64::saturating_add);
+    Err(SweetByteError::PartialRecovery { lost_bytes, lost_ranges: lost.iter().map(|range| (range.start, range.end)).collect() }.into())
+}
+
+/// Checks the hash taken of the plaintext as it was written against the one in the header.
+pub(super) fn verify(hash: &StreamHash, metadata: &Metadata) -> Result<()> {
+    let (actual, _) = hash.finish();
+    if !bool::from(actual.as_slice().ct_eq(&metadata.hash)) {
+        anyhow::bail!("hash verification failed");
+    }
+
+    Ok(())
+}
 
-    let key = KeyDeriver::new(secret)?;
-    let keys = key.derive_keys(header.salt())?;
-    if !header.verify(&keys.signer_key)? {
-        anyhow::bail!("incorrect password or corrupted file");
+async fn extract<R>(pipeline: Pipeline, reader: R, metadata: &Metadata, target: &Path, options: &Options) -> Result<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    if target.exists() {
+        anyhow::bail!("extraction target already exists: {}", target.display());
     }
 
-    let pipeline = Pipeline::new(&keys.primary_key, &keys.secondary_key, Operation::Decryption)?;
-    pipeline.process(reader, writer, header.file_size()).await?;
+    let staging = TempFile::new(target)?;
+    tokio::fs::create_dir(staging.path()).await.context("failed to create extraction directory")?;
 
-    if !crate::files::hash::validate_hash(target, header.file_hash())? {
+    let (writer, unpacking) = tar::writer(staging.path().to_path_buf());
+    pipeline.process(reader, writer, metadata.size, &options.cancel).await?;
+
+    let unpacked = unpacking.await.context("extractor panicked")?.context("failed to extract archive")?;
+    if !bool::from(unpacked.hash.as_slice().ct_eq(&metadata.hash)) {
         anyhow::bail!("hash verification failed");
     }
 
-    Ok(Metadata { name: header.file_name().to_owned(), size: header.file_size(), hash: header.file_hash().to_vec() })
+    staging.persist().await.context("failed to move extracted directory into place")
+}
+
+/// Decrypts only the chunks that overlap the requested range (end exclusive, clamped to the file)
+/// and writes just those bytes. With a chunk index the source is seeked straight to the first chunk
+/// needed; without one the earlier chunks are read past. Every chunk that is decrypted is
+/// authenticated, but the whole-file hash and payload MAC can't be checked on a slice.
+async fn decrypt_range(source: &Files, reader: BufReader<DynReader>, header: &Deserializer, keys: &DerivedKeys, metadata: &Metadata, target: &Path, options: &Options) -> Result<()> {
+    let range = options.range.as_ref().context("no byte range requested")?;
+    let chunk_size = u64::from(header.chunk_size());
+    if chunk_size == 0 {
+        anyhow::bail!("file does not record its chunk size; decrypt it whole instead");
+    }
+
+    let end = range.end.min(metadata.size);
+    if range.start >= end {
+        anyhow::bail!("range starts at byte {} but the file holds {} bytes", range.start, metadata.size);
+    }
+    let first = range.start.checked_div(chunk_size).unwrap_or(0);
+    let last = end.div_ceil(chunk_size);
+
+    let (mut reader, skip) = match locate(source, header, keys, first).await? {
+        Some(located) => (located, first),
+        None => (reader, 0),
+    };
+
+    let process = Process::new(&keys.primary_key, &keys.secondary_key, Operation::Decryption, header.cipher_mode()?, header.nonce_prefix(), COMPRESSION_LEVEL)?
+        .with_padding_scheme(header.padding_scheme())
+        .with_chunk_limit(header.chunk_limit(metadata.size));
+    let total = end.saturating_sub(range.start);
+    let progress = options.progress(total, Operation::Decryption.label());
+    progress.on_event(&ProgressEvent::new(Stage::Started, total));
+
+    let temp = TempFile::new(target)?;
+    let mut writer = Files::new(temp.path()).writer(options.settings.fsync).await.context("failed to create target file")?;
+    let (mut bytes_in, mut bytes_out) = (0u64, 0u64);
+
+    for index in skip..last {
+        if options.cancel.is_cancelled() {
+            return Err(SweetByteError::Cancelled.into());
+        }
+
+        let length = reader.read_u32_le().await.context("payload ends before the requested range")?;
+        if length > MAX_CHUNK_SIZE {
+            anyhow::bail!("chunk {index} claims {length} bytes, more than a chunk can hold");
+        }
+
+        if index < first {
+            let skipped = tokio::io::copy(&mut (&mut reader).take(u64::from(length)), &mut tokio::io::sink())
+                .await
+                .context("failed to skip chunk")?;
+            if skipped != u64::from(length) {
+                anyhow::bail!("payload ends before the requested range");
+            }
+            continue;
+        }
+
+        let mut data = vec![0u8; usize::try_from(length).context("chunk length overflow")?];
+        reader.read_exact(&mut data).await.context("payload ends inside a chunk")?;
+        let plaintext = process.process(&Task { data: SecureBuffer::unlocked(data), index })?.data;
+
+        let chunk_start = index.saturating_mul(chunk_size);
+        let from = usize::try_from(range.start.saturating_sub(chunk_start)).context("range offset overflow")?;
+        let to = usize::try_from(end.saturating_sub(chunk_start)).unwrap_or(usize::MAX).min(plaintext.len());
+        let slice = plaintext.get(from..to).context("chunk is shorter than the recorded chunk size")?;
+        writer.write_all(slice).await.context("failed to write output")?;
+
+        let written = u64::try_from(slice.len()).unwrap_or(u64::MAX);
+        bytes_in = bytes_in.saturating_add(u64::from(length)).saturating_add(u64::try_from(size_of::<u32>()).unwrap_or(u64::MAX));
+        bytes_out = bytes_out.saturating_add(written);
+        let mut event = ProgressEvent::new(Stage::Chunk, total);
+        event.chunk = index;
+        event.chunk_plaintext = written;
+        event.bytes_in = bytes_in;
+        event.bytes_out = bytes_out;
+        progress.on_event(&event);
+    }
+
+    writer.flush().await.context("failed to flush")?;
+    progress.on_event(&ProgressEvent::new(Stage::Finished, total));
+
+    temp.persist().await.context("failed to finalize target file")
+}
+
+/// Opens the source positioned at chunk `first` by way of the chunk index, when the file has one
+/// and can be seeked.
+async fn locate(source: &Files, header: &Deserializer, keys: &DerivedKeys, first: u64) -> Result<Option<BufReader<DynReader>>> {
+    if !header.indexed() {
+        return Ok(None);
+    }
+    let Some(mut file) = crate::files::spanning::seekable(source).await? else {
+        return Ok(None);
+    };
+
+    let entries = read_index(&mut file, header, keys).await?;
+    let entry = entries
+        .get(usize::try_from(first).context("chunk index overflow")?)
+        .with_context(|| format!("chunk index has no entry for chunk {first}"))?;
+    if entry.plaintext_offset != first.saturating_mul(u64::from(header.chunk_size())) {
+        anyhow::bail!("chunk index disagrees with the recorded chunk size");
+    }
+
+    let header_len = u64::try_from(header.encoded_len()).context("header length overflow")?;
+    file.seek(SeekFrom::Start(header_len.saturating_add(entry.offset))).await.context("failed to seek to chunk")?;
+    let file: DynReader = Box::new(file);
+
+    Ok(Some(BufReader::with_capacity(IO_BUFFER_SIZE, file)))
+}
+
+/// Reads the chunk index from the end of `file` and checks that it lists every chunk the header
+/// records.
+pub(super) async fn read_index(file: &mut File, header: &Deserializer, keys: &DerivedKeys) -> Result<Vec<IndexEntry>> {
+    let len = file
+        .metadata()
+        .await
+        .context("failed to read file size")?
+        .len()
+        .checked_sub(header.trailing_len())
+        .context("file is too short to hold its header copy")?;
+    let tail_start = len.checked_sub(INDEX_TAIL_LEN as u64).context("file is too short to hold a chunk index")?;
+    let mut tail = [0u8; INDEX_TAIL_LEN];
+    file.seek(SeekFrom::Start(tail_start)).await.context("failed to seek to chunk index")?;
+    file.read_exact(&mut tail).await.context("failed to read chunk index")?;
+
+    let encoded_len = ChunkIndex::encoded_len(&tail)?;
+    let marker_start = tail_start
+        .checked_sub(u64::from(encoded_len))
+        .and_then(|start| start.checked_sub(size_of::<u32>() as u64))
+        .context("chunk index runs past the start of the file")?;
+    file.seek(SeekFrom::Start(marker_start)).await.context("failed to seek to chunk index")?;
+    if file.read_u32_le().await.context("failed to read chunk index")? != CHUNK_INDEX_MARKER {
+        anyhow::bail!("chunk index is missing its marker");
+    }
+    let mut encoded = vec![0u8; usize::try_from(encoded_len).context("chunk index length overflow")?];
+    file.read_exact(&mut encoded).await.context("failed to read chunk index")?;
+
+    let entries = ChunkIndex::open(&keys.index_key, &encoded)?;
+    if u64::try_from(entries.len()).context("chunk index length overflow")? != header.chunk_count() {
+        anyhow::bail!("chunk index lists {} chunks but the header records {}", entries.len(), header.chunk_count());
+    }
+
+    Ok(entries)
+}
+
+pub(super) fn open(header: &Deserializer, keys: &DerivedKeys, size: u64, options: &Options, progress: Arc<dyn ProgressSink>, stats: Arc<Stats>, losses: Option<Arc<Losses>>) -> Result<Pipeline> {
+    let mode = header.cipher_mode()?;
+    let mut pipeline = Pipeline::new(&keys.primary_key, &keys.secondary_key, Operation::Decryption, mode, header.nonce_prefix(), &options.settings)?
+        .with_progress(progress)
+        .with_expected_chunks(header.chunk_count())
+        .with_expected_size(size)
+        .with_padded_input(header.padded())
+        .with_indexed_input(header.indexed())
+        .with_padding_scheme(header.padding_scheme())
+        .with_chunk_limit(header.chunk_limit(size))
+        .with_stats(stats);
+    if header.payload_mac() {
+        pipeline = pipeline.with_payload_mac(&keys.payload_key)?;
+    }
+    if let Some(losses) = losses {
+        if header.chunk_size() == 0 {
+            anyhow::bail!("file does not record its chunk size, so --keep-going can't tell how much a lost chunk held");
+        }
+        pipeline = pipeline.with_keep_going(losses, u64::from(header.chunk_size()));
+    }
+
+    Ok(pipeline)
+}
+
+pub(super) fn restore_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    let name = Path::new(name).file_name().map(Path::new).context("original filename is not a plain file name")?;
+    let candidate = dir.join(name);
+    if !candidate.exists() {
+        return Ok(candidate);
+    }
+
+    let stem = name.file_stem().unwrap_or(name.as_os_str()).to_string_lossy();
+    let extension = name.extension().map(|e| e.to_string_lossy());
+    for attempt in 1..=MAX_RESTORE_ATTEMPTS {
+        let file_name = match &extension {
+            Some(extension) => format!("{stem} ({attempt}).{extension}"),
+            None => format!("{stem} ({attempt})"),
+        };
+
+        let candidate = dir.join(file_name);
+        if !candidate.exists() {
+            return Ok(candidate);
+        }
+    }
+
+    anyhow::bail!("no free file name for {} in {}", name.display(), dir.display())
 }
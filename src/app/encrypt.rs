@@ -1,28 +1,265 @@
+use std::path::Path;
+use std::sync::Arc;
+
 use anyhow::{Context, Result};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter};
 
-use crate::cipher::KeyDeriver;
-use crate::config::ARGON2_SALT_LEN;
-use crate::files::{Files, Metadata};
+use super::Options;
+use crate::archive::tar;
+use crate::cipher::{KdfSession, KeyDeriver, KeySlot, RandomSource};
+use crate::config::{ARGON2_SALT_LEN, HASH_PATCH_HEADROOM, MASTER_KEY_LEN, MAX_CARRIER_SIZE, NONCE_PREFIX_LEN};
+use crate::files::hash::StreamHash;
+use crate::files::spanning::{DynWriter, Output};
+use crate::files::{Attributes, Files, Metadata, OutputLock, Snapshot, TempFile};
 use crate::header::Serializer;
+use crate::manifest::{Entry, Manifest};
 use crate::pipeline::{Operation, Pipeline};
 use crate::secret::Secret;
 
-pub(crate) async fn encrypt(source: &Files, target: &Files, secret: &Secret) -> Result<Metadata> {
-    let mut writer = target.writer().await.context("failed to create target file")?;
+pub(crate) async fn encrypt(source: &Files, target: &Files, secrets: &[Secret], options: &Options, rng: &dyn RandomSource) -> Result<Metadata> {
+    if source.is_stdin() {
+        return encrypt_stream(tokio::io::stdin(), target, secrets, options, rng).await;
+    }
+
+    let snapshot = if options.paranoid { Some(Snapshot::take(source.path()).await?) } else { None };
+    let size = source.size().await?;
+    super::preflight(output_root(target, options), options.estimate(size), options)?;
+
+    let (_lock, output, writer) = create_output(target, options).await?;
     let reader = source.reader().await.context("failed to open source file")?;
-    let metadata = source.metadata().await.context("failed to read metadata")?;
 
-    let salt = KeyDeriver::generate_salt(ARGON2_SALT_LEN)?;
-    let key = KeyDeriver::new(secret)?;
-    let keys = key.derive_keys(&salt)?;
+    // Convergent keys come from the content hash and a chunk store can't rewrite its header, so both
+    // hash the source up front. Everything else hashes it during the single encrypting read and
+    // patches the header afterwards.
+    let metadata = if options.convergent || options.chunk_store.is_some() {
+        let metadata = source.metadata(options.settings.threads).await.context("failed to read metadata")?;
+        seal(reader, writer, metadata, secrets, options, rng, None).await?
+    } else {
+        let metadata = source.unhashed_metadata().await.context("failed to read metadata")?;
+        let deferred = Deferred { hash: StreamHash::default(), output: &output, sized: true };
+        seal(deferred.hash.reader(reader), writer, metadata, secrets, options, rng, Some(&deferred)).await?
+    };
+    if let Some(snapshot) = snapshot {
+        snapshot.ensure_unchanged(source.path()).await?;
+    }
+    output.persist().await.context("failed to finalize target file")?;
+    if let Some(carrier) = &options.carrier {
+        embed_in_carrier(target, carrier, options).await?;
+    }
+
+    if let Some(manifest) = &options.manifest {
+        let entry = Entry::new(source, target, &metadata, options).await.context("failed to build manifest entry")?;
+        Manifest::record(manifest, entry).await?;
+    }
+
+    Ok(metadata)
+}
+
+/// Encrypts everything `reader` yields in a single pass, without knowing its size beforehand. The
+/// stored name is the target's without `.swx`.
+pub(crate) async fn encrypt_stream<R>(reader: R, target: &Files, secrets: &[Secret], options: &Options, rng: &dyn RandomSource) -> Result<Metadata>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    if options.convergent || options.chunk_store.is_some() {
+        anyhow::bail!("a stream can't be encrypted with --convergent or --chunk-store");
+    }
+
+    let (_lock, output, writer) = create_output(target, options).await?;
+    let name = Files::new(target.output_path(Operation::Decryption)).name().to_owned();
+    let metadata = Metadata { name, size: 0, hash: vec![0u8; blake3::OUT_LEN], attributes: Attributes::default() };
+    let deferred = Deferred { hash: StreamHash::default(), output: &output, sized: false };
+    let metadata = seal(deferred.hash.reader(reader), writer, metadata, secrets, options, rng, Some(&deferred)).await?;
+    output.persist().await.context("failed to finalize target file")?;
+    if let Some(carrier) = &options.carrier {
+        embed_in_carrier(target, carrier, options).await?;
+    }
+
+    Ok(metadata)
+}
+
+pub(crate) async fn encrypt_directory(source: &Path, target: &Files, secrets: &[Secret], options: &Options, rng: &dyn RandomSource) -> Result<Metadata> {
+    if !source.is_dir() {
+        anyhow::bail!("--tar requires a directory: {}", source.display());
+    }
+
+    let name = source.file_name().and_then(|n| n.to_str()).context("invalid directory name")?.to_owned();
+    let dir = source.to_path_buf();
+    let summary = tokio::task::spawn_blocking(move || tar::summarize(&dir)).await.context("archive scan panicked")??;
+    let metadata = Metadata { name, size: summary.size, hash: summary.hash, attributes: Attributes::read(source)? };
+    super::preflight(output_root(target, options), options.estimate(summary.size), options)?;
+
+    let (_lock, output, writer) = create_output(target, options).await?;
+    let (reader, packing) = tar::reader(source);
+    let metadata = seal(reader, writer, metadata, secrets, options, rng, None).await?;
+
+    let packed = packing.await.context("archiver panicked")?.context("failed to archive directory")?;
+    if packed.hash != metadata.hash {
+        anyhow::bail!("directory changed while it was being encrypted");
+    }
+
+    output.persist().await.context("failed to finalize target file")?;
+    if let Some(carrier) = &options.carrier {
+        embed_in_carrier(target, carrier, options).await?;
+    }
+
+    Ok(metadata)
+}
+
+/// Replaces the finished output with a copy of `carrier` that holds it, so it passes for an
+/// ordinary image or sound file. Decryption finds it again on its own.
+async fn embed_in_carrier(target: &Files, carrier: &Path, options: &Options) -> Result<()> {
+    let size = target.size().await?;
+    if size > MAX_CARRIER_SIZE {
+        anyhow::bail!("output is {size} bytes, more than the {MAX_CARRIER_SIZE} a carrier can hold");
+    }
 
-    let header = Serializer::new(metadata.name, metadata.size, metadata.hash)?;
-    let serialized = header.serialize(salt.expose_secret(), &keys.signer_key).context("failed to serialize header")?;
+    let carrier = tokio::fs::read(carrier).await.with_context(|| format!("failed to read carrier {}", carrier.display()))?;
+    let sealed = tokio::fs::read(target.path()).await.context("failed to read encrypted output")?;
+    let embedded = crate::stego::embed(&carrier, &sealed)?;
+
+    let temp = TempFile::new(target.path())?;
+    let mut writer = Files::new(temp.path()).writer(options.settings.fsync).await.context("failed to create target file")?;
+    writer.write_all(&embedded).await.context("failed to write target file")?;
+    writer.shutdown().await.context("failed to flush target file")?;
+
+    temp.persist().await.context("failed to finalize target file")
+}
+
+/// Where the encrypted bytes land: the chunk store's directory when there is one, otherwise the
+/// target itself.
+fn output_root<'a>(target: &'a Files, options: &'a Options) -> &'a Path {
+    options.chunk_store.as_deref().unwrap_or(target.path())
+}
+
+/// Locks the target against other processes and opens the output behind it. The lock is held until
+/// it's dropped, after the output has been persisted or discarded.
+pub(super) async fn create_output(target: &Files, options: &Options) -> Result<(OutputLock, Output, BufWriter<DynWriter>)> {
+    let lock = OutputLock::acquire(target.path())?;
+    let (output, writer) = match &options.chunk_store {
+        Some(root) => Output::store(target.path(), root),
+        None => Output::create(target.path(), options.split_size, options.settings.fsync).await,
+    }
+    .context("failed to create target file")?;
+
+    Ok((lock, output, writer))
+}
+
+/// A header written before the source has been read, rewritten in place once the payload is done.
+pub(super) struct Deferred<'a> {
+    hash: StreamHash,
+    output: &'a Output,
+    /// Whether the size was known up front, so that reading any other amount means the source
+    /// changed underneath.
+    sized: bool,
+}
+
+/// Writes the header and encrypts the payload behind it. A deferred header first goes out with a
+/// placeholder size and hash and spare room, and is rewritten in place with the real ones once the
+/// reader has seen every byte.
+pub(super) async fn seal<R, W>(reader: R, mut writer: W, metadata: Metadata, secrets: &[Secret], options: &Options, rng: &dyn RandomSource, deferred: Option<&Deferred<'_>>) -> Result<Metadata>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let progress = Arc::new(options.progress(metadata.size, Operation::Encryption.label()));
+    let convergent;
+    let rng: &dyn RandomSource = if options.convergent {
+        let secret = secrets.first().context("no password provided")?;
+        convergent = super::convergent_random(secret, &metadata.hash, &options.settings.kdf, progress.as_ref())?;
+        &convergent
+    } else {
+        rng
+    };
+
+    let salt = KeyDeriver::generate_salt(rng, ARGON2_SALT_LEN)?;
+    let mut master_key = vec![0u8; MASTER_KEY_LEN];
+    rng.fill(&mut master_key).context("failed to generate master key")?;
+    let master_key = Secret::new(master_key);
+
+    // Convergent files derive everything from the content, which a per-run session salt would defeat.
+    let session = options.session.as_ref().filter(|_| !options.convergent);
+    let session_salt = session.map(KdfSession::salt);
+    let mut slots = Vec::with_capacity(secrets.len().saturating_add(1));
+    for secret in secrets.iter().chain(&options.share_key) {
+        let kek = super::derive_kek(secret, &salt, session_salt, &options.settings.kdf, session, progress.as_ref())?;
+        let kek = match &options.second_factor {
+            Some(factor) => factor.bind(&kek, &salt)?,
+            None => kek,
+        };
+        slots.push(KeySlot::wrap(&kek, &master_key, rng)?);
+    }
+    let keys = KeyDeriver::expand(&master_key, &salt)?;
+    let hidden = match &options.hidden {
+        Some(hidden) => super::hidden::seal(hidden, secrets, &salt, &options.settings.kdf, rng, progress.as_ref()).await?,
+        None => Vec::new(),
+    };
+
+    let chunk_size = u64::try_from(options.settings.chunk_size).context("chunk size overflow")?;
+    let chunks = metadata.size.div_ceil(chunk_size);
+
+    let mut nonce_prefix = vec![0u8; NONCE_PREFIX_LEN];
+    rng.fill(&mut nonce_prefix).context("failed to generate nonce prefix")?;
+
+    // A chunk store holds chunks as separate objects, so there are no file offsets to index.
+    let indexed = options.settings.chunk_index && options.chunk_store.is_none();
+    // The copy is found by reading back from the end past the chunk index, which only a single file
+    // allows.
+    let copied = options.settings.header_copy && indexed && options.split_size.is_none();
+    // A header can't record an empty file, and a deferred one gets the real size before it's final
+    // anyway.
+    let placeholder_size = if deferred.is_some() { metadata.size.max(1) } else { metadata.size };
+    let mut header = Serializer::new(metadata.name, placeholder_size, metadata.hash, metadata.attributes, options.cipher, nonce_prefix, &options.settings.kdf)?
+        .with_payload_mac(options.settings.payload_mac)
+        .with_chunk_count(chunks)
+        .with_chunk_size(u32::try_from(options.settings.chunk_size).context("chunk size overflow")?)
+        .with_chunk_index(indexed)
+        .with_padding(options.pad_size.is_enabled())
+        .with_padding_scheme(options.settings.padding)
+        .with_convergent(options.convergent)
+        .with_session_salt(session_salt)?
+        .with_second_factor(options.second_factor.is_some())
+        .with_header_copy(copied)
+        .with_headroom(if deferred.is_some() { HASH_PATCH_HEADROOM } else { 0 });
+    let serialized = header.serialize(salt.expose_secret(), slots.clone(), &keys, rng).context("failed to serialize header")?;
     writer.write_all(&serialized).await.context("failed to write header")?;
 
-    let engine = Pipeline::new(&keys.primary_key, &keys.secondary_key, Operation::Encryption)?;
-    engine.process(reader, writer, metadata.size).await?;
+    let header_len = u64::try_from(serialized.len()).context("header length overflow")?;
+    let mut engine = Pipeline::new(&keys.primary_key, &keys.secondary_key, Operation::Encryption, options.cipher, header.nonce_prefix(), &options.settings)?
+        .with_progress(progress)
+        .with_stats(Arc::clone(&options.stats))
+        .with_padding(options.pad_size, header_len)
+        .with_hidden(hidden);
+    if copied {
+        engine = engine.with_header_copy(crate::header::trailing_copy(&serialized)?);
+    }
+    if options.settings.payload_mac {
+        engine = engine.with_payload_mac(&keys.payload_key)?;
+    }
+    if indexed {
+        engine = engine.with_chunk_index(&keys.index_key);
+    }
+    engine.process(reader, writer, metadata.size, &options.cancel).await?;
+
+    if let Some(deferred) = deferred {
+        let (hash, read) = deferred.hash.finish();
+        if deferred.sized && read != metadata.size {
+            anyhow::bail!("source changed while it was being encrypted");
+        }
+        if read == 0 {
+            anyhow::bail!("nothing to encrypt: the source is empty");
+        }
+
+        header = header.with_chunk_count(read.div_ceil(chunk_size)).with_file_content(read, hash)?;
+        let patched = header
+            .serialize_to_len(salt.expose_secret(), slots, &keys, rng, serialized.len())
+            .context("failed to serialize final header")?;
+        deferred.output.patch(&patched, options.settings.fsync).await.context("failed to write final header")?;
+        if copied {
+            let copy = crate::header::trailing_copy(&patched)?;
+            deferred.output.patch_tail(&copy, options.settings.fsync).await.context("failed to write final header copy")?;
+        }
+    }
 
-    Ok(Metadata { name: header.file_name().to_owned(), size: header.file_size(), hash: header.file_hash().to_vec() })
+    Ok(Metadata { name: header.file_name().to_owned(), size: header.file_size(), hash: header.file_hash().to_vec(), attributes: metadata.attributes })
 }
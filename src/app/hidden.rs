@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+use zeroize::Zeroizing;
+
+use crate::cipher::{KdfParams, KeyDeriver, RandomSource};
+use crate::config::{CHUNK_INDEX_MARKER, MAX_HIDDEN_SIZE, SLOT_NONCE_LEN};
+use crate::files::Files;
+use crate::header::Deserializer;
+use crate::pipeline::{ChunkIndex, INDEX_TAIL_LEN, ProgressSink};
+use crate::secret::Secret;
+
+/// The sealed record at the very end of the blob that gives the length of the sealed payload before
+/// it: a nonce, a little-endian `u64` and a Poly1305 tag.
+const LENGTH_RECORD_LEN: usize = SLOT_NONCE_LEN + size_of::<u64>() + 16;
+
+/// A second file written into the padding of a decoy, readable only with its own password. Without
+/// that password the blob is indistinguishable from the random filler around it.
+pub(crate) struct Hidden {
+    pub(crate) source: Files,
+    pub(crate) secret: Secret,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(super) struct Payload {
+    pub(super) name: String,
+    pub(super) data: Vec<u8>,
+}
+
+/// Seals the hidden file under a key stretched from its password and the decoy's `salt`, ready to
+/// end the filler.
+pub(super) async fn seal(hidden: &Hidden, decoys: &[Secret], salt: &Secret, kdf: &KdfParams, rng: &dyn RandomSource, progress: &dyn ProgressSink) -> Result<Vec<u8>> {
+    if decoys.iter().any(|decoy| bool::from(decoy.expose_secret().ct_eq(hidden.secret.expose_secret()))) {
+        anyhow::bail!("the hidden password must differ from the decoy's");
+    }
+    let size = hidden.source.size().await?;
+    if size > MAX_HIDDEN_SIZE {
+        anyhow::bail!("hidden file is {size} bytes, more than the {MAX_HIDDEN_SIZE} a hidden payload can hold");
+    }
+
+    let data = tokio::fs::read(hidden.source.path()).await.context("failed to read hidden file")?;
+    let payload = Payload { name: hidden.source.name().to_owned(), data };
+    let plaintext = Zeroizing::new(postcard::to_allocvec(&payload).context("failed to serialize hidden payload")?);
+    drop(Zeroizing::new(payload.data));
+
+    let key = key(&hidden.secret, salt, kdf, progress)?;
+    let mut blob = crate::cipher::seal(&key, &plaintext, rng)?;
+    let length = u64::try_from(blob.len()).context("hidden payload too large")?;
+    blob.extend_from_slice(&crate::cipher::seal(&key, &length.to_le_bytes(), rng)?);
+
+    Ok(blob)
+}
+
+/// Looks for a payload hidden at the end of the padding of `source` that `secret` opens. `None`
+/// when there is none, which can't be told apart from a password that opens nothing at all.
+pub(super) async fn open(source: &Files, header: &Deserializer, secret: &Secret, progress: &dyn ProgressSink) -> Result<Option<Payload>> {
+    if !header.padded() {
+        return Ok(None);
+    }
+    let Some(mut file) = crate::files::spanning::seekable(source).await? else {
+        return Ok(None);
+    };
+
+    let mut end = file
+        .metadata()
+        .await
+        .context("failed to read file size")?
+        .len()
+        .checked_sub(header.trailing_len())
+        .context("file is too short to hold its header copy")?;
+    if header.indexed() {
+        end = footer_start(&mut file, end).await?;
+    }
+    let header_len = u64::try_from(header.encoded_len()).context("header length overflow")?;
+    let record_len = u64::try_from(LENGTH_RECORD_LEN).context("size overflow")?;
+    let Some(record_start) = end.checked_sub(record_len).filter(|start| *start >= header_len) else {
+        return Ok(None);
+    };
+
+    let key = key(secret, header.salt(), &header.kdf(), progress)?;
+    let record = read_at(&mut file, record_start, LENGTH_RECORD_LEN).await?;
+    let Ok(length) = crate::cipher::open(&key, &record) else {
+        return Ok(None);
+    };
+
+    let length: [u8; size_of::<u64>()] = length.expose_secret().try_into().context("hidden payload length is malformed")?;
+    let length = u64::from_le_bytes(length);
+    let start = record_start
+        .checked_sub(length)
+        .filter(|start| *start >= header_len)
+        .context("hidden payload runs past the start of the padding")?;
+    let sealed = read_at(&mut file, start, usize::try_from(length).context("hidden payload too large")?).await?;
+    let plaintext = crate::cipher::open(&key, &sealed).context("hidden payload is damaged")?;
+
+    postcard::from_bytes(plaintext.expose_secret()).map(Some).context("failed to parse hidden payload")
+}
+
+fn key(secret: &Secret, salt: &Secret, kdf: &KdfParams, progress: &dyn ProgressSink) -> Result<Secret> {
+    let kek = super::derive_kek(secret, salt, None, kdf, None, progress)?;
+
+    KeyDeriver::hidden_key(&kek, salt)
+}
+
+/// Where the chunk index footer starts, i.e. where the padding ends in an indexed file of `len`
+/// bytes.
+async fn footer_start(file: &mut File, len: u64) -> Result<u64> {
+    let tail_start = len.checked_sub(INDEX_TAIL_LEN as u64).context("file is too short to hold a chunk index")?;
+    let tail = read_at(file, tail_start, INDEX_TAIL_LEN).await?;
+    let encoded_len = ChunkIndex::encoded_len(tail.as_slice().try_into().context("invalid chunk index tail")?)?;
+
+    let marker_start = tail_start
+        .checked_sub(u64::from(encoded_len))
+        .and_then(|start| start.checked_sub(size_of::<u32>() as u64))
+        .context("chunk index runs past the start of the file")?;
+    file.seek(SeekFrom::Start(marker_start)).await.context("failed to seek to chunk index")?;
+    if file.read_u32_le().await.context("failed to read chunk index")? != CHUNK_INDEX_MARKER {
+        anyhow::bail!("chunk index is missing its marker");
+    }
+
+    Ok(marker_start)
+}
+
+async fn read_at(file: &mut File, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; len];
+    file.seek(SeekFrom::Start(offset)).await.context("failed to seek to hidden payload")?;
+    file.read_exact(&mut buffer).await.context("failed to read hidden payload")?;
+
+    Ok(buffer)
+}
@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+
+use crate::cipher::{DerivedKeys, KeyDeriver, KeySlot, RandomSource};
+use crate::error::SweetByteError;
+use crate::files::Files;
+use crate::header::Deserializer;
+use crate::pipeline::ProgressSink;
+use crate::secret::Secret;
+
+pub(crate) async fn add_key(source: &Files, secret: &Secret, new_secret: &Secret, progress: &dyn ProgressSink, rng: &dyn RandomSource) -> Result<usize> {
+    let header = read_header(source).await?;
+    let (_, master_key, keys) = open_slot(secret, &header, progress)?;
+
+    let kek = super::derive_kek(new_secret, header.salt(), header.session_salt(), &header.kdf(), None, progress)?;
+    if header.find_slot(&kek).is_some() {
+        anyhow::bail!("password already unlocks this file");
+    }
+
+    let slots = header.slot_count().saturating_add(1);
+    let copied = header.copied();
+    let slot = KeySlot::wrap(&kek, &master_key, rng)?;
+    let serialized = header.add_slot(slot, &keys.signer_key)?;
+    rewrite(source, &serialized, copied).await?;
+
+    Ok(slots)
+}
+
+pub(crate) async fn remove_key(source: &Files, secret: &Secret, progress: &dyn ProgressSink) -> Result<usize> {
+    let header = read_header(source).await?;
+    let (index, _, keys) = open_slot(secret, &header, progress)?;
+
+    let slots = header.slot_count().saturating_sub(1);
+    let copied = header.copied();
+    let serialized = header.remove_slot(index, &keys.signer_key)?;
+    rewrite(source, &serialized, copied).await?;
+
+    Ok(slots)
+}
+
+/// Writes the new header over the old one, and over the copy at the end of the file when there is
+/// one.
+async fn rewrite(source: &Files, serialized: &[u8], copied: bool) -> Result<()> {
+    if copied {
+        let copy = crate::header::trailing_copy(serialized)?;
+        crate::files::spanning::overwrite_tail(source, &copy).await.context("failed to rewrite header copy")?;
+    }
+
+    crate::files::spanning::overwrite_header(source, serialized).await.context("failed to rewrite header")
+}
+
+async fn read_header(source: &Files) -> Result<Deserializer> {
+    let (header, _) = super::read_header(source, None).await?;
+
+    Ok(header)
+}
+
+fn open_slot(secret: &Secret, header: &Deserializer, progress: &dyn ProgressSink) -> Result<(usize, Secret, DerivedKeys)> {
+    let kek = super::derive_kek(secret, header.salt(), header.session_salt(), &header.kdf(), None, progress)?;
+    let (index, master_key) = header.find_slot(&kek).ok_or(SweetByteError::WrongPassword)?;
+
+    let keys = KeyDeriver::expand(&master_key, header.salt())?;
+    if !header.verify(&keys.signer_key)? {
+        return Err(SweetByteError::WrongPassword.into());
+    }
+
+    Ok((index, master_key, keys))
+}
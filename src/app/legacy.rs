@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::io::BufReader;
+
+use super::{Destination, Options};
+use crate::cipher::{DerivedKeys, KeyDeriver, RandomSource};
+use crate::config::CHUNK_SIZE;
+use crate::error::SweetByteError;
+use crate::files::hash::StreamHash;
+use crate::files::spanning::DynReader;
+use crate::files::{Attributes, Files, Metadata, OutputLock, TempFile};
+use crate::header::LegacyHeader;
+use crate::pipeline::{LegacyPipeline, NoProgress, Operation, ProgressEvent, ProgressSink, Stage};
+use crate::secret::Secret;
+
+/// Stretches `secret` the way format 0x0002 did and checks the header's MAC with the result. Those
+/// files record no attributes, so the defaults stand in.
+fn open_header(secret: &Secret, header: &LegacyHeader, progress: &dyn ProgressSink) -> Result<(DerivedKeys, Metadata)> {
+    progress.on_event(&ProgressEvent::new(Stage::Deriving, 0));
+    let keys = KeyDeriver::new(secret).and_then(|key| key.derive_legacy(header.salt()));
+    progress.on_event(&ProgressEvent::new(Stage::Derived, 0));
+    let keys = keys?;
+    if !header.verify(&keys.signer_key)? {
+        return Err(SweetByteError::WrongPassword.into());
+    }
+
+    Ok((keys, Metadata { name: header.name().to_owned(), size: header.size(), hash: header.hash().to_vec(), attributes: Attributes::default() }))
+}
+
+/// Decrypts a format 0x0002 file whole. Those files have no chunk size or index to read a range by,
+/// and were never archives, so only a file or stdout can be written.
+pub(super) async fn decrypt(header: &LegacyHeader, reader: BufReader<DynReader>, destination: &Destination, secret: &Secret, options: &Options) -> Result<(Metadata, PathBuf)> {
+    if options.range.is_some() {
+        anyhow::bail!("a byte range can't be read from a format 0x0002 file; migrate it first");
+    }
+    let unlocking = options.progress(0, Operation::Decryption.label());
+    let (keys, metadata) = open_header(secret, header, &unlocking)?;
+
+    let target = match destination {
+        Destination::File(file) => file.path().to_path_buf(),
+        Destination::Restore(dir) => super::decrypt::restore_path(dir, &metadata.name)?,
+        Destination::Extract(_) => anyhow::bail!("a format 0x0002 file holds a single file and can't be extracted as an archive"),
+        Destination::Stdout => PathBuf::from("-"),
+    };
+
+    let pipeline = LegacyPipeline::new(&keys, Arc::new(options.progress(metadata.size, Operation::Decryption.label())))?;
+    let hash = StreamHash::default();
+    if let Destination::Stdout = destination {
+        pipeline.process(reader, hash.writer(tokio::io::stdout()), metadata.size, &options.cancel).await?;
+        super::decrypt::verify(&hash, &metadata)?;
+        return Ok((metadata, target));
+    }
+
+    super::preflight(&target, metadata.size, options)?;
+    let _lock = OutputLock::acquire(&target)?;
+    let temp = TempFile::new(&target)?;
+    let writer = Files::new(temp.path()).writer(options.settings.fsync).await.context("failed to create target file")?;
+    pipeline.process(reader, hash.writer(writer), metadata.size, &options.cancel).await?;
+    super::decrypt::verify(&hash, &metadata)?;
+
+    temp.persist().await.context("failed to finalize target file")?;
+
+    Ok((metadata, target))
+}
+
+/// Re-encrypts a format 0x0002 file under the current format, the same way [`super::migrate`] does
+/// a current one. It has a single password and no padding, so nothing is left behind.
+pub(super) async fn migrate(header: &LegacyHeader, reader: BufReader<DynReader>, target: &Files, secret: &Secret, options: &Options, rng: &dyn RandomSource) -> Result<Metadata> {
+    let unlocking = options.progress(0, Operation::Decryption.label());
+    let (keys, metadata) = open_header(secret, header, &unlocking)?;
+
+    super::preflight(target.path(), options.estimate(metadata.size), options)?;
+    let (_lock, output, writer) = super::encrypt::create_output(target, options).await?;
+
+    let pipeline = LegacyPipeline::new(&keys, Arc::new(NoProgress))?;
+    let (plaintext, sealing) = tokio::io::duplex(CHUNK_SIZE);
+    let hash = StreamHash::default();
+    let size = metadata.size;
+    let ((), sealed) =
+        tokio::try_join!(pipeline.process(reader, hash.writer(plaintext), size, &options.cancel), super::encrypt::seal(sealing, writer, metadata, std::slice::from_ref(secret), options, rng, None),)?;
+    super::decrypt::verify(&hash, &sealed)?;
+
+    output.persist().await.context("failed to finalize target file")?;
+
+    Ok(sealed)
+}
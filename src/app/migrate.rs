@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use super::{Header, Options};
+use crate::cipher::RandomSource;
+use crate::config::CHUNK_SIZE;
+use crate::files::hash::StreamHash;
+use crate::files::{Files, Metadata};
+use crate::header::Deserializer;
+use crate::pipeline::{NoProgress, Operation, Stats};
+use crate::secret::Secret;
+
+/// Re-encrypts `source` into `target` under the current settings in a single pass: each chunk is
+/// decrypted straight into the new file's encryption, so the plaintext never reaches the disk. The
+/// stored name, size, hash, and attributes carry over, and the plaintext is checked against the old
+/// header's hash before the new file replaces anything. The new file has one key slot, for
+/// `secret`, so a file that would lose anything else is refused unless `options.drop_extras` is
+/// set. `target` may be `source` itself, and `source` may be a format 0x0002 file.
+pub(crate) async fn migrate(source: &Files, target: &Files, secret: &Secret, options: &Options, rng: &dyn RandomSource) -> Result<Metadata> {
+    let (header, reader) = match super::read_any_header(source, options.recovery.as_deref()).await? {
+        (Header::Current(header), reader) => (header, reader),
+        (Header::Legacy(header), reader) => return super::legacy::migrate(&header, reader, target, secret, options, rng).await,
+    };
+    super::check_policy(source.path(), &header, options)?;
+    let dropped = dropped(&header);
+    if !dropped.is_empty() {
+        if !options.drop_extras {
+            anyhow::bail!("migrating {} would drop {}; pass --drop-extras to migrate it anyway", source.path().display(), dropped.join(" and "));
+        }
+        crate::ui::display::dropped(source.path(), &dropped)?;
+    }
+    let unlocking = options.progress(0, Operation::Decryption.label());
+    let (keys, metadata) = super::open_header(secret, &header, options.session.as_ref(), options.second_factor.as_ref(), &unlocking)?;
+
+    super::preflight(target.path(), options.estimate(metadata.size), options)?;
+    let (_lock, output, writer) = super::encrypt::create_output(target, options).await?;
+
+    // The new file's bar covers the whole pass, so the decrypting half reports nothing of its own.
+    let pipeline = super::decrypt::open(&header, &keys, metadata.size, options, Arc::new(NoProgress), Arc::new(Stats::default()), None)?;
+    let (plaintext, sealing) = tokio::io::duplex(CHUNK_SIZE);
+    let hash = StreamHash::default();
+    let size = metadata.size;
+    let ((), sealed) =
+        tokio::try_join!(pipeline.process(reader, hash.writer(plaintext), size, &options.cancel), super::encrypt::seal(sealing, writer, metadata, std::slice::from_ref(secret), options, rng, None),)?;
+    super::decrypt::verify(&hash, &sealed)?;
+
+    output.persist().await.context("failed to finalize target file")?;
+
+    Ok(sealed)
+}
+
+/// What the new file can't carry over: key slots other than the one being opened, whose passwords
+/// or key shares aren't known here, and a payload that may be hidden in the padding, which only its
+/// own password could find.
+fn dropped(header: &Deserializer) -> Vec<String> {
+    let mut dropped = Vec::new();
+    match header.slot_count().saturating_sub(1) {
+        0 => {}
+        1 => dropped.push("1 other key slot".to_owned()),
+        others => dropped.push(format!("{others} other key slots")),
+    }
+    if header.padded() {
+        dropped.push("any payload hidden in its padding".to_owned());
+    }
+
+    dropped
+}
@@ -1,17 +1,331 @@
+mod changed;
+mod check;
+mod chunks;
+mod corrupt;
 mod decrypt;
 mod encrypt;
+mod hidden;
+mod keys;
+mod legacy;
+mod migrate;
+#[cfg(feature = "fuse")]
+mod mount;
+mod recover;
+mod watch;
 
-pub(crate) use decrypt::decrypt;
-pub(crate) use encrypt::encrypt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+pub(crate) use changed::unchanged;
+pub(crate) use check::{check_password, recovery_code};
+pub(crate) use chunks::{ChunkLayout, chunks};
+pub(crate) use corrupt::{Damage, Trial, corrupt};
+pub(crate) use decrypt::{decrypt, read_back};
+pub(crate) use encrypt::{encrypt, encrypt_directory};
+pub(crate) use hidden::Hidden;
+pub(crate) use keys::{add_key, remove_key};
+pub(crate) use migrate::migrate;
+#[cfg(feature = "fuse")]
+pub(crate) use mount::mount;
+pub(crate) use recover::{Recovered, recover};
+use tokio::io::{AsyncReadExt, BufReader};
+use tokio_util::sync::CancellationToken;
+pub(crate) use watch::{WatchOptions, WatchOutcome, Watched, watch};
+
+use crate::cipher::{CipherMode, Convergent, DerivedKeys, KdfParams, KdfSession, KeyDeriver, SecondFactor, SeededRandom};
+use crate::config::{CURRENT_VERSION, LEGACY_VERSION, Settings};
+use crate::error::SweetByteError;
+use crate::estimate::{EstimateOptions, estimate_output_size};
+use crate::files::spanning::DynReader;
+use crate::files::{Files, Metadata};
+use crate::header::{Deserializer, LegacyHeader};
+use crate::pipeline::{PadSize, ProgressEvent, ProgressSink, Stage, Stats};
+use crate::secret::Secret;
+use crate::ui::{BatchProgress, Progress};
+
+pub(crate) struct Options {
+    pub(crate) settings: Settings,
+    pub(crate) cipher: CipherMode,
+    pub(crate) split_size: Option<u64>,
+    pub(crate) chunk_store: Option<PathBuf>,
+    pub(crate) preserve_attrs: bool,
+    /// Let `preserve_attrs` restore setuid, setgid and sticky bits too.
+    pub(crate) preserve_special_bits: bool,
+    pub(crate) if_changed: bool,
+    pub(crate) tar: bool,
+    pub(crate) manifest: Option<PathBuf>,
+    pub(crate) pad_size: PadSize,
+    pub(crate) convergent: bool,
+    pub(crate) range: Option<Range<u64>>,
+    pub(crate) preflight: bool,
+    pub(crate) paranoid: bool,
+    pub(crate) verify_after: bool,
+    /// Decrypt past chunks that can't be recovered, writing zeros in their place.
+    pub(crate) keep_going: bool,
+    pub(crate) batch: Option<Arc<BatchProgress>>,
+    /// Session keys shared by the files of a batch. Files encrypted while it's set are written with
+    /// `session-kdf`.
+    pub(crate) session: Option<KdfSession>,
+    /// A TOTP secret that new files' key slots are bound to, and that reading such files needs.
+    pub(crate) second_factor: Option<SecondFactor>,
+    /// A second file to hide in the padding of the one encrypted, under its own password.
+    pub(crate) hidden: Option<Hidden>,
+    /// A PNG or WAV file the finished output is embedded in.
+    pub(crate) carrier: Option<PathBuf>,
+    /// The key of an extra key slot, split into shares with `--split-key`.
+    pub(crate) share_key: Option<Secret>,
+    /// A header rebuilt from a recovery code, read in place of the one on disk.
+    pub(crate) recovery: Option<Vec<u8>>,
+    /// Open files weaker than the pinned policy, with a warning, instead of refusing them.
+    pub(crate) allow_weak: bool,
+    /// Migrate a file even though its other key slots, or a payload hidden in its padding, won't
+    /// carry over.
+    pub(crate) drop_extras: bool,
+    pub(crate) cancel: CancellationToken,
+    pub(crate) stats: Arc<Stats>,
+}
+
+impl Options {
+    pub(crate) fn new(settings: Settings) -> Self {
+        Self {
+            cipher: settings.cipher,
+            settings,
+            split_size: None,
+            chunk_store: None,
+            preserve_attrs: false,
+            preserve_special_bits: false,
+            if_changed: false,
+            tar: false,
+            manifest: None,
+            pad_size: PadSize::None,
+            convergent: false,
+            range: None,
+            preflight: true,
+            paranoid: false,
+            verify_after: false,
+            keep_going: false,
+            batch: None,
+            session: None,
+            second_factor: None,
+            hidden: None,
+            carrier: None,
+            share_key: None,
+            recovery: None,
+            allow_weak: false,
+            drop_extras: false,
+            cancel: CancellationToken::new(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// An upper bound on the size of the file encrypting `input_len` bytes with these options
+    /// produces.
+    fn estimate(&self, input_len: u64) -> u64 {
+        let options = EstimateOptions {
+            chunk_size: self.settings.chunk_size,
+            cipher: self.cipher,
+            payload_mac: self.settings.payload_mac,
+            chunk_index: self.settings.chunk_index,
+            header_copy: self.settings.header_copy,
+            pad_size: self.pad_size,
+            split_size: self.split_size,
+        };
+
+        estimate_output_size(input_len, &options)
+    }
+
+    /// A standalone bar, or one nested under the batch bar when several files are processed in one
+    /// run. Either can be aborted from the keyboard, which cancels the run.
+    fn progress(&self, total: u64, message: &str) -> Progress {
+        let progress = match &self.batch {
+            Some(batch) => batch.file(total, message),
+            None => Progress::new(total, message, self.settings.progress),
+        };
+
+        progress.with_abort(self.cancel.clone())
+    }
+}
+
+pub(crate) enum Destination {
+    File(Files),
+    Restore(PathBuf),
+    Extract(PathBuf),
+    /// Plaintext goes to stdout, verified by hashing it on the way out since a pipe can't be read
+    /// back.
+    Stdout,
+}
+
+/// Fails before anything is written when the filesystem that will hold `target` has less than
+/// `needed` bytes free, instead of running out partway through. Skipped with `--no-preflight`, and
+/// when the filesystem won't say.
+fn preflight(target: &Path, needed: u64, options: &Options) -> Result<()> {
+    if !options.preflight {
+        return Ok(());
+    }
+    let Some(available) = crate::files::space::available(target) else {
+        return Ok(());
+    };
+
+    if needed > available {
+        anyhow::bail!(
+            "not enough free space for {}: up to {} needed but {} available (use --no-preflight to try anyway)",
+            target.display(),
+            humansize::format_size(needed, humansize::DECIMAL),
+            humansize::format_size(available, humansize::DECIMAL)
+        );
+    }
+
+    Ok(())
+}
+
+/// A header as `decrypt` and `migrate` find it: in the current format, or in format 0x0002, which
+/// only they still read.
+enum Header {
+    Current(Deserializer),
+    Legacy(LegacyHeader),
+}
+
+/// Opens `source` and reads its header, leaving the reader at the start of the payload. A format
+/// 0x0002 header is refused here, since nothing but `decrypt` and `migrate` can use one.
+async fn read_header(source: &Files, recovery: Option<&[u8]>) -> Result<(Deserializer, BufReader<DynReader>)> {
+    match read_any_header(source, recovery).await? {
+        (Header::Current(header), reader) => Ok((header, reader)),
+        (Header::Legacy(_), _) => Err(anyhow::Error::new(SweetByteError::UnsupportedVersion { found: LEGACY_VERSION, supported: CURRENT_VERSION })
+            .context(format!("{} was written by an older format; run `migrate` on it first", source.path().display()))),
+    }
+}
+
+/// Opens `source` and reads its header in whichever format it was written, leaving the reader at
+/// the start of the payload. A `recovery` record from `export-recovery` stands in for the header
+/// when given. Otherwise, if the header at the start can't be read, the copy `header-copy` leaves
+/// at the end of the file is used instead.
+async fn read_any_header(source: &Files, recovery: Option<&[u8]>) -> Result<(Header, BufReader<DynReader>)> {
+    let mut reader = crate::files::spanning::reader(source).await.context("failed to open source file")?;
+    let header = match recovery {
+        Some(record) => Deserializer::from_recovery(record)?,
+        None if LegacyHeader::detect(&mut reader).await? => {
+            let header = LegacyHeader::from_reader(&mut reader).await.context(SweetByteError::CorruptHeader)?;
+            return Ok((Header::Legacy(header), reader));
+        }
+        None => match Deserializer::from_reader(&mut reader).await {
+            Ok(header) => return Ok((Header::Current(header), reader)),
+            Err(error) => trailing_header(source).await.ok_or(error).context(SweetByteError::CorruptHeader)?,
+        },
+    };
+
+    // The damaged header still takes up its bytes, so the payload starts where it always did.
+    let mut reader = crate::files::spanning::reader(source).await.context("failed to open source file")?;
+    let len = u64::try_from(header.encoded_len()).context("header length overflow")?;
+    let skipped = tokio::io::copy(&mut (&mut reader).take(len), &mut tokio::io::sink())
+        .await
+        .context("failed to skip the damaged header")?;
+    if skipped != len {
+        anyhow::bail!("file is shorter than its header");
+    }
+
+    Ok((Header::Current(header), reader))
+}
+
+/// The header copy at the end of `source`, when it is a single file that has one.
+async fn trailing_header(source: &Files) -> Option<Deserializer> {
+    let mut file = crate::files::spanning::seekable(source).await.ok()??;
+
+    crate::header::read_trailing_copy(&mut file).await.ok()
+}
+
+/// The key-encryption key for a file with `salt`. Files written with `session-kdf` name a
+/// `session_salt`: the password is stretched under that instead, once per session when there is one
+/// to remember it in, and the result is expanded under the file's own salt.
+fn derive_kek(secret: &Secret, salt: &Secret, session_salt: Option<&[u8]>, kdf: &KdfParams, session: Option<&KdfSession>, progress: &dyn ProgressSink) -> Result<Secret> {
+    let Some(session_salt) = session_salt else {
+        progress.on_event(&ProgressEvent::new(Stage::Deriving, 0));
+        let kek = KeyDeriver::new(secret).and_then(|key| key.derive_kek(salt, kdf));
+        progress.on_event(&ProgressEvent::new(Stage::Derived, 0));
+
+        return kek;
+    };
+
+    let session_key = match session.and_then(|session| session.cached(secret, session_salt, kdf)) {
+        Some(key) => key,
+        None => {
+            progress.on_event(&ProgressEvent::new(Stage::Deriving, 0));
+            let key = KeyDeriver::new(secret).and_then(|key| key.derive_session_key(session_salt, kdf));
+            progress.on_event(&ProgressEvent::new(Stage::Derived, 0));
+            let key = key?;
+            if let Some(session) = session {
+                session.remember(secret, session_salt, kdf, &key);
+            }
+            key
+        }
+    };
+
+    KeyDeriver::session_kek(&session_key, salt)
+}
+
+fn convergent_random(secret: &Secret, content_hash: &[u8], kdf: &KdfParams, progress: &dyn ProgressSink) -> Result<SeededRandom> {
+    progress.on_event(&ProgressEvent::new(Stage::Deriving, 0));
+    let convergent = Convergent::new(secret, kdf);
+    progress.on_event(&ProgressEvent::new(Stage::Derived, 0));
+
+    convergent?.random(content_hash)
+}
+
+fn unlock(secret: &Secret, header: &Deserializer, session: Option<&KdfSession>, factor: Option<&SecondFactor>, progress: &dyn ProgressSink) -> Result<Option<DerivedKeys>> {
+    let kek = derive_kek(secret, header.salt(), header.session_salt(), &header.kdf(), session, progress)?;
+    let kek = match (header.second_factor(), factor) {
+        (false, _) => kek,
+        (true, Some(factor)) => factor.bind(&kek, header.salt())?,
+        (true, None) => anyhow::bail!("this file is bound to a TOTP secret; pass it with --totp-secret"),
+    };
+
+    header.find_slot(&kek).map(|(_, master_key)| KeyDeriver::expand(&master_key, header.salt())).transpose()
+}
+
+/// Holds `header` to the pinned policy before any password is spent on it, since nothing in it has
+/// been authenticated yet. With `--allow-weak` a shortfall is only warned about.
+fn check_policy(source: &Path, header: &Deserializer, options: &Options) -> Result<()> {
+    let policy = &options.settings.policy;
+    if policy.is_empty() {
+        return Ok(());
+    }
+
+    let shortfalls = policy.shortfalls(&header.kdf(), header.cipher_mode()?, header.payload_mac());
+    if shortfalls.is_empty() {
+        return Ok(());
+    }
+    if options.allow_weak {
+        return crate::ui::display::weak(source, &shortfalls);
+    }
+
+    Err(anyhow::Error::new(SweetByteError::WeakParameters { shortfalls }).context(format!("refusing {}; pass --allow-weak to open it anyway", source.display())))
+}
+
+fn open_header(secret: &Secret, header: &Deserializer, session: Option<&KdfSession>, factor: Option<&SecondFactor>, progress: &dyn ProgressSink) -> Result<(DerivedKeys, Metadata)> {
+    let Some(keys) = unlock(secret, header, session, factor, progress)? else {
+        return Err(SweetByteError::WrongPassword.into());
+    };
+    if !header.verify(&keys.signer_key)? {
+        return Err(SweetByteError::WrongPassword.into());
+    }
+
+    let metadata = header.metadata(&keys.metadata_key)?;
+    let metadata = Metadata { name: metadata.name().to_owned(), size: metadata.size(), hash: metadata.hash().to_vec(), attributes: metadata.attributes() };
+
+    Ok((keys, metadata))
+}
 
 #[cfg(test)]
 mod tests {
     use tempfile::tempdir;
     use tokio::fs;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
     use super::*;
-    use crate::files::Files;
-    use crate::secret::Secret;
+    use crate::cipher::{RandomSource, SeededRandom, SystemRandom};
+    use crate::config::{MIN_CHUNK_SIZE, PAYLOAD_MAC_MARKER, RECOVERY_COUNT};
+    use crate::pipeline::{NoProgress, Operation};
 
     #[tokio::test]
     async fn roundtrip_preserves_content() {
@@ -23,17 +337,786 @@ mod tests {
         fs::write(&source_path, b"test content").await.unwrap();
 
         let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings::default());
 
         let source = Files::new(&source_path);
         let encrypted = Files::new(&encrypted_path);
-        let decrypted = Files::new(&decrypted_path);
+        let decrypted = Destination::File(Files::new(&decrypted_path));
 
-        encrypt(&source, &encrypted, &secret).await.unwrap();
+        encrypt(&source, &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
         assert!(encrypted.exists());
 
-        decrypt(&encrypted, &decrypted, &secret).await.unwrap();
-        assert!(decrypted.exists());
+        let (_, output) = decrypt(&encrypted, &decrypted, &secret, &options).await.unwrap();
+        assert_eq!(output, decrypted_path);
 
         assert_eq!(fs::read(&decrypted_path).await.unwrap(), b"test content");
     }
+
+    #[tokio::test]
+    async fn migrate_rewrites_in_place_under_new_settings() {
+        let dir = tempdir().unwrap();
+        let source = Files::new(dir.path().join("notes.txt"));
+        fs::write(source.path(), b"migrated content").await.unwrap();
+        let secret = Secret::new(b"password".to_vec());
+        let old = Options { cipher: CipherMode::Aes, ..Options::new(Settings { chunk_index: false, ..Settings::default() }) };
+        let encrypted = Files::new(dir.path().join("old.swx"));
+        encrypt(&source, &encrypted, std::slice::from_ref(&secret), &old, &SystemRandom).await.unwrap();
+
+        let options = Options::new(Settings { header_copy: true, ..Settings::default() });
+        let metadata = migrate(&encrypted, &encrypted, &secret, &options, &SystemRandom).await.unwrap();
+        assert_eq!(metadata.name, "notes.txt");
+
+        let mut file = fs::File::open(encrypted.path()).await.unwrap();
+        let header = Deserializer::from_reader(&mut file).await.unwrap();
+        assert!(header.cipher_mode().unwrap() == CipherMode::Dual);
+        assert!(header.indexed());
+
+        let decrypted = dir.path().join("notes.out");
+        decrypt(&encrypted, &Destination::File(Files::new(&decrypted)), &secret, &options).await.unwrap();
+        assert_eq!(fs::read(&decrypted).await.unwrap(), b"migrated content");
+        migrate(&encrypted, &Files::new(dir.path().join("new.swx")), &Secret::new(b"wrong password".to_vec()), &options, &SystemRandom)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn format_0x0002_files_migrate_and_decrypt() {
+        let dir = tempdir().unwrap();
+        let legacy = Files::new(dir.path().join("legacy.txt.swx"));
+        fs::write(legacy.path(), include_bytes!("fixtures/v0002.swx")).await.unwrap();
+        let secret = Secret::new(b"correct horse battery".to_vec());
+        let options = Options::new(Settings::default());
+        let expected: Vec<u8> = (0u32..)
+            .flat_map(|line| format!("line {line} of a file written before the format changed\n").into_bytes())
+            .take(307_230)
+            .collect();
+
+        let error = check_password(&legacy, &secret, None, &NoProgress).await.err().unwrap();
+        assert_eq!(SweetByteError::classify(&error), SweetByteError::UnsupportedVersion { found: LEGACY_VERSION, supported: CURRENT_VERSION });
+        let error = decrypt(&legacy, &Destination::File(Files::new(dir.path().join("wrong.txt"))), &Secret::new(b"wrong password".to_vec()), &options)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(SweetByteError::classify(&error), SweetByteError::WrongPassword);
+
+        let (metadata, output) = decrypt(&legacy, &Destination::Restore(dir.path().to_path_buf()), &secret, &options).await.unwrap();
+        assert_eq!(metadata.name, "legacy.txt");
+        assert_eq!(fs::read(&output).await.unwrap(), expected);
+
+        let migrated = Files::new(dir.path().join("migrated.swx"));
+        migrate(&legacy, &migrated, &secret, &options, &SystemRandom).await.unwrap();
+        let mut file = fs::File::open(migrated.path()).await.unwrap();
+        Deserializer::from_reader(&mut file).await.unwrap();
+
+        let decrypted = dir.path().join("migrated.txt");
+        decrypt(&migrated, &Destination::File(Files::new(&decrypted)), &secret, &options).await.unwrap();
+        assert_eq!(fs::read(&decrypted).await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn migrate_refuses_to_drop_other_key_slots_unless_told_to() {
+        let dir = tempdir().unwrap();
+        let source = Files::new(dir.path().join("shared.txt"));
+        fs::write(source.path(), b"shared content").await.unwrap();
+        let secrets = [Secret::new(b"password".to_vec()), Secret::new(b"other password".to_vec())];
+        let encrypted = Files::new(dir.path().join("shared.swx"));
+        encrypt(&source, &encrypted, &secrets, &Options::new(Settings::default()), &SystemRandom).await.unwrap();
+
+        let target = Files::new(dir.path().join("migrated.swx"));
+        let error = migrate(&encrypted, &target, &secrets[0], &Options::new(Settings::default()), &SystemRandom).await.err().unwrap();
+        assert!(format!("{error:#}").contains("would drop 1 other key slot"));
+        assert!(!target.exists());
+
+        let options = Options { drop_extras: true, ..Options::new(Settings::default()) };
+        migrate(&encrypted, &target, &secrets[0], &options, &SystemRandom).await.unwrap();
+        let mut file = fs::File::open(target.path()).await.unwrap();
+        assert_eq!(Deserializer::from_reader(&mut file).await.unwrap().slot_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn session_kdf_files_share_a_session_salt_but_not_keys() {
+        let dir = tempdir().unwrap();
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options { session: Some(KdfSession::new(&SystemRandom).unwrap()), ..Options::new(Settings::default()) };
+
+        let mut headers = Vec::new();
+        for name in ["first.txt", "second.txt"] {
+            let source = Files::new(dir.path().join(name));
+            fs::write(source.path(), name.as_bytes()).await.unwrap();
+            let target = Files::new(source.output_path(Operation::Encryption));
+            encrypt(&source, &target, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+
+            assert_eq!(check_password(&target, &secret, None, &NoProgress).await.unwrap().name, name);
+            let reading = Options { session: Some(KdfSession::new(&SystemRandom).unwrap()), ..Options::new(Settings::default()) };
+            let decrypted = dir.path().join(format!("{name}.out"));
+            decrypt(&target, &Destination::File(Files::new(&decrypted)), &secret, &reading).await.unwrap();
+            assert_eq!(fs::read(&decrypted).await.unwrap(), name.as_bytes());
+
+            let mut file = fs::File::open(target.path()).await.unwrap();
+            headers.push(Deserializer::from_reader(&mut file).await.unwrap());
+        }
+
+        assert_eq!(headers[0].session_salt(), options.session.as_ref().map(KdfSession::salt));
+        assert_eq!(headers[0].session_salt(), headers[1].session_salt());
+        assert_ne!(headers[0].salt().expose_secret(), headers[1].salt().expose_secret());
+    }
+
+    #[tokio::test]
+    async fn second_factor_files_need_the_totp_secret() {
+        let dir = tempdir().unwrap();
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options { second_factor: SecondFactor::from_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").ok(), ..Options::new(Settings::default()) };
+
+        let source = Files::new(dir.path().join("vault.txt"));
+        fs::write(source.path(), b"two factors").await.unwrap();
+        let target = Files::new(source.output_path(Operation::Encryption));
+        encrypt(&source, &target, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+
+        check_password(&target, &secret, None, &NoProgress).await.unwrap_err();
+        let other = SecondFactor::from_base32("JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP").unwrap();
+        let error = check_password(&target, &secret, Some(&other), &NoProgress).await.unwrap_err();
+        assert_eq!(SweetByteError::classify(&error), SweetByteError::WrongPassword);
+
+        let decrypted = dir.path().join("vault.out");
+        decrypt(&target, &Destination::File(Files::new(&decrypted)), &secret, &options).await.unwrap();
+        assert_eq!(fs::read(&decrypted).await.unwrap(), b"two factors");
+    }
+
+    #[tokio::test]
+    async fn hidden_payloads_open_only_with_their_own_password() {
+        let dir = tempdir().unwrap();
+        let decoy = Secret::new(b"decoy".to_vec());
+        let secret = Secret::new(b"hidden".to_vec());
+        let hidden = Files::new(dir.path().join("diary.txt"));
+        fs::write(hidden.path(), b"the real contents").await.unwrap();
+
+        let source = Files::new(dir.path().join("shopping.txt"));
+        fs::write(source.path(), b"milk, eggs").await.unwrap();
+        let target = Files::new(source.output_path(Operation::Encryption));
+        let options = Options { pad_size: PadSize::Multiple(64 * 1024), hidden: Some(Hidden { source: hidden, secret: Secret::new(b"hidden".to_vec()) }), ..Options::new(Settings::default()) };
+        encrypt(&source, &target, std::slice::from_ref(&decoy), &options, &SystemRandom).await.unwrap();
+        assert_eq!(fs::metadata(target.path()).await.unwrap().len() % (64 * 1024), 0);
+
+        let options = Options::new(Settings::default());
+        fs::create_dir(dir.path().join("out")).await.unwrap();
+        let (metadata, path) = decrypt(&target, &Destination::Restore(dir.path().join("out")), &secret, &options).await.unwrap();
+        assert_eq!(metadata.name, "diary.txt");
+        assert_eq!(fs::read(&path).await.unwrap(), b"the real contents");
+
+        let (metadata, path) = decrypt(&target, &Destination::Restore(dir.path().join("out")), &decoy, &options).await.unwrap();
+        assert_eq!(metadata.name, "shopping.txt");
+        assert_eq!(fs::read(&path).await.unwrap(), b"milk, eggs");
+
+        let error = decrypt(&target, &Destination::Restore(dir.path().join("out")), &Secret::new(b"wrong".to_vec()), &options)
+            .await
+            .unwrap_err();
+        assert_eq!(SweetByteError::classify(&error), SweetByteError::WrongPassword);
+    }
+
+    #[tokio::test]
+    async fn carrier_outputs_decrypt_without_extraction() {
+        let dir = tempdir().unwrap();
+        let carrier = dir.path().join("chirp.wav");
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(b"WAVEdata");
+        wav.extend_from_slice(&4u32.to_le_bytes());
+        wav.extend_from_slice(&[1, 2, 3, 4]);
+        fs::write(&carrier, &wav).await.unwrap();
+
+        let source = Files::new(dir.path().join("notes.txt"));
+        fs::write(source.path(), b"tucked into a sound file").await.unwrap();
+        let target = Files::new(dir.path().join("notes.wav"));
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options { carrier: Some(carrier), ..Options::new(Settings::default()) };
+        encrypt(&source, &target, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+        assert!(fs::read(target.path()).await.unwrap().starts_with(b"RIFF"));
+
+        assert_eq!(check_password(&target, &secret, None, &NoProgress).await.unwrap().name, "notes.txt");
+        let decrypted = dir.path().join("notes.out");
+        decrypt(&target, &Destination::File(Files::new(&decrypted)), &secret, &Options::new(Settings::default())).await.unwrap();
+        assert_eq!(fs::read(&decrypted).await.unwrap(), b"tucked into a sound file");
+    }
+
+    #[tokio::test]
+    async fn long_unicode_paths_roundtrip() {
+        let dir = tempdir().unwrap();
+        let nested = (0..6).fold(dir.path().to_path_buf(), |path, level| path.join(format!("répertoire-{level}-{}", "x".repeat(40))));
+        let source = Files::new(nested.join("日本語のファイル名 📄.txt"));
+        assert!(source.path().as_os_str().len() > 260);
+
+        let mut file = source.create().await.unwrap();
+        file.write_all(b"deeply nested content").await.unwrap();
+        file.flush().await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings::default());
+        let encrypted = Files::new(source.output_path(Operation::Encryption));
+        encrypt(&source, &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+        assert_eq!(check_password(&encrypted, &secret, None, &NoProgress).await.unwrap().name, "日本語のファイル名 📄.txt");
+
+        let decrypted = Files::new(nested.join("déchiffré.txt"));
+        decrypt(&encrypted, &Destination::File(Files::new(decrypted.path())), &secret, &options).await.unwrap();
+        let mut content = Vec::new();
+        decrypted.reader().await.unwrap().read_to_end(&mut content).await.unwrap();
+        assert_eq!(content, b"deeply nested content");
+    }
+
+    #[tokio::test]
+    async fn read_back_confirms_what_was_encrypted() {
+        let dir = tempdir().unwrap();
+        let source = Files::new(dir.path().join("test.txt"));
+        fs::write(source.path(), b"content worth keeping").await.unwrap();
+        let target = Files::new(source.output_path(Operation::Encryption));
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings::default());
+        let metadata = encrypt(&source, &target, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+        read_back(&target, &secret, &metadata, &options).await.unwrap();
+
+        let other = Metadata { hash: vec![0u8; metadata.hash.len()], ..metadata };
+        assert!(read_back(&target, &secret, &other, &options).await.is_err());
+        assert!(read_back(&target, &Secret::new(b"wrong".to_vec()), &other, &options).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn locked_output_is_refused() {
+        let dir = tempdir().unwrap();
+        let source = Files::new(dir.path().join("test.txt"));
+        fs::write(source.path(), b"test content").await.unwrap();
+        let target = Files::new(source.output_path(Operation::Encryption));
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings::default());
+        let lock = crate::files::OutputLock::acquire(target.path()).unwrap();
+        let error = encrypt(&source, &target, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap_err();
+        assert!(error.to_string().contains("operation already in progress"));
+        assert!(!target.exists());
+
+        drop(lock);
+        encrypt(&source, &target, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+    }
+
+    #[test]
+    fn preflight_refuses_what_cannot_fit() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("missing").join("out.swx");
+        let options = Options::new(Settings::default());
+
+        preflight(&target, 1, &options).unwrap();
+        let error = preflight(&target, u64::MAX, &options).unwrap_err();
+        assert!(error.to_string().contains("not enough free space"));
+
+        let options = Options { preflight: false, ..options };
+        preflight(&target, u64::MAX, &options).unwrap();
+    }
+
+    #[tokio::test]
+    async fn any_slot_password_decrypts() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.txt");
+        let encrypted_path = dir.path().join("test.txt.swx");
+
+        fs::write(&source_path, b"shared content").await.unwrap();
+
+        let secrets = [Secret::new(b"first-password".to_vec()), Secret::new(b"second-password".to_vec())];
+        let options = Options::new(Settings::default());
+        let encrypted = Files::new(&encrypted_path);
+        encrypt(&Files::new(&source_path), &encrypted, &secrets, &options, &SystemRandom).await.unwrap();
+
+        for (index, secret) in secrets.iter().enumerate() {
+            let decrypted_path = dir.path().join(format!("test_dec_{index}.txt"));
+            decrypt(&encrypted, &Destination::File(Files::new(&decrypted_path)), secret, &options).await.unwrap();
+            assert_eq!(fs::read(&decrypted_path).await.unwrap(), b"shared content");
+        }
+
+        let wrong = Destination::File(Files::new(dir.path().join("test_wrong.txt")));
+        decrypt(&encrypted, &wrong, &Secret::new(b"third-password".to_vec()), &options).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn key_slots_can_be_added_and_removed() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.txt");
+        let encrypted = Files::new(dir.path().join("test.txt.swx"));
+        fs::write(&source_path, b"rotated content").await.unwrap();
+
+        let original = Secret::new(b"original-password".to_vec());
+        let added = Secret::new(b"added-password".to_vec());
+        let options = Options::new(Settings::default());
+        encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&original), &options, &SystemRandom).await.unwrap();
+        let encrypted_len = fs::metadata(encrypted.path()).await.unwrap().len();
+
+        assert_eq!(add_key(&encrypted, &original, &added, &NoProgress, &SystemRandom).await.unwrap(), 2);
+        add_key(&encrypted, &original, &added, &NoProgress, &SystemRandom).await.unwrap_err();
+        assert_eq!(remove_key(&encrypted, &original, &NoProgress).await.unwrap(), 1);
+        remove_key(&encrypted, &added, &NoProgress).await.unwrap_err();
+        assert_eq!(fs::metadata(encrypted.path()).await.unwrap().len(), encrypted_len);
+
+        check_password(&encrypted, &original, None, &NoProgress).await.unwrap_err();
+        let decrypted_path = dir.path().join("test_dec.txt");
+        decrypt(&encrypted, &Destination::File(Files::new(&decrypted_path)), &added, &options).await.unwrap();
+        assert_eq!(fs::read(&decrypted_path).await.unwrap(), b"rotated content");
+    }
+
+    #[tokio::test]
+    async fn missing_payload_trailer_is_rejected() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.txt");
+        let encrypted_path = dir.path().join("test.txt.swx");
+        fs::write(&source_path, b"authenticated content").await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings::default());
+        let encrypted = Files::new(&encrypted_path);
+        encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+
+        // The chunk index and header copy follow the record, so cut the file where it starts.
+        let bytes = fs::read(&encrypted_path).await.unwrap();
+        let trailer = bytes.windows(4).rposition(|window| window == PAYLOAD_MAC_MARKER.to_le_bytes()).unwrap();
+        fs::write(&encrypted_path, &bytes[..trailer]).await.unwrap();
+
+        let decrypted = Destination::File(Files::new(dir.path().join("test_dec.txt")));
+        let error = decrypt(&encrypted, &decrypted, &secret, &options).await.unwrap_err();
+        assert!(format!("{error:#}").contains("payload truncated"));
+    }
+
+    #[tokio::test]
+    async fn padded_output_roundtrips() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.txt");
+        let encrypted_path = dir.path().join("test.txt.swx");
+        let decrypted_path = dir.path().join("test_dec.txt");
+        fs::write(&source_path, b"padded content").await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options { pad_size: PadSize::PowerOfTwo, ..Options::new(Settings::default()) };
+        let encrypted = Files::new(&encrypted_path);
+        encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+        assert!(fs::metadata(&encrypted_path).await.unwrap().len().is_power_of_two());
+
+        decrypt(&encrypted, &Destination::File(Files::new(&decrypted_path)), &secret, &options).await.unwrap();
+        assert_eq!(fs::read(&decrypted_path).await.unwrap(), b"padded content");
+        assert_eq!(crate::memory::decrypt_bytes(&fs::read(&encrypted_path).await.unwrap(), &secret).unwrap(), b"padded content");
+    }
+
+    #[tokio::test]
+    async fn streamed_hash_is_patched_into_every_layout() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.bin");
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 2 + 13];
+        SeededRandom::new(5).fill(&mut content).unwrap();
+        fs::write(&source_path, &content).await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let layouts = [Options::new(Settings::default()), Options { split_size: Some(1024), ..Options::new(Settings::default()) }];
+        for (index, options) in layouts.iter().enumerate() {
+            let encrypted = Files::new(dir.path().join(format!("test_{index}.bin.swx")));
+            encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), options, &SystemRandom).await.unwrap();
+
+            let encrypted = if options.split_size.is_some() { Files::new(encrypted.path().with_added_extension("001")) } else { encrypted };
+            let metadata = check_password(&encrypted, &secret, None, &NoProgress).await.unwrap();
+            assert_eq!(metadata.hash, blake3::hash(&content).as_bytes());
+
+            let decrypted_path = dir.path().join(format!("test_dec_{index}.bin"));
+            decrypt(&encrypted, &Destination::File(Files::new(&decrypted_path)), &secret, options).await.unwrap();
+            assert_eq!(fs::read(&decrypted_path).await.unwrap(), content);
+        }
+    }
+
+    #[tokio::test]
+    async fn unsized_stream_gets_size_and_hash_afterwards() {
+        let dir = tempdir().unwrap();
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 3 + 1];
+        SeededRandom::new(9).fill(&mut content).unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() });
+        let encrypted = Files::new(dir.path().join("piped.bin.swx"));
+        encrypt::encrypt_stream(std::io::Cursor::new(content.clone()), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom)
+            .await
+            .unwrap();
+
+        let metadata = check_password(&encrypted, &secret, None, &NoProgress).await.unwrap();
+        assert_eq!((metadata.name.as_str(), metadata.size), ("piped.bin", u64::try_from(content.len()).unwrap()));
+        assert_eq!(metadata.hash, blake3::hash(&content).as_bytes());
+
+        let decrypted_path = dir.path().join("piped_dec.bin");
+        decrypt(&encrypted, &Destination::File(Files::new(&decrypted_path)), &secret, &options).await.unwrap();
+        assert_eq!(fs::read(&decrypted_path).await.unwrap(), content);
+
+        let empty = Files::new(dir.path().join("empty.swx"));
+        encrypt::encrypt_stream(std::io::Cursor::new(Vec::new()), &empty, std::slice::from_ref(&secret), &options, &SystemRandom)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn convergent_output_is_reproducible() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.txt");
+        fs::write(&source_path, b"deduplicated content").await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options { convergent: true, ..Options::new(Settings::default()) };
+        let (first, second) = (Files::new(dir.path().join("first.swx")), Files::new(dir.path().join("second.swx")));
+        encrypt(&Files::new(&source_path), &first, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+        encrypt(&Files::new(&source_path), &second, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+        assert_eq!(fs::read(first.path()).await.unwrap(), fs::read(second.path()).await.unwrap());
+
+        let decrypted_path = dir.path().join("test_dec.txt");
+        decrypt(&first, &Destination::File(Files::new(&decrypted_path)), &secret, &options).await.unwrap();
+        assert_eq!(fs::read(&decrypted_path).await.unwrap(), b"deduplicated content");
+    }
+
+    #[tokio::test]
+    async fn truncated_payload_reports_missing_chunks() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.bin");
+        let encrypted_path = dir.path().join("test.bin.swx");
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 3];
+        SeededRandom::new(7).fill(&mut content).unwrap();
+        fs::write(&source_path, &content).await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() });
+        let encrypted = Files::new(&encrypted_path);
+        encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+
+        let bytes = fs::read(&encrypted_path).await.unwrap();
+        fs::write(&encrypted_path, &bytes[..bytes.len().saturating_sub(MIN_CHUNK_SIZE * 5)]).await.unwrap();
+
+        let decrypted = Destination::File(Files::new(dir.path().join("test_dec.bin")));
+        let error = decrypt(&encrypted, &decrypted, &secret, &options).await.unwrap_err();
+        assert!(format!("{error:#}").contains("file truncated: expected 3 chunks, found 1"));
+    }
+
+    #[tokio::test]
+    async fn header_copy_stands_in_for_a_destroyed_header() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.bin");
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 2 + 7];
+        SeededRandom::new(13).fill(&mut content).unwrap();
+        fs::write(&source_path, &content).await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let added = Secret::new(b"added-password".to_vec());
+        let settings = Settings { chunk_size: MIN_CHUNK_SIZE, header_copy: true, ..Settings::default() };
+        let options = Options { pad_size: PadSize::PowerOfTwo, ..Options::new(settings) };
+        let encrypted = Files::new(dir.path().join("test.bin.swx"));
+        encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+        assert!(fs::metadata(encrypted.path()).await.unwrap().len().is_power_of_two());
+
+        let mut data = fs::read(encrypted.path()).await.unwrap();
+        let (header, header_len) = Deserializer::from_bytes(&data).unwrap();
+        assert!(header.copied());
+        data[..header_len].fill(0);
+        fs::write(encrypted.path(), &data).await.unwrap();
+
+        assert_eq!(check_password(&encrypted, &secret, None, &NoProgress).await.unwrap().hash, blake3::hash(&content).as_bytes());
+        let decrypted_path = dir.path().join("test_dec.bin");
+        decrypt(&encrypted, &Destination::File(Files::new(&decrypted_path)), &secret, &options).await.unwrap();
+        assert_eq!(fs::read(&decrypted_path).await.unwrap(), content);
+
+        let range = Options { range: Some(u64::try_from(MIN_CHUNK_SIZE).unwrap()..u64::MAX), ..Options::new(Settings::default()) };
+        let ranged_path = dir.path().join("test_range.bin");
+        decrypt(&encrypted, &Destination::File(Files::new(&ranged_path)), &secret, &range).await.unwrap();
+        assert_eq!(fs::read(&ranged_path).await.unwrap(), &content[MIN_CHUNK_SIZE..]);
+
+        // Rewriting the header for a new key slot restores the one at the start and keeps the copy in step.
+        add_key(&encrypted, &secret, &added, &NoProgress, &SystemRandom).await.unwrap();
+        let data = fs::read(encrypted.path()).await.unwrap();
+        let (restored, _) = Deserializer::from_bytes(&data).unwrap();
+        let copy = crate::header::read_trailing_copy(&mut std::io::Cursor::new(&data)).await.unwrap();
+        assert_eq!((restored.slot_count(), copy.slot_count()), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn recover_extracts_files_from_a_disk_image() {
+        let dir = tempdir().unwrap();
+        let rng = SeededRandom::new(17);
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() });
+
+        let mut image = vec![0u8; 3000];
+        rng.fill(&mut image).unwrap();
+        let mut contents = Vec::new();
+        let mut starts = Vec::new();
+        for name in ["a.bin", "b.bin"] {
+            let mut content = vec![0u8; MIN_CHUNK_SIZE * 2 + 50];
+            rng.fill(&mut content).unwrap();
+            let source_path = dir.path().join(name);
+            fs::write(&source_path, &content).await.unwrap();
+            let encrypted = Files::new(dir.path().join(format!("{name}.swx")));
+            encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+
+            starts.push(image.len());
+            image.extend_from_slice(&fs::read(encrypted.path()).await.unwrap());
+            image.extend_from_within(..777);
+            contents.push(content);
+        }
+
+        // Wreck every byte of the second file's first chunk, but not its length, so the chunks after it
+        // still frame.
+        let (_, header_len) = Deserializer::from_bytes(&image[starts[1]..]).unwrap();
+        let chunk_at = starts[1] + header_len;
+        let chunk_len = u32::from_le_bytes(image[chunk_at..chunk_at + 4].try_into().unwrap()) as usize;
+        image[chunk_at + 4..chunk_at + 4 + chunk_len].fill(0xAA);
+        let image_path = dir.path().join("disk.img");
+        fs::write(&image_path, &image).await.unwrap();
+
+        let out = dir.path().join("recovered");
+        fs::create_dir(&out).await.unwrap();
+        let recovered = recover(&Files::new(&image_path), &secret, &out, &options, &NoProgress).await.unwrap();
+        assert_eq!(recovered.len(), 2);
+        assert_eq!(recovered[0].offset, 3000);
+        assert!(recovered[0].complete);
+        assert_eq!(fs::read(out.join("a.bin")).await.unwrap(), contents[0]);
+
+        let partial = fs::read(out.join("b.bin")).await.unwrap();
+        assert!(!recovered[1].complete);
+        assert_eq!((recovered[1].chunks, recovered[1].lost), (2, 1));
+        assert_eq!(partial.len(), contents[1].len());
+        assert!(partial[..MIN_CHUNK_SIZE].iter().all(|byte| *byte == 0));
+        assert_eq!(&partial[MIN_CHUNK_SIZE..], &contents[1][MIN_CHUNK_SIZE..]);
+
+        let wrong = Secret::new(b"wrong".to_vec());
+        let refused = recover(&Files::new(&image_path), &wrong, &out, &options, &NoProgress).await.unwrap();
+        assert!(refused.iter().all(|file| file.problem.is_some() && file.output.is_none()));
+    }
+
+    #[tokio::test]
+    async fn keep_going_writes_zeros_for_lost_chunks() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.bin");
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 3 + 10];
+        SeededRandom::new(19).fill(&mut content).unwrap();
+        fs::write(&source_path, &content).await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() });
+        let encrypted = Files::new(dir.path().join("test.bin.swx"));
+        encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+
+        // Wreck every byte of the second chunk, leaving its length intact.
+        let mut data = fs::read(encrypted.path()).await.unwrap();
+        let (_, header_len) = Deserializer::from_bytes(&data).unwrap();
+        let first_len = u32::from_le_bytes(data[header_len..header_len + 4].try_into().unwrap()) as usize;
+        let second_at = header_len + 4 + first_len;
+        let second_len = u32::from_le_bytes(data[second_at..second_at + 4].try_into().unwrap()) as usize;
+        data[second_at + 4..second_at + 4 + second_len].fill(0);
+        fs::write(encrypted.path(), &data).await.unwrap();
+
+        let decrypted_path = dir.path().join("test_dec.bin");
+        let destination = Destination::File(Files::new(&decrypted_path));
+        decrypt(&encrypted, &destination, &secret, &options).await.unwrap_err();
+        assert!(!decrypted_path.exists());
+
+        let keep_going = Options { keep_going: true, ..Options::new(Settings::default()) };
+        let error = decrypt(&encrypted, &destination, &secret, &keep_going).await.unwrap_err();
+        let (start, end) = (MIN_CHUNK_SIZE as u64, 2 * MIN_CHUNK_SIZE as u64);
+        assert_eq!(SweetByteError::classify(&error), SweetByteError::PartialRecovery { lost_bytes: end - start, lost_ranges: vec![(start, end)] });
+        let partial = fs::read(&decrypted_path).await.unwrap();
+        assert_eq!(partial.len(), content.len());
+        assert_eq!(&partial[..MIN_CHUNK_SIZE], &content[..MIN_CHUNK_SIZE]);
+        assert!(partial[MIN_CHUNK_SIZE..2 * MIN_CHUNK_SIZE].iter().all(|byte| *byte == 0));
+        assert_eq!(&partial[2 * MIN_CHUNK_SIZE..], &content[2 * MIN_CHUNK_SIZE..]);
+
+        // A payload cut short loses everything after the cut, and the file is still written at full size.
+        fs::write(encrypted.path(), &data[..second_at + 2]).await.unwrap();
+        fs::remove_file(&decrypted_path).await.unwrap();
+        let error = decrypt(&encrypted, &destination, &secret, &keep_going).await.unwrap_err();
+        let end = content.len() as u64;
+        assert_eq!(SweetByteError::classify(&error), SweetByteError::PartialRecovery { lost_bytes: end - start, lost_ranges: vec![(start, end)] });
+        assert_eq!(fs::read(&decrypted_path).await.unwrap().len(), content.len());
+    }
+
+    #[tokio::test]
+    async fn range_decrypts_only_the_requested_bytes() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.bin");
+        let encrypted_path = dir.path().join("test.bin.swx");
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 3 + 100];
+        SeededRandom::new(11).fill(&mut content).unwrap();
+        fs::write(&source_path, &content).await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() });
+        let encrypted = Files::new(&encrypted_path);
+        encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+
+        let ranges = [(MIN_CHUNK_SIZE + 10, MIN_CHUNK_SIZE * 2 + 20), (MIN_CHUNK_SIZE * 3, usize::MAX), (0, 5)];
+        for (index, (start, end)) in ranges.into_iter().enumerate() {
+            let decrypted_path = dir.path().join(format!("test_range_{index}.bin"));
+            let range = Some(u64::try_from(start).unwrap()..u64::try_from(end).unwrap());
+            let options = Options { range, ..Options::new(Settings::default()) };
+            decrypt(&encrypted, &Destination::File(Files::new(&decrypted_path)), &secret, &options).await.unwrap();
+            assert_eq!(fs::read(&decrypted_path).await.unwrap(), &content[start..end.min(content.len())]);
+        }
+
+        let past_end = Options { range: Some(u64::MAX - 1..u64::MAX), ..Options::new(Settings::default()) };
+        decrypt(&encrypted, &Destination::File(Files::new(dir.path().join("test_none.bin"))), &secret, &past_end)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn files_with_and_without_chunk_index_decrypt() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.bin");
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 2 + 7];
+        SeededRandom::new(17).fill(&mut content).unwrap();
+        fs::write(&source_path, &content).await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let variants = [
+            Options { pad_size: PadSize::PowerOfTwo, ..Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() }) },
+            Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, chunk_index: false, ..Settings::default() }),
+        ];
+        for (index, options) in variants.iter().enumerate() {
+            let encrypted = Files::new(dir.path().join(format!("test_{index}.bin.swx")));
+            encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), options, &SystemRandom).await.unwrap();
+
+            let whole = dir.path().join(format!("test_{index}_whole.bin"));
+            decrypt(&encrypted, &Destination::File(Files::new(&whole)), &secret, options).await.unwrap();
+            assert_eq!(fs::read(&whole).await.unwrap(), content);
+            assert_eq!(crate::memory::decrypt_bytes(&fs::read(encrypted.path()).await.unwrap(), &secret).unwrap(), content);
+
+            let slice = dir.path().join(format!("test_{index}_slice.bin"));
+            let range = Options { range: Some(3_000..8_195), ..Options::new(Settings::default()) };
+            decrypt(&encrypted, &Destination::File(Files::new(&slice)), &secret, &range).await.unwrap();
+            assert_eq!(fs::read(&slice).await.unwrap(), &content[3_000..8_195]);
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_report_damaged_shards() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.bin");
+        let encrypted_path = dir.path().join("test.bin.swx");
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 3];
+        SeededRandom::new(13).fill(&mut content).unwrap();
+        fs::write(&source_path, &content).await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() });
+        let encrypted = Files::new(&encrypted_path);
+        encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+
+        let layout = chunks(&encrypted, None, &NoProgress).await.unwrap();
+        assert_eq!(layout.chunks.len(), 3);
+        assert!(layout.trailer);
+        assert!(layout.chunks.iter().all(|chunk| chunk.healthy() && chunk.decrypts.is_none()));
+
+        let mut bytes = fs::read(&encrypted_path).await.unwrap();
+        let damaged = usize::try_from(layout.chunks[1].offset).unwrap() + 16;
+        bytes[damaged] ^= 0xFF;
+        fs::write(&encrypted_path, &bytes).await.unwrap();
+
+        let layout = chunks(&encrypted, Some(&secret), &NoProgress).await.unwrap();
+        let chunk = &layout.chunks[1];
+        assert_eq!(chunk.shards_intact, chunk.shards_total - 1);
+        assert!(chunk.recoverable && chunk.nonce_matches && chunk.decrypts == Some(true));
+    }
+
+    #[tokio::test]
+    async fn damage_within_parity_is_recovered() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.bin");
+        let encrypted_path = dir.path().join("test.bin.swx");
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 2];
+        SeededRandom::new(17).fill(&mut content).unwrap();
+        fs::write(&source_path, &content).await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() });
+        let encrypted = Files::new(&encrypted_path);
+        encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+        let original = fs::read(&encrypted_path).await.unwrap();
+
+        let within = Damage { flip_bits: 0, wipe_shards: RECOVERY_COUNT };
+        let trials = corrupt(&encrypted, &secret, within, 2, &SeededRandom::new(1), &options.cancel).await.unwrap();
+        assert!(trials.iter().all(|trial| trial.recovered));
+
+        let beyond = Damage { flip_bits: 0, wipe_shards: RECOVERY_COUNT + 1 };
+        let trials = corrupt(&encrypted, &secret, beyond, 1, &SeededRandom::new(1), &options.cancel).await.unwrap();
+        assert!(trials.iter().all(|trial| !trial.recovered && trial.error.is_some()));
+        assert_eq!(fs::read(&encrypted_path).await.unwrap(), original);
+    }
+
+    #[tokio::test]
+    async fn chunk_store_roundtrip() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.bin");
+        let encrypted_path = dir.path().join("test.bin.swx");
+        let store = dir.path().join("store");
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 3];
+        SeededRandom::new(11).fill(&mut content).unwrap();
+        fs::write(&source_path, &content).await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options { chunk_store: Some(store.clone()), ..Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() }) };
+        let encrypted = Files::new(&encrypted_path);
+        encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+
+        let index = crate::storage::Index::load(&encrypted_path).unwrap();
+        assert_eq!(index.chunks.len(), 3);
+        assert!(index.header.is_some() && index.trailer.is_some());
+
+        let added = Secret::new(b"second-password".to_vec());
+        add_key(&encrypted, &secret, &added, &NoProgress, &SystemRandom).await.unwrap();
+
+        let decrypted_path = dir.path().join("test_dec.bin");
+        decrypt(&encrypted, &Destination::File(Files::new(&decrypted_path)), &added, &options).await.unwrap();
+        assert_eq!(fs::read(&decrypted_path).await.unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn unchanged_source_is_detected_from_header() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.txt");
+        let encrypted_path = dir.path().join("test.txt.swx");
+        fs::write(&source_path, b"backup content").await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings::default());
+        let source = Files::new(&source_path);
+        let encrypted = Files::new(&encrypted_path);
+        assert!(unchanged(&source, &encrypted, &secret, None, &NoProgress, options.settings.threads).await.unwrap().is_none());
+
+        encrypt(&source, &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+        assert!(
+            unchanged(&source, &encrypted, &Secret::new(b"other-password".to_vec()), None, &NoProgress, options.settings.threads)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let header = unchanged(&source, &encrypted, &secret, None, &NoProgress, options.settings.threads).await.unwrap().unwrap();
+        assert_eq!(header.name, "test.txt");
+
+        fs::write(&source_path, b"backup content, edited").await.unwrap();
+        assert!(unchanged(&source, &encrypted, &secret, None, &NoProgress, options.settings.threads).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn directory_roundtrips_through_tar() {
+        let dir = tempdir().unwrap();
+        let source_dir = dir.path().join("docs");
+        fs::create_dir_all(source_dir.join("nested")).await.unwrap();
+        fs::write(source_dir.join("a.txt"), b"alpha").await.unwrap();
+        fs::write(source_dir.join("nested").join("b.txt"), b"beta").await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings::default());
+        let encrypted = Files::new(dir.path().join("docs.swx"));
+        let header = encrypt_directory(&source_dir, &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom).await.unwrap();
+        assert_eq!(header.name, "docs");
+
+        let extracted = dir.path().join("restored");
+        decrypt(&encrypted, &Destination::Extract(extracted.clone()), &secret, &options).await.unwrap();
+        assert_eq!(fs::read(extracted.join("a.txt")).await.unwrap(), b"alpha");
+        assert_eq!(fs::read(extracted.join("nested").join("b.txt")).await.unwrap(), b"beta");
+
+        decrypt(&encrypted, &Destination::Extract(extracted), &secret, &options).await.unwrap_err();
+    }
 }
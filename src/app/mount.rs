@@ -0,0 +1,88 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use fuser::BackgroundSession;
+
+use crate::config::COMPRESSION_LEVEL;
+use crate::error::SweetByteError;
+use crate::files::{Files, Metadata};
+use crate::fuse::Image;
+use crate::header::Deserializer;
+use crate::pipeline::{Operation, Process, ProgressSink};
+use crate::secret::Secret;
+
+/// Mounts the decrypted content of `source` read-only at `mountpoint` until the session is dropped.
+/// Reads are served through the chunk index, so only a single file written with one can be mounted.
+/// Each chunk is authenticated as it is decrypted; the whole-file hash is never checked because
+/// nothing reads the whole file.
+pub(crate) async fn mount(source: &Files, mountpoint: &Path, secret: &Secret, cache_size: u64, progress: &dyn ProgressSink) -> Result<BackgroundSession> {
+    let (image, metadata) = open(source, secret, cache_size, progress).await?;
+
+    let fallback = source.output_path(Operation::Decryption);
+    let name = Path::new(&metadata.name)
+        .file_name()
+        .or_else(|| fallback.file_name())
+        .context("no file name to mount the content under")?;
+    crate::fuse::mount(image, name.to_string_lossy().into_owned(), metadata.attributes, mountpoint)
+}
+
+async fn open(source: &Files, secret: &Secret, cache_size: u64, progress: &dyn ProgressSink) -> Result<(Image, Metadata)> {
+    let Some(mut file) = crate::files::spanning::seekable(source).await? else {
+        anyhow::bail!("only a single .swx file can be mounted, not split volumes, a chunk store, or a carrier");
+    };
+    let header = match Deserializer::from_reader(&mut file).await {
+        Ok(header) => header,
+        Err(error) => crate::header::read_trailing_copy(&mut file).await.map_err(|_| error).context(SweetByteError::CorruptHeader)?,
+    };
+    if !header.indexed() {
+        anyhow::bail!("file has no chunk index; decrypt it whole or re-encrypt it to mount it");
+    }
+
+    let (keys, metadata) = super::open_header(secret, &header, None, None, progress)?;
+    let entries = super::decrypt::read_index(&mut file, &header, &keys).await?;
+    let process = Process::new(&keys.primary_key, &keys.secondary_key, Operation::Decryption, header.cipher_mode()?, header.nonce_prefix(), COMPRESSION_LEVEL)?
+        .with_padding_scheme(header.padding_scheme())
+        .with_chunk_limit(header.chunk_limit(metadata.size));
+
+    let header_len = u64::try_from(header.encoded_len()).context("header length overflow")?;
+    let cached_chunks = usize::try_from(cache_size.checked_div(u64::from(header.chunk_size())).unwrap_or(1)).unwrap_or(usize::MAX);
+    let image = Image::new(file.into_std().await, process, entries, header_len, metadata.size, cached_chunks);
+
+    Ok((image, metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use tokio::fs;
+
+    use super::*;
+    use crate::app::Options;
+    use crate::cipher::{RandomSource, SeededRandom, SystemRandom};
+    use crate::config::{MIN_CHUNK_SIZE, Settings};
+    use crate::pipeline::NoProgress;
+
+    #[tokio::test]
+    async fn image_reads_any_range() {
+        let dir = tempdir().unwrap();
+        let source_path = dir.path().join("test.bin");
+        let mut content = vec![0u8; MIN_CHUNK_SIZE * 3 + 50];
+        SeededRandom::new(23).fill(&mut content).unwrap();
+        fs::write(&source_path, &content).await.unwrap();
+
+        let secret = Secret::new(b"password".to_vec());
+        let options = Options::new(Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() });
+        let encrypted = Files::new(dir.path().join("test.bin.swx"));
+        crate::app::encrypt(&Files::new(&source_path), &encrypted, std::slice::from_ref(&secret), &options, &SystemRandom)
+            .await
+            .unwrap();
+
+        let (mut image, _) = open(&encrypted, &secret, 0, &NoProgress).await.unwrap();
+        assert_eq!(image.size(), u64::try_from(content.len()).unwrap());
+        for (offset, len) in [(0, 10), (MIN_CHUNK_SIZE - 5, 10), (MIN_CHUNK_SIZE * 3, 4_096), (100, u32::MAX as usize)] {
+            let data = image.read(u64::try_from(offset).unwrap(), u32::try_from(len).unwrap()).unwrap();
+            assert_eq!(data, &content[offset..offset.saturating_add(len).min(content.len())]);
+        }
+        assert!(image.read(u64::MAX, 10).unwrap().is_empty());
+    }
+}
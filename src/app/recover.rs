@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, SeekFrom};
+
+use super::Options;
+use crate::config::{COMPRESSION_LEVEL, IO_BUFFER_SIZE, MAX_CHUNK_SIZE};
+use crate::files::{Files, TempFile};
+use crate::pipeline::{Operation, Process, ProgressSink, Task};
+use crate::recovery::Candidate;
+use crate::secret::Secret;
+use crate::secure_temp::SecureBuffer;
+
+/// What became of one header found while scanning.
+#[derive(Serialize)]
+pub(crate) struct Recovered {
+    pub(crate) offset: u64,
+    pub(crate) name: Option<String>,
+    pub(crate) output: Option<PathBuf>,
+    pub(crate) chunks: u64,
+    /// Chunks that were missing or didn't decrypt; each is written as zeros so the rest stay at
+    /// their offsets.
+    pub(crate) lost: u64,
+    /// Whether every chunk decrypted and the result matches the hash in the header.
+    pub(crate) complete: bool,
+    pub(crate) problem: Option<String>,
+}
+
+/// Scans `source`, which may be any file or disk image, for headers and writes whatever each one's
+/// payload still yields into `dir`. Header copies are skipped, since no payload follows them.
+pub(crate) async fn recover(source: &Files, secret: &Secret, dir: &Path, options: &Options, progress: &dyn ProgressSink) -> Result<Vec<Recovered>> {
+    let mut file = source.open().await?;
+    let candidates = crate::recovery::scan(&mut file).await?;
+
+    let mut recovered = Vec::new();
+    for candidate in candidates.iter().filter(|candidate| !candidate.copy) {
+        let outcome = match extract(&mut file, source.path(), candidate, secret, dir, options, progress).await {
+            Ok(outcome) => outcome,
+            Err(error) => Recovered { offset: candidate.offset, name: None, output: None, chunks: 0, lost: 0, complete: false, problem: Some(format!("{error:#}")) },
+        };
+        recovered.push(outcome);
+    }
+
+    Ok(recovered)
+}
+
+async fn extract(file: &mut File, source: &Path, candidate: &Candidate, secret: &Secret, dir: &Path, options: &Options, progress: &dyn ProgressSink) -> Result<Recovered> {
+    let header = &candidate.header;
+    super::check_policy(source, header, options)?;
+    let (keys, metadata) = super::open_header(secret, header, options.session.as_ref(), options.second_factor.as_ref(), progress)?;
+    let process = Process::new(&keys.primary_key, &keys.secondary_key, Operation::Decryption, header.cipher_mode()?, header.nonce_prefix(), COMPRESSION_LEVEL)?
+        .with_padding_scheme(header.padding_scheme())
+        .with_chunk_limit(header.chunk_limit(metadata.size));
+
+    let target = super::decrypt::restore_path(dir, &metadata.name)?;
+    let temp = TempFile::new(&target)?;
+    let mut writer = Files::new(temp.path()).writer(options.settings.fsync).await.context("failed to create target file")?;
+
+    let start = candidate.offset.saturating_add(u64::try_from(header.encoded_len()).context("header length overflow")?);
+    file.seek(SeekFrom::Start(start)).await.context("failed to seek to payload")?;
+    let mut reader = BufReader::with_capacity(IO_BUFFER_SIZE, &mut *file);
+    let mut hasher = blake3::Hasher::new();
+    let (mut written, mut lost) = (0u64, 0u64);
+    let mut readable = true;
+
+    for index in 0..header.chunk_count() {
+        let chunk = if readable { read_chunk(&mut reader, &process, index).await } else { None };
+        let plaintext = match chunk {
+            Some(Ok(plaintext)) => plaintext,
+            failed => {
+                // Once a length can't be read, nothing after it can be framed either.
+                readable = readable && failed.is_some();
+                lost = lost.saturating_add(1);
+                let len = u64::from(header.chunk_size()).min(metadata.size.saturating_sub(written));
+                SecureBuffer::unlocked(vec![0u8; usize::try_from(len).context("chunk length overflow")?])
+            }
+        };
+        hasher.update(&plaintext);
+        writer.write_all(&plaintext).await.context("failed to write recovered file")?;
+        written = written.saturating_add(u64::try_from(plaintext.len()).context("size overflow")?);
+    }
+
+    writer.shutdown().await.context("failed to flush recovered file")?;
+    temp.persist().await.context("failed to finalize recovered file")?;
+
+    let complete = lost == 0 && written == metadata.size && bool::from(hasher.finalize().as_bytes().ct_eq(metadata.hash.as_slice()));
+    Ok(Recovered { offset: candidate.offset, name: Some(metadata.name), output: Some(target), chunks: header.chunk_count().saturating_sub(lost), lost, complete, problem: None })
+}
+
+/// The next chunk record decrypted, `Some(Err)` when it was read but didn't decrypt, or `None` when
+/// no plausible record could be read at all.
+async fn read_chunk(reader: &mut BufReader<&mut File>, process: &Process, index: u64) -> Option<Result<SecureBuffer>> {
+    let length = reader.read_u32_le().await.ok().filter(|length| *length <= MAX_CHUNK_SIZE)?;
+    let mut data = vec![0u8; usize::try_from(length).ok()?];
+    reader.read_exact(&mut data).await.ok()?;
+
+    Some(process.process(&Task { data: SecureBuffer::unlocked(data), index }).map(|result| result.data))
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use super::Options;
+use crate::cipher::SystemRandom;
+use crate::files::{Discover, Files, Metadata};
+use crate::pipeline::{NoProgress, Operation};
+use crate::secret::Secret;
+use crate::state::State;
+
+/// One file a watch picked up, and what came of it.
+pub(crate) struct Watched {
+    pub(crate) source: PathBuf,
+    pub(crate) output: PathBuf,
+    pub(crate) outcome: WatchOutcome,
+    pub(crate) elapsed: Duration,
+}
+
+pub(crate) enum WatchOutcome {
+    Encrypted,
+    /// The output already held the current content.
+    Unchanged,
+    Failed(anyhow::Error),
+}
+
+/// How a watch runs, beyond the options every encryption takes.
+pub(crate) struct WatchOptions {
+    /// How long a file must go without changing before it's encrypted.
+    pub(crate) settle: Duration,
+    /// Stop after bringing the output up to date instead of watching for changes.
+    pub(crate) once: bool,
+    /// Skip files the journal says were encrypted as they are now, without opening their outputs.
+    pub(crate) since_last_run: bool,
+    /// Where to keep the run lock and the journal; without one, neither is kept.
+    pub(crate) state_dir: Option<PathBuf>,
+}
+
+/// Encrypts every eligible file under `source` into the same relative path under `target`, then
+/// keeps watching `source` and encrypts each file that is created or changed there once nothing has
+/// touched it for `settle`. A file whose output already holds its content is skipped. Every file
+/// handled goes to `log`, and a failure doesn't stop the watch, which runs until `options.cancel`
+/// fires, or ends after the first pass with `once`. With a state directory, the run holds its lock
+/// and records each file it encrypts in the journal.
+pub(crate) async fn watch(source: &Path, target: &Path, secret: &Secret, watch_options: &WatchOptions, options: &Options, log: &dyn Fn(Watched)) -> Result<()> {
+    let source = source.canonicalize().with_context(|| format!("failed to open watched directory {}", source.display()))?;
+    tokio::fs::create_dir_all(target).await.with_context(|| format!("failed to create {}", target.display()))?;
+    let target = target.canonicalize().with_context(|| format!("failed to open output directory {}", target.display()))?;
+    if watch_options.since_last_run && watch_options.state_dir.is_none() {
+        anyhow::bail!("--since-last-run needs a state directory; pass --state-dir");
+    }
+    let state = watch_options.state_dir.as_deref().map(|dir| State::open(dir, &source)).transpose()?;
+    let mut watch = Watch { source, target, secret, options, state, since_last_run: watch_options.since_last_run };
+
+    if watch_options.once {
+        return watch.sweep(&watch.source.clone(), log).await;
+    }
+
+    let (sender, mut changes) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if let Ok(event) = event
+            && matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+        {
+            for path in event.paths {
+                sender.send(path).unwrap_or_default();
+            }
+        }
+    })
+    .context("failed to start file watcher")?;
+    watcher
+        .watch(&watch.source, RecursiveMode::Recursive)
+        .with_context(|| format!("failed to watch {}", watch.source.display()))?;
+
+    // Anything already there is brought up to date first; the watcher queues what changes meanwhile.
+    watch.sweep(&watch.source.clone(), log).await?;
+
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    loop {
+        let due = pending.values().min().copied();
+        tokio::select! {
+            () = options.cancel.cancelled() => return Ok(()),
+            change = changes.recv() => {
+                let path = change.context("file watcher stopped")?;
+                let now = Instant::now();
+                pending.insert(path, now.checked_add(watch_options.settle).unwrap_or(now));
+            }
+            () = settled(due) => {
+                let now = Instant::now();
+                let ready: Vec<PathBuf> = pending.iter().filter(|&(_, due)| *due <= now).map(|(path, _)| path.clone()).collect();
+                for path in ready {
+                    pending.remove(&path);
+                    watch.sweep(&path, log).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Resolves at `due`, or never when nothing is waiting to settle.
+async fn settled(due: Option<Instant>) {
+    match due {
+        Some(due) => tokio::time::sleep_until(due).await,
+        None => std::future::pending().await,
+    }
+}
+
+struct Watch<'a> {
+    source: PathBuf,
+    target: PathBuf,
+    secret: &'a Secret,
+    options: &'a Options,
+    state: Option<State>,
+    since_last_run: bool,
+}
+
+impl Watch<'_> {
+    /// Encrypts `path`, or every eligible file under it when it's a directory, and saves the
+    /// journal. Hidden, excluded, and already encrypted files are left alone, and so is the
+    /// output directory when it lies inside the watched one. Only failing to save the journal
+    /// is an error; a file that fails is logged.
+    async fn sweep(&mut self, path: &Path, log: &dyn Fn(Watched)) -> Result<()> {
+        if !path.exists() || path.starts_with(&self.target) {
+            return Ok(());
+        }
+
+        for file in Discover::new(path.to_string_lossy(), Operation::Encryption, &self.options.settings.exclude).run() {
+            if self.options.cancel.is_cancelled() {
+                break;
+            }
+            let Some(output) = self.output(&file).filter(|_| !file.starts_with(&self.target)) else {
+                continue;
+            };
+            if self.since_last_run && self.state.as_ref().is_some_and(|state| state.journal().is_current(&file, &output)) {
+                continue;
+            }
+
+            let started = Instant::now();
+            let modified = crate::state::modified(&file);
+            let outcome = match self.encrypt(&file, &output).await {
+                Ok((outcome, metadata)) => {
+                    if let Some(state) = &mut self.state {
+                        state.journal_mut().record(&file, modified, &output, &metadata);
+                    }
+                    outcome
+                }
+                Err(error) => WatchOutcome::Failed(error),
+            };
+            log(Watched { source: file, output, outcome, elapsed: started.elapsed() });
+        }
+
+        if let Some(state) = &self.state {
+            state.save()?;
+        }
+
+        Ok(())
+    }
+
+    /// Where `file` is encrypted to: its path under the watched directory, under the output
+    /// directory.
+    fn output(&self, file: &Path) -> Option<PathBuf> {
+        let relative = file.strip_prefix(&self.source).ok()?;
+
+        Some(Files::new(self.target.join(relative)).output_path(Operation::Encryption))
+    }
+
+    async fn encrypt(&self, file: &Path, output: &Path) -> Result<(WatchOutcome, Metadata)> {
+        let (source, target) = (Files::new(file), Files::new(output));
+        let options = self.options;
+        if let Some(metadata) = super::unchanged(&source, &target, self.secret, options.second_factor.as_ref(), &NoProgress, options.settings.threads).await? {
+            return Ok((WatchOutcome::Unchanged, metadata));
+        }
+
+        if let Some(parent) = output.parent() {
+            tokio::fs::create_dir_all(parent).await.with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let metadata = super::encrypt(&source, &target, std::slice::from_ref(self.secret), options, &SystemRandom).await?;
+
+        Ok((WatchOutcome::Encrypted, metadata))
+    }
+}
@@ -0,0 +1,154 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use blake3::Hasher;
+use tokio::io::DuplexStream;
+use tokio::task::JoinHandle;
+use tokio_util::io::SyncIoBridge;
+use walkdir::WalkDir;
+
+const PIPE_CAPACITY: usize = 1024 * 1024;
+
+pub(crate) struct Summary {
+    pub(crate) size: u64,
+    pub(crate) hash: Vec<u8>,
+}
+
+struct Digest<W> {
+    inner: W,
+    hasher: Hasher,
+    size: u64,
+}
+
+impl<W> Digest<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, hasher: Hasher::new(), size: 0 }
+    }
+
+    fn summary(&self) -> Summary {
+        Summary { size: self.size, hash: self.hasher.finalize().as_bytes().to_vec() }
+    }
+
+    fn record(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.size = self.size.saturating_add(u64::try_from(data.len()).unwrap_or(u64::MAX));
+    }
+}
+
+impl<W: Write> Write for Digest<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.record(buf.get(..written).unwrap_or_default());
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Digest<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.record(buf.get(..read).unwrap_or_default());
+
+        Ok(read)
+    }
+}
+
+pub(crate) fn summarize(dir: &Path) -> Result<Summary> {
+    let digest = build(dir, Digest::new(io::sink()))?;
+
+    Ok(digest.summary())
+}
+
+pub(crate) fn reader(dir: &Path) -> (DuplexStream, JoinHandle<Result<Summary>>) {
+    let (read, write) = tokio::io::duplex(PIPE_CAPACITY);
+    let bridge = SyncIoBridge::new(write);
+    let dir = dir.to_path_buf();
+
+    let handle = tokio::task::spawn_blocking(move || {
+        let mut digest = build(&dir, Digest::new(bridge))?;
+        digest.flush().context("failed to flush archive stream")?;
+        digest.inner.shutdown().context("failed to close archive stream")?;
+
+        Ok(digest.summary())
+    });
+
+    (read, handle)
+}
+
+pub(crate) fn writer(target: PathBuf) -> (DuplexStream, JoinHandle<Result<Summary>>) {
+    let (write, read) = tokio::io::duplex(PIPE_CAPACITY);
+    let bridge = SyncIoBridge::new(read);
+
+    let handle = tokio::task::spawn_blocking(move || {
+        let mut digest = Digest::new(bridge);
+        unpack(&mut digest, &target)?;
+        io::copy(&mut digest, &mut io::sink()).context("failed to read end of archive")?;
+
+        Ok(digest.summary())
+    });
+
+    (write, handle)
+}
+
+fn unpack<R: Read>(source: R, target: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(source);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+
+    archive.unpack(target).with_context(|| format!("failed to extract archive into {}", target.display()))
+}
+
+fn build<W: Write>(dir: &Path, sink: W) -> Result<W> {
+    let mut builder = tar::Builder::new(sink);
+    builder.follow_symlinks(false);
+
+    for entry in WalkDir::new(dir).min_depth(1).sort_by_file_name() {
+        let entry = entry.context("failed to walk directory")?;
+        let name = entry.path().strip_prefix(dir).context("entry is outside the archived directory")?;
+        builder
+            .append_path_with_name(entry.path(), name)
+            .with_context(|| format!("failed to archive {}", entry.path().display()))?;
+    }
+
+    builder.into_inner().context("failed to finish archive")
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn directory_streams_through_archive() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("source");
+        std::fs::create_dir_all(source.join("nested")).unwrap();
+        std::fs::write(source.join("a.txt"), b"alpha").unwrap();
+        std::fs::write(source.join("nested").join("b.txt"), b"beta").unwrap();
+
+        let expected = summarize(&source).unwrap();
+        let (mut read, packing) = reader(&source);
+        let mut stream = Vec::new();
+        read.read_to_end(&mut stream).await.unwrap();
+        let packed = packing.await.unwrap().unwrap();
+        assert_eq!(packed.hash, expected.hash);
+        assert_eq!(packed.size, u64::try_from(stream.len()).unwrap());
+
+        let target = dir.path().join("target");
+        let (mut write, unpacking) = writer(target.clone());
+        write.write_all(&stream).await.unwrap();
+        drop(write);
+        let unpacked = unpacking.await.unwrap().unwrap();
+        assert_eq!(unpacked.hash, expected.hash);
+
+        assert_eq!(std::fs::read(target.join("a.txt")).unwrap(), b"alpha");
+        assert_eq!(std::fs::read(target.join("nested").join("b.txt")).unwrap(), b"beta");
+    }
+}
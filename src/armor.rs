@@ -0,0 +1,58 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+const BEGIN: &str = "-----BEGIN SWEETBYTE MESSAGE-----";
+const END: &str = "-----END SWEETBYTE MESSAGE-----";
+const LINE_LEN: usize = 64;
+
+/// Wraps a complete `.swx` file in base64 lines between begin and end markers, so it survives being
+/// pasted into email or chat.
+pub(crate) fn armor(data: &[u8]) -> String {
+    let encoded = STANDARD.encode(data);
+
+    let mut armored = String::with_capacity(
+        encoded
+            .len()
+            .saturating_add(encoded.len().div_ceil(LINE_LEN))
+            .saturating_add(BEGIN.len())
+            .saturating_add(END.len())
+            .saturating_add(2),
+    );
+    armored.push_str(BEGIN);
+    armored.push('\n');
+    for line in encoded.as_bytes().chunks(LINE_LEN) {
+        armored.push_str(&String::from_utf8_lossy(line));
+        armored.push('\n');
+    }
+    armored.push_str(END);
+    armored.push('\n');
+
+    armored
+}
+
+/// Finds the armored block in `text` and decodes it. Anything around the markers is ignored, and so
+/// is whitespace or `>` quoting inside them, since mail clients and chat apps add both.
+pub(crate) fn dearmor(text: &str) -> Result<Vec<u8>> {
+    let (_, rest) = text.split_once(BEGIN).context("no SweetByte message found")?;
+    let (body, _) = rest.split_once(END).context("SweetByte message is cut short: end marker not found")?;
+    let encoded: String = body.chars().filter(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=')).collect();
+
+    STANDARD.decode(encoded).context("SweetByte message is not valid base64")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn armor_roundtrips_through_quoting() {
+        let data: Vec<u8> = (0..=255).collect();
+        let armored = armor(&data);
+        assert!(armored.lines().all(|line| line.len() <= LINE_LEN || line.starts_with("-----")));
+
+        let quoted: String = armored.lines().map(|line| format!("> {line}\r\n")).collect();
+        assert_eq!(dearmor(&format!("Here it is:\n{quoted}\nthanks")).unwrap(), data);
+        dearmor(&armored.replace(END, "")).unwrap_err();
+    }
+}
@@ -0,0 +1,108 @@
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::cipher::{Argon2Params, Kdf};
+use crate::config::{ARGON2_P_COST, ARGON2_SALT_LEN, ARGON2_T_COST, MAX_KDF_ITERATIONS, MAX_KDF_MEMORY, MIN_KDF_MEMORY};
+
+/// Memory costs are rounded down to whole MiB so the suggested values read cleanly in a config
+/// file.
+const MEMORY_STEP: u32 = 1024;
+
+pub(crate) struct Calibration {
+    pub(crate) params: Argon2Params,
+    pub(crate) measured: Duration,
+}
+
+/// The lanes to suggest: one per core, up to the default.
+pub(crate) fn default_parallelism() -> u32 {
+    let cores = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    u32::try_from(cores).unwrap_or(u32::MAX).min(ARGON2_P_COST)
+}
+
+/// Finds Argon2id costs that take about `target` on this machine.
+///
+/// Memory is doubled from the minimum until a derivation takes longer than the target, then
+/// narrowed between the last two measurements, since cost grows about linearly with it. Only when
+/// `max_memory` (KiB) stops the doubling short are extra passes added instead.
+pub(crate) fn calibrate(target: Duration, max_memory: u32, parallelism: u32) -> Result<Calibration> {
+    let max_memory = max_memory.clamp(MIN_KDF_MEMORY, MAX_KDF_MEMORY);
+    let mut params = Argon2Params { memory: MIN_KDF_MEMORY, iterations: ARGON2_T_COST, parallelism };
+    params.validate()?;
+
+    let mut measured = measure(params)?;
+    let mut below = (params.memory, measured);
+    while measured < target {
+        let Some(memory) = params.memory.checked_mul(2).filter(|&memory| memory <= max_memory) else {
+            break;
+        };
+        below = (params.memory, measured);
+        params.memory = memory;
+        measured = measure(params)?;
+    }
+
+    if measured > target && params.memory > MIN_KDF_MEMORY {
+        params.memory = interpolate(below, (params.memory, measured), target);
+        measured = measure(params)?;
+    } else if measured < target {
+        params.iterations = scale(params.iterations, measured, target).clamp(ARGON2_T_COST, MAX_KDF_ITERATIONS);
+        measured = measure(params)?;
+    }
+
+    Ok(Calibration { params, measured })
+}
+
+fn measure(params: Argon2Params) -> Result<Duration> {
+    let mut output = [0u8; 32];
+    let started = Instant::now();
+    params.stretch(b"calibrate", &[0u8; ARGON2_SALT_LEN], &mut output).context("failed to time argon2id")?;
+
+    Ok(started.elapsed())
+}
+
+/// The memory between `low` and `high` whose derivation should take `target`, assuming time grows
+/// linearly with it.
+fn interpolate((low, low_time): (u32, Duration), (high, high_time): (u32, Duration), target: Duration) -> u32 {
+    let span = high_time.saturating_sub(low_time).as_micros();
+    let reach = target.saturating_sub(low_time).as_micros();
+    let extra = u128::from(high.saturating_sub(low)).saturating_mul(reach).checked_div(span).unwrap_or(0);
+    let memory = u32::try_from(extra).unwrap_or(u32::MAX).saturating_add(low).min(high);
+
+    memory.saturating_sub(memory.checked_rem(MEMORY_STEP).unwrap_or(0)).max(low)
+}
+
+/// The passes that should stretch a derivation taking `measured` at `iterations` out to `target`.
+fn scale(iterations: u32, measured: Duration, target: Duration) -> u32 {
+    let scaled = u128::from(iterations)
+        .saturating_mul(target.as_micros())
+        .checked_div(measured.as_micros())
+        .unwrap_or(u128::from(iterations));
+
+    u32::try_from(scaled).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolation_lands_between_the_measurements() {
+        let low = (65536, Duration::from_millis(200));
+        let high = (131072, Duration::from_millis(400));
+
+        assert_eq!(interpolate(low, high, Duration::from_millis(300)), 98304);
+        assert_eq!(interpolate(low, high, Duration::from_millis(100)), 65536);
+        assert_eq!(interpolate(low, high, Duration::from_secs(1)), 131072);
+        assert_eq!(scale(3, Duration::from_millis(100), Duration::from_millis(500)), 15);
+    }
+
+    #[test]
+    fn calibration_respects_the_memory_ceiling() {
+        let calibration = calibrate(Duration::from_millis(1), MIN_KDF_MEMORY, 1).unwrap();
+
+        assert_eq!(calibration.params.memory, MIN_KDF_MEMORY);
+        assert!(calibration.params.iterations >= ARGON2_T_COST);
+        calibration.params.validate().unwrap();
+    }
+}
@@ -1,60 +1,69 @@
 use std::marker::PhantomData;
 
-use aead::{Aead, AeadCore, Generate, KeyInit, Nonce};
+use aead::{AeadCore, AeadInOut, KeyInit, Nonce, Tag};
 use anyhow::{Context, Result};
 use hybrid_array::typenum::Unsigned;
 
+use super::nonce::NonceSequence;
 use crate::secret::Secret;
 use crate::validation::KeyBytes;
 
 pub(super) struct AeadCipher<Cipher> {
     key: Secret,
+    nonces: NonceSequence,
     cipher: PhantomData<Cipher>,
 }
 
 impl<Cipher> AeadCipher<Cipher>
 where
-    Cipher: Aead + AeadCore + KeyInit,
+    Cipher: AeadInOut + KeyInit,
 {
-    pub(super) fn new(key: &Secret) -> Result<Self> {
+    pub(super) fn new(key: &Secret, nonce_prefix: &[u8]) -> Result<Self> {
         let key = KeyBytes::try_new(key.expose_secret().to_vec()).context("key must be 32 bytes")?;
+        let nonces = NonceSequence::new(nonce_prefix, <Cipher as AeadCore>::NonceSize::USIZE)?;
 
-        Ok(Self { key: key.into_secret(), cipher: PhantomData })
+        Ok(Self { key: key.into_secret(), nonces, cipher: PhantomData })
     }
 
+    /// Encrypts `buffer[start..]` in place and appends the tag. The nonce is written into the bytes
+    /// just before `start`, which the caller leaves free; returns where the nonce begins.
     #[inline]
-    pub(super) fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
-        if plaintext.is_empty() {
-            anyhow::bail!("plaintext must not be empty");
-        }
+    pub(super) fn encrypt_in_place(&self, buffer: &mut Vec<u8>, start: usize, index: u64) -> Result<usize> {
+        let nonce_start = start.checked_sub(<Cipher as AeadCore>::NonceSize::USIZE).context("no room left for the nonce")?;
+        let plaintext = buffer.get_mut(start..).filter(|plaintext| !plaintext.is_empty()).context("plaintext must not be empty")?;
 
         let cipher = Cipher::new_from_slice(self.key.expose_secret()).context("failed to setup key")?;
-        let nonce = Nonce::<Cipher>::generate();
-        let ciphertext = cipher.encrypt(&nonce, plaintext).context("failed to encrypt")?;
+        let mut nonce = Nonce::<Cipher>::default();
+        self.nonces.fill(index, &mut nonce).context("failed to derive nonce")?;
+        let tag = cipher.encrypt_inout_detached(&nonce, &[], plaintext.into()).context("failed to encrypt")?;
 
-        let mut result = Vec::with_capacity(nonce.len().saturating_add(ciphertext.len()));
-        result.extend_from_slice(&nonce);
-        result.extend_from_slice(&ciphertext);
+        buffer.get_mut(nonce_start..start).context("no room left for the nonce")?.copy_from_slice(&nonce);
+        buffer.extend_from_slice(&tag);
 
-        Ok(result)
+        Ok(nonce_start)
     }
 
+    /// Decrypts the nonce, ciphertext and tag in `buffer[start..]` in place and drops the tag;
+    /// returns where the plaintext begins, just past the nonce.
     #[inline]
-    pub(super) fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
-        if ciphertext.is_empty() {
-            anyhow::bail!("ciphertext must not be empty");
-        }
-
+    pub(super) fn decrypt_in_place(&self, buffer: &mut Vec<u8>, start: usize, index: u64) -> Result<usize> {
+        let sealed = buffer.get_mut(start..).filter(|sealed| !sealed.is_empty()).context("ciphertext must not be empty")?;
         let nonce_len = <Cipher as AeadCore>::NonceSize::USIZE;
-        if ciphertext.len() < nonce_len {
-            anyhow::bail!("ciphertext shorter than nonce");
+        let (nonce_bytes, body) = sealed.split_at_mut_checked(nonce_len).context("ciphertext shorter than nonce")?;
+
+        let mut expected = Nonce::<Cipher>::default();
+        self.nonces.fill(index, &mut expected).context("failed to derive nonce")?;
+        if expected.as_slice() != nonce_bytes {
+            anyhow::bail!("chunk {index} nonce mismatch: chunk is out of order or from another file");
         }
 
-        let (nonce_bytes, body) = ciphertext.split_at(nonce_len);
-        let nonce = Nonce::<Cipher>::try_from(nonce_bytes).context("invalid nonce")?;
+        let tag_start = body.len().checked_sub(<Cipher as AeadCore>::TagSize::USIZE).context("failed to decrypt")?;
+        let (ciphertext, tag) = body.split_at_mut(tag_start);
+        let tag = Tag::<Cipher>::try_from(&*tag).context("failed to decrypt")?;
         let cipher = Cipher::new_from_slice(self.key.expose_secret()).context("failed to setup key")?;
-        let plaintext = cipher.decrypt(&nonce, body).context("failed to decrypt")?;
+        cipher.decrypt_inout_detached(&expected, &[], ciphertext.into(), &tag).context("failed to decrypt")?;
 
-        Ok(plaintext)
+        buffer.truncate(buffer.len().saturating_sub(tag.len()));
+        Ok(start.saturating_add(nonce_len))
     }
 }
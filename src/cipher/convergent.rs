@@ -0,0 +1,62 @@
+use anyhow::{Context, Result};
+
+#[cfg(feature = "cli")]
+use super::kdf::KdfParams;
+#[cfg(feature = "cli")]
+use super::key_deriver::KeyDeriver;
+use super::rng::SeededRandom;
+use crate::secret::Secret;
+
+/// Convergent encryption: the salt, master key, slot nonces, and nonce prefix are read from a
+/// stream keyed by the password and bound to the content hash instead of the system RNG, so the
+/// same content under the same password always produces the same payload. This is what lets a
+/// deduplicating store keep one copy, and it is also what leaks: equal files are visibly equal, and
+/// anyone with the password can confirm a guessed content.
+#[cfg(feature = "cli")]
+pub(crate) struct Convergent {
+    key: Secret,
+}
+
+#[cfg(feature = "cli")]
+impl Convergent {
+    pub(crate) fn new(secret: &Secret, kdf: &KdfParams) -> Result<Self> {
+        let key = KeyDeriver::new(secret)?.derive_convergent_key(kdf)?;
+
+        Ok(Self { key })
+    }
+
+    /// Stand-in for the system RNG while encrypting content with the given hash.
+    pub(crate) fn random(&self, content_hash: &[u8]) -> Result<SeededRandom> {
+        SeededRandom::keyed(&self.key, content_hash).context("failed to seed convergent stream")
+    }
+}
+
+/// Nonce source for a header section sealed under `key`, derived from the section itself.
+pub(crate) fn section_random(key: &Secret, plaintext: &[u8]) -> Result<SeededRandom> {
+    SeededRandom::keyed(key, plaintext).context("failed to seed section nonce")
+}
+
+#[cfg(test)]
+#[cfg(feature = "cli")]
+mod tests {
+    use super::*;
+    use crate::cipher::{Argon2Params, RandomSource};
+
+    fn draw(random: &SeededRandom) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        random.fill(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn stream_depends_on_password_and_content() {
+        let kdf = KdfParams::Argon2id(Argon2Params { memory: 8, iterations: 1, parallelism: 1 });
+        let first = Convergent::new(&Secret::new(b"password".to_vec()), &kdf).unwrap();
+        let again = Convergent::new(&Secret::new(b"password".to_vec()), &kdf).unwrap();
+        let other = Convergent::new(&Secret::new(b"different".to_vec()), &kdf).unwrap();
+
+        assert_eq!(draw(&first.random(b"content").unwrap()), draw(&again.random(b"content").unwrap()));
+        assert_ne!(draw(&first.random(b"content").unwrap()), draw(&first.random(b"changed").unwrap()));
+        assert_ne!(draw(&first.random(b"content").unwrap()), draw(&other.random(b"content").unwrap()));
+    }
+}
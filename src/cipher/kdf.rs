@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+#[cfg(feature = "cli")]
+use serde::Deserialize;
+
+use crate::config::{ARGON2_M_COST, ARGON2_P_COST, ARGON2_T_COST, MAX_KDF_MEMORY, SCRYPT_BLOCK_SIZE, SCRYPT_LOG_N, SCRYPT_P_COST};
+use crate::validation::{KdfIterations, KdfMemory, KdfParallelism, ScryptBlockSize, ScryptLogN};
+
+/// A password-stretching function together with its cost parameters.
+pub(crate) trait Kdf {
+    /// Checks the parameters against what a header may record, so nothing is written that can't be
+    /// read back.
+    fn validate(&self) -> Result<()>;
+
+    /// Fills `output` from `password` and `salt`.
+    fn stretch(&self, password: &[u8], salt: &[u8], output: &mut [u8]) -> Result<()>;
+}
+
+/// Which function stretches the password, chosen with `--kdf` or `[kdf] algorithm`.
+#[cfg(feature = "cli")]
+#[derive(Clone, Copy, Default, Eq, PartialEq, Deserialize, strum::EnumString, strum::Display)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub(crate) enum KdfAlgorithm {
+    #[default]
+    Argon2id,
+    Scrypt,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(crate) struct Argon2Params {
+    /// KiB of memory.
+    pub(crate) memory: u32,
+    pub(crate) iterations: u32,
+    pub(crate) parallelism: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self { memory: ARGON2_M_COST, iterations: ARGON2_T_COST, parallelism: ARGON2_P_COST }
+    }
+}
+
+impl Kdf for Argon2Params {
+    fn validate(&self) -> Result<()> {
+        KdfMemory::try_new(self.memory).context("invalid kdf memory cost")?;
+        KdfIterations::try_new(self.iterations).context("invalid kdf iteration count")?;
+        KdfParallelism::try_new(self.parallelism).context("invalid kdf parallelism")?;
+
+        Ok(())
+    }
+
+    fn stretch(&self, password: &[u8], salt: &[u8], output: &mut [u8]) -> Result<()> {
+        let params = Params::new(self.memory, self.iterations, self.parallelism, Some(output.len())).context("invalid argon2 parameters")?;
+
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password, salt, output)
+            .context("failed to stretch key with argon2")
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(crate) struct ScryptParams {
+    /// Log2 of the CPU/memory cost `N`.
+    pub(crate) log_n: u8,
+    /// The block size `r`; memory use is 128 * r * N bytes.
+    pub(crate) block_size: u32,
+    pub(crate) parallelism: u32,
+}
+
+impl ScryptParams {
+    /// KiB of memory one lane takes, saturating for costs far past the limit.
+    fn memory(&self) -> u64 {
+        let blocks = 1u64.checked_shl(u32::from(self.log_n)).unwrap_or(u64::MAX);
+
+        blocks.saturating_mul(u64::from(self.block_size)).saturating_mul(128).saturating_div(1024)
+    }
+}
+
+impl Default for ScryptParams {
+    fn default() -> Self {
+        Self { log_n: SCRYPT_LOG_N, block_size: SCRYPT_BLOCK_SIZE, parallelism: SCRYPT_P_COST }
+    }
+}
+
+impl Kdf for ScryptParams {
+    fn validate(&self) -> Result<()> {
+        ScryptLogN::try_new(self.log_n).context("invalid scrypt cost")?;
+        ScryptBlockSize::try_new(self.block_size).context("invalid scrypt block size")?;
+        KdfParallelism::try_new(self.parallelism).context("invalid kdf parallelism")?;
+        if self.memory() > u64::from(MAX_KDF_MEMORY) {
+            anyhow::bail!("scrypt parameters need {} KiB of memory, more than the {MAX_KDF_MEMORY} allowed", self.memory());
+        }
+
+        Ok(())
+    }
+
+    fn stretch(&self, password: &[u8], salt: &[u8], output: &mut [u8]) -> Result<()> {
+        let params = scrypt::Params::new(self.log_n, self.block_size, self.parallelism, output.len()).map_err(|error| anyhow::anyhow!("invalid scrypt parameters: {error}"))?;
+
+        scrypt::scrypt(password, salt, &params, output).map_err(|error| anyhow::anyhow!("failed to stretch key with scrypt: {error}"))
+    }
+}
+
+/// The function and costs a file's key-encryption key was stretched with, as recorded in its
+/// header.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(crate) enum KdfParams {
+    Argon2id(Argon2Params),
+    Scrypt(ScryptParams),
+}
+
+impl KdfParams {
+    /// KiB of memory stretching a password takes, one lane's worth for scrypt.
+    #[cfg(feature = "cli")]
+    pub(crate) fn memory(&self) -> u64 {
+        match self {
+            Self::Argon2id(params) => u64::from(params.memory),
+            Self::Scrypt(params) => params.memory(),
+        }
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self::Argon2id(Argon2Params::default())
+    }
+}
+
+impl Kdf for KdfParams {
+    fn validate(&self) -> Result<()> {
+        match self {
+            Self::Argon2id(params) => params.validate(),
+            Self::Scrypt(params) => params.validate(),
+        }
+    }
+
+    fn stretch(&self, password: &[u8], salt: &[u8], output: &mut [u8]) -> Result<()> {
+        match self {
+            Self::Argon2id(params) => params.stretch(password, salt, output),
+            Self::Scrypt(params) => params.stretch(password, salt, output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrypt_costs_are_bounded() {
+        ScryptParams::default().validate().unwrap();
+        assert!(ScryptParams { log_n: 30, ..ScryptParams::default() }.validate().is_err());
+        assert!(ScryptParams { log_n: 24, block_size: 32, parallelism: 1 }.validate().is_err());
+        assert!(ScryptParams { block_size: 0, ..ScryptParams::default() }.validate().is_err());
+    }
+}
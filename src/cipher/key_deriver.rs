@@ -1,11 +1,14 @@
 use anyhow::{Context, Result};
-use argon2::{Algorithm, Argon2, Params, Version};
 use hkdf::Hkdf;
-use rand::TryRng;
-use rand::rngs::SysRng;
 use sha2::Sha256;
 
-use crate::config::{ARGON2_KEY_LEN, ARGON2_M_COST, ARGON2_P_COST, ARGON2_T_COST, KDF_INFO, KEY_LEN};
+#[cfg(feature = "cli")]
+use super::kdf::Argon2Params;
+use super::kdf::{Kdf, KdfParams};
+use super::rng::RandomSource;
+use crate::config::{ARGON2_KEY_LEN, KDF_INFO, KEK_INFO, KEY_LEN, METADATA_KEY_INFO, PAYLOAD_MAC_INFO, SESSION_INFO};
+#[cfg(feature = "cli")]
+use crate::config::{CHUNK_INDEX_INFO, CONVERGENT_INFO, CONVERGENT_SALT, HIDDEN_INFO};
 use crate::secret::Secret;
 use crate::validation::NonEmptyKey;
 
@@ -13,6 +16,10 @@ pub(crate) struct DerivedKeys {
     pub(crate) primary_key: Secret,
     pub(crate) secondary_key: Secret,
     pub(crate) signer_key: Secret,
+    pub(crate) payload_key: Secret,
+    pub(crate) metadata_key: Secret,
+    #[cfg(feature = "cli")]
+    pub(crate) index_key: Secret,
 }
 
 pub(crate) struct KeyDeriver {
@@ -26,32 +33,119 @@ impl KeyDeriver {
         Ok(Self { key: key.into_secret() })
     }
 
-    pub(crate) fn derive_keys(&self, salt: &Secret) -> Result<DerivedKeys> {
-        let params = Params::new(ARGON2_M_COST, ARGON2_T_COST, ARGON2_P_COST, Some(ARGON2_KEY_LEN)).context("invalid argon2 parameters")?;
-        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+    pub(crate) fn derive_kek(&self, salt: &Secret, kdf: &KdfParams) -> Result<Secret> {
+        self.stretch(salt.expose_secret(), kdf, KEK_INFO).context("failed to derive key-encryption key")
+    }
+
+    /// Key for convergent encryption. It is stretched under a fixed salt so that it is the same for
+    /// every file, and still costs a full Argon2id run per password guess.
+    #[cfg(feature = "cli")]
+    pub(crate) fn derive_convergent_key(&self, kdf: &KdfParams) -> Result<Secret> {
+        self.stretch(CONVERGENT_SALT, kdf, CONVERGENT_INFO).context("failed to derive convergent key")
+    }
+
+    /// Key shared by every file encrypted in one `session-kdf` run, stretched once under the
+    /// session salt. Each file's key-encryption key is expanded from it with
+    /// [`Self::session_kek`].
+    pub(crate) fn derive_session_key(&self, session_salt: &[u8], kdf: &KdfParams) -> Result<Secret> {
+        self.stretch(session_salt, kdf, SESSION_INFO).context("failed to derive session key")
+    }
+
+    /// Key-encryption key for one file of a session, bound to the file's own salt so no two files
+    /// share one.
+    pub(crate) fn session_kek(session_key: &Secret, salt: &Secret) -> Result<Secret> {
+        let hkdf = Hkdf::<Sha256>::new(Some(salt.expose_secret()), session_key.expose_secret());
+        let mut kek = vec![0u8; KEY_LEN];
+        hkdf.expand(KEK_INFO, &mut kek).context("failed to expand session key")?;
 
+        Ok(Secret::new(kek))
+    }
+
+    /// Key for a payload hidden in a file's padding, kept apart from the key slots its password
+    /// would otherwise open.
+    #[cfg(feature = "cli")]
+    pub(crate) fn hidden_key(kek: &Secret, salt: &Secret) -> Result<Secret> {
+        let hkdf = Hkdf::<Sha256>::new(Some(salt.expose_secret()), kek.expose_secret());
+        let mut key = vec![0u8; KEY_LEN];
+        hkdf.expand(HIDDEN_INFO, &mut key).context("failed to expand hidden payload key")?;
+
+        Ok(Secret::new(key))
+    }
+
+    /// Keys for a file written by format 0x0002, which had no key slots: the password itself was
+    /// stretched under the default Argon2id costs, and the subkeys expanded from that.
+    #[cfg(feature = "cli")]
+    pub(crate) fn derive_legacy(&self, salt: &Secret) -> Result<DerivedKeys> {
         let mut stretched = vec![0u8; ARGON2_KEY_LEN];
-        argon2
-            .hash_password_into(self.key.expose_secret(), salt.expose_secret(), &mut stretched)
-            .context("failed to stretch key with argon2")?;
+        Argon2Params::default()
+            .stretch(self.key.expose_secret(), salt.expose_secret(), &mut stretched)
+            .context("failed to derive legacy keys")?;
 
-        let hkdf = Hkdf::<Sha256>::new(Some(salt.expose_secret()), &stretched);
+        Self::expand(&Secret::new(stretched), salt)
+    }
+
+    fn stretch(&self, salt: &[u8], kdf: &KdfParams, info: &[u8]) -> Result<Secret> {
+        let mut stretched = vec![0u8; ARGON2_KEY_LEN];
+        kdf.stretch(self.key.expose_secret(), salt, &mut stretched)?;
+
+        let hkdf = Hkdf::<Sha256>::new(Some(salt), &stretched);
+        let mut key = vec![0u8; KEY_LEN];
+        hkdf.expand(info, &mut key).context("failed to expand stretched key")?;
+
+        Ok(Secret::new(key))
+    }
+
+    pub(crate) fn expand(master_key: &Secret, salt: &Secret) -> Result<DerivedKeys> {
+        let hkdf = Hkdf::<Sha256>::new(Some(salt.expose_secret()), master_key.expose_secret());
         let mut primary_key = vec![0u8; KEY_LEN];
         let mut secondary_key = vec![0u8; KEY_LEN];
         let mut signer_key = vec![0u8; KEY_LEN];
+        let mut payload_key = vec![0u8; KEY_LEN];
+        let mut metadata_key = vec![0u8; KEY_LEN];
+        #[cfg(feature = "cli")]
+        let mut index_key = vec![0u8; KEY_LEN];
 
         hkdf.expand(&KDF_INFO[0], &mut primary_key).context("failed to expand primary key")?;
         hkdf.expand(&KDF_INFO[1], &mut secondary_key).context("failed to expand secondary key")?;
         hkdf.expand(&KDF_INFO[2], &mut signer_key).context("failed to expand signer key")?;
+        hkdf.expand(PAYLOAD_MAC_INFO, &mut payload_key).context("failed to expand payload key")?;
+        hkdf.expand(METADATA_KEY_INFO, &mut metadata_key).context("failed to expand metadata key")?;
+        #[cfg(feature = "cli")]
+        hkdf.expand(CHUNK_INDEX_INFO, &mut index_key).context("failed to expand chunk index key")?;
 
-        Ok(DerivedKeys { primary_key: Secret::new(primary_key), secondary_key: Secret::new(secondary_key), signer_key: Secret::new(signer_key) })
+        Ok(DerivedKeys {
+            primary_key: Secret::new(primary_key),
+            secondary_key: Secret::new(secondary_key),
+            signer_key: Secret::new(signer_key),
+            payload_key: Secret::new(payload_key),
+            metadata_key: Secret::new(metadata_key),
+            #[cfg(feature = "cli")]
+            index_key: Secret::new(index_key),
+        })
     }
 
-    pub(crate) fn generate_salt(salt_size: usize) -> Result<Secret> {
+    pub(crate) fn generate_salt(rng: &dyn RandomSource, salt_size: usize) -> Result<Secret> {
         let mut salt_bytes = vec![0u8; salt_size];
 
-        SysRng.try_fill_bytes(&mut salt_bytes).context("failed to generate salt")?;
+        rng.fill(&mut salt_bytes).context("failed to generate salt")?;
 
         Ok(Secret::new(salt_bytes))
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "cli")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_subkey_has_its_own_label() {
+        let keys = KeyDeriver::expand(&Secret::new(vec![7u8; KEY_LEN]), &Secret::new(vec![1u8; 32])).unwrap();
+        let subkeys = [&keys.primary_key, &keys.secondary_key, &keys.signer_key, &keys.payload_key, &keys.metadata_key, &keys.index_key];
+
+        for (index, key) in subkeys.iter().enumerate() {
+            assert_eq!(key.expose_secret().len(), KEY_LEN);
+            assert!(subkeys.iter().skip(index + 1).all(|other| other.expose_secret() != key.expose_secret()));
+        }
+    }
+}
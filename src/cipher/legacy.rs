@@ -0,0 +1,39 @@
+use aead::{Aead, AeadCore, KeyInit, Nonce};
+use aes_gcm::Aes256Gcm;
+use anyhow::{Context, Result};
+use chacha20poly1305::XChaCha20Poly1305;
+use hybrid_array::typenum::Unsigned;
+
+use crate::secret::Secret;
+use crate::secure_temp::SecureBuffer;
+use crate::validation::KeyBytes;
+
+/// Opens chunks written by format 0x0002: XChaCha20-Poly1305 around AES-256-GCM, each layer with a
+/// random nonce in front of its ciphertext rather than one derived from the chunk's position.
+pub(crate) struct LegacyCipher {
+    primary_key: Secret,
+    secondary_key: Secret,
+}
+
+impl LegacyCipher {
+    pub(crate) fn new(primary_key: &Secret, secondary_key: &Secret) -> Result<Self> {
+        let primary_key = KeyBytes::try_new(primary_key.expose_secret().to_vec()).context("primary key must be 32 bytes")?;
+        let secondary_key = KeyBytes::try_new(secondary_key.expose_secret().to_vec()).context("secondary key must be 32 bytes")?;
+
+        Ok(Self { primary_key: primary_key.into_secret(), secondary_key: secondary_key.into_secret() })
+    }
+
+    pub(crate) fn open(&self, sealed: &[u8]) -> Result<SecureBuffer> {
+        let inner = SecureBuffer::unlocked(open_layer::<XChaCha20Poly1305>(&self.secondary_key, sealed)?);
+
+        Ok(SecureBuffer::locked(open_layer::<Aes256Gcm>(&self.primary_key, &inner)?))
+    }
+}
+
+fn open_layer<Cipher: Aead + KeyInit>(key: &Secret, sealed: &[u8]) -> Result<Vec<u8>> {
+    let (nonce, ciphertext) = sealed.split_at_checked(<Cipher as AeadCore>::NonceSize::USIZE).context("ciphertext shorter than nonce")?;
+    let nonce = Nonce::<Cipher>::try_from(nonce).context("invalid nonce")?;
+    let cipher = Cipher::new_from_slice(key.expose_secret()).context("failed to setup key")?;
+
+    cipher.decrypt(&nonce, ciphertext).context("failed to decrypt")
+}
@@ -1,14 +1,52 @@
 mod aead;
+mod convergent;
+mod kdf;
 mod key_deriver;
+#[cfg(feature = "cli")]
+mod legacy;
+mod nonce;
+mod rng;
+mod sealed;
+#[cfg(feature = "cli")]
+mod second_factor;
+#[cfg(feature = "cli")]
+mod session;
 mod signer;
+mod slots;
+#[cfg(feature = "cli")]
+pub(crate) mod sss;
 
 use aead::AeadCipher;
 use aes_gcm::Aes256Gcm;
 use anyhow::{Context, Result};
 use chacha20poly1305::XChaCha20Poly1305;
-pub(crate) use key_deriver::KeyDeriver;
+#[cfg(feature = "cli")]
+pub(crate) use convergent::Convergent;
+pub(crate) use convergent::section_random;
+use hybrid_array::typenum::Unsigned;
+#[cfg(feature = "cli")]
+pub(crate) use kdf::KdfAlgorithm;
+pub(crate) use kdf::{Argon2Params, Kdf, KdfParams, ScryptParams};
+pub(crate) use key_deriver::{DerivedKeys, KeyDeriver};
+#[cfg(feature = "cli")]
+pub(crate) use legacy::LegacyCipher;
+#[cfg(any(feature = "cli", test))]
+use nonce::NonceSequence;
+#[cfg(any(feature = "cli", test))]
+pub(crate) use rng::SeededRandom;
+pub(crate) use rng::{RandomSource, SystemRandom};
+pub(crate) use sealed::{open, seal};
+#[cfg(feature = "cli")]
+pub(crate) use second_factor::SecondFactor;
+use serde::Deserialize;
+#[cfg(feature = "cli")]
+pub(crate) use session::KdfSession;
 pub(crate) use signer::Signer;
+pub(crate) use slots::KeySlot;
+#[cfg(feature = "cli")]
+pub(crate) use sss::{Share, Threshold};
 
+use crate::config::{ALGORITHM_AES, ALGORITHM_CHACHA};
 use crate::secret::Secret;
 use crate::validation::KeyBytes;
 
@@ -17,34 +55,126 @@ pub(crate) enum Algorithm {
     ChaCha20Poly1305,
 }
 
+impl Algorithm {
+    fn nonce_len(&self) -> usize {
+        match self {
+            Self::Aes256Gcm => <Aes256Gcm as ::aead::AeadCore>::NonceSize::USIZE,
+            Self::ChaCha20Poly1305 => <XChaCha20Poly1305 as ::aead::AeadCore>::NonceSize::USIZE,
+        }
+    }
+
+    fn tag_len(&self) -> usize {
+        match self {
+            Self::Aes256Gcm => <Aes256Gcm as ::aead::AeadCore>::TagSize::USIZE,
+            Self::ChaCha20Poly1305 => <XChaCha20Poly1305 as ::aead::AeadCore>::TagSize::USIZE,
+        }
+    }
+}
+
+/// What can be told about a sealed chunk without its keys.
+#[cfg(any(feature = "cli", test))]
+pub(crate) struct Framing {
+    /// The leading nonce is the one the outer layer derives for this chunk's position.
+    pub(crate) nonce_matches: bool,
+    /// The chunk is long enough to hold a nonce and tag for every layer.
+    pub(crate) tags_present: bool,
+}
+
+/// Which AEAD layers seal each chunk: AES-256-GCM, XChaCha20-Poly1305, or one inside the other.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Deserialize, strum::EnumString, strum::Display)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum CipherMode {
+    Aes,
+    Chacha,
+    #[default]
+    Dual,
+}
+
+impl CipherMode {
+    pub(crate) fn bits(self) -> u8 {
+        match self {
+            Self::Aes => ALGORITHM_AES,
+            Self::Chacha => ALGORITHM_CHACHA,
+            Self::Dual => ALGORITHM_AES | ALGORITHM_CHACHA,
+        }
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Result<Self> {
+        match bits {
+            ALGORITHM_AES => Ok(Self::Aes),
+            ALGORITHM_CHACHA => Ok(Self::Chacha),
+            bits if bits == ALGORITHM_AES | ALGORITHM_CHACHA => Ok(Self::Dual),
+            other => anyhow::bail!("unsupported algorithm flags: {other:#04x}"),
+        }
+    }
+
+    pub(crate) fn uses(self, algorithm: &Algorithm) -> bool {
+        let flag = match algorithm {
+            Algorithm::Aes256Gcm => ALGORITHM_AES,
+            Algorithm::ChaCha20Poly1305 => ALGORITHM_CHACHA,
+        };
+
+        self.bits() & flag != 0
+    }
+
+    /// Bytes sealing adds to a chunk: a nonce and a tag for every layer.
+    pub(crate) fn overhead(self) -> usize {
+        let layers = [Algorithm::Aes256Gcm, Algorithm::ChaCha20Poly1305];
+        layers.iter().filter(|layer| self.uses(layer)).map(|layer| layer.nonce_len().saturating_add(layer.tag_len())).sum()
+    }
+
+    /// Bytes of nonce ahead of a sealed chunk, which sealing in place needs left free in front of
+    /// the plaintext.
+    pub(crate) fn nonce_room(self) -> usize {
+        let layers = [Algorithm::Aes256Gcm, Algorithm::ChaCha20Poly1305];
+        layers.iter().filter(|layer| self.uses(layer)).map(Algorithm::nonce_len).sum()
+    }
+
+    #[cfg(any(feature = "cli", test))]
+    pub(crate) fn framing(self, sealed: &[u8], nonce_prefix: &[u8], index: u64) -> Result<Framing> {
+        let overhead = self.overhead();
+        let outer = if self.uses(&Algorithm::ChaCha20Poly1305) { Algorithm::ChaCha20Poly1305 } else { Algorithm::Aes256Gcm };
+
+        let mut expected = vec![0u8; outer.nonce_len()];
+        NonceSequence::new(nonce_prefix, outer.nonce_len())?.fill(index, &mut expected)?;
+
+        Ok(Framing { nonce_matches: sealed.get(..expected.len()) == Some(expected.as_slice()), tags_present: sealed.len() > overhead })
+    }
+}
+
 pub(crate) struct Cipher {
     primary_cipher: AeadCipher<Aes256Gcm>,
     secondary_cipher: AeadCipher<XChaCha20Poly1305>,
 }
 
 impl Cipher {
-    pub(crate) fn new(primary_key: &Secret, secondary_key: &Secret) -> Result<Self> {
+    pub(crate) fn new(primary_key: &Secret, secondary_key: &Secret, nonce_prefix: &[u8]) -> Result<Self> {
         let primary_key = KeyBytes::try_new(primary_key.expose_secret().to_vec()).context("primary key must be 32 bytes")?;
         let secondary_key = KeyBytes::try_new(secondary_key.expose_secret().to_vec()).context("secondary key must be 32 bytes")?;
-        let primary_cipher = AeadCipher::<Aes256Gcm>::new(&primary_key.into_secret()).context("failed to initialize primary cipher")?;
-        let secondary_cipher = AeadCipher::<XChaCha20Poly1305>::new(&secondary_key.into_secret()).context("failed to initialize secondary cipher")?;
+        let primary_cipher = AeadCipher::<Aes256Gcm>::new(&primary_key.into_secret(), nonce_prefix).context("failed to initialize primary cipher")?;
+        let secondary_cipher = AeadCipher::<XChaCha20Poly1305>::new(&secondary_key.into_secret(), nonce_prefix).context("failed to initialize secondary cipher")?;
 
         Ok(Self { primary_cipher, secondary_cipher })
     }
 
+    /// Seals `buffer[start..]` in place under one layer, writing its nonce into the free bytes just
+    /// before `start`; returns where the sealed layer begins.
     #[inline]
-    pub(crate) fn encrypt(&self, algorithm: &Algorithm, plaintext: &[u8]) -> Result<Vec<u8>> {
+    pub(crate) fn encrypt_in_place(&self, algorithm: &Algorithm, buffer: &mut Vec<u8>, start: usize, index: u64) -> Result<usize> {
         match algorithm {
-            Algorithm::Aes256Gcm => self.primary_cipher.encrypt(plaintext),
-            Algorithm::ChaCha20Poly1305 => self.secondary_cipher.encrypt(plaintext),
+            Algorithm::Aes256Gcm => self.primary_cipher.encrypt_in_place(buffer, start, index),
+            Algorithm::ChaCha20Poly1305 => self.secondary_cipher.encrypt_in_place(buffer, start, index),
         }
     }
 
+    /// Opens the layer sealed at `buffer[start..]` in place; returns where its plaintext begins.
     #[inline]
-    pub(crate) fn decrypt(&self, algorithm: &Algorithm, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    pub(crate) fn decrypt_in_place(&self, algorithm: &Algorithm, buffer: &mut Vec<u8>, start: usize, index: u64) -> Result<usize> {
         match algorithm {
-            Algorithm::Aes256Gcm => self.primary_cipher.decrypt(ciphertext),
-            Algorithm::ChaCha20Poly1305 => self.secondary_cipher.decrypt(ciphertext),
+            Algorithm::Aes256Gcm => self.primary_cipher.decrypt_in_place(buffer, start, index),
+            Algorithm::ChaCha20Poly1305 => self.secondary_cipher.decrypt_in_place(buffer, start, index),
         }
     }
 }
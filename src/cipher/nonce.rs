@@ -0,0 +1,29 @@
+use anyhow::{Context, Result};
+
+const COUNTER_LEN: usize = 8;
+
+pub(super) struct NonceSequence {
+    prefix: Vec<u8>,
+}
+
+impl NonceSequence {
+    pub(super) fn new(prefix: &[u8], nonce_len: usize) -> Result<Self> {
+        let prefix_len = nonce_len.checked_sub(COUNTER_LEN).context("nonce too short for chunk counter")?;
+        let prefix = prefix.get(..prefix_len).context("nonce prefix too short")?.to_vec();
+
+        Ok(Self { prefix })
+    }
+
+    #[inline]
+    pub(super) fn fill(&self, index: u64, dest: &mut [u8]) -> Result<()> {
+        let (head, tail) = dest.split_at_mut_checked(self.prefix.len()).context("nonce shorter than prefix")?;
+        if tail.len() != COUNTER_LEN {
+            anyhow::bail!("invalid nonce length");
+        }
+
+        head.copy_from_slice(&self.prefix);
+        tail.copy_from_slice(&index.to_be_bytes());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::{Context, Result};
+use blake3::{Hasher, OutputReader};
+use rand::TryRng;
+use rand::rngs::SysRng;
+
+use crate::secret::Secret;
+
+#[cfg(any(feature = "cli", test))]
+const SEED_CONTEXT: &str = "sweetbyte/v1/deterministic-rng";
+
+pub(crate) trait RandomSource: Send + Sync {
+    fn fill(&self, dest: &mut [u8]) -> Result<()>;
+}
+
+pub(crate) struct SystemRandom;
+
+impl RandomSource for SystemRandom {
+    fn fill(&self, dest: &mut [u8]) -> Result<()> {
+        SysRng.try_fill_bytes(dest).context("failed to read system randomness")
+    }
+}
+
+pub(crate) struct SeededRandom {
+    stream: Mutex<OutputReader>,
+}
+
+impl SeededRandom {
+    #[cfg(any(feature = "cli", test))]
+    pub(crate) fn new(seed: u64) -> Self {
+        let stream = Hasher::new_derive_key(SEED_CONTEXT).update(&seed.to_le_bytes()).finalize_xof();
+
+        Self { stream: Mutex::new(stream) }
+    }
+
+    /// Stream keyed by a 32-byte secret and bound to `input`; the same key and input always give
+    /// the same bytes.
+    pub(crate) fn keyed(key: &Secret, input: &[u8]) -> Result<Self> {
+        let key: &[u8; 32] = key.expose_secret().try_into().context("stream key must be 32 bytes")?;
+        let stream = Hasher::new_keyed(key).update(input).finalize_xof();
+
+        Ok(Self { stream: Mutex::new(stream) })
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn fill(&self, dest: &mut [u8]) -> Result<()> {
+        self.stream.lock().unwrap_or_else(PoisonError::into_inner).fill(dest);
+
+        Ok(())
+    }
+}
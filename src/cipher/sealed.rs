@@ -0,0 +1,44 @@
+use aead::{Aead, KeyInit, Nonce};
+use anyhow::{Context, Result};
+use chacha20poly1305::XChaCha20Poly1305;
+
+use super::rng::RandomSource;
+use crate::config::SLOT_NONCE_LEN;
+use crate::secret::Secret;
+
+/// Encrypts a header section with a fresh random nonce, returned in front of the ciphertext.
+pub(crate) fn seal(key: &Secret, plaintext: &[u8], rng: &dyn RandomSource) -> Result<Vec<u8>> {
+    let mut nonce = Nonce::<XChaCha20Poly1305>::default();
+    rng.fill(&mut nonce).context("failed to generate section nonce")?;
+
+    let cipher = XChaCha20Poly1305::new_from_slice(key.expose_secret()).context("failed to setup key")?;
+    let ciphertext = cipher.encrypt(&nonce, plaintext).context("failed to seal section")?;
+
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+pub(crate) fn open(key: &Secret, sealed: &[u8]) -> Result<Secret> {
+    let (nonce, ciphertext) = sealed.split_at_checked(SLOT_NONCE_LEN).context("sealed section is truncated")?;
+    let nonce = Nonce::<XChaCha20Poly1305>::try_from(nonce).context("invalid section nonce")?;
+
+    let cipher = XChaCha20Poly1305::new_from_slice(key.expose_secret()).context("failed to setup key")?;
+    let plaintext = cipher.decrypt(&nonce, ciphertext).context("failed to open sealed section")?;
+
+    Ok(Secret::new(plaintext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::SeededRandom;
+
+    #[test]
+    fn sealed_section_roundtrips_and_rejects_wrong_key() {
+        let key = Secret::new(vec![3u8; 32]);
+        let sealed = seal(&key, b"report.pdf", &SeededRandom::new(5)).unwrap();
+
+        assert_eq!(open(&key, &sealed).unwrap().expose_secret(), b"report.pdf");
+        assert!(open(&Secret::new(vec![4u8; 32]), &sealed).is_err());
+        assert!(open(&key, &sealed[..SLOT_NONCE_LEN]).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use hkdf::Hkdf;
+use hmac::{Hmac, KeyInit, Mac};
+use sha1::Sha1;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+use crate::config::{KEY_LEN, MIN_TOTP_SECRET_LEN, SECOND_FACTOR_INFO, TOTP_DIGITS, TOTP_SKEW, TOTP_STEP};
+use crate::secret::Secret;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A TOTP secret as an authenticator app holds it (RFC 6238 with HMAC-SHA1, 30-second steps, 6
+/// digits). Key slots of a file written with one are wrapped under a key-encryption key bound to
+/// the secret as well as the password, so unlocking needs both. A code can't be checked without the
+/// secret, so the code only confirms that the secret given is the one the authenticator holds; the
+/// protection comes from keeping the secret apart from the password.
+pub(crate) struct SecondFactor {
+    secret: Secret,
+}
+
+impl SecondFactor {
+    /// Reads a base32 secret as authenticator apps show it; case, spaces, dashes, and padding are
+    /// ignored.
+    pub(crate) fn from_base32(encoded: &str) -> Result<Self> {
+        let secret = decode_base32(encoded).context("TOTP secret is not valid base32")?;
+        if secret.len() < MIN_TOTP_SECRET_LEN {
+            anyhow::bail!("TOTP secret must be at least {MIN_TOTP_SECRET_LEN} bytes");
+        }
+
+        Ok(Self { secret: Secret::new(secret) })
+    }
+
+    /// The code shown at `unix_time`.
+    pub(crate) fn code_at(&self, unix_time: u64) -> Result<String> {
+        let counter = unix_time.saturating_div(TOTP_STEP);
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.secret.expose_secret()).context("invalid TOTP secret")?;
+        mac.update(&counter.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let offset = usize::from(digest.last().context("empty TOTP digest")? & 0x0f);
+        let bytes: [u8; 4] = digest.get(offset..offset.saturating_add(4)).and_then(|bytes| bytes.try_into().ok()).context("short TOTP digest")?;
+        let modulus = 10u32.checked_pow(TOTP_DIGITS).context("too many TOTP digits")?;
+        let code = (u32::from_be_bytes(bytes) & 0x7fff_ffff).checked_rem(modulus).context("invalid TOTP modulus")?;
+
+        Ok(format!("{code:0width$}", width = usize::try_from(TOTP_DIGITS).context("too many TOTP digits")?))
+    }
+
+    /// Whether `code` is the one shown at `unix_time` or up to [`TOTP_SKEW`] steps either side of
+    /// it.
+    pub(crate) fn verify(&self, code: &str, unix_time: u64) -> Result<bool> {
+        let skew = TOTP_SKEW.saturating_mul(TOTP_STEP);
+        let mut matched = false;
+        for time in [unix_time.saturating_sub(skew), unix_time, unix_time.saturating_add(skew)] {
+            matched |= bool::from(self.code_at(time)?.as_bytes().ct_eq(code.trim().as_bytes()));
+        }
+
+        Ok(matched)
+    }
+
+    /// Whether `code` is valid right now.
+    pub(crate) fn verify_now(&self, code: &str) -> Result<bool> {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).context("system clock is before 1970")?;
+
+        self.verify(code, now.as_secs())
+    }
+
+    /// Binds the password's key-encryption key to the secret, under the file's `salt`.
+    pub(crate) fn bind(&self, kek: &Secret, salt: &Secret) -> Result<Secret> {
+        let mut input = Zeroizing::new(Vec::with_capacity(kek.expose_secret().len().saturating_add(self.secret.expose_secret().len())));
+        input.extend_from_slice(kek.expose_secret());
+        input.extend_from_slice(self.secret.expose_secret());
+
+        let hkdf = Hkdf::<Sha256>::new(Some(salt.expose_secret()), &input);
+        let mut key = vec![0u8; KEY_LEN];
+        hkdf.expand(SECOND_FACTOR_INFO, &mut key).context("failed to expand second factor key")?;
+
+        Ok(Secret::new(key))
+    }
+}
+
+fn decode_base32(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0u32;
+    for symbol in encoded.bytes().filter(|byte| !byte.is_ascii_whitespace() && !matches!(byte, b'=' | b'-')) {
+        let value = BASE32_ALPHABET.iter().position(|candidate| *candidate == symbol.to_ascii_uppercase())?;
+        buffer = (buffer.wrapping_shl(5) | u32::try_from(value).ok()?) & 0xffff;
+        bits = bits.saturating_add(5);
+        if bits >= 8 {
+            bits = bits.saturating_sub(8);
+            decoded.push(u8::try_from(buffer.wrapping_shr(bits) & 0xff).ok()?);
+        }
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA-1 secret from RFC 6238 appendix B, "12345678901234567890".
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn codes_match_the_rfc_vectors() {
+        let factor = SecondFactor::from_base32(RFC_SECRET).unwrap();
+        assert_eq!(factor.secret.expose_secret(), b"12345678901234567890");
+
+        assert_eq!(factor.code_at(59).unwrap(), "287082");
+        assert_eq!(factor.code_at(1_111_111_109).unwrap(), "081804");
+        assert_eq!(factor.code_at(1_234_567_890).unwrap(), "005924");
+    }
+
+    #[test]
+    fn codes_are_accepted_one_step_either_side() {
+        let factor = SecondFactor::from_base32(&RFC_SECRET.to_lowercase()).unwrap();
+        let code = factor.code_at(1_111_111_109).unwrap();
+
+        assert!(factor.verify(&code, 1_111_111_109 + 30).unwrap());
+        assert!(factor.verify(&code, 1_111_111_109 - 30).unwrap());
+        assert!(!factor.verify(&code, 1_111_111_109 + 90).unwrap());
+        assert!(SecondFactor::from_base32("GEZDGNBV").is_err());
+        assert!(SecondFactor::from_base32("not base32!").is_err());
+    }
+
+    #[test]
+    fn bound_keys_depend_on_the_secret() {
+        let kek = Secret::new(vec![3u8; KEY_LEN]);
+        let salt = Secret::new(vec![1u8; 32]);
+        let first = SecondFactor::from_base32(RFC_SECRET).unwrap().bind(&kek, &salt).unwrap();
+        let other = SecondFactor::from_base32("JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP").unwrap().bind(&kek, &salt).unwrap();
+
+        assert_ne!(first.expose_secret(), kek.expose_secret());
+        assert_ne!(first.expose_secret(), other.expose_secret());
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::Result;
+use subtle::ConstantTimeEq;
+
+use super::kdf::KdfParams;
+use super::key_deriver::KeyDeriver;
+use super::rng::RandomSource;
+use crate::config::ARGON2_SALT_LEN;
+use crate::secret::Secret;
+
+/// Session keys kept for the length of a batch, so Argon2id runs once per password instead of once
+/// per file. Files encrypted in the session share its salt and tell their keys apart by their own
+/// salt; files read in it may come from any earlier session, so keys are looked up by password,
+/// salt, and cost.
+pub(crate) struct KdfSession {
+    salt: Secret,
+    keys: Mutex<Vec<SessionKey>>,
+}
+
+struct SessionKey {
+    password: Secret,
+    salt: Vec<u8>,
+    kdf: KdfParams,
+    key: Secret,
+}
+
+impl KdfSession {
+    pub(crate) fn new(rng: &dyn RandomSource) -> Result<Self> {
+        let salt = KeyDeriver::generate_salt(rng, ARGON2_SALT_LEN)?;
+
+        Ok(Self { salt, keys: Mutex::new(Vec::new()) })
+    }
+
+    /// The salt files encrypted in this session record in their header.
+    pub(crate) fn salt(&self) -> &[u8] {
+        self.salt.expose_secret()
+    }
+
+    /// The session key already derived for `password` under `salt` and `kdf`, if any.
+    pub(crate) fn cached(&self, password: &Secret, salt: &[u8], kdf: &KdfParams) -> Option<Secret> {
+        let keys = self.keys.lock().unwrap_or_else(PoisonError::into_inner);
+        keys.iter()
+            .find(|entry| entry.salt == salt && entry.kdf == *kdf && bool::from(entry.password.expose_secret().ct_eq(password.expose_secret())))
+            .map(|entry| Secret::new(entry.key.expose_secret().to_vec()))
+    }
+
+    /// Drops every session key along with the password copies kept to look them up.
+    pub(crate) fn forget(&self) {
+        self.keys.lock().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+
+    pub(crate) fn remember(&self, password: &Secret, salt: &[u8], kdf: &KdfParams, key: &Secret) {
+        let entry = SessionKey { password: Secret::new(password.expose_secret().to_vec()), salt: salt.to_vec(), kdf: *kdf, key: Secret::new(key.expose_secret().to_vec()) };
+        self.keys.lock().unwrap_or_else(PoisonError::into_inner).push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::{Argon2Params, SeededRandom};
+
+    #[test]
+    fn keys_are_cached_per_password_salt_and_cost() {
+        let session = KdfSession::new(&SeededRandom::new(1)).unwrap();
+        let argon2 = Argon2Params { memory: 8, iterations: 1, parallelism: 1 };
+        let kdf = KdfParams::Argon2id(argon2);
+        let password = Secret::new(b"password".to_vec());
+        let key = KeyDeriver::new(&password).unwrap().derive_session_key(session.salt(), &kdf).unwrap();
+        session.remember(&password, session.salt(), &kdf, &key);
+
+        assert_eq!(session.cached(&password, session.salt(), &kdf).unwrap().expose_secret(), key.expose_secret());
+        assert!(session.cached(&Secret::new(b"different".to_vec()), session.salt(), &kdf).is_none());
+        assert!(session.cached(&password, &[0u8; ARGON2_SALT_LEN], &kdf).is_none());
+        assert!(session.cached(&password, session.salt(), &KdfParams::Argon2id(Argon2Params { iterations: 2, ..argon2 })).is_none());
+
+        let first = KeyDeriver::session_kek(&key, &Secret::new(vec![1u8; ARGON2_SALT_LEN])).unwrap();
+        let second = KeyDeriver::session_kek(&key, &Secret::new(vec![2u8; ARGON2_SALT_LEN])).unwrap();
+        assert_ne!(first.expose_secret(), second.expose_secret());
+    }
+}
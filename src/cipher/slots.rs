@@ -0,0 +1,33 @@
+use aead::{Aead, KeyInit, Nonce};
+use anyhow::{Context, Result};
+use chacha20poly1305::XChaCha20Poly1305;
+use serde::{Deserialize, Serialize};
+
+use super::rng::RandomSource;
+use crate::secret::Secret;
+use crate::validation::{SlotNonce, WrappedKey};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct KeySlot {
+    nonce: SlotNonce,
+    wrapped: WrappedKey,
+}
+
+impl KeySlot {
+    pub(crate) fn wrap(kek: &Secret, master_key: &Secret, rng: &dyn RandomSource) -> Result<Self> {
+        let mut nonce = Nonce::<XChaCha20Poly1305>::default();
+        rng.fill(&mut nonce).context("failed to generate slot nonce")?;
+
+        let cipher = XChaCha20Poly1305::new_from_slice(kek.expose_secret()).context("failed to setup key")?;
+        let wrapped = cipher.encrypt(&nonce, master_key.expose_secret()).context("failed to wrap master key")?;
+
+        Ok(Self { nonce: SlotNonce::try_new(nonce.to_vec()).context("invalid slot nonce")?, wrapped: WrappedKey::try_new(wrapped).context("invalid wrapped key")? })
+    }
+
+    pub(crate) fn unwrap(&self, kek: &Secret) -> Option<Secret> {
+        let cipher = XChaCha20Poly1305::new_from_slice(kek.expose_secret()).ok()?;
+        let nonce = Nonce::<XChaCha20Poly1305>::try_from(self.nonce.as_ref().as_slice()).ok()?;
+
+        cipher.decrypt(&nonce, self.wrapped.as_ref().as_slice()).ok().map(Secret::new)
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+use zeroize::Zeroizing;
+
+use super::rng::RandomSource;
+use crate::secret::Secret;
+
+const SHARE_PREFIX: &str = "sweetbyte-share";
+
+/// How many shares a key is split into and how many of them rebuild it, written `3-of-5`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Threshold {
+    needed: u8,
+    shares: u8,
+}
+
+impl Threshold {
+    pub(crate) fn new(needed: u8, shares: u8) -> Result<Self> {
+        if needed < 2 || needed > shares {
+            anyhow::bail!("a key split {needed}-of-{shares} needs at least 2 shares to rebuild it and no more than it makes");
+        }
+
+        Ok(Self { needed, shares })
+    }
+}
+
+/// One share of a key split with Shamir's scheme over GF(2^8). Any `threshold` shares of the same
+/// key rebuild it; fewer reveal nothing about it.
+pub(crate) struct Share {
+    threshold: u8,
+    index: u8,
+    value: Secret,
+}
+
+impl Share {
+    /// `sweetbyte-share-<threshold>-<index>-<hex>-<crc32>`, short enough to print, with a checksum
+    /// that catches typos.
+    pub(crate) fn encode(&self) -> String {
+        let hex: String = self.value.expose_secret().iter().map(|byte| format!("{byte:02x}")).collect();
+        let body = format!("{SHARE_PREFIX}-{}-{}-{hex}", self.threshold, self.index);
+
+        format!("{body}-{:08x}", crc32fast::hash(body.as_bytes()))
+    }
+
+    pub(crate) fn decode(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let (body, checksum) = text.rsplit_once('-').context("key share is cut short")?;
+        if u32::from_str_radix(checksum, 16).ok() != Some(crc32fast::hash(body.as_bytes())) {
+            anyhow::bail!("key share checksum does not match; check it for typos");
+        }
+
+        let fields = body.strip_prefix(SHARE_PREFIX).and_then(|rest| rest.strip_prefix('-')).context("not a sweetbyte key share")?;
+        let mut fields = fields.splitn(3, '-');
+        let mut field = || fields.next().context("key share is cut short");
+        let threshold = field()?.parse().context("invalid key share threshold")?;
+        let index = field()?.parse().context("invalid key share index")?;
+        let hex = field()?;
+
+        let value = (0..hex.len())
+            .step_by(2)
+            .map(|at| hex.get(at..at.saturating_add(2)).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .context("key share is not valid hex")?;
+        if index == 0 || value.is_empty() {
+            anyhow::bail!("key share is malformed");
+        }
+
+        Ok(Self { threshold, index, value: Secret::new(value) })
+    }
+}
+
+/// Splits `secret` into `threshold.shares` shares, `threshold.needed` of which rebuild it.
+pub(crate) fn split(secret: &Secret, threshold: Threshold, rng: &dyn RandomSource) -> Result<Vec<Share>> {
+    let len = secret.expose_secret().len();
+    let mut coefficients = Zeroizing::new(vec![0u8; len.saturating_mul(usize::from(threshold.needed.saturating_sub(1)))]);
+    rng.fill(&mut coefficients).context("failed to generate share coefficients")?;
+
+    let mut shares = Vec::with_capacity(usize::from(threshold.shares));
+    for index in 1..=threshold.shares {
+        let mut value = vec![0u8; len];
+        for (position, (out, byte)) in value.iter_mut().zip(secret.expose_secret()).enumerate() {
+            // Horner's rule over the polynomial whose constant term is the secret byte.
+            let higher = coefficients.iter().skip(position).step_by(len.max(1));
+            *out = higher.rev().fold(0u8, |acc, coefficient| mul(acc, index) ^ coefficient);
+            *out = mul(*out, index) ^ byte;
+        }
+        shares.push(Share { threshold: threshold.needed, index, value: Secret::new(value) });
+    }
+
+    Ok(shares)
+}
+
+/// Rebuilds the key from at least as many shares as it was split to need.
+pub(crate) fn combine(shares: &[Share]) -> Result<Secret> {
+    let first = shares.first().context("no key shares given")?;
+    let needed = usize::from(first.threshold);
+    if shares
+        .iter()
+        .any(|share| share.threshold != first.threshold || share.value.expose_secret().len() != first.value.expose_secret().len())
+    {
+        anyhow::bail!("key shares come from different splits");
+    }
+    let mut indices: Vec<u8> = shares.iter().map(|share| share.index).collect();
+    indices.sort_unstable();
+    indices.dedup();
+    if indices.len() != shares.len() {
+        anyhow::bail!("the same key share was given twice");
+    }
+    if shares.len() < needed {
+        anyhow::bail!("{} key share(s) given but {needed} are needed", shares.len());
+    }
+
+    let shares = shares.get(..needed).context("not enough key shares")?;
+    let mut secret = vec![0u8; first.value.expose_secret().len()];
+    for share in shares {
+        // The Lagrange basis polynomial for this share, evaluated at zero.
+        let basis = shares
+            .iter()
+            .filter(|other| other.index != share.index)
+            .fold(1u8, |acc, other| mul(acc, mul(other.index, inverse(other.index ^ share.index))));
+        for (out, byte) in secret.iter_mut().zip(share.value.expose_secret()) {
+            *out ^= mul(*byte, basis);
+        }
+    }
+
+    Ok(Secret::new(secret))
+}
+
+/// Multiplication in GF(2^8) modulo the AES polynomial, without branching on the operands.
+fn mul(a: u8, b: u8) -> u8 {
+    let (mut a, mut b, mut product) = (a, b, 0u8);
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = a.wrapping_shr(7).wrapping_neg();
+        a = a.wrapping_shl(1) ^ (0x1b & carry);
+        b = b.wrapping_shr(1);
+    }
+
+    product
+}
+
+/// `a^254`, which is `a`'s inverse for every `a` but zero.
+fn inverse(a: u8) -> u8 {
+    let (mut square, mut result) = (a, 1u8);
+    for _ in 1..8 {
+        square = mul(square, square);
+        result = mul(result, square);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::SeededRandom;
+
+    #[test]
+    fn field_inverses_multiply_to_one() {
+        assert_eq!(mul(0x53, 0xca), 1);
+        assert!((1..=255u8).all(|a| mul(a, inverse(a)) == 1));
+    }
+
+    #[test]
+    fn any_threshold_of_shares_rebuilds_the_key() {
+        let key = Secret::new((0..32).collect());
+        let shares = split(&key, Threshold::new(3, 5).unwrap(), &SeededRandom::new(9)).unwrap();
+        let decoded: Vec<Share> = shares.iter().map(|share| Share::decode(&share.encode()).unwrap()).collect();
+
+        for picked in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let subset: Vec<Share> = picked.iter().map(|&at| Share::decode(&decoded[at].encode()).unwrap()).collect();
+            assert_eq!(combine(&subset).unwrap().expose_secret(), key.expose_secret());
+        }
+        assert!(combine(&decoded[..2]).is_err());
+        assert!(shares.iter().all(|share| share.value.expose_secret() != key.expose_secret()));
+    }
+
+    #[test]
+    fn malformed_shares_are_rejected() {
+        let key = Secret::new(vec![7u8; 32]);
+        let share = split(&key, Threshold::new(2, 3).unwrap(), &SeededRandom::new(1)).unwrap().remove(0).encode();
+
+        let typo = share.replacen("-1-", "-2-", 1);
+        assert!(Share::decode(&typo).is_err());
+        assert!(Share::decode("sweetbyte-share-2-1").is_err());
+        Threshold::new(1, 3).unwrap_err();
+        Threshold::new(4, 3).unwrap_err();
+    }
+}
@@ -0,0 +1,529 @@
+use std::io::{IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::engine::{ArgValueCompleter, PathCompleter};
+use clap_complete::env::{Bash, EnvCompleter, Fish, Zsh};
+
+use crate::cipher::{CipherMode, KdfAlgorithm, Threshold};
+use crate::config::layer::{KdfLayer, Layer};
+use crate::config::{MAX_KEY_SLOTS, MIN_SPLIT_SIZE, Profile, Settings};
+use crate::files::{Files, FsyncPolicy};
+use crate::pipeline::PadSize;
+use crate::ui::OutputFormat;
+
+#[derive(Parser)]
+#[command(version, about)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    #[arg(long, global = true, value_enum, help = "Settings bundle for new files; explicit settings still win")]
+    profile: Option<Profile>,
+
+    #[arg(long, global = true, value_name = "N", help = "Limit worker and hashing threads (defaults to all cores)")]
+    threads: Option<NonZeroUsize>,
+
+    #[arg(long, global = true, value_name = "PATH", help = "Config file (defaults to ~/.config/sweetbyte/config.toml)")]
+    config: Option<PathBuf>,
+
+    #[arg(long, global = true, value_enum, help = "Password KDF for new files: argon2id (default) or scrypt")]
+    kdf: Option<KdfAlgorithm>,
+
+    #[arg(long, global = true, value_enum, help = "When to fsync output: after every chunk, once at the end (default), or never")]
+    fsync: Option<FsyncPolicy>,
+
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_reorder_limit, help = "Finished chunks to hold while waiting for a slow one, e.g. 64MiB (default)")]
+    reorder_limit: Option<usize>,
+
+    #[arg(long, global = true, value_enum, default_value_t, help = "Result format for subcommands")]
+    pub(crate) output_format: OutputFormat,
+
+    #[arg(long, global = true, help = "Never prompt; fail instead and print progress as plain lines (implied without a terminal)")]
+    non_interactive: bool,
+
+    #[arg(long, global = true, value_name = "PATH", help = "Read passwords from PATH, one per line, instead of prompting (- for stdin)")]
+    pub(crate) password_file: Option<PathBuf>,
+
+    #[arg(long, global = true, value_name = "PATH", help = "Bind new files to the base32 TOTP secret in PATH, or unlock files bound to it; asks for a current code")]
+    pub(crate) totp_secret: Option<PathBuf>,
+
+    #[arg(short, long, global = true, visible_alias = "assume-yes", help = "Overwrite existing outputs without asking")]
+    pub(crate) yes: bool,
+
+    #[arg(long, global = true, help = "Open files weaker than the config's [policy] with a warning instead of refusing them")]
+    pub(crate) allow_weak: bool,
+
+    #[arg(long, global = true, value_name = "CMD", help = "Shell command to run when a batch or watch ends, given the run's JSON summary on stdin")]
+    notify_cmd: Option<String>,
+
+    #[arg(long, global = true, value_name = "URL", help = "Post the run's JSON summary to URL when a batch or watch ends")]
+    notify_url: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    #[command(about = "Encrypt a file")]
+    Encrypt(EncryptArgs),
+    #[command(about = "Decrypt a file")]
+    Decrypt(DecryptArgs),
+    #[command(about = "Encrypt a short message and print it as armored text")]
+    EncryptText(EncryptTextArgs),
+    #[command(about = "Decrypt an armored message and print the text")]
+    DecryptText(DecryptTextArgs),
+    #[command(about = "Verify a password against the header without decrypting")]
+    CheckPassword(CheckPasswordArgs),
+    #[command(about = "Add a password to an encrypted file by rewriting its header")]
+    AddKey(KeyArgs),
+    #[command(about = "Revoke a password from an encrypted file by rewriting its header")]
+    RemoveKey(KeyArgs),
+    #[command(about = "Print a recovery code that can stand in for the file's header if it is destroyed")]
+    ExportRecovery(ExportRecoveryArgs),
+    #[command(about = "List every payload chunk with its offset, length, and shard health")]
+    Chunks(ChunksArgs),
+    #[command(about = "Scan a disk image or any damaged file for encrypted files and extract what still decrypts")]
+    Recover(RecoverArgs),
+    #[cfg(feature = "fuse")]
+    #[command(about = "Mount an encrypted file read-only, decrypting chunks as they are read")]
+    Mount(MountArgs),
+    #[command(about = "Watch a directory and encrypt files into another as they are added or changed")]
+    Watch(WatchArgs),
+    #[command(about = "Re-encrypt a file under the current settings in one streaming pass")]
+    Migrate(MigrateArgs),
+    #[command(about = "Re-check every file recorded in a manifest")]
+    VerifyManifest(VerifyManifestArgs),
+    #[command(about = "Run built-in known-answer tests for every primitive")]
+    Selftest,
+    #[command(about = "Time Argon2id on this machine and suggest costs that take about as long as a target")]
+    Calibrate(CalibrateArgs),
+    #[command(about = "Print a shell completion script")]
+    Completions(CompletionsArgs),
+    #[command(subcommand, about = "Print the man page or the file format specification")]
+    Docs(Docs),
+    #[command(hide = true, about = "Damage copies of an encrypted file in memory and report how many still decrypt")]
+    FuzzCorrupt(FuzzCorruptArgs),
+    #[command(hide = true, about = "Generate deterministic test vectors")]
+    GenVectors(GenVectorsArgs),
+}
+
+#[derive(Args)]
+pub(crate) struct EncryptArgs {
+    #[arg(short, long, add = ArgValueCompleter::new(PathCompleter::any().filter(plaintext)), help = "File to encrypt (- for stdin)")]
+    pub(crate) input: PathBuf,
+
+    #[arg(short, long, help = "Output path (defaults to <input>.swx)")]
+    pub(crate) output: Option<PathBuf>,
+
+    #[arg(long, value_enum, help = "Cipher layers to apply [default: dual, or the profile's]")]
+    pub(crate) cipher: Option<CipherMode>,
+
+    #[arg(long, value_name = "SIZE", value_parser = parse_split_size, help = "Split output into volumes of at most SIZE (e.g. 100MB)")]
+    pub(crate) split_size: Option<u64>,
+
+    #[arg(long, value_name = "DIR", conflicts_with = "split_size", help = "Store encrypted chunks as content-addressed objects in DIR and write an index as the output")]
+    pub(crate) chunk_store: Option<PathBuf>,
+
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = parse_password_count, help = "Number of passwords that can each decrypt the file")]
+    pub(crate) passwords: usize,
+
+    #[arg(long, value_name = "PATH", help = "Record original and encrypted hashes in a JSON manifest")]
+    pub(crate) emit_manifest: Option<PathBuf>,
+
+    #[arg(long, help = "Skip encryption when the existing output already holds the current source")]
+    pub(crate) if_changed: bool,
+
+    #[arg(long, conflicts_with_all = ["if_changed", "emit_manifest"], help = "Encrypt a directory as a tar archive streamed into the pipeline")]
+    pub(crate) tar: bool,
+
+    #[arg(long, conflicts_with_all = ["output", "if_changed"], help = "Write the output under a random name; decrypt restores the original from the header")]
+    pub(crate) hide_name: bool,
+
+    #[arg(long, value_name = "SIZE", value_parser = parse_pad_size, conflicts_with = "chunk_store", help = "Pad the output with random bytes to the next power of two (pow2) or a multiple of SIZE")]
+    pub(crate) pad_size: Option<PadSize>,
+
+    #[arg(long, value_name = "PATH", requires = "pad_size", conflicts_with_all = ["split_size", "tar"], help = "Hide PATH in the padding under a second password, leaving the input as a decoy")]
+    pub(crate) hidden: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", requires = "output", conflicts_with_all = ["split_size", "chunk_store", "hidden"], help = "Embed the output in a copy of the PNG or WAV file at PATH; decrypt extracts it automatically")]
+    pub(crate) carrier: Option<PathBuf>,
+
+    #[arg(long, value_name = "K-of-N", value_parser = parse_split_key, help = "Add a key slot whose key is split into N shares, any K of which decrypt the file")]
+    pub(crate) split_key: Option<Threshold>,
+
+    #[arg(long, value_name = "DIR", requires = "split_key", help = "Write each key share to its own file in DIR instead of printing them")]
+    pub(crate) share_dir: Option<PathBuf>,
+
+    #[arg(long, conflicts_with = "pad_size", help = "Derive salt, keys, and nonces from the content and password so identical files encrypt identically")]
+    pub(crate) convergent: bool,
+
+    #[arg(long, help = "Start even when the destination may not have room for the output")]
+    pub(crate) no_preflight: bool,
+
+    #[arg(long, help = "Fail, discarding the output, if the source's size or modification time changes while it is encrypted")]
+    pub(crate) paranoid: bool,
+
+    #[arg(long, help = "Decrypt the output into a hashing sink right after writing it, to prove it can be recovered")]
+    pub(crate) verify_after: bool,
+
+    #[arg(long, conflicts_with = "tar", help = "Delete the source once it has been encrypted (shredded first when shred-passes is set)")]
+    pub(crate) delete_source: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct DecryptArgs {
+    #[arg(short, long, add = ArgValueCompleter::new(PathCompleter::file().filter(encrypted)), help = "File to decrypt")]
+    pub(crate) input: PathBuf,
+
+    #[arg(short, long, conflicts_with = "restore_name", help = "Output path, or - for stdout (defaults to <input> without .swx)")]
+    pub(crate) output: Option<PathBuf>,
+
+    #[arg(long, help = "Write output under the original filename stored in the header")]
+    pub(crate) restore_name: bool,
+
+    #[arg(long, value_name = "DIR", requires = "restore_name", help = "Directory for --restore-name output (defaults to the input's directory)")]
+    pub(crate) output_dir: Option<PathBuf>,
+
+    #[arg(long, help = "Restore the original modification time and permissions")]
+    pub(crate) preserve_attrs: bool,
+
+    #[arg(long, requires = "preserve_attrs", help = "Also restore setuid, setgid and sticky bits with --preserve-attrs")]
+    pub(crate) preserve_special_bits: bool,
+
+    #[arg(long, conflicts_with_all = ["restore_name", "preserve_attrs"], help = "Extract a --tar archive into the output directory")]
+    pub(crate) untar: bool,
+
+    #[arg(long, value_name = "START-END", value_parser = parse_range, conflicts_with_all = ["untar", "preserve_attrs"], help = "Decrypt only this byte range, e.g. 100MB-200MB (end exclusive, either side may be left open)")]
+    pub(crate) range: Option<Range<u64>>,
+
+    #[arg(long, conflicts_with_all = ["untar", "range"], help = "Write zeros in place of chunks that can't be recovered, list the lost byte ranges, and exit with status 3")]
+    pub(crate) keep_going: bool,
+
+    #[arg(long, value_name = "SHARE", help = "A key share or a file holding one, from encrypt --split-key; repeat until enough are given")]
+    pub(crate) key_share: Vec<String>,
+
+    #[arg(long, value_name = "PATH", help = "Read the header from the recovery code in PATH, from export-recovery, instead of from the file")]
+    pub(crate) recovery_code: Option<PathBuf>,
+
+    #[arg(long, help = "Start even when the destination may not have room for the output")]
+    pub(crate) no_preflight: bool,
+
+    #[arg(long, help = "Delete the encrypted file once it has been decrypted and verified (shredded first when shred-passes is set)")]
+    pub(crate) delete_source: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct EncryptTextArgs {
+    #[arg(help = "Message to encrypt (read from stdin when omitted)")]
+    pub(crate) text: Option<String>,
+
+    #[arg(long, value_enum, help = "Cipher layers to apply [default: dual, or the profile's]")]
+    pub(crate) cipher: Option<CipherMode>,
+
+    #[cfg(feature = "qr")]
+    #[arg(long, help = "Also draw the armored message as a QR code on stderr")]
+    pub(crate) qr: bool,
+
+    #[cfg(feature = "qr")]
+    #[arg(long, value_name = "PATH", help = "Also save the armored message as a QR code PNG")]
+    pub(crate) qr_png: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub(crate) struct DecryptTextArgs {
+    #[arg(help = "Armored message to decrypt (read from stdin when omitted)")]
+    pub(crate) text: Option<String>,
+}
+
+#[derive(Args)]
+pub(crate) struct CheckPasswordArgs {
+    #[arg(short, long, add = ArgValueCompleter::new(PathCompleter::file().filter(encrypted)), help = "Encrypted file to check")]
+    pub(crate) input: PathBuf,
+}
+
+#[derive(Args)]
+pub(crate) struct ExportRecoveryArgs {
+    #[arg(short, long, add = ArgValueCompleter::new(PathCompleter::file().filter(encrypted)), help = "Encrypted file whose header to export")]
+    pub(crate) input: PathBuf,
+}
+
+#[derive(Args)]
+pub(crate) struct ChunksArgs {
+    #[arg(short, long, add = ArgValueCompleter::new(PathCompleter::file().filter(encrypted)), help = "Encrypted file to inspect")]
+    pub(crate) input: PathBuf,
+
+    #[arg(long, help = "Also ask for the password and check that each chunk decrypts (nothing is written)")]
+    pub(crate) decrypt: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct RecoverArgs {
+    #[arg(short, long, add = ArgValueCompleter::new(PathCompleter::file()), help = "Disk image, partition dump, or damaged file to scan")]
+    pub(crate) input: PathBuf,
+
+    #[arg(long, value_name = "DIR", help = "Directory for recovered files (defaults to the input's directory)")]
+    pub(crate) output_dir: Option<PathBuf>,
+}
+
+#[cfg(feature = "fuse")]
+#[derive(Args)]
+pub(crate) struct MountArgs {
+    #[arg(add = ArgValueCompleter::new(PathCompleter::file().filter(encrypted)), help = "Encrypted file to mount")]
+    pub(crate) input: PathBuf,
+
+    #[arg(help = "Empty directory to mount it on")]
+    pub(crate) mountpoint: PathBuf,
+
+    #[arg(long, value_name = "SIZE", default_value = "64MiB", value_parser = parse_size, help = "Decrypted chunks to keep in memory")]
+    pub(crate) cache_size: u64,
+}
+
+#[derive(Args)]
+pub(crate) struct WatchArgs {
+    #[arg(add = ArgValueCompleter::new(PathCompleter::dir()), help = "Directory to watch")]
+    pub(crate) dir: PathBuf,
+
+    #[arg(short, long, value_name = "DIR", help = "Directory for encrypted files, laid out like the watched one")]
+    pub(crate) output_dir: PathBuf,
+
+    #[arg(long, value_enum, help = "Cipher layers to apply [default: dual, or the profile's]")]
+    pub(crate) cipher: Option<CipherMode>,
+
+    #[arg(long, value_name = "SECONDS", default_value_t = 2, help = "Encrypt a file only once it has gone this long without changing")]
+    pub(crate) settle: u64,
+
+    #[arg(long, help = "Bring the output directory up to date and exit instead of watching")]
+    pub(crate) once: bool,
+
+    #[arg(long, help = "Skip files the journal shows were encrypted unchanged by an earlier run")]
+    pub(crate) since_last_run: bool,
+
+    #[arg(long, value_name = "DIR", help = "Where to keep the run lock and journal [default: $XDG_STATE_HOME/sweetbyte]")]
+    pub(crate) state_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub(crate) struct MigrateArgs {
+    #[arg(short, long, add = ArgValueCompleter::new(PathCompleter::file().filter(encrypted)), help = "Encrypted file to migrate")]
+    pub(crate) input: PathBuf,
+
+    #[arg(short, long, required_unless_present = "in_place", help = "Where to write the migrated file")]
+    pub(crate) output: Option<PathBuf>,
+
+    #[arg(long, conflicts_with = "output", help = "Replace the input once the migrated file is complete")]
+    pub(crate) in_place: bool,
+
+    #[arg(long, value_enum, help = "Cipher layers to apply [default: dual, or the profile's]")]
+    pub(crate) cipher: Option<CipherMode>,
+
+    #[arg(long, help = "Migrate even if other key slots or a payload hidden in the padding would be lost")]
+    pub(crate) drop_extras: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct KeyArgs {
+    #[arg(short, long, add = ArgValueCompleter::new(PathCompleter::file().filter(encrypted)), help = "Encrypted file to update")]
+    pub(crate) input: PathBuf,
+}
+
+#[derive(Args)]
+pub(crate) struct VerifyManifestArgs {
+    #[arg(short, long, help = "Manifest written by --emit-manifest")]
+    pub(crate) input: PathBuf,
+}
+
+#[derive(Args)]
+pub(crate) struct CalibrateArgs {
+    #[arg(long, value_name = "MS", default_value_t = 500, help = "How long one derivation should take")]
+    pub(crate) target_ms: u64,
+
+    #[arg(long, value_name = "SIZE", default_value = "1GiB", value_parser = parse_size, help = "Most memory a derivation may use")]
+    pub(crate) max_memory: u64,
+
+    #[arg(long, help = "Lanes to use [default: one per core, up to 4]")]
+    pub(crate) parallelism: Option<u32>,
+
+    #[arg(long, help = "Write the suggested costs into the config file's [kdf] table")]
+    pub(crate) write: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct CompletionsArgs {
+    #[arg(value_enum, help = "Shell to print the script for")]
+    pub(crate) shell: Shell,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Docs {
+    #[command(about = "Print a man page in roff format")]
+    Man,
+    #[command(about = "Print the on-disk format specification as Markdown")]
+    Formats,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Args)]
+pub(crate) struct FuzzCorruptArgs {
+    #[arg(short, long, add = ArgValueCompleter::new(PathCompleter::file().filter(encrypted)), help = "Encrypted file to damage (it is only read)")]
+    pub(crate) input: PathBuf,
+
+    #[arg(long, value_name = "N", default_value_t = 0, help = "Random bits to flip anywhere in each copy")]
+    pub(crate) flip_bits: usize,
+
+    #[arg(long, value_name = "N", default_value_t = 0, help = "Shards to zero in every chunk of each copy")]
+    pub(crate) wipe_shards: usize,
+
+    #[arg(long, value_name = "N", default_value_t = 10, help = "Damaged copies to try")]
+    pub(crate) trials: u32,
+
+    #[arg(long, help = "Seed for the damage, to repeat a run (random by default)")]
+    pub(crate) seed: Option<u64>,
+}
+
+#[derive(Args)]
+pub(crate) struct GenVectorsArgs {
+    #[arg(short, long, default_value = "tests/vectors", help = "Directory to write vectors into")]
+    pub(crate) output: PathBuf,
+}
+
+impl Cli {
+    pub(crate) fn settings(&self) -> Result<Settings> {
+        let kdf = KdfLayer { algorithm: self.kdf, ..KdfLayer::default() };
+
+        Settings::load(
+            self.config.as_deref(),
+            Layer {
+                profile: self.profile,
+                threads: self.threads,
+                kdf,
+                fsync: self.fsync,
+                reorder_limit: self.reorder_limit,
+                notify_cmd: self.notify_cmd.clone(),
+                notify_url: self.notify_url.clone(),
+                ..Layer::default()
+            },
+        )
+    }
+
+    pub(crate) fn config_path(&self) -> Option<&Path> {
+        self.config.as_deref()
+    }
+
+    /// Whether prompts and live progress bars can be used: not forced off and both stdin and stderr
+    /// are terminals.
+    pub(crate) fn interactive(&self) -> bool {
+        !self.non_interactive && std::io::stdin().is_terminal() && std::io::stderr().is_terminal()
+    }
+}
+
+/// Environment variable the shell sets when it calls back into the binary for suggestions.
+pub(crate) const COMPLETE_VAR: &str = "COMPLETE";
+
+/// Writes the script that registers the binary as its own completer. Suggestions are then worked
+/// out at completion time from the argument definitions, which is what lets file arguments offer
+/// only `.swx` files or only plain ones.
+pub(crate) fn completions(shell: Shell, out: &mut dyn Write) -> Result<()> {
+    let name = Cli::command().get_name().to_owned();
+    let completer: &dyn EnvCompleter = match shell {
+        Shell::Bash => &Bash,
+        Shell::Zsh => &Zsh,
+        Shell::Fish => &Fish,
+    };
+
+    completer.write_registration(COMPLETE_VAR, &name, &name, &name, out).context("failed to write completion script")
+}
+
+/// Renders the man page for the whole command line, subcommands included.
+pub(crate) fn man(out: &mut dyn Write) -> Result<()> {
+    clap_mangen::Man::new(Cli::command()).render(out).context("failed to render man page")
+}
+
+/// Completion filter for arguments that take an encrypted file; directories are still offered to
+/// descend into.
+fn encrypted(path: &Path) -> bool {
+    Files::new(path).is_encrypted()
+}
+
+fn plaintext(path: &Path) -> bool {
+    !encrypted(path)
+}
+
+pub(crate) fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (digits, unit) = value.split_at_checked(split).ok_or_else(|| format!("invalid size {value:?}"))?;
+    let number: u64 = digits.parse().map_err(|e| format!("invalid size {value:?}: {e}"))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1_000,
+        "KIB" => 1 << 10,
+        "M" | "MB" => 1_000_000,
+        "MIB" => 1 << 20,
+        "G" | "GB" => 1_000_000_000,
+        "GIB" => 1 << 30,
+        other => return Err(format!("unknown size unit {other:?}")),
+    };
+
+    number.checked_mul(multiplier).ok_or_else(|| format!("size {value:?} is too large"))
+}
+
+fn parse_split_size(value: &str) -> Result<u64, String> {
+    let size = parse_size(value)?;
+    if size < MIN_SPLIT_SIZE {
+        return Err(format!("split size must be at least {MIN_SPLIT_SIZE} bytes"));
+    }
+
+    Ok(size)
+}
+
+fn parse_pad_size(value: &str) -> Result<PadSize, String> {
+    if value.trim().eq_ignore_ascii_case("pow2") {
+        return Ok(PadSize::PowerOfTwo);
+    }
+
+    match parse_size(value)? {
+        0 => Err("pad size must be greater than zero".to_owned()),
+        size => Ok(PadSize::Multiple(size)),
+    }
+}
+
+fn parse_split_key(value: &str) -> Result<Threshold, String> {
+    let (needed, shares) = value.split_once("-of-").ok_or_else(|| format!("invalid key split {value:?}, expected K-of-N"))?;
+    let needed = needed.trim().parse().map_err(|e| format!("invalid share threshold {needed:?}: {e}"))?;
+    let shares = shares.trim().parse().map_err(|e| format!("invalid share count {shares:?}: {e}"))?;
+
+    Threshold::new(needed, shares).map_err(|e| e.to_string())
+}
+
+fn parse_range(value: &str) -> Result<Range<u64>, String> {
+    let (start, end) = value.split_once('-').ok_or_else(|| format!("invalid range {value:?}, expected START-END"))?;
+    let start = if start.trim().is_empty() { 0 } else { parse_size(start)? };
+    let end = if end.trim().is_empty() { u64::MAX } else { parse_size(end)? };
+    if start >= end {
+        return Err(format!("range {value:?} is empty"));
+    }
+
+    Ok(start..end)
+}
+
+fn parse_reorder_limit(value: &str) -> Result<usize, String> {
+    usize::try_from(parse_size(value)?).map_err(|e| format!("reorder limit {value:?} is too large: {e}"))
+}
+
+fn parse_password_count(value: &str) -> Result<usize, String> {
+    let count: usize = value.parse().map_err(|e| format!("invalid password count {value:?}: {e}"))?;
+    if !(1..=MAX_KEY_SLOTS).contains(&count) {
+        return Err(format!("password count must be between 1 and {MAX_KEY_SLOTS}"));
+    }
+
+    Ok(count)
+}
@@ -1,3 +1,5 @@
+use std::io::Read;
+
 use anyhow::{Context, Result};
 
 pub(crate) struct Compression {
@@ -15,19 +17,40 @@ impl Compression {
     }
 
     pub(crate) fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        self.compress_into(data, &mut output)?;
+
+        Ok(output)
+    }
+
+    /// Appends the compressed form of `data` to `output`, so a caller can compress into a buffer it
+    /// reuses.
+    pub(crate) fn compress_into(&self, data: &[u8], output: &mut Vec<u8>) -> Result<()> {
         if data.is_empty() {
             anyhow::bail!("data must not be empty");
         }
 
-        zstd::stream::encode_all(data, self.level).context("failed to compress")
+        zstd::stream::copy_encode(data, output, self.level).context("failed to compress")
     }
 
+    /// Stops once the output passes `limit` bytes, so a small input that claims to expand
+    /// enormously is rejected before it fills memory.
     #[expect(clippy::unused_self, reason = "consistent API with compress")]
-    pub(crate) fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+    pub(crate) fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>> {
         if data.is_empty() {
             anyhow::bail!("data must not be empty");
         }
 
-        zstd::stream::decode_all(data).context("failed to decompress")
+        let decoder = zstd::stream::read::Decoder::new(data).context("failed to initialize decompressor")?;
+        let mut output = Vec::new();
+        decoder
+            .take(u64::try_from(limit).context("limit overflow")?.saturating_add(1))
+            .read_to_end(&mut output)
+            .context("failed to decompress")?;
+        if output.len() > limit {
+            anyhow::bail!("decompressed data exceeds {limit} bytes");
+        }
+
+        Ok(output)
     }
 }
@@ -1,52 +0,0 @@
-pub(crate) const FILE_EXTENSION: &str = "swx";
-
-pub(crate) const ARGON2_M_COST: u32 = 65536;
-
-pub(crate) const ARGON2_T_COST: u32 = 3;
-
-pub(crate) const ARGON2_P_COST: u32 = 4;
-
-pub(crate) const ARGON2_KEY_LEN: usize = 64;
-
-pub(crate) const ARGON2_SALT_LEN: usize = 32;
-
-pub(crate) const KEY_LEN: usize = 32;
-
-pub(crate) const ORIGINAL_COUNT: usize = 4;
-
-pub(crate) const BLOCK_SIZE: usize = 128;
-
-pub(crate) const COMPRESSION_LEVEL: i32 = 1;
-
-pub(crate) const RECOVERY_COUNT: usize = 10;
-
-pub(crate) const CHUNK_SIZE: usize = 256 * 1024;
-
-pub(crate) const MAGIC_BYTES: u32 = 0xDEAD_BEEF;
-
-pub(crate) const CURRENT_VERSION: u16 = 0x0002;
-
-pub(crate) const MAX_FILENAME_LEN: usize = 256;
-
-pub(crate) const MAX_CHUNK_SIZE: u32 = 1024 * 1024;
-
-pub(crate) const MAX_SECTION_SIZE: u32 = 1024;
-
-pub(crate) const PASSWORD_LEN: usize = 8;
-
-pub(crate) const KDF_INFO: [[u8; 64]; 3] = [
-    [
-        0x65, 0x37, 0x34, 0x66, 0x62, 0x38, 0x65, 0x37, 0x65, 0x65, 0x62, 0x37, 0x62, 0x35, 0x32, 0x64, 0x61, 0x31, 0x33, 0x36, 0x66, 0x34, 0x38, 0x39, 0x36, 0x62, 0x30, 0x34, 0x30, 0x36, 0x61, 0x36,
-        0x30, 0x35, 0x31, 0x31, 0x62, 0x32, 0x63, 0x64, 0x30, 0x34, 0x32, 0x34, 0x36, 0x33, 0x30, 0x64, 0x35, 0x66, 0x65, 0x36, 0x37, 0x33, 0x39, 0x34, 0x64, 0x66, 0x63, 0x30, 0x64, 0x32, 0x34, 0x64,
-    ], // sweetbyte/v1/hkdf-sha256/key-derivation/role=first
-    [
-        0x63, 0x64, 0x36, 0x31, 0x63, 0x33, 0x36, 0x34, 0x65, 0x33, 0x62, 0x38, 0x63, 0x61, 0x30, 0x36, 0x66, 0x36, 0x31, 0x38, 0x30, 0x62, 0x34, 0x63, 0x64, 0x35, 0x31, 0x36, 0x39, 0x61, 0x34, 0x32,
-        0x66, 0x38, 0x62, 0x36, 0x62, 0x61, 0x64, 0x62, 0x63, 0x30, 0x66, 0x33, 0x30, 0x65, 0x61, 0x32, 0x35, 0x39, 0x33, 0x33, 0x64, 0x35, 0x65, 0x65, 0x39, 0x61, 0x36, 0x65, 0x31, 0x38, 0x33, 0x32,
-    ], // sweetbyte/v1/hkdf-sha256/key-derivation/role=second
-    [
-        0x66, 0x37, 0x33, 0x61, 0x35, 0x66, 0x36, 0x66, 0x65, 0x34, 0x32, 0x65, 0x39, 0x64, 0x33, 0x37, 0x34, 0x36, 0x37, 0x61, 0x63, 0x33, 0x36, 0x32, 0x64, 0x39, 0x35, 0x31, 0x30, 0x37, 0x34, 0x62,
-        0x31, 0x65, 0x62, 0x34, 0x33, 0x35, 0x65, 0x62, 0x64, 0x33, 0x65, 0x30, 0x32, 0x62, 0x61, 0x66, 0x62, 0x35, 0x38, 0x31, 0x30, 0x39, 0x65, 0x63, 0x61, 0x63, 0x36, 0x66, 0x36, 0x30, 0x61, 0x31,
-    ], // sweetbyte/v1/hkdf-sha256/key-derivation/role=third
-];
-
-pub(crate) const EXCLUDED_PATTERNS: &[&str] = &["target", "vendor", "node_modules", ".git", ".github", ".config", ".local", ".cache", ".ssh", ".gnupg", "*.rs", "*.go", "*.nix", "*.toml", "*.lock"];
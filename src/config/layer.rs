@@ -0,0 +1,442 @@
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use super::{CHUNK_SIZE, CONFIG_DIR, CONFIG_FILE, ENV_PREFIX, MIN_CHUNK_SIZE, Policy, Profile, Settings};
+use crate::cipher::{Argon2Params, CipherMode, Kdf, KdfAlgorithm, KdfParams, ScryptParams};
+use crate::files::FsyncPolicy;
+use crate::padding::PaddingScheme;
+use crate::ui::{HistoryMode, ProgressStyle};
+
+impl Settings {
+    pub(crate) fn load(path: Option<&Path>, cli: Layer) -> Result<Self> {
+        let file = match path {
+            Some(path) => Layer::from_file(path)?,
+            None => match default_path().filter(|path| path.exists()) {
+                Some(path) => Layer::from_file(&path)?,
+                None => Layer::default(),
+            },
+        };
+
+        let env = Layer::from_vars(|name| std::env::var(name).ok())?;
+
+        file.merge(env).merge(cli).resolve()
+    }
+
+    /// Writes the options the interactive menu can change into the config file, keeping every other
+    /// key as it was.
+    pub(crate) fn save(&self, path: Option<&Path>) -> Result<PathBuf> {
+        edit_file(path, |table| {
+            table.insert("compression-level".to_owned(), toml::Value::Integer(self.compression_level.into()));
+            table.insert("shred-passes".to_owned(), toml::Value::Integer(self.shred_passes.into()));
+            table.insert("history".to_owned(), toml::Value::String(self.history.to_string()));
+        })
+    }
+
+    /// Writes calibrated Argon2id costs into the `[kdf]` table, dropping the scrypt-only keys they
+    /// would clash with.
+    pub(crate) fn save_kdf(params: Argon2Params, path: Option<&Path>) -> Result<PathBuf> {
+        edit_file(path, |table| {
+            let mut kdf = match table.remove("kdf") {
+                Some(toml::Value::Table(kdf)) => kdf,
+                _ => toml::Table::new(),
+            };
+            kdf.remove("log-n");
+            kdf.remove("block-size");
+            kdf.insert("algorithm".to_owned(), toml::Value::String(KdfAlgorithm::Argon2id.to_string()));
+            kdf.insert("memory".to_owned(), toml::Value::Integer(params.memory.into()));
+            kdf.insert("iterations".to_owned(), toml::Value::Integer(params.iterations.into()));
+            kdf.insert("parallelism".to_owned(), toml::Value::Integer(params.parallelism.into()));
+            table.insert("kdf".to_owned(), toml::Value::Table(kdf));
+        })
+    }
+}
+
+/// Applies `edit` to the config file's top-level table, creating the file if it does not exist yet.
+fn edit_file(path: Option<&Path>, edit: impl FnOnce(&mut toml::Table)) -> Result<PathBuf> {
+    let path = path.map(Path::to_path_buf).or_else(default_path).context("no config file location available")?;
+    let mut table = if path.exists() {
+        let contents = std::fs::read_to_string(&path).with_context(|| format!("failed to read config file {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))?
+    } else {
+        toml::Table::new()
+    };
+
+    edit(&mut table);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("failed to create config directory {}", dir.display()))?;
+    }
+    let contents = toml::to_string(&table).context("failed to serialize config file")?;
+    std::fs::write(&path, contents).with_context(|| format!("failed to write config file {}", path.display()))?;
+
+    Ok(path)
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Layer {
+    pub(crate) profile: Option<Profile>,
+    pub(crate) threads: Option<NonZeroUsize>,
+    pub(crate) kdf: KdfLayer,
+    pub(crate) cipher: Option<CipherMode>,
+    pub(crate) compression_level: Option<i32>,
+    pub(crate) chunk_size: Option<usize>,
+    pub(crate) progress: Option<ProgressStyle>,
+    pub(crate) shred_passes: Option<u32>,
+    pub(crate) exclude: Option<Vec<String>>,
+    pub(crate) fsync: Option<FsyncPolicy>,
+    pub(crate) reorder_limit: Option<usize>,
+    pub(crate) payload_mac: Option<bool>,
+    pub(crate) padding: Option<PaddingScheme>,
+    pub(crate) chunk_index: Option<bool>,
+    pub(crate) header_copy: Option<bool>,
+    pub(crate) history: Option<HistoryMode>,
+    pub(crate) session_kdf: Option<bool>,
+    pub(crate) password_cache: Option<u64>,
+    pub(crate) notify_cmd: Option<String>,
+    pub(crate) notify_url: Option<String>,
+    pub(crate) policy: Option<Policy>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct KdfLayer {
+    pub(crate) algorithm: Option<KdfAlgorithm>,
+    pub(crate) memory: Option<u32>,
+    pub(crate) iterations: Option<u32>,
+    pub(crate) log_n: Option<u8>,
+    pub(crate) block_size: Option<u32>,
+    pub(crate) parallelism: Option<u32>,
+}
+
+impl KdfLayer {
+    fn merge(self, over: Self) -> Self {
+        Self {
+            algorithm: over.algorithm.or(self.algorithm),
+            memory: over.memory.or(self.memory),
+            iterations: over.iterations.or(self.iterations),
+            log_n: over.log_n.or(self.log_n),
+            block_size: over.block_size.or(self.block_size),
+            parallelism: over.parallelism.or(self.parallelism),
+        }
+    }
+
+    fn resolve(self) -> Result<KdfParams> {
+        let kdf = match self.algorithm.unwrap_or_default() {
+            KdfAlgorithm::Argon2id => {
+                if self.log_n.is_some() || self.block_size.is_some() {
+                    anyhow::bail!("kdf log-n and block-size only apply to scrypt");
+                }
+                let defaults = Argon2Params::default();
+                KdfParams::Argon2id(Argon2Params {
+                    memory: self.memory.unwrap_or(defaults.memory),
+                    iterations: self.iterations.unwrap_or(defaults.iterations),
+                    parallelism: self.parallelism.unwrap_or(defaults.parallelism),
+                })
+            }
+            KdfAlgorithm::Scrypt => {
+                if self.memory.is_some() || self.iterations.is_some() {
+                    anyhow::bail!("kdf memory and iterations only apply to argon2id");
+                }
+                let defaults = ScryptParams::default();
+                KdfParams::Scrypt(ScryptParams {
+                    log_n: self.log_n.unwrap_or(defaults.log_n),
+                    block_size: self.block_size.unwrap_or(defaults.block_size),
+                    parallelism: self.parallelism.unwrap_or(defaults.parallelism),
+                })
+            }
+        };
+        kdf.validate()?;
+
+        Ok(kdf)
+    }
+}
+
+impl Layer {
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).with_context(|| format!("failed to read config file {}", path.display()))?;
+        let layer: Self = toml::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))?;
+        if layer.policy.as_ref().is_some_and(|policy| !policy.is_empty()) {
+            ensure_private(path)?;
+        }
+
+        Ok(layer)
+    }
+
+    fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let var = |name: &str| lookup(&format!("{ENV_PREFIX}{name}"));
+
+        Ok(Self {
+            profile: parse_var(var, "PROFILE", |v| v.parse().ok())?,
+            threads: parse_var(var, "THREADS", |v| v.parse().ok())?,
+            kdf: KdfLayer {
+                algorithm: parse_var(var, "KDF_ALGORITHM", |v| v.parse().ok())?,
+                memory: parse_var(var, "KDF_MEMORY", |v| v.parse().ok())?,
+                iterations: parse_var(var, "KDF_ITERATIONS", |v| v.parse().ok())?,
+                log_n: parse_var(var, "KDF_LOG_N", |v| v.parse().ok())?,
+                block_size: parse_var(var, "KDF_BLOCK_SIZE", |v| v.parse().ok())?,
+                parallelism: parse_var(var, "KDF_PARALLELISM", |v| v.parse().ok())?,
+            },
+            cipher: parse_var(var, "CIPHER", |v| v.parse().ok())?,
+            compression_level: parse_var(var, "COMPRESSION_LEVEL", |v| v.parse().ok())?,
+            chunk_size: parse_var(var, "CHUNK_SIZE", |v| crate::cli::parse_size(v).ok().and_then(|size| usize::try_from(size).ok()))?,
+            progress: parse_var(var, "PROGRESS", |v| v.parse().ok())?,
+            shred_passes: parse_var(var, "SHRED_PASSES", |v| v.parse().ok())?,
+            fsync: parse_var(var, "FSYNC", |v| v.parse().ok())?,
+            reorder_limit: parse_var(var, "REORDER_LIMIT", |v| crate::cli::parse_size(v).ok().and_then(|size| usize::try_from(size).ok()))?,
+            payload_mac: parse_var(var, "PAYLOAD_MAC", |v| v.parse().ok())?,
+            padding: parse_var(var, "PADDING", |v| v.parse().ok())?,
+            chunk_index: parse_var(var, "CHUNK_INDEX", |v| v.parse().ok())?,
+            header_copy: parse_var(var, "HEADER_COPY", |v| v.parse().ok())?,
+            history: parse_var(var, "HISTORY", |v| v.parse().ok())?,
+            session_kdf: parse_var(var, "SESSION_KDF", |v| v.parse().ok())?,
+            password_cache: parse_var(var, "PASSWORD_CACHE", |v| v.parse().ok())?,
+            notify_cmd: var("NOTIFY_CMD").filter(|v| !v.trim().is_empty()),
+            notify_url: var("NOTIFY_URL").filter(|v| !v.trim().is_empty()),
+            policy: None,
+            exclude: var("EXCLUDE").map(|v| v.split(',').map(str::trim).filter(|p| !p.is_empty()).map(str::to_owned).collect()),
+        })
+    }
+
+    fn merge(self, over: Self) -> Self {
+        Self {
+            profile: over.profile.or(self.profile),
+            threads: over.threads.or(self.threads),
+            kdf: self.kdf.merge(over.kdf),
+            cipher: over.cipher.or(self.cipher),
+            compression_level: over.compression_level.or(self.compression_level),
+            chunk_size: over.chunk_size.or(self.chunk_size),
+            progress: over.progress.or(self.progress),
+            shred_passes: over.shred_passes.or(self.shred_passes),
+            exclude: over.exclude.or(self.exclude),
+            fsync: over.fsync.or(self.fsync),
+            reorder_limit: over.reorder_limit.or(self.reorder_limit),
+            payload_mac: over.payload_mac.or(self.payload_mac),
+            padding: over.padding.or(self.padding),
+            chunk_index: over.chunk_index.or(self.chunk_index),
+            header_copy: over.header_copy.or(self.header_copy),
+            history: over.history.or(self.history),
+            session_kdf: over.session_kdf.or(self.session_kdf),
+            password_cache: over.password_cache.or(self.password_cache),
+            notify_cmd: over.notify_cmd.or(self.notify_cmd),
+            notify_url: over.notify_url.or(self.notify_url),
+            policy: over.policy.or(self.policy),
+        }
+    }
+
+    /// Fills in what the layers leave unset from the chosen profile, then from the built-in
+    /// defaults.
+    fn resolve(mut self) -> Result<Settings> {
+        if let Some(profile) = self.profile.take() {
+            return profile.layer(self.kdf.algorithm.unwrap_or_default()).merge(self).resolve();
+        }
+
+        let defaults = Settings::default();
+        let kdf = self.kdf.resolve()?;
+
+        let chunk_size = self.chunk_size.unwrap_or(defaults.chunk_size);
+        if !(MIN_CHUNK_SIZE..=CHUNK_SIZE).contains(&chunk_size) {
+            anyhow::bail!("chunk size must be between {MIN_CHUNK_SIZE} and {CHUNK_SIZE} bytes");
+        }
+
+        let compression_level = self.compression_level.unwrap_or(defaults.compression_level);
+        if !zstd::compression_level_range().contains(&compression_level) {
+            anyhow::bail!("unsupported compression level {compression_level}");
+        }
+
+        if let Some(url) = &self.notify_url
+            && !(url.starts_with("http://") || url.starts_with("https://"))
+        {
+            anyhow::bail!("notify-url must be an http:// or https:// URL, not {url:?}");
+        }
+
+        Ok(Settings {
+            threads: self.threads.unwrap_or(defaults.threads),
+            kdf,
+            cipher: self.cipher.unwrap_or(defaults.cipher),
+            compression_level,
+            chunk_size,
+            progress: self.progress.unwrap_or(defaults.progress),
+            shred_passes: self.shred_passes.unwrap_or(defaults.shred_passes),
+            exclude: self.exclude.unwrap_or(defaults.exclude),
+            fsync: self.fsync.unwrap_or(defaults.fsync),
+            reorder_limit: self.reorder_limit.unwrap_or(defaults.reorder_limit),
+            payload_mac: self.payload_mac.unwrap_or(defaults.payload_mac),
+            padding: self.padding.unwrap_or(defaults.padding),
+            chunk_index: self.chunk_index.unwrap_or(defaults.chunk_index),
+            header_copy: self.header_copy.unwrap_or(defaults.header_copy),
+            history: self.history.unwrap_or(defaults.history),
+            session_kdf: self.session_kdf.unwrap_or(defaults.session_kdf),
+            password_cache: self.password_cache.map(Duration::from_secs).or(defaults.password_cache),
+            notify_cmd: self.notify_cmd.or(defaults.notify_cmd),
+            notify_url: self.notify_url.or(defaults.notify_url),
+            policy: self.policy.unwrap_or(defaults.policy),
+        })
+    }
+}
+
+fn parse_var<T>(var: impl Fn(&str) -> Option<String>, name: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Option<T>> {
+    match var(name) {
+        Some(value) => parse(value.trim()).map(Some).with_context(|| format!("invalid value {value:?} for {ENV_PREFIX}{name}")),
+        None => Ok(None),
+    }
+}
+
+/// A pinned policy is only as trustworthy as the file holding it, so one that others can rewrite is
+/// refused.
+#[cfg(unix)]
+fn ensure_private(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path).with_context(|| format!("failed to read config file {}", path.display()))?.permissions().mode();
+    if mode & 0o022 != 0 {
+        anyhow::bail!("config file {} pins a [policy] but others can write to it; run chmod go-w on it", path.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn ensure_private(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(CONFIG_FILE))
+}
+
+/// The per-user config directory, which also holds the interactive history.
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from);
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|dir| !dir.is_empty())
+        .map(|home| PathBuf::from(home).join(".config"));
+
+    config_home.or(home).map(|dir| dir.join(CONFIG_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Layer {
+        let map: HashMap<String, String> = pairs.iter().map(|(k, v)| (format!("{ENV_PREFIX}{k}"), (*v).to_owned())).collect();
+        Layer::from_vars(|name| map.get(name).cloned()).unwrap()
+    }
+
+    #[test]
+    fn env_overrides_file_and_cli_overrides_env() {
+        let file: Layer = toml::from_str("threads = 2\ncompression-level = 5\nshred-passes = 1\n[kdf]\nmemory = 16384\n").unwrap();
+        let env = vars(&[("THREADS", "3"), ("COMPRESSION_LEVEL", "7"), ("PADDING", "ISO7816")]);
+        let cli = Layer { threads: NonZeroUsize::new(4), ..Layer::default() };
+
+        let settings = file.merge(env).merge(cli).resolve().unwrap();
+
+        assert_eq!(settings.threads.get(), 4);
+        assert_eq!(settings.compression_level, 7);
+        assert_eq!(settings.shred_passes, 1);
+        assert_eq!(settings.padding, PaddingScheme::Iso7816);
+        assert!(settings.kdf == KdfParams::Argon2id(Argon2Params { memory: 16384, ..Argon2Params::default() }));
+    }
+
+    #[test]
+    fn scrypt_is_selected_with_its_own_costs() {
+        let file: Layer = toml::from_str("[kdf]\nalgorithm = \"scrypt\"\nlog-n = 15\n").unwrap();
+        let settings = file.merge(vars(&[("KDF_BLOCK_SIZE", "4")])).resolve().unwrap();
+        assert!(settings.kdf == KdfParams::Scrypt(ScryptParams { log_n: 15, block_size: 4, ..ScryptParams::default() }));
+
+        assert!(toml::from_str::<Layer>("[kdf]\nalgorithm = \"scrypt\"\nmemory = 16384\n").unwrap().resolve().is_err());
+        assert!(toml::from_str::<Layer>("[kdf]\nlog-n = 15\n").unwrap().resolve().is_err());
+    }
+
+    #[test]
+    fn profile_fills_only_what_is_unset() {
+        let file: Layer = toml::from_str("profile = \"paranoid\"\nheader-copy = false\n").unwrap();
+        let cli = Layer { cipher: Some(CipherMode::Chacha), ..Layer::default() };
+
+        let settings = file.merge(cli).resolve().unwrap();
+
+        assert!(settings.cipher == CipherMode::Chacha);
+        assert!(!settings.header_copy);
+        assert!(settings.kdf == KdfParams::Argon2id(Argon2Params { memory: 1024 * 1024, iterations: 8, ..Argon2Params::default() }));
+
+        let settings = vars(&[("PROFILE", "fast"), ("KDF_ALGORITHM", "scrypt")]).resolve().unwrap();
+        assert!(settings.cipher == CipherMode::Aes);
+        assert!(settings.kdf == KdfParams::Scrypt(ScryptParams { log_n: 15, ..ScryptParams::default() }));
+    }
+
+    #[test]
+    fn env_values_are_parsed() {
+        let settings = vars(&[("CHUNK_SIZE", "64KiB"), ("PROGRESS", "hidden"), ("EXCLUDE", "*.log, target"), ("PAYLOAD_MAC", "false"), ("SESSION_KDF", "true"), ("PASSWORD_CACHE", "300")])
+            .resolve()
+            .unwrap();
+
+        assert_eq!(settings.chunk_size, 64 * 1024);
+        assert!(!settings.payload_mac);
+        assert!(settings.session_kdf);
+        assert_eq!(settings.password_cache, Some(Duration::from_secs(300)));
+        assert!(matches!(settings.progress, ProgressStyle::Hidden));
+        assert_eq!(settings.exclude, ["*.log", "target"]);
+    }
+
+    #[test]
+    fn save_keeps_unrelated_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "threads = 2\ncompression-level = 3\n").unwrap();
+
+        let settings = Settings { compression_level: 9, shred_passes: 2, ..Settings::default() };
+        assert_eq!(settings.save(Some(&path)).unwrap(), path);
+
+        let loaded = Layer::from_file(&path).unwrap().resolve().unwrap();
+        assert_eq!(loaded.threads.get(), 2);
+        assert_eq!(loaded.compression_level, 9);
+        assert_eq!(loaded.shred_passes, 2);
+    }
+
+    #[test]
+    fn save_kdf_replaces_scrypt_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "threads = 2\n[kdf]\nalgorithm = \"scrypt\"\nlog-n = 15\n").unwrap();
+
+        let params = Argon2Params { memory: 131072, iterations: 4, parallelism: 2 };
+        Settings::save_kdf(params, Some(&path)).unwrap();
+
+        let loaded = Layer::from_file(&path).unwrap().resolve().unwrap();
+        assert_eq!(loaded.threads.get(), 2);
+        assert!(loaded.kdf == KdfParams::Argon2id(params));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn policy_is_refused_from_a_file_others_can_write() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[policy]\nmin-kdf-memory = 65536\nrequire-dual-cipher = true\n").unwrap();
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let settings = Layer::from_file(&path).unwrap().resolve().unwrap();
+        assert_eq!(settings.policy.min_kdf_memory, Some(65536));
+        assert!(settings.policy.require_dual_cipher);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666)).unwrap();
+        assert!(Layer::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(Layer::from_vars(|name| (name == "SWEETBYTE_THREADS").then(|| "zero".to_owned())).is_err());
+        assert!(toml::from_str::<Layer>("unknown = 1").is_err());
+        assert!(toml::from_str::<Layer>("chunk-size = 16").unwrap().resolve().is_err());
+    }
+}
@@ -0,0 +1,247 @@
+#[cfg(feature = "cli")]
+pub(crate) mod layer;
+#[cfg(feature = "cli")]
+mod policy;
+#[cfg(feature = "cli")]
+mod profile;
+pub(crate) mod settings;
+
+#[cfg(feature = "cli")]
+pub(crate) use policy::Policy;
+#[cfg(feature = "cli")]
+pub(crate) use profile::Profile;
+pub(crate) use settings::Settings;
+
+#[cfg(feature = "cli")]
+pub(crate) const FILE_EXTENSION: &str = "swx";
+
+#[cfg(feature = "cli")]
+pub(crate) const HIDDEN_NAME_LEN: usize = 16;
+
+pub(crate) const ARGON2_M_COST: u32 = 65536;
+
+pub(crate) const ARGON2_T_COST: u32 = 3;
+
+pub(crate) const ARGON2_P_COST: u32 = 4;
+
+pub(crate) const MIN_KDF_MEMORY: u32 = 8 * 1024;
+
+pub(crate) const MAX_KDF_MEMORY: u32 = 4 * 1024 * 1024;
+
+pub(crate) const MAX_KDF_ITERATIONS: u32 = 64;
+
+pub(crate) const MAX_KDF_PARALLELISM: u32 = 64;
+
+pub(crate) const SCRYPT_LOG_N: u8 = 17;
+
+pub(crate) const SCRYPT_BLOCK_SIZE: u32 = 8;
+
+pub(crate) const SCRYPT_P_COST: u32 = 1;
+
+pub(crate) const MIN_SCRYPT_LOG_N: u8 = 10;
+
+pub(crate) const MAX_SCRYPT_LOG_N: u8 = 24;
+
+pub(crate) const MAX_SCRYPT_BLOCK_SIZE: u32 = 32;
+
+pub(crate) const ARGON2_KEY_LEN: usize = 64;
+
+pub(crate) const ARGON2_SALT_LEN: usize = 32;
+
+pub(crate) const KEY_LEN: usize = 32;
+
+pub(crate) const MASTER_KEY_LEN: usize = 64;
+
+pub(crate) const MAX_KEY_SLOTS: usize = 8;
+
+pub(crate) const SLOT_RESERVE_LEN: usize = 768;
+
+#[cfg(feature = "cli")]
+/// Room left in a header written before its content hash is known, in case the final header packs a
+/// little larger.
+pub(crate) const HASH_PATCH_HEADROOM: usize = 256;
+
+pub(crate) const SLOT_NONCE_LEN: usize = 24;
+
+pub(crate) const WRAPPED_KEY_LEN: usize = MASTER_KEY_LEN + 16;
+
+pub(crate) const KEK_INFO: &[u8] = b"sweetbyte/v1/hkdf-sha256/key-wrapping";
+
+pub(crate) const PAYLOAD_MAC_INFO: &[u8] = b"sweetbyte/v1/hkdf-sha256/payload-mac";
+
+pub(crate) const METADATA_KEY_INFO: &[u8] = b"sweetbyte/v1/hkdf-sha256/metadata";
+
+#[cfg(feature = "cli")]
+pub(crate) const CHUNK_INDEX_INFO: &[u8] = b"sweetbyte/v1/hkdf-sha256/chunk-index";
+
+#[cfg(feature = "cli")]
+pub(crate) const CONVERGENT_SALT: &[u8] = b"sweetbyte/v1/argon2id/convergent";
+
+#[cfg(feature = "cli")]
+pub(crate) const CONVERGENT_INFO: &[u8] = b"sweetbyte/v1/hkdf-sha256/convergent";
+
+pub(crate) const SESSION_INFO: &[u8] = b"sweetbyte/v1/hkdf-sha256/session";
+
+#[cfg(feature = "cli")]
+pub(crate) const SECOND_FACTOR_INFO: &[u8] = b"sweetbyte/v1/hkdf-sha256/second-factor";
+
+#[cfg(feature = "cli")]
+pub(crate) const HIDDEN_INFO: &[u8] = b"sweetbyte/v1/hkdf-sha256/hidden";
+
+#[cfg(feature = "cli")]
+pub(crate) const MAX_HIDDEN_SIZE: u64 = 64 * 1024 * 1024;
+
+#[cfg(feature = "cli")]
+pub(crate) const MAX_CARRIER_SIZE: u64 = 256 * 1024 * 1024;
+
+#[cfg(feature = "cli")]
+pub(crate) const TOTP_STEP: u64 = 30;
+
+#[cfg(feature = "cli")]
+pub(crate) const TOTP_DIGITS: u32 = 6;
+
+#[cfg(feature = "cli")]
+pub(crate) const TOTP_SKEW: u64 = 1;
+
+#[cfg(feature = "cli")]
+pub(crate) const MIN_TOTP_SECRET_LEN: usize = 16;
+
+pub(crate) const PAYLOAD_MAC_MARKER: u32 = u32::MAX;
+
+pub(crate) const PADDING_MARKER: u32 = u32::MAX - 1;
+
+pub(crate) const CHUNK_INDEX_MARKER: u32 = u32::MAX - 2;
+
+pub(crate) const CHUNK_INDEX_MAGIC: [u8; 4] = *b"SWXI";
+
+pub(crate) const HEADER_COPY_MAGIC: [u8; 4] = *b"SWXH";
+
+#[cfg(feature = "cli")]
+pub(crate) const MAX_CHUNK_INDEX_LEN: u32 = 256 * 1024 * 1024;
+
+#[cfg(feature = "cli")]
+pub(crate) const FILLER_BLOCK_LEN: usize = 64 * 1024;
+
+pub(crate) const PAYLOAD_MAC_LEN: usize = 32;
+
+pub(crate) const NONCE_PREFIX_LEN: usize = 16;
+
+pub(crate) const ORIGINAL_COUNT: usize = 4;
+
+pub(crate) const BLOCK_SIZE: usize = 128;
+
+pub(crate) const COMPRESSION_LEVEL: i32 = 1;
+
+/// How far decrypted output may run past the size the header declares before decryption gives up.
+/// Anything within it is dropped rather than written.
+pub(crate) const MAX_OUTPUT_OVERRUN: u64 = BLOCK_SIZE as u64;
+
+pub(crate) const RECOVERY_COUNT: usize = 10;
+
+/// Header fields of at most this many bytes are stored as checksummed copies rather than
+/// Reed-Solomon shards.
+pub(crate) const COMPACT_SECTION_LEN: usize = 64;
+
+pub(crate) const COMPACT_COPIES: usize = 3;
+
+pub(crate) const CHUNK_SIZE: usize = 256 * 1024;
+
+#[cfg(feature = "cli")]
+/// The buffer each file stream gets, once, where it is opened. It holds the largest chunk, so whole
+/// chunks pass through without being split into several reads or writes.
+pub(crate) const IO_BUFFER_SIZE: usize = 1024 * 1024;
+
+pub(crate) const MIN_CHUNK_SIZE: usize = 4 * 1024;
+
+#[cfg(feature = "cli")]
+pub(crate) const REORDER_LIMIT: usize = 64 * 1024 * 1024;
+
+#[cfg(feature = "cli")]
+pub(crate) const MIN_SPLIT_SIZE: u64 = 64 * 1024;
+
+/// Magic, volume number, and the id shared by a file's volumes at the start of every split volume.
+pub(crate) const VOLUME_HEADER_LEN: usize = 16;
+
+pub(crate) const MAGIC_BYTES: u32 = 0xDEAD_BEEF;
+
+pub(crate) const CURRENT_VERSION: u16 = 0x000E;
+
+#[cfg(feature = "cli")]
+/// The oldest format still read, by `decrypt` and `migrate` only: no key slots, keys stretched
+/// straight from the password, and a random nonce in front of every layer of every chunk.
+pub(crate) const LEGACY_VERSION: u16 = 0x0002;
+
+#[cfg(feature = "cli")]
+/// The most a format 0x0002 header took up, which had no reserved capacity in front of it.
+pub(crate) const LEGACY_SECTION_SIZE: u32 = 1024;
+
+pub(crate) const ALGORITHM_AES: u8 = 0x01;
+
+pub(crate) const ALGORITHM_CHACHA: u8 = 0x02;
+
+pub(crate) const MAX_FILENAME_LEN: usize = 256;
+
+pub(crate) const MAX_CHUNK_SIZE: u32 = 1024 * 1024;
+
+pub(crate) const MAX_SECTION_SIZE: u32 = 16 * 1024;
+
+pub(crate) const MAX_DECOMPRESSED_SECTION_SIZE: usize = 256 * 1024;
+
+pub(crate) const MAX_PARAMS_SECTION_LEN: usize = 256;
+
+pub(crate) const MAX_SLOTS_SECTION_LEN: usize = MAX_KEY_SLOTS * SLOT_RESERVE_LEN;
+
+pub(crate) const MAX_METADATA_SECTION_LEN: usize = 4 * 1024;
+
+pub(crate) const HEADER_MAC_LEN: usize = 32;
+
+#[cfg(feature = "cli")]
+pub(crate) const PASSWORD_LEN: usize = 8;
+
+#[cfg(feature = "cli")]
+pub(crate) const CONFIG_DIR: &str = "sweetbyte";
+
+#[cfg(feature = "cli")]
+pub(crate) const CONFIG_FILE: &str = "config.toml";
+
+#[cfg(feature = "cli")]
+pub(crate) const HISTORY_FILE: &str = "history.json";
+
+#[cfg(feature = "cli")]
+pub(crate) const HISTORY_ENCRYPTED_FILE: &str = "history.json.swx";
+
+#[cfg(feature = "cli")]
+pub(crate) const HISTORY_LEN: usize = 10;
+
+#[cfg(feature = "cli")]
+/// Under a watched directory's state directory: the lock held for as long as a run works on it.
+pub(crate) const RUN_LOCK_FILE: &str = "run.lock";
+
+#[cfg(feature = "cli")]
+/// Under a watched directory's state directory: what earlier runs encrypted.
+pub(crate) const JOURNAL_FILE: &str = "journal.json";
+
+#[cfg(feature = "cli")]
+/// Seconds a notify command or webhook gets before it's given up on.
+pub(crate) const NOTIFY_TIMEOUT_SECS: u64 = 30;
+
+#[cfg(feature = "cli")]
+pub(crate) const ENV_PREFIX: &str = "SWEETBYTE_";
+
+pub(crate) const KDF_INFO: [[u8; 64]; 3] = [
+    [
+        0x65, 0x37, 0x34, 0x66, 0x62, 0x38, 0x65, 0x37, 0x65, 0x65, 0x62, 0x37, 0x62, 0x35, 0x32, 0x64, 0x61, 0x31, 0x33, 0x36, 0x66, 0x34, 0x38, 0x39, 0x36, 0x62, 0x30, 0x34, 0x30, 0x36, 0x61, 0x36,
+        0x30, 0x35, 0x31, 0x31, 0x62, 0x32, 0x63, 0x64, 0x30, 0x34, 0x32, 0x34, 0x36, 0x33, 0x30, 0x64, 0x35, 0x66, 0x65, 0x36, 0x37, 0x33, 0x39, 0x34, 0x64, 0x66, 0x63, 0x30, 0x64, 0x32, 0x34, 0x64,
+    ], // sweetbyte/v1/hkdf-sha256/key-derivation/role=first
+    [
+        0x63, 0x64, 0x36, 0x31, 0x63, 0x33, 0x36, 0x34, 0x65, 0x33, 0x62, 0x38, 0x63, 0x61, 0x30, 0x36, 0x66, 0x36, 0x31, 0x38, 0x30, 0x62, 0x34, 0x63, 0x64, 0x35, 0x31, 0x36, 0x39, 0x61, 0x34, 0x32,
+        0x66, 0x38, 0x62, 0x36, 0x62, 0x61, 0x64, 0x62, 0x63, 0x30, 0x66, 0x33, 0x30, 0x65, 0x61, 0x32, 0x35, 0x39, 0x33, 0x33, 0x64, 0x35, 0x65, 0x65, 0x39, 0x61, 0x36, 0x65, 0x31, 0x38, 0x33, 0x32,
+    ], // sweetbyte/v1/hkdf-sha256/key-derivation/role=second
+    [
+        0x66, 0x37, 0x33, 0x61, 0x35, 0x66, 0x36, 0x66, 0x65, 0x34, 0x32, 0x65, 0x39, 0x64, 0x33, 0x37, 0x34, 0x36, 0x37, 0x61, 0x63, 0x33, 0x36, 0x32, 0x64, 0x39, 0x35, 0x31, 0x30, 0x37, 0x34, 0x62,
+        0x31, 0x65, 0x62, 0x34, 0x33, 0x35, 0x65, 0x62, 0x64, 0x33, 0x65, 0x30, 0x32, 0x62, 0x61, 0x66, 0x62, 0x35, 0x38, 0x31, 0x30, 0x39, 0x65, 0x63, 0x61, 0x63, 0x36, 0x66, 0x36, 0x30, 0x61, 0x31,
+    ], // sweetbyte/v1/hkdf-sha256/key-derivation/role=third
+];
+
+#[cfg(feature = "cli")]
+pub(crate) const EXCLUDED_PATTERNS: &[&str] = &["target", "vendor", "node_modules", ".git", ".github", ".config", ".local", ".cache", ".ssh", ".gnupg", "*.rs", "*.go", "*.nix", "*.toml", "*.lock"];
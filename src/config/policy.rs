@@ -0,0 +1,67 @@
+use serde::Deserialize;
+
+use crate::cipher::{CipherMode, KdfParams};
+
+/// Floors that files being opened must meet, pinned under `[policy]` in the config file. A header
+/// declaring anything weaker is refused before the password is spent on it, unless `--allow-weak`
+/// is given. Only the config file can set these, so nothing in the environment or on the command
+/// line loosens them by accident.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Policy {
+    /// KiB the password must have been stretched with at least, whichever KDF did it.
+    pub(crate) min_kdf_memory: Option<u64>,
+    /// Passes Argon2id must have made at least; scrypt files are held to `min_kdf_memory` alone.
+    pub(crate) min_kdf_iterations: Option<u32>,
+    pub(crate) require_dual_cipher: bool,
+    pub(crate) require_payload_mac: bool,
+}
+
+impl Policy {
+    /// Whether anything is pinned at all.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.min_kdf_memory.is_none() && self.min_kdf_iterations.is_none() && !self.require_dual_cipher && !self.require_payload_mac
+    }
+
+    /// Each way a file with these header parameters falls short, as a phrase for the user; empty
+    /// when it doesn't.
+    pub(crate) fn shortfalls(&self, kdf: &KdfParams, cipher: CipherMode, payload_mac: bool) -> Vec<String> {
+        let mut shortfalls = Vec::new();
+        if let Some(min) = self.min_kdf_memory
+            && kdf.memory() < min
+        {
+            shortfalls.push(format!("kdf memory {} KiB is below the pinned {min} KiB", kdf.memory()));
+        }
+        if let (Some(min), KdfParams::Argon2id(params)) = (self.min_kdf_iterations, kdf)
+            && params.iterations < min
+        {
+            shortfalls.push(format!("{} argon2id passes are below the pinned {min}", params.iterations));
+        }
+        if self.require_dual_cipher && cipher != CipherMode::Dual {
+            shortfalls.push(format!("a single {cipher} cipher layer where both are required"));
+        }
+        if self.require_payload_mac && !payload_mac {
+            shortfalls.push("no payload mac where one is required".to_owned());
+        }
+
+        shortfalls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::{Argon2Params, ScryptParams};
+
+    #[test]
+    fn weaker_parameters_are_named() {
+        let policy = Policy { min_kdf_memory: Some(65536), min_kdf_iterations: Some(3), require_dual_cipher: true, require_payload_mac: true };
+
+        assert!(policy.shortfalls(&KdfParams::default(), CipherMode::Dual, true).is_empty());
+        let weak = KdfParams::Argon2id(Argon2Params { memory: 8192, iterations: 1, ..Argon2Params::default() });
+        assert_eq!(policy.shortfalls(&weak, CipherMode::Aes, false).len(), 4);
+
+        let scrypt = KdfParams::Scrypt(ScryptParams { log_n: 10, ..ScryptParams::default() });
+        assert_eq!(policy.shortfalls(&scrypt, CipherMode::Dual, true), ["kdf memory 1024 KiB is below the pinned 65536 KiB"]);
+    }
+}
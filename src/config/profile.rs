@@ -0,0 +1,51 @@
+use serde::Deserialize;
+
+use super::layer::{KdfLayer, Layer};
+use crate::cipher::{CipherMode, KdfAlgorithm};
+
+/// A named bundle of settings for new files, chosen with `--profile` or `profile` in the config
+/// file. It only fills in what nothing else sets: the config file, the environment, and flags given
+/// on the command line all win over it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, strum::EnumString, strum::Display)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub(crate) enum Profile {
+    /// One cipher layer, zstd's fastest level, and a lighter KDF.
+    Fast,
+    /// The built-in defaults.
+    Balanced,
+    /// Both cipher layers, a KDF costing 1 GiB, and a second copy of the header.
+    Paranoid,
+    /// Both cipher layers, strong compression, a second copy of the header, and a stronger KDF.
+    Archival,
+}
+
+impl Profile {
+    /// The settings this profile stands for. KDF costs are given for `algorithm`, since each takes
+    /// different ones.
+    pub(super) fn layer(self, algorithm: KdfAlgorithm) -> Layer {
+        match self {
+            Self::Fast => Layer { kdf: Self::kdf(algorithm, 32 * 1024, 2, 15), cipher: Some(CipherMode::Aes), compression_level: Some(-3), ..Layer::default() },
+            Self::Balanced => Layer::default(),
+            Self::Paranoid => Layer { kdf: Self::kdf(algorithm, 1024 * 1024, 8, 20), cipher: Some(CipherMode::Dual), header_copy: Some(true), payload_mac: Some(true), ..Layer::default() },
+            Self::Archival => Layer {
+                kdf: Self::kdf(algorithm, 256 * 1024, 4, 18),
+                cipher: Some(CipherMode::Dual),
+                compression_level: Some(19),
+                header_copy: Some(true),
+                payload_mac: Some(true),
+                chunk_index: Some(true),
+                ..Layer::default()
+            },
+        }
+    }
+
+    /// Argon2id takes `memory` KiB over `iterations` passes; scrypt takes N = 2^`log_n`.
+    fn kdf(algorithm: KdfAlgorithm, memory: u32, iterations: u32, log_n: u8) -> KdfLayer {
+        match algorithm {
+            KdfAlgorithm::Argon2id => KdfLayer { memory: Some(memory), iterations: Some(iterations), ..KdfLayer::default() },
+            KdfAlgorithm::Scrypt => KdfLayer { log_n: Some(log_n), ..KdfLayer::default() },
+        }
+    }
+}
@@ -0,0 +1,106 @@
+#[cfg(feature = "cli")]
+use std::num::NonZeroUsize;
+#[cfg(feature = "cli")]
+use std::time::Duration;
+
+use super::{CHUNK_SIZE, COMPRESSION_LEVEL};
+#[cfg(feature = "cli")]
+use super::{EXCLUDED_PATTERNS, Policy, REORDER_LIMIT};
+#[cfg(feature = "cli")]
+use crate::cipher::CipherMode;
+use crate::cipher::KdfParams;
+#[cfg(feature = "cli")]
+use crate::files::FsyncPolicy;
+use crate::padding::PaddingScheme;
+#[cfg(feature = "cli")]
+use crate::ui::{HistoryMode, ProgressStyle};
+
+#[derive(Clone)]
+pub(crate) struct Settings {
+    #[cfg(feature = "cli")]
+    pub(crate) threads: NonZeroUsize,
+    pub(crate) kdf: KdfParams,
+    /// Which cipher layers new files get when `--cipher` isn't given.
+    #[cfg(feature = "cli")]
+    pub(crate) cipher: CipherMode,
+    pub(crate) compression_level: i32,
+    pub(crate) chunk_size: usize,
+    #[cfg(feature = "cli")]
+    pub(crate) progress: ProgressStyle,
+    #[cfg(feature = "cli")]
+    pub(crate) shred_passes: u32,
+    #[cfg(feature = "cli")]
+    pub(crate) exclude: Vec<String>,
+    #[cfg(feature = "cli")]
+    pub(crate) fsync: FsyncPolicy,
+    #[cfg(feature = "cli")]
+    pub(crate) reorder_limit: usize,
+    pub(crate) payload_mac: bool,
+    pub(crate) padding: PaddingScheme,
+    #[cfg(feature = "cli")]
+    pub(crate) chunk_index: bool,
+    /// Whether a copy of the header ends the file, to read it by when the one at the start is
+    /// destroyed.
+    #[cfg(feature = "cli")]
+    pub(crate) header_copy: bool,
+    #[cfg(feature = "cli")]
+    pub(crate) history: HistoryMode,
+    /// Whether a batch stretches each password once and derives every file's keys from that.
+    #[cfg(feature = "cli")]
+    pub(crate) session_kdf: bool,
+    /// How long an interactive batch keeps unused passwords; `None` keeps the first one for the
+    /// whole batch.
+    #[cfg(feature = "cli")]
+    pub(crate) password_cache: Option<Duration>,
+    /// Shell command run once a batch or watch ends, with the run's JSON summary on stdin.
+    #[cfg(feature = "cli")]
+    pub(crate) notify_cmd: Option<String>,
+    /// Where the run's JSON summary is posted once a batch or watch ends.
+    #[cfg(feature = "cli")]
+    pub(crate) notify_url: Option<String>,
+    /// Floors that files being opened must meet.
+    #[cfg(feature = "cli")]
+    pub(crate) policy: Policy,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "cli")]
+            threads: std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+            kdf: KdfParams::default(),
+            #[cfg(feature = "cli")]
+            cipher: CipherMode::default(),
+            compression_level: COMPRESSION_LEVEL,
+            chunk_size: CHUNK_SIZE,
+            #[cfg(feature = "cli")]
+            progress: ProgressStyle::default(),
+            #[cfg(feature = "cli")]
+            shred_passes: 0,
+            #[cfg(feature = "cli")]
+            exclude: EXCLUDED_PATTERNS.iter().map(|pattern| (*pattern).to_owned()).collect(),
+            #[cfg(feature = "cli")]
+            fsync: FsyncPolicy::default(),
+            #[cfg(feature = "cli")]
+            reorder_limit: REORDER_LIMIT,
+            payload_mac: true,
+            padding: PaddingScheme::default(),
+            #[cfg(feature = "cli")]
+            chunk_index: true,
+            #[cfg(feature = "cli")]
+            header_copy: false,
+            #[cfg(feature = "cli")]
+            history: HistoryMode::default(),
+            #[cfg(feature = "cli")]
+            session_kdf: false,
+            #[cfg(feature = "cli")]
+            password_cache: None,
+            #[cfg(feature = "cli")]
+            notify_cmd: None,
+            #[cfg(feature = "cli")]
+            notify_url: None,
+            #[cfg(feature = "cli")]
+            policy: Policy::default(),
+        }
+    }
+}
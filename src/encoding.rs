@@ -1,10 +1,28 @@
+use std::cell::RefCell;
+#[cfg(any(feature = "cli", test))]
+use std::ops::Range;
+
 use anyhow::{Context, Result};
+use reed_solomon_simd::{ReedSolomonDecoder, ReedSolomonEncoder};
 use subtle::ConstantTimeEq;
 
+use crate::error::SweetByteError;
+
 const LEN: usize = 4;
 const CRC: usize = 4;
 const MIN: usize = 2;
 
+thread_local! {
+    /// Reed-Solomon work buffers each thread keeps between blocks, resized when the shard size changes.
+    static ENCODER: RefCell<Option<ReedSolomonEncoder>> = const { RefCell::new(None) };
+    static DECODER: RefCell<Option<ReedSolomonDecoder>> = const { RefCell::new(None) };
+}
+
+/// Reed-Solomon over `original_count` data shards and `recovery_count` parity shards. Every shard
+/// is stored behind a CRC-32 of itself, and decoding treats a shard whose CRC-32 doesn't match as
+/// an erasure, so any `original_count` intact shards rebuild the data. The data's length is sharded
+/// along with it, so what comes back is exactly what went in; the copy in front of the shards only
+/// bounds the size before anything is decoded.
 pub(crate) struct Encoding {
     original_count: usize,
     recovery_count: usize,
@@ -21,66 +39,405 @@ impl Encoding {
         Ok(Self { original_count, recovery_count, total_count })
     }
 
+    /// Length of what [`Self::encode`] produces from `len` bytes: the length prefix, then every
+    /// shard with its checksum.
+    pub(crate) fn encoded_len(&self, len: usize) -> usize {
+        LEN.saturating_add(self.total_count.saturating_mul(CRC.saturating_add(self.shard_size(LEN.saturating_add(len)))))
+    }
+
+    fn shard_size(&self, len: usize) -> usize {
+        len.div_ceil(self.original_count).next_multiple_of(MIN).max(MIN)
+    }
+
+    /// Lays the length prefix and `data` straight into the original shards' places in the output
+    /// and writes the recovery shards after them, so the data is copied once and nothing but
+    /// the output is allocated.
     #[inline]
     pub(crate) fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
-        let shard_size = data.len().div_ceil(self.original_count).next_multiple_of(MIN).max(MIN);
+        let len = u32::try_from(data.len())?.to_le_bytes();
+        let shard_size = self.shard_size(LEN.saturating_add(data.len()));
+        let stride = CRC.saturating_add(shard_size);
+
+        let mut result = vec![0u8; self.encoded_len(data.len())];
+        let (prefix, slots) = result.split_first_chunk_mut::<LEN>().context("invalid shard slice")?;
+        *prefix = len;
 
-        let mut original = vec![0u8; self.original_count.saturating_mul(shard_size)];
-        for (shard, chunk) in original.chunks_mut(shard_size).zip(data.chunks(shard_size)) {
-            let prefix = shard.get_mut(..chunk.len()).context("invalid shard slice")?;
-            prefix.copy_from_slice(chunk);
+        let mut source = [len.as_slice(), data];
+        for slot in slots.chunks_exact_mut(stride).take(self.original_count) {
+            scatter(slot.get_mut(CRC..).unwrap_or_default(), &mut source);
         }
 
-        let mut result = Vec::with_capacity(LEN.saturating_add(self.total_count.saturating_mul(CRC.saturating_add(shard_size))));
-        result.extend_from_slice(&u32::try_from(data.len())?.to_le_bytes());
+        ENCODER.with_borrow_mut(|cached| {
+            let mut encoder = match cached.take() {
+                Some(mut encoder) => encoder.reset(self.original_count, self.recovery_count, shard_size).map(|()| encoder),
+                None => ReedSolomonEncoder::new(self.original_count, self.recovery_count, shard_size),
+            }
+            .context("failed to initialize reed-solomon encoder")?;
+
+            for slot in slots.chunks_exact(stride).take(self.original_count) {
+                encoder.add_original_shard(slot.get(CRC..).unwrap_or_default()).context("failed to encode reed-solomon shards")?;
+            }
+            {
+                let recovery = encoder.encode().context("failed to encode reed-solomon shards")?;
+                for (slot, shard) in slots.chunks_exact_mut(stride).skip(self.original_count).zip(recovery.recovery_iter()) {
+                    slot.get_mut(CRC..).unwrap_or_default().copy_from_slice(shard);
+                }
+            }
+            *cached = Some(encoder);
+
+            anyhow::Ok(())
+        })?;
 
-        let recovery = reed_solomon_simd::encode(self.original_count, self.recovery_count, original.chunks(shard_size)).context("failed to encode reed-solomon shards")?;
-        for shard in original.chunks(shard_size).chain(recovery.iter().map(Vec::as_slice)) {
-            result.extend_from_slice(&crc32fast::hash(shard).to_le_bytes());
-            result.extend_from_slice(shard);
+        for slot in slots.chunks_exact_mut(stride) {
+            let (crc, shard) = slot.split_at_mut(CRC);
+            crc.copy_from_slice(&crc32fast::hash(shard).to_le_bytes());
         }
 
         Ok(result)
     }
 
+    /// Size of the original data as recorded at the front of an encoded block, read without
+    /// decoding anything. Nothing protects this copy, so it is only good for turning away
+    /// blocks that claim too much; [`Self::decode`] goes by the one inside the shards.
+    pub(crate) fn decoded_len(data: &[u8]) -> Result<usize> {
+        let len_bytes = data.first_chunk::<LEN>().context("data too short")?;
+
+        usize::try_from(u32::from_le_bytes(*len_bytes)).context("decoded length overflow")
+    }
+
+    /// Counts shards whose checksum still matches, without reconstructing anything.
+    pub(crate) fn intact_shards(&self, data: &[u8]) -> Result<usize> {
+        let shard_bytes = data.get(LEN..).context("data too short")?;
+        let shard_size = shard_bytes.len().checked_div(self.total_count).context("invalid shard count")?;
+        if shard_size <= CRC {
+            anyhow::bail!("invalid shard size");
+        }
+
+        Ok(shard_bytes
+            .chunks_exact(shard_size)
+            .filter(|chunk| {
+                chunk
+                    .split_first_chunk::<CRC>()
+                    .is_some_and(|(crc, shard)| bool::from(crc.ct_eq(&crc32fast::hash(shard).to_le_bytes())))
+            })
+            .count())
+    }
+
+    /// Where each shard, checksum included, sits in an encoded block of `len` bytes.
+    #[cfg(any(feature = "cli", test))]
+    pub(crate) fn shard_spans(&self, len: usize) -> Vec<Range<usize>> {
+        let stride = len.saturating_sub(LEN).checked_div(self.total_count).unwrap_or(0);
+
+        (0..self.total_count)
+            .map(|index| {
+                let start = LEN.saturating_add(index.saturating_mul(stride));
+                start..start.saturating_add(stride)
+            })
+            .collect()
+    }
+
+    pub(crate) fn total_count(&self) -> usize {
+        self.total_count
+    }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn original_count(&self) -> usize {
+        self.original_count
+    }
+
     #[inline]
     pub(crate) fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
-        let (len_bytes, shard_bytes) = data.split_at_checked(LEN).context("data too short")?;
-        let len_bytes: [u8; LEN] = len_bytes.try_into().context("invalid header length")?;
-        let original_size = u32::from_le_bytes(len_bytes) as usize;
+        self.reconstruct(&self.shards(data)?, None)
+    }
+
+    /// Splits an encoded block into the shards whose checksum still matches. Shards that fail it
+    /// are left out, so Reed-Solomon rebuilds them as erasures.
+    pub(crate) fn shards<'a>(&self, data: &'a [u8]) -> Result<Shards<'a>> {
+        let shard_bytes = data.get(LEN..).context("data too short")?;
         let shard_size = shard_bytes.len().checked_div(self.total_count).context("invalid shard count")?;
         if shard_size <= CRC {
             anyhow::bail!("invalid shard size");
         }
 
-        let mut original = Vec::with_capacity(self.original_count);
-        let mut recovery = Vec::with_capacity(self.recovery_count);
+        let intact = shard_bytes
+            .chunks_exact(shard_size)
+            .enumerate()
+            .filter_map(|(index, chunk)| {
+                let (crc, shard) = chunk.split_at(CRC);
+                bool::from(crc.ct_eq(&crc32fast::hash(shard).to_le_bytes())).then_some((index, shard))
+            })
+            .collect();
 
-        for (index, chunk) in shard_bytes.chunks_exact(shard_size).enumerate() {
-            let (crc, shard) = chunk.split_at(CRC);
-            if !bool::from(crc.ct_eq(&crc32fast::hash(shard).to_le_bytes())) {
-                continue;
-            }
-            if index < self.original_count {
-                original.push((index, shard));
-            } else {
-                recovery.push((index.saturating_sub(self.original_count), shard));
+        Ok(Shards { intact })
+    }
+
+    /// Rebuilds the original data from the intact `shards`, leaving out `without` as though its
+    /// checksum had failed. The data is copied once, straight out of the shards it lies in.
+    pub(crate) fn reconstruct(&self, shards: &Shards<'_>, without: Option<usize>) -> Result<Vec<u8>> {
+        self.rebuild(shards, without, |shard| self.join(shards.shard_size(), shard))
+    }
+
+    /// Decodes a block written by format 0x0002, whose shards hold only the data, zero-padded; its
+    /// length is the unprotected copy in front of them.
+    #[cfg(feature = "cli")]
+    pub(crate) fn decode_legacy(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let len = Self::decoded_len(data)?;
+        let shards = self.shards(data)?;
+
+        self.rebuild(&shards, None, |shard| {
+            if len > self.original_count.saturating_mul(shards.shard_size()) {
+                anyhow::bail!("decoded length exceeds the shards");
             }
+            let mut decoded = vec![0u8; len];
+            gather((0..self.original_count).map(|index| shard(index).with_context(|| format!("missing shard {index}"))), 0, &mut decoded)?;
+
+            Ok(decoded)
+        })
+    }
+
+    /// Hands `join` every original shard, rebuilding those missing from the intact `shards` other
+    /// than `without`.
+    fn rebuild(&self, shards: &Shards<'_>, without: Option<usize>, join: impl for<'s> FnOnce(&dyn Fn(usize) -> Option<&'s [u8]>) -> Result<Vec<u8>>) -> Result<Vec<u8>> {
+        let original = |index: usize| shards.kept(without).find(|&(intact, _)| intact == index).map(|(_, shard)| shard);
+        if shards.kept(without).filter(|&(index, _)| index < self.original_count).count() == self.original_count {
+            return join(&original);
         }
 
-        let mut result = Vec::with_capacity(self.original_count.saturating_mul(shard_size.saturating_sub(CRC)));
-        if original.len() == self.original_count {
-            for (_, shard) in original {
-                result.extend_from_slice(shard);
+        let intact_shards = shards.kept(without).count();
+        if intact_shards < self.original_count {
+            return Err(SweetByteError::Unrecoverable { intact_shards, required_shards: self.original_count, total_shards: self.total_count }.into());
+        }
+
+        DECODER.with_borrow_mut(|cached| {
+            let mut decoder = match cached.take() {
+                Some(mut decoder) => decoder.reset(self.original_count, self.recovery_count, shards.shard_size()).map(|()| decoder),
+                None => ReedSolomonDecoder::new(self.original_count, self.recovery_count, shards.shard_size()),
             }
-        } else {
-            let restored = reed_solomon_simd::decode(self.original_count, self.recovery_count, original, recovery).context("failed to decode reed-solomon shards")?;
-            for index in 0..self.original_count {
-                result.extend_from_slice(restored.get(&index).with_context(|| format!("missing shard {index}"))?);
+            .context("failed to initialize reed-solomon decoder")?;
+
+            for (index, shard) in shards.kept(without) {
+                match index.checked_sub(self.original_count) {
+                    None => decoder.add_original_shard(index, shard),
+                    Some(recovery) => decoder.add_recovery_shard(recovery, shard),
+                }
+                .context("failed to decode reed-solomon shards")?;
             }
+            let joined = {
+                let restored = decoder.decode().context("failed to decode reed-solomon shards")?;
+                join(&|index| original(index).or_else(|| restored.restored_original(index)))
+            };
+            *cached = Some(decoder);
+
+            joined
+        })
+    }
+
+    /// Reads the length prefix across the original shards, then copies that much data out of them.
+    fn join<'s>(&self, shard_size: usize, shard: impl Fn(usize) -> Option<&'s [u8]>) -> Result<Vec<u8>> {
+        let shard = &shard;
+        let shards = move || (0..self.original_count).map(move |index| shard(index).with_context(|| format!("missing shard {index}")));
+
+        let mut len_bytes = [0u8; LEN];
+        gather(shards(), 0, &mut len_bytes)?;
+        let len = usize::try_from(u32::from_le_bytes(len_bytes)).context("decoded length overflow")?;
+        if len > self.original_count.saturating_mul(shard_size).saturating_sub(LEN) {
+            anyhow::bail!("decoded length exceeds the shards");
         }
-        result.truncate(original_size);
 
-        Ok(result)
+        let mut data = vec![0u8; len];
+        gather(shards(), LEN, &mut data)?;
+
+        Ok(data)
+    }
+
+    /// The shards worth leaving out, one at a time, when a reconstruction passes every checksum but
+    /// fails to authenticate: those it was built from, as long as enough intact ones remain to
+    /// rebuild without each. When every original shard is intact only they were used, so only
+    /// they are suspect.
+    pub(crate) fn suspects(&self, shards: &Shards<'_>) -> Vec<usize> {
+        if shards.intact.len() <= self.original_count {
+            return Vec::new();
+        }
+        let originals_intact = shards.intact.iter().filter(|(index, _)| *index < self.original_count).count() == self.original_count;
+
+        shards
+            .intact
+            .iter()
+            .map(|(index, _)| *index)
+            .filter(|index| !originals_intact || *index < self.original_count)
+            .collect()
+    }
+}
+
+/// An encoded block split into its shards, of which only those whose checksum matched are kept.
+pub(crate) struct Shards<'a> {
+    intact: Vec<(usize, &'a [u8])>,
+}
+
+impl<'a> Shards<'a> {
+    fn kept(&self, without: Option<usize>) -> impl Iterator<Item = (usize, &'a [u8])> + '_ {
+        self.intact.iter().copied().filter(move |&(index, _)| Some(index) != without)
+    }
+
+    fn shard_size(&self) -> usize {
+        self.intact.first().map_or(0, |(_, shard)| shard.len())
+    }
+}
+
+/// Copies the front of `parts`, in order, into `slot` and moves each part past what was taken; a
+/// slot the parts run out in is left as it was from there on.
+fn scatter(slot: &mut [u8], parts: &mut [&[u8]]) {
+    let mut filled = 0usize;
+    for part in parts.iter_mut() {
+        let free = slot.get_mut(filled..).unwrap_or_default();
+        let (taken, rest) = part.split_at(part.len().min(free.len()));
+        free.get_mut(..taken.len()).unwrap_or_default().copy_from_slice(taken);
+        *part = rest;
+        filled = filled.saturating_add(taken.len());
+    }
+}
+
+/// Fills `out` from the bytes of `shards` laid end to end, starting `skip` bytes in.
+fn gather<'s>(shards: impl Iterator<Item = Result<&'s [u8]>>, mut skip: usize, out: &mut [u8]) -> Result<()> {
+    let mut filled = 0usize;
+    for shard in shards {
+        if filled == out.len() {
+            break;
+        }
+        let shard = shard?;
+        let Some(shard) = shard.get(skip..) else {
+            skip = skip.saturating_sub(shard.len());
+            continue;
+        };
+        skip = 0;
+
+        let free = out.get_mut(filled..).unwrap_or_default();
+        let taken = shard.get(..shard.len().min(free.len())).unwrap_or_default();
+        free.get_mut(..taken.len()).unwrap_or_default().copy_from_slice(taken);
+        filled = filled.saturating_add(taken.len());
+    }
+
+    if filled < out.len() {
+        anyhow::bail!("decoded length exceeds the shards");
+    }
+
+    Ok(())
+}
+
+/// Starts a block written by [`Replicas`]. A Reed-Solomon block starts with its decoded length
+/// instead, which never comes near it, so the two can share a field.
+pub(crate) const REPLICA_MARKER: u32 = u32::MAX;
+
+/// `copies` copies of a small block, each behind a CRC-32 of itself, for data too short to be worth
+/// splitting into shards. A copy's length follows from the block's, so there is no separate length
+/// field to lose; the first copy whose checksum matches is the data.
+pub(crate) struct Replicas {
+    copies: usize,
+}
+
+impl Replicas {
+    pub(crate) fn new(copies: usize) -> Result<Self> {
+        if copies == 0 {
+            anyhow::bail!("at least one copy is required");
+        }
+
+        Ok(Self { copies })
+    }
+
+    /// Length of what [`Self::encode`] produces from `len` bytes: the marker, then every copy with
+    /// its checksum.
+    pub(crate) fn encoded_len(&self, len: usize) -> usize {
+        LEN.saturating_add(self.copies.saturating_mul(CRC.saturating_add(len)))
+    }
+
+    pub(crate) fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.encoded_len(data.len()));
+        result.extend_from_slice(&REPLICA_MARKER.to_le_bytes());
+        for _ in 0..self.copies {
+            result.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+            result.extend_from_slice(data);
+        }
+
+        result
+    }
+
+    /// Whether `data` was written by [`Self::encode`] rather than [`Encoding::encode`].
+    pub(crate) fn matches(data: &[u8]) -> bool {
+        data.first_chunk::<LEN>().is_some_and(|marker| u32::from_le_bytes(*marker) == REPLICA_MARKER)
+    }
+
+    /// Size of one copy without its checksum, worked out from the block's length.
+    pub(crate) fn decoded_len(&self, data: &[u8]) -> Result<usize> {
+        let copies = data.get(LEN..).context("data too short")?;
+        if copies.len().checked_rem(self.copies) != Some(0) {
+            anyhow::bail!("block does not split into {} copies", self.copies);
+        }
+
+        copies.len().checked_div(self.copies).and_then(|len| len.checked_sub(CRC)).context("data too short")
+    }
+
+    pub(crate) fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let copy_len = self.decoded_len(data)?.saturating_add(CRC);
+        let copies = data.get(LEN..).context("data too short")?;
+
+        copies
+            .chunks_exact(copy_len)
+            .find_map(|chunk| {
+                let (crc, copy) = chunk.split_at(CRC);
+                bool::from(crc.ct_eq(&crc32fast::hash(copy).to_le_bytes())).then(|| copy.to_vec())
+            })
+            .ok_or_else(|| SweetByteError::Unrecoverable { intact_shards: 0, required_shards: 1, total_shards: self.copies }.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replicas_survive_all_but_one_damaged_copy() {
+        let replicas = Replicas::new(3).unwrap();
+        let data = [5u8; 32];
+        let mut encoded = replicas.encode(&data);
+        assert!(Replicas::matches(&encoded) && encoded.len() == replicas.encoded_len(data.len()));
+        assert!(!Replicas::matches(&Encoding::new(4, 10).unwrap().encode(&data).unwrap()));
+
+        encoded[LEN + 1] ^= 1;
+        encoded[LEN + CRC + data.len() + CRC] ^= 1;
+        assert_eq!(replicas.decode(&encoded).unwrap(), data);
+
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+        replicas.decode(&encoded).unwrap_err();
+        replicas.decode(&encoded[..encoded.len() - 1]).unwrap_err();
+    }
+
+    #[test]
+    fn lost_original_shards_are_rebuilt_at_every_size() {
+        let encoding = Encoding::new(4, 10).unwrap();
+        // Sizes alternate so each thread's decoder is reset to a different shard size between blocks.
+        for len in [1000, 0, 70_000, 1, 5, 262_144] {
+            let data: Vec<u8> = (0..len).map(|byte: usize| byte.to_le_bytes()[0]).collect();
+            let mut encoded = encoding.encode(&data).unwrap();
+            // Zeros rather than 0xFF: four 0xFF bytes are their own CRC-32, so a small shard would still pass.
+            for span in &encoding.shard_spans(encoded.len())[..3] {
+                encoded[span.clone()].fill(0);
+            }
+
+            assert_eq!(encoding.decode(&encoded).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn decoding_ignores_a_damaged_length_prefix() {
+        let encoding = Encoding::new(4, 10).unwrap();
+        for len in [0, 1, 5, 32, 1000] {
+            let data = vec![9u8; len];
+            let mut encoded = encoding.encode(&data).unwrap();
+            assert_eq!(encoded.len(), encoding.encoded_len(len));
+
+            encoded[..LEN].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert_eq!(encoding.decode(&encoded).unwrap(), data);
+        }
     }
 }
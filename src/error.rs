@@ -0,0 +1,100 @@
+//! The failures callers may want to tell apart: a wrong password, a damaged header or payload, a
+//! partial recovery, an unsupported format version, a file weaker than the pinned policy, I/O, and
+//! cancellation. They travel inside `anyhow::Error` like every other error, with context added on
+//! top, and [`SweetByteError::classify`] finds them again.
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// A failure with a stable kind, serialized as `{"kind": "...", ...}` in `--output-format json`
+/// error reports.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum SweetByteError {
+    /// No key slot opens with the password, or the header MAC doesn't match.
+    WrongPassword,
+    /// The header can't be parsed or fails its checks.
+    CorruptHeader,
+    /// Too few shards of a chunk survived for Reed-Solomon to rebuild it.
+    Unrecoverable { intact_shards: usize, required_shards: usize, total_shards: usize },
+    /// `--keep-going` wrote the file, but with zeros in place of these plaintext ranges, each
+    /// `[start, end)`.
+    PartialRecovery { lost_bytes: u64, lost_ranges: Vec<(u64, u64)> },
+    /// Reading or writing failed; `io_kind` is the `std::io::ErrorKind`.
+    Io { io_kind: String },
+    /// The run was cancelled before it finished.
+    Cancelled,
+    /// The header was written by a format version this build doesn't read.
+    UnsupportedVersion { found: u16, supported: u16 },
+    /// The header declares parameters weaker than the config's pinned `[policy]`, one phrase for
+    /// each.
+    WeakParameters { shortfalls: Vec<String> },
+    /// Anything without a kind of its own.
+    Other,
+}
+
+impl SweetByteError {
+    /// The kind of `error`: the innermost `SweetByteError` in its chain, then one added as context,
+    /// then the innermost I/O error, or `Other`.
+    pub fn classify(error: &anyhow::Error) -> Self {
+        let typed = error.chain().rev().find_map(|cause| cause.downcast_ref::<Self>()).or_else(|| error.downcast_ref::<Self>());
+        if let Some(typed) = typed {
+            return typed.clone();
+        }
+
+        error
+            .chain()
+            .rev()
+            .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .map_or(Self::Other, |io| Self::Io { io_kind: format!("{:?}", io.kind()) })
+    }
+}
+
+impl fmt::Display for SweetByteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongPassword => f.write_str("incorrect password or corrupted file"),
+            Self::CorruptHeader => f.write_str("failed to deserialize header"),
+            Self::Unrecoverable { intact_shards, required_shards, total_shards } => {
+                write!(f, "only {intact_shards} of {total_shards} shards are intact, {required_shards} are needed to recover the chunk")
+            }
+            Self::PartialRecovery { lost_bytes, lost_ranges } => {
+                let ranges: Vec<String> = lost_ranges.iter().map(|(start, end)| format!("{start}-{end}")).collect();
+                write!(f, "only partially recovered: {lost_bytes} byte(s) lost and written as zeros at {} (end exclusive)", ranges.join(", "))
+            }
+            Self::Io { io_kind } => write!(f, "i/o error ({io_kind})"),
+            Self::Cancelled => f.write_str("operation cancelled"),
+            Self::UnsupportedVersion { found, supported } => write!(f, "unsupported format version 0x{found:04X} (this build reads 0x{supported:04X})"),
+            Self::WeakParameters { shortfalls } => write!(f, "file falls short of the pinned policy: {}", shortfalls.join(", ")),
+            Self::Other => f.write_str("unclassified error"),
+        }
+    }
+}
+
+impl std::error::Error for SweetByteError {}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn innermost_kind_wins_through_context() {
+        let error = Err::<(), _>(SweetByteError::UnsupportedVersion { found: 3, supported: 11 })
+            .context(SweetByteError::CorruptHeader)
+            .context("failed to open source file")
+            .unwrap_err();
+        assert_eq!(SweetByteError::classify(&error), SweetByteError::UnsupportedVersion { found: 3, supported: 11 });
+
+        let error = Err::<(), _>(std::io::Error::from(std::io::ErrorKind::NotFound)).context("failed to open source file").unwrap_err();
+        assert_eq!(SweetByteError::classify(&error), SweetByteError::Io { io_kind: "NotFound".to_owned() });
+        let error = anyhow::anyhow!("params section claims 1024 bytes")
+            .context(SweetByteError::CorruptHeader)
+            .context("failed to open source file");
+        assert_eq!(SweetByteError::classify(&error), SweetByteError::CorruptHeader);
+        assert_eq!(SweetByteError::classify(&anyhow::anyhow!("something else")), SweetByteError::Other);
+    }
+}
@@ -0,0 +1,133 @@
+//! Upper bounds on how large an encrypted file comes out, so there's a way to tell whether it will
+//! fit before any of it is written.
+
+pub use crate::cipher::CipherMode;
+use crate::config::{BLOCK_SIZE, CHUNK_SIZE, ORIGINAL_COUNT, RECOVERY_COUNT, VOLUME_HEADER_LEN};
+use crate::encoding::Encoding;
+use crate::header::{COPY_TAIL_LEN, MAX_FRAMED_LEN};
+pub use crate::pipeline::PadSize;
+use crate::pipeline::{INDEX_TAIL_LEN, TRAILER_BODY_LEN};
+
+/// The most bytes postcard spends on a varint-encoded `u64`.
+const MAX_VARINT_LEN: usize = 10;
+
+/// What, besides the length of the input, decides how large the output gets.
+#[derive(Clone, Copy)]
+pub struct EstimateOptions {
+    /// Plaintext bytes per chunk.
+    pub chunk_size: usize,
+    /// The cipher layers each chunk is sealed with.
+    pub cipher: CipherMode,
+    /// Whether a payload MAC trailer follows the last chunk.
+    pub payload_mac: bool,
+    /// Whether a chunk index footer ends the file.
+    pub chunk_index: bool,
+    /// Whether a copy of the header follows the chunk index.
+    pub header_copy: bool,
+    /// The size the finished file is padded up to.
+    pub pad_size: PadSize,
+    /// The size of each volume when the output is split.
+    pub split_size: Option<u64>,
+}
+
+impl Default for EstimateOptions {
+    /// The layout a file encrypted with the default settings has.
+    fn default() -> Self {
+        Self { chunk_size: CHUNK_SIZE, cipher: CipherMode::default(), payload_mac: true, chunk_index: true, header_copy: false, pad_size: PadSize::None, split_size: None }
+    }
+}
+
+/// An upper bound on the bytes an encrypted file takes up on disk, across all of its volumes when
+/// split, for `input_len` bytes of plaintext. Every chunk is assumed to be incompressible, to need
+/// a whole block of padding, and to sit behind the largest header the format allows, so real files
+/// come out smaller.
+pub fn estimate_output_size(input_len: u64, options: &EstimateOptions) -> u64 {
+    let Ok(encoding) = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT) else {
+        return u64::MAX;
+    };
+
+    let chunk_size = u64::try_from(options.chunk_size.max(1)).unwrap_or(u64::MAX);
+    let full_chunks = input_len.checked_div(chunk_size).unwrap_or(0);
+    let last_chunk = input_len.checked_rem(chunk_size).unwrap_or(0);
+    let chunks = full_chunks.saturating_add(u64::from(last_chunk != 0));
+
+    let mut total = (MAX_FRAMED_LEN as u64)
+        .saturating_add(record_len(&encoding, chunk_size, options.cipher).saturating_mul(full_chunks))
+        .saturating_add(if last_chunk == 0 { 0 } else { record_len(&encoding, last_chunk, options.cipher) });
+    if options.payload_mac {
+        total = total.saturating_add(size_of::<u32>().saturating_add(TRAILER_BODY_LEN) as u64);
+    }
+    if options.chunk_index {
+        total = total.saturating_add(index_len(&encoding, chunks));
+    }
+    if options.chunk_index && options.header_copy {
+        total = total.saturating_add(MAX_FRAMED_LEN.saturating_add(COPY_TAIL_LEN) as u64);
+    }
+    if options.pad_size.is_enabled() {
+        total = total.saturating_add(size_of::<u32>() as u64).saturating_add(options.pad_size.filler(total));
+    }
+    if let Some(split_size) = options.split_size {
+        let room = split_size.saturating_sub(VOLUME_HEADER_LEN as u64).max(1);
+        total = total.saturating_add(total.div_ceil(room).saturating_mul(VOLUME_HEADER_LEN as u64));
+    }
+
+    total
+}
+
+/// A chunk of `plaintext_len` bytes once it has been through every stage, with the length in front
+/// of it: zstd's worst case, PKCS7 padding, a nonce and tag per cipher layer, and the Reed-Solomon
+/// shards.
+fn record_len(encoding: &Encoding, plaintext_len: u64, cipher: CipherMode) -> u64 {
+    let compressed = zstd::zstd_safe::compress_bound(usize::try_from(plaintext_len).unwrap_or(usize::MAX));
+    let padded = compressed.saturating_div(BLOCK_SIZE).saturating_add(1).saturating_mul(BLOCK_SIZE);
+    let sealed = padded.saturating_add(cipher.overhead());
+
+    size_of::<u32>().saturating_add(encoding.encoded_len(sealed)) as u64
+}
+
+/// The chunk index footer for `chunks` chunks, with every offset taking the longest varint it
+/// could.
+fn index_len(encoding: &Encoding, chunks: u64) -> u64 {
+    let entries = usize::try_from(chunks).unwrap_or(usize::MAX).saturating_mul(MAX_VARINT_LEN.saturating_mul(2));
+    let body = MAX_VARINT_LEN.saturating_add(entries).saturating_add(blake3::OUT_LEN);
+
+    size_of::<u32>().saturating_add(encoding.encoded_len(body)).saturating_add(INDEX_TAIL_LEN) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::{RandomSource, SystemRandom};
+    use crate::config::{MIN_CHUNK_SIZE, Settings};
+    use crate::secret::Secret;
+
+    #[test]
+    fn estimate_covers_incompressible_input() {
+        let secret = Secret::new(b"password".to_vec());
+        for (cipher, len) in [(CipherMode::Dual, MIN_CHUNK_SIZE * 3), (CipherMode::Aes, MIN_CHUNK_SIZE * 2 + 17), (CipherMode::Chacha, 1)] {
+            let settings = Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() };
+            let mut plaintext = vec![0u8; len];
+            SystemRandom.fill(&mut plaintext).unwrap();
+
+            let encrypted = crate::memory::encrypt_bytes(&plaintext, &secret, cipher, &settings, &SystemRandom).unwrap().len() as u64;
+            let options = EstimateOptions { chunk_size: MIN_CHUNK_SIZE, cipher, chunk_index: false, ..EstimateOptions::default() };
+            let estimate = estimate_output_size(len as u64, &options);
+            assert!(estimate >= encrypted, "{estimate} < {encrypted}");
+
+            let chunks = len.div_ceil(MIN_CHUNK_SIZE) as u64;
+            assert!(estimate - encrypted <= MAX_FRAMED_LEN as u64 + chunks * 1024, "{estimate} is far above {encrypted}");
+        }
+    }
+
+    #[test]
+    fn padding_and_volumes_only_add() {
+        let plain = EstimateOptions::default();
+        let padded = EstimateOptions { pad_size: PadSize::PowerOfTwo, ..plain };
+        let split = EstimateOptions { split_size: Some(64 * 1024), ..plain };
+
+        let size = estimate_output_size(1_000_000, &plain);
+        assert!(estimate_output_size(1_000_000, &padded).is_power_of_two());
+        assert!(estimate_output_size(1_000_000, &padded) >= size);
+        assert!(estimate_output_size(1_000_000, &split) > size);
+    }
+}
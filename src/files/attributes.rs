@@ -0,0 +1,120 @@
+#[cfg(feature = "cli")]
+use std::path::Path;
+#[cfg(feature = "cli")]
+use std::time::{Duration, UNIX_EPOCH};
+
+#[cfg(feature = "cli")]
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "cli")]
+use super::long_path::extended;
+
+/// Every mode bit a header records, setuid, setgid and sticky included.
+#[cfg(feature = "cli")]
+const MODE_MASK: u32 = 0o7777;
+
+/// The mode bits restored unless special bits are asked for: a header is untrusted input, and
+/// decrypting someone else's file shouldn't hand out a setuid binary.
+#[cfg(feature = "cli")]
+const PERMISSION_MASK: u32 = 0o777;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Attributes {
+    modified: Option<u64>,
+    mode: Option<u32>,
+}
+
+impl Attributes {
+    #[cfg(feature = "cli")]
+    pub(crate) fn new(modified: Option<u64>, mode: Option<u32>) -> Self {
+        Self { modified, mode }
+    }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(extended(path)).context("failed to read file attributes")?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .and_then(|elapsed| u64::try_from(elapsed.as_nanos()).ok());
+
+        Ok(Self { modified, mode: mode(&metadata) })
+    }
+
+    #[cfg(feature = "fuse")]
+    pub(crate) fn modified(&self) -> Option<std::time::SystemTime> {
+        self.modified.and_then(|modified| UNIX_EPOCH.checked_add(Duration::from_nanos(modified)))
+    }
+
+    #[cfg(feature = "fuse")]
+    pub(crate) fn mode(&self) -> Option<u32> {
+        self.mode.map(|mode| mode & PERMISSION_MASK)
+    }
+
+    /// Restores the modification time and permission bits on `path`. Setuid, setgid and sticky are
+    /// only restored with `special_bits`.
+    #[cfg(feature = "cli")]
+    pub(crate) fn apply(&self, path: &Path, special_bits: bool) -> Result<()> {
+        if let Some(modified) = self.modified {
+            let time = UNIX_EPOCH.checked_add(Duration::from_nanos(modified)).context("invalid modification time")?;
+            let file = std::fs::File::options().write(true).open(extended(path)).context("failed to open file to restore timestamps")?;
+            file.set_modified(time).context("failed to restore modification time")?;
+        }
+
+        if let Some(mode) = self.mode {
+            set_mode(path, mode & if special_bits { MODE_MASK } else { PERMISSION_MASK })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "cli", unix))]
+fn mode(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(metadata.permissions().mode() & MODE_MASK)
+}
+
+#[cfg(all(feature = "cli", not(unix)))]
+fn mode(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(all(feature = "cli", unix))]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).context("failed to restore permissions")
+}
+
+#[cfg(all(feature = "cli", not(unix)))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(all(feature = "cli", unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn restored(special_bits: bool) -> u32 {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tool");
+        std::fs::write(&path, b"#!/bin/sh\n").unwrap();
+        Attributes::new(None, Some(0o4755)).apply(&path, special_bits).unwrap();
+        std::fs::metadata(&path).unwrap().permissions().mode() & MODE_MASK
+    }
+
+    #[test]
+    fn special_bits_are_only_restored_when_asked_for() {
+        assert_eq!(restored(false), 0o755);
+        assert_eq!(restored(true), 0o4755);
+    }
+}
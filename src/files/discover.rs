@@ -2,17 +2,18 @@ use std::path::{Path, PathBuf};
 
 use walkdir::WalkDir;
 
-use crate::config::{EXCLUDED_PATTERNS, FILE_EXTENSION};
+use crate::config::FILE_EXTENSION;
 use crate::pipeline::Operation;
 
 pub(crate) struct Discover {
     root: String,
     operation: Operation,
+    exclude: Vec<String>,
 }
 
 impl Discover {
-    pub(crate) fn new(root: impl Into<String>, operation: Operation) -> Self {
-        Self { root: root.into(), operation }
+    pub(crate) fn new(root: impl Into<String>, operation: Operation, exclude: &[String]) -> Self {
+        Self { root: root.into(), operation, exclude: exclude.to_vec() }
     }
 
     pub(crate) fn run(&self) -> Vec<PathBuf> {
@@ -41,7 +42,7 @@ impl Discover {
             return false;
         }
 
-        if Self::is_excluded(path) {
+        if self.is_excluded(path) {
             return false;
         }
 
@@ -52,6 +53,10 @@ impl Discover {
     }
 
     fn is_hidden(path: &Path) -> bool {
+        if Self::has_hidden_attribute(path) {
+            return true;
+        }
+
         let Some(file_name) = path.file_name() else {
             return false;
         };
@@ -63,14 +68,29 @@ impl Discover {
         file_name.starts_with('.')
     }
 
-    fn is_excluded(path: &Path) -> bool {
+    #[cfg(windows)]
+    fn has_hidden_attribute(path: &Path) -> bool {
+        use std::os::windows::fs::MetadataExt;
+
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+
+        std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_attributes() & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM) != 0)
+    }
+
+    #[cfg(not(windows))]
+    fn has_hidden_attribute(_path: &Path) -> bool {
+        false
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
         for component in path {
             let Some(part) = component.to_str() else {
                 continue;
             };
 
-            for pattern in EXCLUDED_PATTERNS {
-                if fast_glob::glob_match(pattern, part) {
+            for pattern in &self.exclude {
+                if fast_glob::glob_match(pattern.as_str(), part) {
                     return true;
                 }
             }
@@ -80,6 +100,10 @@ impl Discover {
     }
 
     fn is_encrypted(path: &Path) -> bool {
+        if super::spanning::is_first_volume(path) {
+            return true;
+        }
+
         let Some(extension) = path.extension() else {
             return false;
         };
@@ -91,3 +115,54 @@ impl Discover {
         extension == FILE_EXTENSION
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn dotfiles_are_hidden() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(".secret");
+        std::fs::write(&path, b"data").unwrap();
+
+        assert!(Discover::is_hidden(&path));
+    }
+
+    #[test]
+    fn plain_files_are_visible() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("visible.txt");
+        std::fs::write(&path, b"data").unwrap();
+
+        assert!(!Discover::is_hidden(&path));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn hidden_attribute_is_detected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("hidden.txt");
+        std::fs::write(&path, b"data").unwrap();
+
+        let status = std::process::Command::new("attrib").arg("+h").arg(&path).status().unwrap();
+        assert!(status.success());
+
+        assert!(Discover::is_hidden(&path));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn system_attribute_is_detected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("system.txt");
+        std::fs::write(&path, b"data").unwrap();
+
+        let status = std::process::Command::new("attrib").arg("+s").arg(&path).status().unwrap();
+        assert!(status.success());
+
+        assert!(Discover::is_hidden(&path));
+    }
+}
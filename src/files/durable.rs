@@ -0,0 +1,75 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use serde::Deserialize;
+use tokio::fs::File;
+use tokio::io::AsyncWrite;
+use tokio::task::JoinHandle;
+
+#[derive(Clone, Copy, Default, Eq, PartialEq, Deserialize, clap::ValueEnum, strum::EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub(crate) enum FsyncPolicy {
+    Always,
+    #[default]
+    End,
+    Never,
+}
+
+/// Output file whose `flush` also syncs data to disk unless the policy is `Never`.
+///
+/// There is deliberately no flush on drop. `Drop` can't await, so it could only flush by blocking
+/// a runtime thread or by handing the file to a task that runtime shutdown may cancel, and it
+/// would still miss whatever the `BufWriter` in front of it holds. Every path that keeps its
+/// output flushes instead: the pipeline writer after the last chunk (and after every chunk
+/// under `Always`), a split output before it opens the next volume. A file dropped unflushed
+/// belongs to a run that failed or was cancelled, whose temporary output is deleted anyway.
+pub(crate) struct DurableFile {
+    file: File,
+    handle: Arc<std::fs::File>,
+    sync: bool,
+    pending: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl DurableFile {
+    pub(crate) fn new(file: std::fs::File, policy: FsyncPolicy) -> io::Result<Self> {
+        let handle = Arc::new(file.try_clone()?);
+
+        Ok(Self { file: File::from_std(file), handle, sync: policy != FsyncPolicy::Never, pending: None })
+    }
+}
+
+impl AsyncWrite for DurableFile {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().file).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        let pending = match this.pending.as_mut() {
+            Some(pending) => pending,
+            None => {
+                ready!(Pin::new(&mut this.file).poll_flush(cx))?;
+                if !this.sync {
+                    return Poll::Ready(Ok(()));
+                }
+
+                let handle = Arc::clone(&this.handle);
+                this.pending.insert(tokio::task::spawn_blocking(move || handle.sync_data()))
+            }
+        };
+
+        let result = ready!(Pin::new(pending).poll(cx));
+        this.pending = None;
+
+        Poll::Ready(result.map_err(io::Error::other).and_then(|synced| synced))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+    }
+}
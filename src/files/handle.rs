@@ -1,16 +1,23 @@
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use tokio::fs::File;
 use tokio::io::{BufReader, BufWriter};
 
-use crate::config::FILE_EXTENSION;
+use super::attributes::Attributes;
+use super::durable::{DurableFile, FsyncPolicy};
+use super::long_path::extended;
+use crate::cipher::RandomSource;
+use crate::config::{FILE_EXTENSION, HIDDEN_NAME_LEN, IO_BUFFER_SIZE};
 use crate::pipeline::Operation;
 
+#[derive(Debug)]
 pub(crate) struct Metadata {
     pub(crate) name: String,
     pub(crate) size: u64,
     pub(crate) hash: Vec<u8>,
+    pub(crate) attributes: Attributes,
 }
 
 pub(crate) struct Files {
@@ -30,53 +37,126 @@ impl Files {
         self.path.file_name().and_then(|n| n.to_str()).unwrap_or_default()
     }
 
+    /// Whether this names standard input (`-`) rather than a file.
+    pub(crate) fn is_stdin(&self) -> bool {
+        self.path == Path::new("-")
+    }
+
     pub(crate) fn exists(&self) -> bool {
-        self.path.exists()
+        extended(&self.path).exists()
     }
 
     pub(crate) fn is_encrypted(&self) -> bool {
-        self.path.extension().and_then(|e| e.to_str()).is_some_and(|e| e == FILE_EXTENSION)
+        self.path.extension().and_then(|e| e.to_str()).is_some_and(|e| e == FILE_EXTENSION) || super::spanning::is_first_volume(&self.path)
     }
 
     pub(crate) fn output_path(&self, operation: Operation) -> PathBuf {
         match operation {
             Operation::Encryption => self.path.with_added_extension(FILE_EXTENSION),
-            Operation::Decryption => self.path.with_extension(""),
+            Operation::Decryption => super::spanning::base_path(&self.path).with_extension(""),
         }
     }
 
+    /// A random `<hex>.swx` name next to the source, so the output name does not reveal the
+    /// original one.
+    pub(crate) fn hidden_output_path(&self, rng: &dyn RandomSource) -> Result<PathBuf> {
+        let mut id = [0u8; HIDDEN_NAME_LEN];
+        rng.fill(&mut id).context("failed to generate output name")?;
+
+        Ok(self.path.with_file_name(hex::encode(id)).with_added_extension(FILE_EXTENSION))
+    }
+
+    pub(crate) fn has_hidden_name(&self) -> bool {
+        let base = super::spanning::base_path(&self.path);
+        let is_swx = base.extension().and_then(|e| e.to_str()).is_some_and(|e| e == FILE_EXTENSION);
+        let stem = base.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+
+        is_swx && stem.len() == HIDDEN_NAME_LEN.saturating_mul(2) && stem.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    }
+
+    /// Opens the file for reading only; nothing that reads a source can write to it.
     pub(crate) async fn reader(&self) -> Result<BufReader<File>> {
-        File::open(&self.path).await.map(BufReader::new).context("failed to open file")
+        self.open().await.map(|file| BufReader::with_capacity(IO_BUFFER_SIZE, file))
+    }
+
+    /// Like `reader`, without the buffer, for callers that seek around in the file.
+    pub(crate) async fn open(&self) -> Result<File> {
+        tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(false)
+            .append(false)
+            .open(extended(&self.path))
+            .await
+            .context("failed to open file")
     }
 
-    pub(crate) async fn writer(&self) -> Result<BufWriter<File>> {
+    pub(crate) async fn writer(&self, fsync: FsyncPolicy) -> Result<BufWriter<DurableFile>> {
+        self.durable(fsync).await.map(|file| BufWriter::with_capacity(IO_BUFFER_SIZE, file))
+    }
+
+    pub(crate) async fn durable(&self, fsync: FsyncPolicy) -> Result<DurableFile> {
+        let file = self.create().await?.into_std().await;
+
+        DurableFile::new(file, fsync).context("failed to prepare file for writing")
+    }
+
+    pub(crate) async fn create(&self) -> Result<File> {
         if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
-            tokio::fs::create_dir_all(parent).await.context("failed to create directory")?;
+            tokio::fs::create_dir_all(extended(parent)).await.context("failed to create directory")?;
         }
 
         tokio::fs::OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(&self.path)
+            .open(extended(&self.path))
             .await
-            .map(BufWriter::new)
             .context("failed to create file")
     }
 
-    pub(crate) async fn delete(&self) -> Result<()> {
+    pub(crate) async fn delete(&self, shred_passes: u32) -> Result<()> {
         if !self.exists() {
             anyhow::bail!("file does not exist: {}", self.path.display());
         }
 
-        tokio::fs::remove_file(&self.path).await.context("failed to delete file")
+        if super::spanning::is_first_volume(&self.path) {
+            return super::spanning::remove_volumes(&super::spanning::base_path(&self.path), shred_passes).await;
+        }
+
+        super::shred::shred(&self.path, shred_passes).await?;
+        tokio::fs::remove_file(extended(&self.path)).await.context("failed to delete file")
     }
 
     pub(crate) async fn size(&self) -> Result<u64> {
-        tokio::fs::metadata(&self.path).await.map(|m| m.len()).context("failed to read metadata")
+        tokio::fs::metadata(extended(&self.path)).await.map(|m| m.len()).context("failed to read metadata")
     }
 
-    pub(crate) async fn metadata(&self) -> Result<Metadata> {
-        Ok(Metadata { name: self.name().to_owned(), size: self.size().await?, hash: super::hash::hash(self)? })
+    pub(crate) async fn metadata(&self, threads: NonZeroUsize) -> Result<Metadata> {
+        Ok(Metadata { hash: super::hash::hash(self, threads)?, ..self.unhashed_metadata().await? })
+    }
+
+    /// Metadata with a zeroed hash, for callers that hash the content as they stream it.
+    pub(crate) async fn unhashed_metadata(&self) -> Result<Metadata> {
+        let attributes = Attributes::read(&self.path)?;
+
+        Ok(Metadata { name: self.name().to_owned(), size: self.size().await?, hash: vec![0u8; blake3::OUT_LEN], attributes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::SeededRandom;
+
+    #[test]
+    fn hidden_names_are_recognized() {
+        let source = Files::new(Path::new("docs").join("report.pdf"));
+        let hidden = Files::new(source.hidden_output_path(&SeededRandom::new(1)).unwrap());
+
+        assert_eq!(hidden.path().parent(), Some(Path::new("docs")));
+        assert!(hidden.is_encrypted());
+        assert!(hidden.has_hidden_name());
+        assert!(Files::new(hidden.path().with_added_extension("001")).has_hidden_name());
+        assert!(!Files::new(source.output_path(Operation::Encryption)).has_hidden_name());
     }
 }
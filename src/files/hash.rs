@@ -1,18 +1,114 @@
+use std::io;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context as TaskContext, Poll, ready};
+
 use anyhow::{Context, Result};
 use blake3::Hasher;
-use subtle::ConstantTimeEq;
+use rayon::ThreadPoolBuilder;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 use super::handle::Files;
+use super::long_path::extended;
 
-pub(crate) fn hash(file: &Files) -> Result<Vec<u8>> {
+pub(crate) fn hash(file: &Files, threads: NonZeroUsize) -> Result<Vec<u8>> {
+    let pool = ThreadPoolBuilder::new().num_threads(threads.get()).build().context("failed to build hashing pool")?;
     let mut hasher = Hasher::new();
-    hasher.update_mmap_rayon(file.path()).context("failed to memory-map file for hashing")?;
+    pool.install(|| hasher.update_mmap_rayon(extended(file.path()))).context("failed to memory-map file for hashing")?;
 
     Ok(hasher.finalize().as_bytes().to_vec())
 }
 
-pub(crate) fn validate_hash(file: &Files, expected: &[u8]) -> Result<bool> {
-    let actual = hash(file)?;
+/// Hashes whatever passes through the readers and writers it hands out, so encryption can learn the
+/// source's hash from the same read that feeds the pipeline, and decryption can check the output
+/// without reading it back.
+#[derive(Clone, Default)]
+pub(crate) struct StreamHash(Arc<Mutex<Hasher>>);
+
+impl StreamHash {
+    pub(crate) fn reader<R>(&self, inner: R) -> HashingReader<R> {
+        HashingReader { inner, hasher: Arc::clone(&self.0) }
+    }
+
+    pub(crate) fn writer<W>(&self, inner: W) -> HashingWriter<W> {
+        HashingWriter { inner, hasher: Arc::clone(&self.0) }
+    }
+
+    /// Returns the hash and how many bytes it covers.
+    pub(crate) fn finish(&self) -> (Vec<u8>, u64) {
+        let hasher = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+
+        (hasher.finalize().as_bytes().to_vec(), hasher.count())
+    }
+}
+
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    hasher: Arc<Mutex<Hasher>>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        let read = buf.filled().get(before..).unwrap_or_default();
+        this.hasher.lock().unwrap_or_else(PoisonError::into_inner).update(read);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+pub(crate) struct HashingWriter<W> {
+    inner: W,
+    hasher: Arc<Mutex<Hasher>>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.hasher.lock().unwrap_or_else(PoisonError::into_inner).update(buf.get(..written).unwrap_or_default());
+
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stream_hash_matches_whole_input() {
+        let data = vec![7u8; 100_000];
+        let stream = StreamHash::default();
+        let mut read = Vec::new();
+        stream.reader(data.as_slice()).read_to_end(&mut read).await.unwrap();
+
+        assert_eq!(read, data);
+        assert_eq!(stream.finish(), (blake3::hash(&data).as_bytes().to_vec(), 100_000));
+    }
+
+    #[tokio::test]
+    async fn stream_hash_covers_what_was_written() {
+        let stream = StreamHash::default();
+        let mut writer = stream.writer(Vec::new());
+        writer.write_all(b"first ").await.unwrap();
+        writer.write_all(b"second").await.unwrap();
 
-    Ok(bool::from(actual.as_slice().ct_eq(expected)))
+        assert_eq!(writer.inner, b"first second");
+        assert_eq!(stream.finish(), (blake3::hash(b"first second").as_bytes().to_vec(), 12));
+    }
 }
@@ -0,0 +1,75 @@
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::long_path::extended;
+
+/// An advisory lock on an output path, held through a hidden `.<name>.lock` file beside it for as
+/// long as the operation writing that output runs. A second process aiming at the same path fails
+/// up front instead of racing the first.
+pub(crate) struct OutputLock {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl OutputLock {
+    pub(crate) fn acquire(target: &Path) -> Result<Self> {
+        let name = target.file_name().and_then(|n| n.to_str()).context("invalid target file name")?;
+        let path = target.with_file_name(format!(".{name}.lock"));
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(extended(parent)).context("failed to create directory")?;
+        }
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(extended(&path))
+            .with_context(|| format!("failed to open lock file {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => anyhow::bail!("operation already in progress on {}", target.display()),
+            Err(TryLockError::Error(error)) => return Err(error).with_context(|| format!("failed to lock {}", path.display())),
+        }
+        super::temp::register(&path);
+
+        Ok(Self { path, file: Some(file) })
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        // Unix lets the file go while it's still locked, so nobody can lock it in the moment before it
+        // disappears. Windows won't delete an open file, so there it goes once the handle is
+        // closed.
+        if std::fs::remove_file(extended(&self.path)).is_err() {
+            drop(self.file.take());
+            std::fs::remove_file(extended(&self.path)).unwrap_or_default();
+        }
+        super::temp::unregister(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn second_lock_on_the_same_output_fails() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("report.pdf.swx");
+
+        let lock = OutputLock::acquire(&target).unwrap();
+        let error = OutputLock::acquire(&target).err().unwrap();
+        assert!(error.to_string().contains("operation already in progress"));
+        OutputLock::acquire(&dir.path().join("other.swx")).unwrap();
+
+        drop(lock);
+        assert!(!dir.path().join(".report.pdf.swx.lock").exists());
+        OutputLock::acquire(&target).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+/// The form of `path` to hand to the OS. On Windows that is the extended-length form (`\\?\C:\…` or
+/// `\\?\UNC\server\share\…`), which lifts the 260-character `MAX_PATH` limit. The prefix also
+/// switches off Windows' own handling of `.`, `..`, and `/`, so the path is made absolute and
+/// normalized first. Elsewhere it is returned as is.
+#[cfg(not(windows))]
+pub(crate) fn extended(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+#[cfg(windows)]
+pub(crate) fn extended(path: &Path) -> Cow<'_, Path> {
+    use std::ffi::OsString;
+    use std::path::{Component, PathBuf, Prefix};
+
+    let Ok(absolute) = std::path::absolute(path) else {
+        return Cow::Borrowed(path);
+    };
+
+    let mut components = absolute.components();
+    let Some(Component::Prefix(prefix)) = components.next() else {
+        return Cow::Owned(absolute);
+    };
+    let mut extended = match prefix.kind() {
+        Prefix::Disk(_) => {
+            let mut extended = OsString::from(r"\\?\");
+            extended.push(prefix.as_os_str());
+            extended
+        }
+        Prefix::UNC(server, share) => {
+            let mut extended = OsString::from(r"\\?\UNC\");
+            extended.push(server);
+            extended.push(r"\");
+            extended.push(share);
+            extended
+        }
+        Prefix::Verbatim(_) | Prefix::VerbatimUNC(..) | Prefix::VerbatimDisk(_) | Prefix::DeviceNS(_) => return Cow::Owned(absolute),
+    };
+
+    let mut named = false;
+    for component in components.filter(|component| !matches!(component, Component::RootDir)) {
+        extended.push(r"\");
+        extended.push(component.as_os_str());
+        named = true;
+    }
+    if !named {
+        extended.push(r"\");
+    }
+
+    Cow::Owned(PathBuf::from(extended))
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_paths_get_the_extended_prefix() {
+        assert_eq!(extended(Path::new(r"C:\projects\..\data\report.pdf")), Path::new(r"\\?\C:\data\report.pdf"));
+        assert_eq!(extended(Path::new("C:/data/report.pdf")), Path::new(r"\\?\C:\data\report.pdf"));
+        assert_eq!(extended(Path::new(r"C:\")), Path::new(r"\\?\C:\"));
+        assert_eq!(extended(Path::new(r"\\server\share\data\report.pdf")), Path::new(r"\\?\UNC\server\share\data\report.pdf"));
+        assert_eq!(extended(Path::new(r"\\?\C:\already\extended")), Path::new(r"\\?\C:\already\extended"));
+    }
+}
@@ -1,6 +1,37 @@
+mod attributes;
+#[cfg(feature = "cli")]
 mod discover;
+#[cfg(feature = "cli")]
+mod durable;
+#[cfg(feature = "cli")]
 mod handle;
+#[cfg(feature = "cli")]
+mod lock;
+#[cfg(feature = "cli")]
+mod long_path;
+#[cfg(feature = "cli")]
+mod shred;
+#[cfg(feature = "cli")]
+mod snapshot;
 
+#[cfg(feature = "cli")]
 pub(crate) mod hash;
+#[cfg(feature = "cli")]
+pub(crate) mod space;
+#[cfg(feature = "cli")]
+pub(crate) mod spanning;
+#[cfg(feature = "cli")]
+pub(crate) mod temp;
+pub(crate) use attributes::Attributes;
+#[cfg(feature = "cli")]
 pub(crate) use discover::Discover;
+#[cfg(feature = "cli")]
+pub(crate) use durable::FsyncPolicy;
+#[cfg(feature = "cli")]
 pub(crate) use handle::{Files, Metadata};
+#[cfg(feature = "cli")]
+pub(crate) use lock::OutputLock;
+#[cfg(feature = "cli")]
+pub(crate) use snapshot::Snapshot;
+#[cfg(feature = "cli")]
+pub(crate) use temp::TempFile;
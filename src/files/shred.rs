@@ -0,0 +1,37 @@
+use std::io::SeekFrom;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use super::long_path::extended;
+use crate::cipher::{RandomSource, SystemRandom};
+
+const SHRED_BLOCK_SIZE: usize = 64 * 1024;
+
+pub(crate) async fn shred(path: &Path, passes: u32) -> Result<()> {
+    if passes == 0 {
+        return Ok(());
+    }
+
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(extended(path)).await.context("failed to open file for shredding")?;
+    let len = file.metadata().await.context("failed to read metadata")?.len();
+    let mut block = vec![0u8; SHRED_BLOCK_SIZE];
+
+    for _ in 0..passes {
+        file.seek(SeekFrom::Start(0)).await.context("failed to rewind file")?;
+
+        let mut remaining = len;
+        while remaining > 0 {
+            let size = usize::try_from(remaining).unwrap_or(usize::MAX).min(SHRED_BLOCK_SIZE);
+            let block = block.get_mut(..size).context("shred block out of range")?;
+            SystemRandom.fill(block)?;
+            file.write_all(block).await.context("failed to overwrite file")?;
+            remaining = remaining.saturating_sub(size as u64);
+        }
+
+        file.sync_data().await.context("failed to sync overwritten file")?;
+    }
+
+    Ok(())
+}
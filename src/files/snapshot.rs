@@ -0,0 +1,63 @@
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+
+use super::long_path::extended;
+
+/// A file's size and modification time, taken before it is read so `--paranoid` can tell afterwards
+/// whether anything wrote to it in the meantime.
+#[derive(Clone, Copy)]
+pub(crate) struct Snapshot {
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Snapshot {
+    pub(crate) async fn take(path: &Path) -> Result<Self> {
+        let metadata = tokio::fs::metadata(extended(path)).await.context("failed to read file metadata")?;
+
+        Ok(Self { size: metadata.len(), modified: metadata.modified().ok() })
+    }
+
+    /// Fails when the file at `path` no longer has the size and modification time it had when this
+    /// was taken.
+    pub(crate) async fn ensure_unchanged(self, path: &Path) -> Result<()> {
+        let now = Self::take(path).await?;
+        if now.size != self.size {
+            anyhow::bail!("{} changed from {} to {} bytes while it was being read", path.display(), self.size, now.size);
+        }
+        if now.modified != self.modified {
+            anyhow::bail!("{} was modified while it was being read", path.display());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn writes_after_the_snapshot_are_caught() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("source.txt");
+        std::fs::write(&path, b"original").unwrap();
+
+        let snapshot = Snapshot::take(&path).await.unwrap();
+        snapshot.ensure_unchanged(&path).await.unwrap();
+
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert!(snapshot.ensure_unchanged(&path).await.is_err());
+
+        let snapshot = Snapshot::take(&path).await.unwrap();
+        std::fs::write(&path, b"original, then more").unwrap();
+        assert!(snapshot.ensure_unchanged(&path).await.is_err());
+    }
+}
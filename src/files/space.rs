@@ -0,0 +1,13 @@
+use std::path::Path;
+
+use super::long_path;
+
+/// Bytes free to this user on the filesystem that holds `path` (`statvfs`'s `f_bavail` on Unix,
+/// `GetDiskFreeSpaceExW` on Windows), asked of its nearest existing ancestor since the file itself
+/// usually isn't there yet. `None` when the filesystem won't say.
+pub(crate) fn available(path: &Path) -> Option<u64> {
+    let absolute = std::path::absolute(path).ok()?;
+    let existing = absolute.ancestors().find(|ancestor| ancestor.exists())?;
+
+    fs4::available_space(long_path::extended(existing)).ok()
+}
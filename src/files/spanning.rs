@@ -0,0 +1,470 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context as TaskContext, Poll, ready};
+
+use anyhow::{Context, Result};
+use rand::TryRng;
+use rand::rngs::SysRng;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, SeekFrom};
+
+use super::durable::{DurableFile, FsyncPolicy};
+use super::handle::Files;
+use super::long_path::extended;
+use super::temp::TempFile;
+use crate::config::{FILE_EXTENSION, IO_BUFFER_SIZE, MAX_CARRIER_SIZE, VOLUME_HEADER_LEN};
+use crate::storage::{DirectoryStore, Index, StoreOutput, StoreReader, StoreWriter};
+
+pub(crate) const VOLUME_MAGIC: u32 = 0x5357_5856;
+/// Random bytes every volume of one output shares, so a volume left over from an earlier, longer
+/// output isn't joined onto a shorter one.
+pub(crate) const VOLUME_ID_LEN: usize = 8;
+const MAGIC_LEN: usize = 4;
+const INDEX_LEN: usize = 4;
+const FIRST_VOLUME: &str = "001";
+
+pub(crate) type DynReader = Box<dyn AsyncRead + Unpin + Send>;
+pub(crate) type DynWriter = Box<dyn AsyncWrite + Unpin + Send>;
+
+pub(crate) fn volume_path(base: &Path, index: u32) -> PathBuf {
+    base.with_added_extension(format!("{index:03}"))
+}
+
+pub(crate) fn is_first_volume(path: &Path) -> bool {
+    let is_first = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e == FIRST_VOLUME);
+    let is_swx = path.file_stem().map(Path::new).and_then(Path::extension).and_then(|e| e.to_str()).is_some_and(|e| e == FILE_EXTENSION);
+
+    is_first && is_swx
+}
+
+pub(crate) fn base_path(path: &Path) -> PathBuf {
+    if is_first_volume(path) { path.with_extension("") } else { path.to_path_buf() }
+}
+
+pub(crate) fn is_spanned(path: &Path) -> bool {
+    is_first_volume(path) || (!extended(path).exists() && extended(&volume_path(path, 1)).exists())
+}
+
+pub(crate) fn volumes(path: &Path) -> Vec<PathBuf> {
+    if !is_spanned(path) {
+        return vec![path.to_path_buf()];
+    }
+
+    let base = base_path(path);
+    (1u32..).map(|index| volume_path(&base, index)).take_while(|path| extended(path).exists()).collect()
+}
+
+pub(crate) async fn reader(file: &Files) -> Result<BufReader<DynReader>> {
+    if crate::storage::is_index(file.path()) {
+        let index = Index::load(file.path())?;
+        let reader: DynReader = Box::new(StoreReader::new(index));
+        return Ok(BufReader::with_capacity(IO_BUFFER_SIZE, reader));
+    }
+
+    if !is_spanned(file.path()) {
+        let mut opened = File::open(extended(file.path())).await.context("failed to open file")?;
+        if is_carrier(&mut opened).await? {
+            let carried: DynReader = Box::new(io::Cursor::new(carried(opened).await?));
+            return Ok(BufReader::with_capacity(IO_BUFFER_SIZE, carried));
+        }
+        let file: DynReader = Box::new(opened);
+        return Ok(BufReader::with_capacity(IO_BUFFER_SIZE, file));
+    }
+
+    let base = base_path(file.path());
+    let mut reader: Option<DynReader> = None;
+    let mut id = None;
+    for index in 1u32.. {
+        let path = volume_path(&base, index);
+        if !extended(&path).exists() {
+            break;
+        }
+
+        let mut volume = File::open(extended(&path)).await.with_context(|| format!("failed to open volume {}", path.display()))?;
+        let mut header = [0u8; VOLUME_HEADER_LEN];
+        volume.read_exact(&mut header).await.with_context(|| format!("failed to read volume header {}", path.display()))?;
+        id = Some(validate_header(&header, index, id).with_context(|| format!("invalid volume {}", path.display()))?);
+
+        reader = Some(match reader {
+            Some(previous) => Box::new(previous.chain(volume)),
+            None => Box::new(volume),
+        });
+    }
+
+    reader.map(|reader| BufReader::with_capacity(IO_BUFFER_SIZE, reader)).context("no volumes found")
+}
+
+/// The file itself when it is a single plain file that can be seeked; `None` for volumes, chunk
+/// stores, and carriers.
+pub(crate) async fn seekable(file: &Files) -> Result<Option<File>> {
+    if crate::storage::is_index(file.path()) || is_spanned(file.path()) {
+        return Ok(None);
+    }
+
+    let mut opened = File::open(extended(file.path())).await.context("failed to open file")?;
+    if is_carrier(&mut opened).await? {
+        return Ok(None);
+    }
+
+    Ok(Some(opened))
+}
+
+/// Whether `file` is a PNG or WAV carrier rather than a bare `.swx`. The file is rewound either
+/// way.
+async fn is_carrier(file: &mut File) -> Result<bool> {
+    let mut prefix = Vec::with_capacity(crate::stego::SNIFF_LEN);
+    (&mut *file).take(crate::stego::SNIFF_LEN as u64).read_to_end(&mut prefix).await.context("failed to read file")?;
+    file.rewind().await.context("failed to rewind file")?;
+
+    Ok(crate::stego::is_carrier(&prefix))
+}
+
+/// The `.swx` file a carrier holds, read whole since its chunks can sit anywhere in it.
+async fn carried(mut file: File) -> Result<Vec<u8>> {
+    let len = file.metadata().await.context("failed to read file size")?.len();
+    if len > MAX_CARRIER_SIZE {
+        anyhow::bail!("carrier is {len} bytes, more than the {MAX_CARRIER_SIZE} that can be read");
+    }
+
+    let mut data = Vec::with_capacity(usize::try_from(len).context("carrier too large")?);
+    file.read_to_end(&mut data).await.context("failed to read carrier")?;
+
+    crate::stego::extract(&data)
+}
+
+pub(crate) async fn overwrite_header(file: &Files, header: &[u8]) -> Result<()> {
+    if crate::storage::is_index(file.path()) {
+        return Index::replace_header(file.path(), header).await;
+    }
+
+    let (path, offset) = if is_spanned(file.path()) {
+        (volume_path(&base_path(file.path()), 1), VOLUME_HEADER_LEN as u64)
+    } else {
+        (file.path().to_path_buf(), 0)
+    };
+
+    let mut target = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(extended(&path))
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    target.seek(SeekFrom::Start(offset)).await.context("failed to seek to header")?;
+    target.write_all(header).await.context("failed to write header")?;
+    target.sync_all().await.context("failed to sync header")
+}
+
+/// Rewrites the last `bytes.len()` bytes of a single file, where its header copy sits.
+pub(crate) async fn overwrite_tail(file: &Files, bytes: &[u8]) -> Result<()> {
+    if seekable(file).await?.is_none() {
+        anyhow::bail!("only a single .swx file can hold a header copy");
+    }
+
+    let len = tokio::fs::metadata(extended(file.path())).await.context("failed to read file size")?.len();
+    let offset = len.checked_sub(bytes.len() as u64).context("file is shorter than its header copy")?;
+    overwrite(file.path(), offset, bytes, FsyncPolicy::Always).await
+}
+
+pub(crate) async fn remove_volumes(base: &Path, shred_passes: u32) -> Result<()> {
+    for index in 1u32.. {
+        let path = volume_path(base, index);
+        if !extended(&path).exists() {
+            break;
+        }
+
+        super::shred::shred(&path, shred_passes).await?;
+        tokio::fs::remove_file(extended(&path)).await.with_context(|| format!("failed to delete volume {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn volume_header(index: u32, id: &[u8; VOLUME_ID_LEN]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(VOLUME_HEADER_LEN);
+    header.extend_from_slice(&VOLUME_MAGIC.to_le_bytes());
+    header.extend_from_slice(&index.to_le_bytes());
+    header.extend_from_slice(id);
+    header
+}
+
+/// Checks a volume's header and returns its id, which has to match `expected_id` when the volume
+/// isn't the first.
+fn validate_header(header: &[u8; VOLUME_HEADER_LEN], expected: u32, expected_id: Option<[u8; VOLUME_ID_LEN]>) -> Result<[u8; VOLUME_ID_LEN]> {
+    let (magic, rest) = header.split_at(MAGIC_LEN);
+    let (index, id) = rest.split_at(INDEX_LEN);
+    let magic = u32::from_le_bytes(magic.try_into().context("invalid volume magic")?);
+    let index = u32::from_le_bytes(index.try_into().context("invalid volume index")?);
+    let id: [u8; VOLUME_ID_LEN] = id.try_into().context("invalid volume id")?;
+
+    if magic != VOLUME_MAGIC {
+        anyhow::bail!("not a sweetbyte volume");
+    }
+
+    if index != expected {
+        anyhow::bail!("volume out of sequence: expected {expected}, found {index}");
+    }
+
+    if expected_id.is_some_and(|expected_id| expected_id != id) {
+        anyhow::bail!("volume {index} belongs to a different file");
+    }
+
+    Ok(id)
+}
+
+pub(crate) enum Output {
+    Single(TempFile),
+    /// The volumes written so far, and the base path they're named after.
+    Spanned(PathBuf, Arc<Mutex<Vec<TempFile>>>),
+    Store(StoreOutput),
+}
+
+impl Output {
+    pub(crate) async fn create(target: &Path, split_size: Option<u64>, fsync: FsyncPolicy) -> Result<(Self, BufWriter<DynWriter>)> {
+        match split_size {
+            None => {
+                let temp = TempFile::new(target)?;
+                let file: DynWriter = Box::new(Files::new(temp.path()).durable(fsync).await?);
+                Ok((Self::Single(temp), BufWriter::with_capacity(IO_BUFFER_SIZE, file)))
+            }
+            Some(volume_size) => {
+                let mut id = [0u8; VOLUME_ID_LEN];
+                SysRng.try_fill_bytes(&mut id).context("failed to generate volume id")?;
+
+                let volumes = Arc::new(Mutex::new(Vec::new()));
+                let writer: DynWriter = Box::new(SpanningWriter::new(target, volume_size, fsync, id, Arc::clone(&volumes)));
+                Ok((Self::Spanned(target.to_path_buf(), volumes), BufWriter::with_capacity(IO_BUFFER_SIZE, writer)))
+            }
+        }
+    }
+
+    pub(crate) fn store(target: &Path, root: &Path) -> Result<(Self, BufWriter<DynWriter>)> {
+        std::fs::create_dir_all(root).with_context(|| format!("failed to create chunk store {}", root.display()))?;
+        let root = root.canonicalize().with_context(|| format!("failed to resolve chunk store {}", root.display()))?;
+
+        let (output, writer) = StoreWriter::create(target, root.clone(), Arc::new(DirectoryStore::new(root)));
+        let writer: DynWriter = Box::new(writer);
+        Ok((Self::Store(output), BufWriter::with_capacity(IO_BUFFER_SIZE, writer)))
+    }
+
+    /// Moves the output into place. Volumes past the last one written, left by an earlier output
+    /// that needed more, are deleted.
+    pub(crate) async fn persist(self) -> Result<()> {
+        match self {
+            Self::Store(output) => output.persist().await,
+            Self::Single(temp) => temp.persist().await,
+            Self::Spanned(base, volumes) => {
+                let volumes = std::mem::take(&mut *volumes.lock().unwrap_or_else(PoisonError::into_inner));
+                let count = u32::try_from(volumes.len()).context("too many volumes")?;
+                for volume in volumes {
+                    volume.persist().await?;
+                }
+
+                for index in count.saturating_add(1).. {
+                    let path = volume_path(&base, index);
+                    if !extended(&path).exists() {
+                        break;
+                    }
+
+                    tokio::fs::remove_file(extended(&path))
+                        .await
+                        .with_context(|| format!("failed to delete stale volume {}", path.display()))?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Overwrites the end of a single output file with `bytes`, for a header copy rewritten along
+    /// with the header.
+    pub(crate) async fn patch_tail(&self, bytes: &[u8], fsync: FsyncPolicy) -> Result<()> {
+        let Self::Single(temp) = self else {
+            anyhow::bail!("only a single output file can hold a header copy");
+        };
+
+        let len = tokio::fs::metadata(extended(temp.path())).await.context("failed to read output size")?.len();
+        let offset = len.checked_sub(bytes.len() as u64).context("output is shorter than its header copy")?;
+        overwrite(temp.path(), offset, bytes, fsync).await
+    }
+
+    /// Overwrites the start of the written stream with `bytes`, for a header that is rewritten once
+    /// the payload is done. Split output is patched around each volume's own header.
+    pub(crate) async fn patch(&self, bytes: &[u8], fsync: FsyncPolicy) -> Result<()> {
+        match self {
+            Self::Store(_) => anyhow::bail!("a chunk store's header can't be rewritten"),
+            Self::Single(temp) => overwrite(temp.path(), 0, bytes, fsync).await,
+            Self::Spanned(_, volumes) => {
+                let paths: Vec<PathBuf> = volumes.lock().unwrap_or_else(PoisonError::into_inner).iter().map(|v| v.path().to_path_buf()).collect();
+                let mut rest = bytes;
+                for path in paths {
+                    if rest.is_empty() {
+                        break;
+                    }
+
+                    let room = tokio::fs::metadata(extended(&path))
+                        .await
+                        .context("failed to read volume size")?
+                        .len()
+                        .saturating_sub(VOLUME_HEADER_LEN as u64);
+                    let (head, tail) = rest.split_at(usize::try_from(room).unwrap_or(usize::MAX).min(rest.len()));
+                    overwrite(&path, VOLUME_HEADER_LEN as u64, head, fsync).await?;
+                    rest = tail;
+                }
+                if !rest.is_empty() {
+                    anyhow::bail!("output is shorter than its header");
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn overwrite(path: &Path, offset: u64, bytes: &[u8], fsync: FsyncPolicy) -> Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(extended(path))
+        .await
+        .with_context(|| format!("failed to reopen {}", path.display()))?;
+    file.seek(SeekFrom::Start(offset)).await.context("failed to seek to header")?;
+    file.write_all(bytes).await.context("failed to rewrite header")?;
+    if fsync != FsyncPolicy::Never {
+        file.sync_data().await.context("failed to sync header")?;
+    }
+
+    Ok(())
+}
+
+struct SpanningWriter {
+    base: PathBuf,
+    volume_size: u64,
+    fsync: FsyncPolicy,
+    index: u32,
+    written: u64,
+    current: Option<DurableFile>,
+    id: [u8; VOLUME_ID_LEN],
+    header: Vec<u8>,
+    header_pos: usize,
+    volumes: Arc<Mutex<Vec<TempFile>>>,
+}
+
+impl SpanningWriter {
+    fn new(base: &Path, volume_size: u64, fsync: FsyncPolicy, id: [u8; VOLUME_ID_LEN], volumes: Arc<Mutex<Vec<TempFile>>>) -> Self {
+        Self { base: base.to_path_buf(), volume_size, fsync, index: 0, written: 0, current: None, id, header: Vec::new(), header_pos: 0, volumes }
+    }
+
+    fn open_next(&mut self) -> io::Result<()> {
+        self.index = self.index.checked_add(1).ok_or_else(|| io::Error::other("too many volumes"))?;
+
+        let temp = TempFile::new(volume_path(&self.base, self.index)).map_err(io::Error::other)?;
+        let file = std::fs::File::create(extended(temp.path()))?;
+        self.volumes.lock().unwrap_or_else(PoisonError::into_inner).push(temp);
+
+        self.current = Some(DurableFile::new(file, self.fsync)?);
+        self.header = volume_header(self.index, &self.id);
+        self.header_pos = 0;
+        self.written = 0;
+
+        Ok(())
+    }
+
+    fn advance(&mut self, written: usize) {
+        self.written = self.written.saturating_add(u64::try_from(written).unwrap_or(u64::MAX));
+    }
+}
+
+impl AsyncWrite for SpanningWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        loop {
+            if this.current.is_none() || this.written >= this.volume_size {
+                if let Some(file) = this.current.as_mut() {
+                    ready!(Pin::new(file).poll_flush(cx))?;
+                }
+                this.open_next()?;
+            }
+
+            while this.header_pos < this.header.len() {
+                let pending = this.header.get(this.header_pos..).unwrap_or_default();
+                let Some(file) = this.current.as_mut() else { return Poll::Ready(Err(io::Error::other("volume not open"))) };
+                let written = ready!(Pin::new(file).poll_write(cx, pending))?;
+                if written == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+
+                this.header_pos = this.header_pos.saturating_add(written);
+                this.advance(written);
+            }
+
+            let room = usize::try_from(this.volume_size.saturating_sub(this.written)).unwrap_or(usize::MAX);
+            if room == 0 {
+                continue;
+            }
+
+            let chunk = buf.get(..room.min(buf.len())).unwrap_or_default();
+            let Some(file) = this.current.as_mut() else { return Poll::Ready(Err(io::Error::other("volume not open"))) };
+            let written = ready!(Pin::new(file).poll_write(cx, chunk))?;
+            this.advance(written);
+
+            return Poll::Ready(Ok(written));
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut().current.as_mut() {
+            Some(file) => Pin::new(file).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut().current.as_mut() {
+            Some(file) => Pin::new(file).poll_shutdown(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    async fn write(target: &Path, content: &[u8]) {
+        let (output, mut writer) = Output::create(target, Some(64), FsyncPolicy::Never).await.unwrap();
+        writer.write_all(content).await.unwrap();
+        writer.shutdown().await.unwrap();
+        drop(writer);
+        output.persist().await.unwrap();
+    }
+
+    async fn read(target: &Path) -> Result<Vec<u8>> {
+        let mut content = Vec::new();
+        reader(&Files::new(target)).await?.read_to_end(&mut content).await?;
+        Ok(content)
+    }
+
+    #[tokio::test]
+    async fn shorter_output_drops_the_old_volumes_past_it() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("file.swx");
+
+        write(&target, &[1u8; 130]).await;
+        assert_eq!(volumes(&target).len(), 3);
+        let stale = std::fs::read(volume_path(&target, 3)).unwrap();
+
+        write(&target, &[2u8; 90]).await;
+        assert_eq!(volumes(&target).len(), 2);
+        assert_eq!(read(&target).await.unwrap(), [2u8; 90]);
+
+        std::fs::write(volume_path(&target, 3), stale).unwrap();
+        let error = read(&target).await.unwrap_err();
+        assert!(format!("{error:#}").contains("belongs to a different file"));
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+use anyhow::{Context, Result};
+use rand::TryRng;
+use rand::rngs::SysRng;
+
+use super::long_path::extended;
+
+const SUFFIX_LEN: usize = 8;
+
+static REGISTRY: LazyLock<Mutex<HashSet<PathBuf>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+pub(crate) struct TempFile {
+    path: PathBuf,
+    target: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    pub(crate) fn new(target: impl Into<PathBuf>) -> Result<Self> {
+        let target = target.into();
+        let name = target.file_name().and_then(|n| n.to_str()).context("invalid target file name")?;
+
+        let mut suffix = [0u8; SUFFIX_LEN];
+        SysRng.try_fill_bytes(&mut suffix).context("failed to generate temp file name")?;
+
+        let path = target.with_file_name(format!(".{name}.{}.tmp", hex::encode(suffix)));
+        register(&path);
+
+        Ok(Self { path, target, persisted: false })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) async fn persist(mut self) -> Result<()> {
+        tokio::fs::rename(extended(&self.path), extended(&self.target)).await.context("failed to move temp file into place")?;
+        unregister(&self.path);
+        self.persisted = true;
+
+        Ok(())
+    }
+
+    pub(crate) fn persist_blocking(mut self) -> Result<()> {
+        std::fs::rename(extended(&self.path), extended(&self.target)).context("failed to move temp file into place")?;
+        unregister(&self.path);
+        self.persisted = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            remove(&self.path);
+        }
+    }
+}
+
+pub(crate) fn register(path: &Path) {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner).insert(path.to_path_buf());
+}
+
+pub(crate) fn unregister(path: &Path) {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner).remove(path);
+}
+
+pub(crate) fn remove(path: &Path) {
+    unregister(path);
+    discard(path);
+}
+
+pub(crate) fn cleanup() {
+    let paths: Vec<PathBuf> = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner).drain().collect();
+    for path in paths {
+        discard(&path);
+    }
+}
+
+fn discard(path: &Path) {
+    let path = extended(path);
+    if path.is_dir() {
+        std::fs::remove_dir_all(&path).unwrap_or_default();
+    } else {
+        std::fs::remove_file(&path).unwrap_or_default();
+    }
+}
+
+pub(crate) fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        cleanup();
+        previous(info);
+    }));
+}
@@ -0,0 +1,67 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use crate::secure_temp::SecureBuffer;
+
+/// Decrypted chunks, evicting the least recently used once more than `capacity` are held.
+pub(super) struct ChunkCache {
+    capacity: usize,
+    chunks: HashMap<u64, Arc<SecureBuffer>>,
+    order: VecDeque<u64>,
+}
+
+impl ChunkCache {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), chunks: HashMap::new(), order: VecDeque::new() }
+    }
+
+    pub(super) fn get(&mut self, index: u64) -> Option<Arc<SecureBuffer>> {
+        let chunk = self.chunks.get(&index).cloned()?;
+        self.touch(index);
+
+        Some(chunk)
+    }
+
+    pub(super) fn insert(&mut self, index: u64, chunk: Arc<SecureBuffer>) {
+        if self.chunks.insert(index, chunk).is_some() {
+            self.touch(index);
+            return;
+        }
+
+        self.order.push_back(index);
+        while self.order.len() > self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.chunks.remove(&evicted);
+            }
+        }
+    }
+
+    fn touch(&mut self, index: u64) {
+        if let Some(position) = self.order.iter().position(|&cached| cached == index) {
+            self.order.remove(position);
+        }
+        self.order.push_back(index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(byte: u8) -> Arc<SecureBuffer> {
+        Arc::new(SecureBuffer::unlocked(vec![byte; 4]))
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ChunkCache::new(2);
+        cache.insert(0, chunk(0));
+        cache.insert(1, chunk(1));
+        assert!(cache.get(0).is_some());
+
+        cache.insert(2, chunk(2));
+        assert!(cache.get(1).is_none());
+        assert_eq!(cache.get(0).unwrap()[0], 0);
+        assert_eq!(cache.get(2).unwrap()[0], 2);
+    }
+}
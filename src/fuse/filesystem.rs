@@ -0,0 +1,118 @@
+use std::ffi::OsStr;
+use std::time::{Duration, SystemTime};
+
+use fuser::{FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen, Request};
+
+use super::image::Image;
+use crate::files::Attributes;
+
+const TTL: Duration = Duration::from_secs(1);
+const ROOT: u64 = fuser::FUSE_ROOT_ID;
+const FILE: u64 = 2;
+const DIRECTORY_MODE: u16 = 0o555;
+const READ_ONLY: u32 = 0o444;
+const ATTR_BLOCK_SIZE: u32 = 4096;
+
+/// A read-only directory holding a single file: the decrypted original.
+pub(super) struct DecryptedFs {
+    image: Image,
+    name: String,
+    attributes: Attributes,
+    mounted: SystemTime,
+}
+
+impl DecryptedFs {
+    pub(super) fn new(image: Image, name: String, attributes: Attributes) -> Self {
+        Self { image, name, attributes, mounted: SystemTime::now() }
+    }
+
+    fn attr(&self, ino: u64, request: &Request<'_>) -> Option<FileAttr> {
+        let time = self.attributes.modified().unwrap_or(self.mounted);
+        let (kind, perm, size, nlink) = match ino {
+            ROOT => (FileType::Directory, DIRECTORY_MODE, 0, 2),
+            FILE => {
+                let mode = self.attributes.mode().map_or(READ_ONLY, |mode| mode & READ_ONLY);
+                (FileType::RegularFile, u16::try_from(mode).unwrap_or(0), self.image.size(), 1)
+            }
+            _ => return None,
+        };
+
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: time,
+            mtime: time,
+            ctime: time,
+            crtime: time,
+            kind,
+            perm,
+            nlink,
+            uid: request.uid(),
+            gid: request.gid(),
+            rdev: 0,
+            blksize: ATTR_BLOCK_SIZE,
+            flags: 0,
+        })
+    }
+}
+
+impl Filesystem for DecryptedFs {
+    fn lookup(&mut self, request: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.attr(FILE, request) {
+            Some(attr) if parent == ROOT && name == OsStr::new(&self.name) => reply.entry(&TTL, &attr, 0),
+            _ => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, request: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino, request) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn open(&mut self, _request: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        match ino {
+            FILE if flags & libc::O_ACCMODE == libc::O_RDONLY => reply.opened(0, 0),
+            FILE => reply.error(libc::EROFS),
+            ROOT => reply.error(libc::EISDIR),
+            _ => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(&mut self, _request: &Request<'_>, ino: u64, _fh: u64, offset: i64, size: u32, _flags: i32, _lock_owner: Option<u64>, reply: ReplyData) {
+        if ino != FILE {
+            reply.error(libc::EISDIR);
+            return;
+        }
+        let Ok(offset) = u64::try_from(offset) else {
+            reply.error(libc::EINVAL);
+            return;
+        };
+
+        match self.image.read(offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(error) => {
+                eprintln!("failed to read {} at offset {offset}: {error:#}", self.name);
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
+    fn readdir(&mut self, _request: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        if ino != ROOT {
+            reply.error(libc::ENOTDIR);
+            return;
+        }
+
+        let entries = [(ROOT, FileType::Directory, "."), (ROOT, FileType::Directory, ".."), (FILE, FileType::RegularFile, self.name.as_str())];
+        for (position, (ino, kind, name)) in entries.into_iter().enumerate().skip(usize::try_from(offset).unwrap_or(0)) {
+            let next = i64::try_from(position).unwrap_or(i64::MAX).saturating_add(1);
+            if reply.add(ino, next, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
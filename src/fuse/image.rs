@@ -0,0 +1,75 @@
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use super::cache::ChunkCache;
+use crate::config::MAX_CHUNK_SIZE;
+use crate::pipeline::{IndexEntry, Process, Task};
+use crate::secure_temp::SecureBuffer;
+
+/// Random-access view of the original: a read decrypts only the chunks it covers, found through the
+/// chunk index and kept in a small cache so sequential reads don't decrypt the same chunk twice.
+pub(crate) struct Image {
+    file: File,
+    process: Process,
+    entries: Vec<IndexEntry>,
+    header_len: u64,
+    size: u64,
+    cache: ChunkCache,
+}
+
+impl Image {
+    pub(crate) fn new(file: File, process: Process, entries: Vec<IndexEntry>, header_len: u64, size: u64, cached_chunks: usize) -> Self {
+        Self { file, process, entries, header_len, size, cache: ChunkCache::new(cached_chunks) }
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Up to `len` bytes starting at `offset`; fewer only where the original ends.
+    pub(crate) fn read(&mut self, offset: u64, len: u32) -> Result<Vec<u8>> {
+        let end = offset.saturating_add(u64::from(len)).min(self.size);
+        let mut output = Vec::with_capacity(usize::try_from(end.saturating_sub(offset)).unwrap_or(0));
+
+        let mut position = offset;
+        while position < end {
+            let index = self.entries.partition_point(|entry| entry.plaintext_offset <= position).saturating_sub(1);
+            let entry = *self.entries.get(index).context("chunk index is empty")?;
+            let chunk = self.chunk(index, entry)?;
+
+            let from = usize::try_from(position.saturating_sub(entry.plaintext_offset)).context("read offset overflow")?;
+            let to = usize::try_from(end.saturating_sub(entry.plaintext_offset)).unwrap_or(usize::MAX).min(chunk.len());
+            let bytes = chunk.get(from..to).filter(|bytes| !bytes.is_empty()).context("chunk is shorter than the chunk index says")?;
+            output.extend_from_slice(bytes);
+            position = position.saturating_add(u64::try_from(bytes.len()).context("read length overflow")?);
+        }
+
+        Ok(output)
+    }
+
+    fn chunk(&mut self, index: usize, entry: IndexEntry) -> Result<Arc<SecureBuffer>> {
+        let index = u64::try_from(index).context("chunk index overflow")?;
+        if let Some(chunk) = self.cache.get(index) {
+            return Ok(chunk);
+        }
+
+        let start = self.header_len.saturating_add(entry.offset);
+        let mut length = [0u8; size_of::<u32>()];
+        self.file.read_exact_at(&mut length, start).context("failed to read chunk length")?;
+        let length = u32::from_le_bytes(length);
+        if length > MAX_CHUNK_SIZE {
+            anyhow::bail!("chunk {index} claims {length} bytes, more than a chunk can hold");
+        }
+
+        let mut data = vec![0u8; usize::try_from(length).context("chunk length overflow")?];
+        self.file.read_exact_at(&mut data, start.saturating_add(size_of::<u32>() as u64)).context("failed to read chunk")?;
+
+        let chunk = Arc::new(self.process.process(&Task { data: SecureBuffer::unlocked(data), index })?.data);
+        self.cache.insert(index, Arc::clone(&chunk));
+
+        Ok(chunk)
+    }
+}
@@ -0,0 +1,20 @@
+mod cache;
+mod filesystem;
+mod image;
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use fuser::{BackgroundSession, MountOption};
+pub(crate) use image::Image;
+
+use crate::files::Attributes;
+
+/// Mounts `image` read-only at `mountpoint` as a directory holding one file, `name`. The mount
+/// lives until the returned session is dropped.
+pub(crate) fn mount(image: Image, name: String, attributes: Attributes, mountpoint: &Path) -> Result<BackgroundSession> {
+    let decrypted = filesystem::DecryptedFs::new(image, name, attributes);
+    let options = [MountOption::RO, MountOption::NoExec, MountOption::FSName("sweetbyte".to_owned()), MountOption::Subtype("swx".to_owned())];
+
+    fuser::spawn_mount2(decrypted, mountpoint, &options).with_context(|| format!("failed to mount {}", mountpoint.display()))
+}
@@ -0,0 +1,34 @@
+//! Entry points for the `fuzz/` targets, built only with the `fuzzing` feature. Each runs one
+//! parser over raw bytes and reports whether it accepted them; a panic or a runaway allocation is
+//! the bug being looked for.
+
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::config::{ORIGINAL_COUNT, RECOVERY_COUNT};
+use crate::encoding::Encoding;
+use crate::header::Deserializer;
+use crate::header::types::Metadata;
+
+/// Parses a header from a byte slice, the way in-memory decryption does.
+pub fn header(data: &[u8]) -> bool {
+    Deserializer::from_bytes(data).is_ok()
+}
+
+/// Parses a header through the streaming reader used for files. Reading from a slice never waits,
+/// so a single poll runs it to completion.
+pub fn header_stream(mut data: &[u8]) -> bool {
+    let parsing = pin!(Deserializer::from_reader(&mut data));
+
+    matches!(parsing.poll(&mut Context::from_waker(Waker::noop())), Poll::Ready(Ok(_)))
+}
+
+/// Parses decrypted header metadata.
+pub fn metadata(data: &[u8]) -> bool {
+    postcard::from_bytes::<Metadata>(data).is_ok()
+}
+
+/// Repairs and decodes a Reed-Solomon block, as every header field and payload chunk is.
+pub fn shards(data: &[u8]) -> bool {
+    Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT).and_then(|encoding| encoding.decode(data)).is_ok()
+}
@@ -0,0 +1,97 @@
+#[cfg(feature = "cli")]
+use anyhow::{Context, Result};
+#[cfg(feature = "cli")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, SeekFrom};
+
+#[cfg(feature = "cli")]
+use super::Deserializer;
+#[cfg(feature = "cli")]
+use super::section::MAX_FRAMED_LEN;
+use crate::config::HEADER_COPY_MAGIC;
+
+/// The last bytes of a file with a header copy: the copy's length and a magic, so it can be found
+/// from the end.
+pub(crate) const COPY_TAIL_LEN: usize = size_of::<u32>() + HEADER_COPY_MAGIC.len();
+
+/// Frames a serialized header as the copy that ends a file: the header again, byte for byte, then
+/// its length and `SWXH`.
+#[cfg(feature = "cli")]
+pub(crate) fn trailing_copy(serialized: &[u8]) -> Result<Vec<u8>> {
+    let len = u32::try_from(serialized.len()).context("header too large to copy")?;
+
+    let mut copy = Vec::with_capacity(serialized.len().saturating_add(COPY_TAIL_LEN));
+    copy.extend_from_slice(serialized);
+    copy.extend_from_slice(&len.to_le_bytes());
+    copy.extend_from_slice(&HEADER_COPY_MAGIC);
+
+    Ok(copy)
+}
+
+/// Reads the header copy at the end of `file`, for when the header at the start can't be read.
+#[cfg(feature = "cli")]
+pub(crate) async fn read_trailing_copy<R: AsyncRead + AsyncSeek + Unpin>(file: &mut R) -> Result<Deserializer> {
+    let len = file.seek(SeekFrom::End(0)).await.context("failed to seek to header copy")?;
+    let tail_start = len.checked_sub(COPY_TAIL_LEN as u64).context("file is too short to hold a header copy")?;
+    file.seek(SeekFrom::Start(tail_start)).await.context("failed to seek to header copy")?;
+
+    let copy_len = file.read_u32_le().await.context("failed to read header copy")?;
+    let mut magic = [0u8; HEADER_COPY_MAGIC.len()];
+    file.read_exact(&mut magic).await.context("failed to read header copy")?;
+    if magic != HEADER_COPY_MAGIC {
+        anyhow::bail!("file has no header copy");
+    }
+
+    let copy_len = usize::try_from(copy_len).context("header copy length overflow")?;
+    if copy_len > MAX_FRAMED_LEN {
+        anyhow::bail!("header copy claims {copy_len} bytes, more than a header can take");
+    }
+    let start = tail_start.checked_sub(copy_len as u64).context("header copy runs past the start of the file")?;
+    file.seek(SeekFrom::Start(start)).await.context("failed to seek to header copy")?;
+    let mut copy = vec![0u8; copy_len];
+    file.read_exact(&mut copy).await.context("failed to read header copy")?;
+
+    let (header, consumed) = Deserializer::from_bytes(&copy).context("failed to parse header copy")?;
+    if consumed != copy_len || !header.copied() {
+        anyhow::bail!("header copy is malformed");
+    }
+
+    Ok(header)
+}
+
+#[cfg(test)]
+#[cfg(feature = "cli")]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::cipher::{CipherMode, KdfParams, KeyDeriver, KeySlot, SeededRandom};
+    use crate::config::{ARGON2_SALT_LEN, KEY_LEN, MASTER_KEY_LEN, NONCE_PREFIX_LEN};
+    use crate::files::Attributes;
+    use crate::header::Serializer;
+    use crate::secret::Secret;
+
+    #[tokio::test]
+    async fn trailing_copy_stands_in_for_a_destroyed_header() {
+        let rng = SeededRandom::new(4);
+        let salt = KeyDeriver::generate_salt(&rng, ARGON2_SALT_LEN).unwrap();
+        let master_key = Secret::new(vec![3u8; MASTER_KEY_LEN]);
+        let keys = KeyDeriver::expand(&master_key, &salt).unwrap();
+        let slot = KeySlot::wrap(&Secret::new(vec![9u8; KEY_LEN]), &master_key, &rng).unwrap();
+        let header = Serializer::new("a.txt", 1, vec![0u8; blake3::OUT_LEN], Attributes::default(), CipherMode::default(), vec![1u8; NONCE_PREFIX_LEN], &KdfParams::default())
+            .unwrap()
+            .with_header_copy(true);
+        let serialized = header.serialize(salt.expose_secret(), vec![slot], &keys, &rng).unwrap();
+
+        let mut file = serialized.clone();
+        file.extend_from_slice(b"payload");
+        file.extend_from_slice(&trailing_copy(&serialized).unwrap());
+        file[..serialized.len()].fill(0);
+
+        assert!(Deserializer::from_bytes(&file).is_err());
+        let recovered = read_trailing_copy(&mut Cursor::new(&file)).await.unwrap();
+        assert_eq!(recovered.encoded_len(), serialized.len());
+        assert!(recovered.copied());
+
+        assert!(read_trailing_copy(&mut Cursor::new(&serialized)).await.is_err());
+    }
+}
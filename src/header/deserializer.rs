@@ -1,49 +1,356 @@
 use anyhow::{Context, Result};
+#[cfg(any(feature = "cli", feature = "fuzzing"))]
 use tokio::io::AsyncRead;
 
+#[cfg(feature = "cli")]
+use super::copy::COPY_TAIL_LEN;
+#[cfg(feature = "cli")]
+use super::section::MAX_FRAMED_LEN;
 use super::section::{Section, SectionData};
+#[cfg(feature = "cli")]
+use super::serializer::pack;
+#[cfg(feature = "cli")]
+use super::types::RecoveryRecord;
 use super::types::{Metadata, Parameters};
-use crate::cipher::Signer;
-use crate::config::{COMPRESSION_LEVEL, ORIGINAL_COUNT, RECOVERY_COUNT};
+#[cfg(feature = "cli")]
+use crate::cipher::KeySlot;
+use crate::cipher::{CipherMode, Kdf, KdfParams, Signer};
+#[cfg(feature = "cli")]
+use crate::config::MAX_KEY_SLOTS;
+use crate::config::{CHUNK_SIZE, COMPRESSION_LEVEL, CURRENT_VERSION, MAGIC_BYTES, ORIGINAL_COUNT, RECOVERY_COUNT};
+use crate::error::SweetByteError;
+use crate::padding::PaddingScheme;
 use crate::secret::Secret;
+use crate::validation::KeySlots;
 
 pub(crate) struct Deserializer {
     params: Parameters,
-    metadata: Metadata,
+    slots: KeySlots,
     section_data: SectionData,
 }
 
 impl Deserializer {
+    #[cfg(any(feature = "cli", feature = "fuzzing"))]
     pub(crate) async fn from_reader<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Self> {
         let section: Section = Section::new(COMPRESSION_LEVEL, ORIGINAL_COUNT, RECOVERY_COUNT).context("failed to initialize section encoder")?;
         let section_data: SectionData = section.unpack(reader).await.context("failed to unpack section data")?;
-        let params: Parameters = postcard::from_bytes(section_data.params.expose_secret()).context("failed to deserialize params")?;
-        let metadata: Metadata = postcard::from_bytes(section_data.metadata.expose_secret()).context("failed to deserialize metadata")?;
 
-        Ok(Self { params, metadata, section_data })
+        Self::from_section(section_data)
     }
 
-    pub(crate) fn file_name(&self) -> &str {
-        self.metadata.name()
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<(Self, usize)> {
+        let section: Section = Section::new(COMPRESSION_LEVEL, ORIGINAL_COUNT, RECOVERY_COUNT).context("failed to initialize section encoder")?;
+        let (section_data, consumed) = section.unpack_bytes(data).context("failed to unpack section data")?;
+
+        Ok((Self::from_section(section_data)?, consumed))
+    }
+
+    /// Rebuilds a header from a [`Self::recovery_record`], to read a file whose own header is gone.
+    #[cfg(feature = "cli")]
+    pub(crate) fn from_recovery(record: &[u8]) -> Result<Self> {
+        let record: RecoveryRecord = postcard::from_bytes(record).context("failed to parse recovery record")?;
+        let capacity = usize::try_from(record.capacity).context("recovery record capacity overflow")?;
+        if capacity > MAX_FRAMED_LEN {
+            anyhow::bail!("recovery record claims a {capacity}-byte header, more than a header can take");
+        }
+
+        Self::from_section(SectionData {
+            salt: Secret::new(record.salt),
+            params: Secret::new(record.params),
+            slots: Secret::new(record.slots),
+            metadata: Secret::new(record.metadata),
+            mac: Secret::new(record.mac),
+            capacity,
+        })
+    }
+
+    fn from_section(section_data: SectionData) -> Result<Self> {
+        let params_bytes = section_data.params.expose_secret();
+        let params: Parameters = postcard::from_bytes(params_bytes)
+            .map_err(|error| unsupported_version(params_bytes).map_or_else(|| anyhow::Error::new(error), anyhow::Error::new))
+            .context("failed to deserialize params")?;
+        params.kdf().validate().context("header records kdf costs out of range")?;
+        let slots: KeySlots = postcard::from_bytes(section_data.slots.expose_secret()).context("failed to deserialize key slots")?;
+
+        Ok(Self { params, slots, section_data })
+    }
+
+    /// Decrypts the name, size, hash, and attributes; call [`Self::verify`] first so a tampered
+    /// header is reported as such rather than as a decryption failure.
+    pub(crate) fn metadata(&self, metadata_key: &Secret) -> Result<Metadata> {
+        let metadata = crate::cipher::open(metadata_key, self.section_data.metadata.expose_secret()).context("failed to decrypt metadata")?;
+
+        postcard::from_bytes(metadata.expose_secret()).context("failed to deserialize metadata")
+    }
+
+    pub(crate) fn cipher_mode(&self) -> Result<CipherMode> {
+        CipherMode::from_bits(self.params.algorithm())
+    }
+
+    pub(crate) fn nonce_prefix(&self) -> &[u8] {
+        self.params.nonce_prefix()
+    }
+
+    pub(crate) fn kdf(&self) -> KdfParams {
+        self.params.kdf()
+    }
+
+    pub(crate) fn payload_mac(&self) -> bool {
+        self.params.payload_mac
+    }
+
+    pub(crate) fn chunk_count(&self) -> u64 {
+        self.params.chunk_count
+    }
+
+    pub(crate) fn chunk_size(&self) -> u32 {
+        self.params.chunk_size
+    }
+
+    /// The most plaintext one chunk can decrypt to: the recorded chunk size, and never more than
+    /// the `file_size` bytes the whole file holds.
+    pub(crate) fn chunk_limit(&self, file_size: u64) -> usize {
+        let chunk_size = usize::try_from(self.chunk_size()).ok().filter(|&size| size > 0).unwrap_or(CHUNK_SIZE);
+
+        usize::try_from(file_size).map_or(chunk_size, |size| chunk_size.min(size))
+    }
+
+    pub(crate) fn indexed(&self) -> bool {
+        self.params.indexed
+    }
+
+    pub(crate) fn padded(&self) -> bool {
+        self.params.padded
+    }
+
+    pub(crate) fn padding_scheme(&self) -> PaddingScheme {
+        self.params.padding_scheme
+    }
+
+    /// The salt the session key was stretched under, when the file was encrypted with
+    /// `session-kdf`.
+    pub(crate) fn session_salt(&self) -> Option<&[u8]> {
+        self.params.session_salt()
+    }
+
+    pub(crate) fn second_factor(&self) -> bool {
+        self.params.second_factor
     }
 
-    pub(crate) fn file_size(&self) -> u64 {
-        self.metadata.size()
+    /// Whether a copy of the header ends the file.
+    #[cfg(feature = "cli")]
+    pub(crate) fn copied(&self) -> bool {
+        self.params.copied
     }
 
-    pub(crate) fn file_hash(&self) -> &[u8] {
-        self.metadata.hash()
+    /// Bytes the header copy and its tail take up at the end of the file, or zero without one.
+    /// Whatever else is found from the end, such as the chunk index, ends that many bytes
+    /// earlier.
+    #[cfg(feature = "cli")]
+    pub(crate) fn trailing_len(&self) -> u64 {
+        if self.copied() { self.encoded_len().saturating_add(COPY_TAIL_LEN) as u64 } else { 0 }
+    }
+
+    /// Bytes the header takes up on disk, i.e. where the payload starts.
+    #[cfg(feature = "cli")]
+    pub(crate) fn encoded_len(&self) -> usize {
+        self.section_data.framed_len()
     }
 
     pub(crate) fn salt(&self) -> &Secret {
         &self.section_data.salt
     }
 
+    pub(crate) fn slot_count(&self) -> usize {
+        self.slots.as_ref().len()
+    }
+
+    pub(crate) fn find_slot(&self, kek: &Secret) -> Option<(usize, Secret)> {
+        self.slots.as_ref().iter().enumerate().find_map(|(index, slot)| slot.unwrap(kek).map(|master_key| (index, master_key)))
+    }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn add_slot(self, slot: KeySlot, signer_key: &Secret) -> Result<Vec<u8>> {
+        if self.slot_count() >= MAX_KEY_SLOTS {
+            anyhow::bail!("file already has the maximum of {MAX_KEY_SLOTS} key slots");
+        }
+
+        let mut slots = self.slots.into_inner();
+        slots.push(slot);
+
+        Self::repack(&self.params, &self.section_data, slots, signer_key).context("no reserved header space left for another key slot")
+    }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn remove_slot(self, index: usize, signer_key: &Secret) -> Result<Vec<u8>> {
+        if self.slot_count() <= 1 {
+            anyhow::bail!("cannot remove the only key slot");
+        }
+
+        let mut slots = self.slots.into_inner();
+        if index >= slots.len() {
+            anyhow::bail!("key slot {index} does not exist");
+        }
+        slots.remove(index);
+
+        Self::repack(&self.params, &self.section_data, slots, signer_key)
+    }
+
+    #[cfg(feature = "cli")]
+    fn repack(params: &Parameters, data: &SectionData, slots: Vec<KeySlot>, signer_key: &Secret) -> Result<Vec<u8>> {
+        let packed = pack(data.salt.expose_secret(), params, slots, data.metadata.expose_secret(), signer_key)?;
+
+        Section::frame(&packed, data.capacity).context("failed to frame header")
+    }
+
+    /// Everything needed to stand in for this header if it's later destroyed: the decoded sections
+    /// and how much space the header takes. It holds nothing the header on disk doesn't, and
+    /// still needs a password to unlock.
+    #[cfg(feature = "cli")]
+    pub(crate) fn recovery_record(&self) -> Result<Vec<u8>> {
+        let data = &self.section_data;
+        let record = RecoveryRecord {
+            salt: data.salt.expose_secret().to_vec(),
+            params: data.params.expose_secret().to_vec(),
+            slots: data.slots.expose_secret().to_vec(),
+            metadata: data.metadata.expose_secret().to_vec(),
+            mac: data.mac.expose_secret().to_vec(),
+            capacity: u32::try_from(data.capacity).context("header capacity overflow")?,
+        };
+
+        postcard::to_allocvec(&record).context("failed to serialize recovery record")
+    }
+
     pub(crate) fn verify(&self, signer_key: &Secret) -> Result<bool> {
         let params_bytes = postcard::to_allocvec(&self.params).context("failed to serialize params")?;
-        let metadata_bytes = postcard::to_allocvec(&self.metadata).context("failed to serialize metadata")?;
+        let slots_bytes = postcard::to_allocvec(&self.slots).context("failed to serialize key slots")?;
         let signer = Signer::new(signer_key).context("failed to create signer")?;
 
-        Ok(signer.verify_parts(self.section_data.mac.expose_secret(), &[self.section_data.salt.expose_secret(), &params_bytes, &metadata_bytes]))
+        Ok(signer.verify_parts(self.section_data.mac.expose_secret(), &[self.section_data.salt.expose_secret(), &params_bytes, &slots_bytes, self.section_data.metadata.expose_secret()]))
+    }
+}
+
+/// Reads just the magic and version a header starts with when the rest of its params don't parse,
+/// so a file from an older or newer format is reported as such rather than as damage.
+fn unsupported_version(params: &[u8]) -> Option<SweetByteError> {
+    let ((magic, version), _) = postcard::take_from_bytes::<(u32, u16)>(params).ok()?;
+
+    (magic == MAGIC_BYTES && version != CURRENT_VERSION).then_some(SweetByteError::UnsupportedVersion { found: version, supported: CURRENT_VERSION })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::LazyLock;
+
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    use super::*;
+    use crate::cipher::{Argon2Params, KeyDeriver, KeySlot, SeededRandom};
+    use crate::config::{ARGON2_SALT_LEN, KEY_LEN, MASTER_KEY_LEN, MAX_SECTION_SIZE, MIN_KDF_MEMORY, NONCE_PREFIX_LEN};
+    use crate::files::Attributes;
+    use crate::header::Serializer;
+
+    static HEADER: LazyLock<Vec<u8>> = LazyLock::new(|| {
+        let rng = SeededRandom::new(3);
+        let kdf = KdfParams::Argon2id(Argon2Params { memory: MIN_KDF_MEMORY, iterations: 1, parallelism: 1 });
+        let master_key = Secret::new(vec![7u8; MASTER_KEY_LEN]);
+        let salt = Secret::new(vec![1u8; ARGON2_SALT_LEN]);
+        let keys = KeyDeriver::expand(&master_key, &salt).unwrap();
+        let slot = KeySlot::wrap(&Secret::new(vec![9u8; KEY_LEN]), &master_key, &rng).unwrap();
+
+        Serializer::new("name.txt", 10, vec![0u8; 32], Attributes::default(), CipherMode::default(), vec![0u8; NONCE_PREFIX_LEN], &kdf)
+            .unwrap()
+            .serialize(salt.expose_secret(), vec![slot], &keys, &rng)
+            .unwrap()
+    });
+
+    proptest! {
+        #[test]
+        fn arbitrary_bytes_never_panic(data in vec(any::<u8>(), 0..4096)) {
+            let _parsed = Deserializer::from_bytes(&data);
+        }
+
+        #[test]
+        fn damaged_headers_never_panic(damage in vec((any::<Index>(), 1..=u8::MAX), 1..32)) {
+            let mut data = HEADER.clone();
+            for (index, mask) in damage {
+                let index = index.index(data.len());
+                data[index] ^= mask;
+            }
+
+            let _parsed = Deserializer::from_bytes(&data);
+        }
+
+        #[test]
+        fn oversized_frames_are_rejected(length in any::<u32>(), capacity in MAX_SECTION_SIZE.saturating_add(1).., tail in vec(any::<u8>(), 0..64)) {
+            let mut data = [length.to_le_bytes(), capacity.to_le_bytes()].concat();
+            data.extend_from_slice(&tail);
+
+            prop_assert!(Deserializer::from_bytes(&data).is_err());
+        }
+
+        #[test]
+        fn arbitrary_metadata_never_panics(data in vec(any::<u8>(), 0..512)) {
+            let _parsed = postcard::from_bytes::<Metadata>(&data);
+        }
+    }
+
+    #[test]
+    fn intact_header_parses() {
+        let (header, consumed) = Deserializer::from_bytes(&HEADER).unwrap();
+        assert_eq!(consumed, HEADER.len());
+        assert_eq!(header.slot_count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn recovery_records_stand_in_for_the_header() {
+        let (header, consumed) = Deserializer::from_bytes(&HEADER).unwrap();
+        let recovered = Deserializer::from_recovery(&header.recovery_record().unwrap()).unwrap();
+
+        assert_eq!(recovered.encoded_len(), consumed);
+        assert_eq!(recovered.salt().expose_secret(), header.salt().expose_secret());
+        assert!(recovered.find_slot(&Secret::new(vec![9u8; KEY_LEN])).is_some());
+        assert!(Deserializer::from_recovery(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn oversized_fields_are_rejected() {
+        let section = Section::new(COMPRESSION_LEVEL, ORIGINAL_COUNT, RECOVERY_COUNT).unwrap();
+        let packed = section.pack(&[1u8; ARGON2_SALT_LEN], &[0u8; 1024], &[2u8; 8], &[3u8; 8], &[4u8; 32]).unwrap();
+        let framed = Section::frame(&packed, packed.len()).unwrap();
+
+        let error = Deserializer::from_bytes(&framed).err().unwrap();
+        assert!(format!("{error:#}").contains("params section claims 1024 bytes"));
+    }
+
+    #[test]
+    fn other_versions_are_named() {
+        let params = postcard::to_allocvec(&(MAGIC_BYTES, 3u16, 0u8)).unwrap();
+        let section = Section::new(COMPRESSION_LEVEL, ORIGINAL_COUNT, RECOVERY_COUNT).unwrap();
+        let packed = section.pack(&[1u8; ARGON2_SALT_LEN], &params, &[2u8; 8], &[3u8; 8], &[4u8; 32]).unwrap();
+        let framed = Section::frame(&packed, packed.len()).unwrap();
+
+        let error = Deserializer::from_bytes(&framed).err().unwrap();
+        assert_eq!(SweetByteError::classify(&error), SweetByteError::UnsupportedVersion { found: 3, supported: CURRENT_VERSION });
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "cli")]
+    async fn capacity_is_checked_before_allocating() {
+        let data = [8u32.to_le_bytes(), u32::MAX.to_le_bytes()].concat();
+        let error = Deserializer::from_reader(&mut data.as_slice()).await.err().unwrap();
+        assert!(format!("{error:#}").contains("exceeds maximum"));
+    }
+
+    #[test]
+    fn decompression_bomb_is_rejected() {
+        let bomb = zstd::stream::encode_all(vec![0u8; 16 * 1024 * 1024].as_slice(), COMPRESSION_LEVEL).unwrap();
+        let framed = Section::frame(&bomb, bomb.len()).unwrap();
+
+        let error = Deserializer::from_bytes(&framed).err().unwrap();
+        assert!(format!("{error:#}").contains("decompressed data exceeds"));
     }
 }
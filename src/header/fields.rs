@@ -0,0 +1,51 @@
+use serde::de::{self, Deserialize, Visitor};
+use serde::forward_to_deserialize_any;
+
+/// A deserializer that never produces a value. A derived `Deserialize` for a struct hands it the
+/// field names in declaration order, which is also the order postcard writes them, and that list is
+/// all it keeps.
+struct FieldNames {
+    fields: &'static [&'static str],
+}
+
+impl<'de> de::Deserializer<'de> for &mut FieldNames {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], _visitor: V) -> Result<V::Value, Self::Error> {
+        self.fields = fields;
+        Err(de::Error::custom("field names read"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit unit_struct
+        newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Serialized field names of `T`, or nothing if `T` isn't a struct.
+pub(crate) fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut names = FieldNames { fields: &[] };
+    if T::deserialize(&mut names).is_ok() {
+        return &[];
+    }
+
+    names.fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::types::Parameters;
+
+    #[test]
+    fn lists_fields_in_serialization_order() {
+        let fields = field_names::<Parameters>();
+        assert_eq!(fields.first(), Some(&"magic"));
+        assert_eq!(fields.last(), Some(&"padding_scheme"));
+        assert!(field_names::<u32>().is_empty());
+    }
+}
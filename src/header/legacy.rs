@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_with::base64::Base64;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt};
+
+use crate::cipher::Signer;
+use crate::compression::Compression;
+use crate::config::{ARGON2_SALT_LEN, COMPRESSION_LEVEL, CURRENT_VERSION, LEGACY_SECTION_SIZE, LEGACY_VERSION, MAGIC_BYTES, MAX_DECOMPRESSED_SECTION_SIZE, ORIGINAL_COUNT, RECOVERY_COUNT};
+use crate::encoding::Encoding;
+use crate::error::SweetByteError;
+use crate::secret::Secret;
+use crate::validation::{FileHash, FileSize, Filename};
+
+/// Where a format 0x0002 header's zstd frame starts, right after its length. The current format
+/// keeps its reserved capacity there, which is never anywhere near this large.
+const FRAME_OFFSET: usize = 4;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+#[serde_with::serde_as]
+#[derive(Deserialize)]
+struct SectionList {
+    #[serde_as(as = "Base64")]
+    salt: Vec<u8>,
+    #[serde_as(as = "Base64")]
+    params: Vec<u8>,
+    #[serde_as(as = "Base64")]
+    metadata: Vec<u8>,
+    #[serde_as(as = "Base64")]
+    mac: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Parameters {
+    magic: u32,
+    version: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    name: Filename,
+    size: FileSize,
+    hash: FileHash,
+}
+
+/// A header written by format 0x0002: the salt, the magic and version, and the name, size, and hash
+/// in the clear, under a MAC keyed straight from the password. There are no key slots, and nothing
+/// else to read from it.
+pub(crate) struct LegacyHeader {
+    salt: Secret,
+    params: Parameters,
+    metadata: Metadata,
+    mac: Vec<u8>,
+}
+
+impl LegacyHeader {
+    /// Whether `reader` starts with a format 0x0002 header, told by the zstd frame right after the
+    /// length, without consuming anything.
+    pub(crate) async fn detect<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<bool> {
+        let prefix = reader.fill_buf().await.context("failed to read header")?;
+
+        Ok(prefix.get(FRAME_OFFSET..FRAME_OFFSET.saturating_add(ZSTD_MAGIC.len())) == Some(ZSTD_MAGIC.as_slice()))
+    }
+
+    pub(crate) async fn from_reader<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Self> {
+        let section_size = reader.read_u32_le().await.context("failed to read section length")?;
+        if section_size > LEGACY_SECTION_SIZE {
+            anyhow::bail!("section size {section_size} exceeds maximum {LEGACY_SECTION_SIZE}");
+        }
+
+        let mut buffer = vec![0u8; section_size as usize];
+        reader.read_exact(&mut buffer).await.context("failed to read section")?;
+
+        let compressor = Compression::new(COMPRESSION_LEVEL).context("failed to initialize compression")?;
+        let decompressed = compressor.decompress(&buffer, MAX_DECOMPRESSED_SECTION_SIZE).context("failed to decompress section")?;
+        let section: SectionList = postcard::from_bytes(&decompressed).context("failed to deserialize section")?;
+
+        let encoder = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT).context("failed to initialize encoder")?;
+        let field = |name: &str, encoded: &[u8]| encoder.decode_legacy(encoded).with_context(|| format!("failed to decode {name}"));
+
+        let salt = field("salt", &section.salt)?;
+        if salt.len() != ARGON2_SALT_LEN {
+            anyhow::bail!("salt is {} bytes, expected {ARGON2_SALT_LEN}", salt.len());
+        }
+        let params: Parameters = postcard::from_bytes(&field("params", &section.params)?).context("failed to deserialize params")?;
+        if params.magic != MAGIC_BYTES {
+            anyhow::bail!("invalid magic bytes");
+        }
+        if params.version != LEGACY_VERSION {
+            return Err(SweetByteError::UnsupportedVersion { found: params.version, supported: CURRENT_VERSION }.into());
+        }
+        let metadata: Metadata = postcard::from_bytes(&field("metadata", &section.metadata)?).context("failed to deserialize metadata")?;
+
+        Ok(Self { salt: Secret::new(salt), params, metadata, mac: field("mac", &section.mac)? })
+    }
+
+    pub(crate) fn salt(&self) -> &Secret {
+        &self.salt
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        self.metadata.name.as_ref()
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        *self.metadata.size.as_ref()
+    }
+
+    pub(crate) fn hash(&self) -> &[u8] {
+        self.metadata.hash.as_ref()
+    }
+
+    pub(crate) fn verify(&self, signer_key: &Secret) -> Result<bool> {
+        let params_bytes = postcard::to_allocvec(&self.params).context("failed to serialize params")?;
+        let metadata_bytes = postcard::to_allocvec(&self.metadata).context("failed to serialize metadata")?;
+        let signer = Signer::new(signer_key).context("failed to create signer")?;
+
+        Ok(signer.verify_parts(&self.mac, &[self.salt.expose_secret(), &params_bytes, &metadata_bytes]))
+    }
+}
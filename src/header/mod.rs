@@ -1,7 +1,30 @@
+mod copy;
 mod deserializer;
+#[cfg(feature = "cli")]
+mod fields;
+#[cfg(feature = "cli")]
+mod legacy;
 mod section;
 mod serializer;
-mod types;
+pub(crate) mod types;
 
+pub(crate) use copy::COPY_TAIL_LEN;
+#[cfg(feature = "cli")]
+pub(crate) use copy::{read_trailing_copy, trailing_copy};
 pub(crate) use deserializer::Deserializer;
+#[cfg(feature = "cli")]
+pub(crate) use fields::field_names;
+#[cfg(feature = "cli")]
+pub(crate) use legacy::LegacyHeader;
+pub(crate) use section::MAX_FRAMED_LEN;
 pub(crate) use serializer::Serializer;
+
+#[cfg(feature = "cli")]
+use crate::cipher::KeySlot;
+
+/// Every structure the header serializes, with its fields in the order they're written, so the
+/// format can be described from the code that writes it.
+#[cfg(feature = "cli")]
+pub(crate) fn structures() -> [(&'static str, &'static [&'static str]); 4] {
+    [("section", field_names::<section::SectionList>()), ("parameters", field_names::<types::Parameters>()), ("key slot", field_names::<KeySlot>()), ("metadata", field_names::<types::Metadata>())]
+}
@@ -1,23 +1,31 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_with::base64::Base64;
+#[cfg(any(feature = "cli", feature = "fuzzing"))]
 use tokio::io::{AsyncRead, AsyncReadExt};
 
 use crate::compression::Compression;
-use crate::config::MAX_SECTION_SIZE;
-use crate::encoding::Encoding;
+use crate::config::{
+    ARGON2_SALT_LEN, COMPACT_COPIES, COMPACT_SECTION_LEN, HEADER_MAC_LEN, MAX_DECOMPRESSED_SECTION_SIZE, MAX_METADATA_SECTION_LEN, MAX_PARAMS_SECTION_LEN, MAX_SECTION_SIZE, MAX_SLOTS_SECTION_LEN,
+};
+use crate::encoding::{Encoding, Replicas};
 use crate::secret::Secret;
 
-const PREFIX_LEN: usize = 4;
+pub(super) const PREFIX_LEN: usize = 8;
+
+/// The most a framed header can take up, however many slots and however long a name it holds.
+pub(crate) const MAX_FRAMED_LEN: usize = PREFIX_LEN.saturating_add(MAX_SECTION_SIZE as usize);
 
 #[serde_with::serde_as]
 #[derive(Serialize, Deserialize)]
-struct SectionList {
+pub(super) struct SectionList {
     #[serde_as(as = "Base64")]
     salt: Vec<u8>,
     #[serde_as(as = "Base64")]
     params: Vec<u8>,
     #[serde_as(as = "Base64")]
+    slots: Vec<u8>,
+    #[serde_as(as = "Base64")]
     metadata: Vec<u8>,
     #[serde_as(as = "Base64")]
     mac: Vec<u8>,
@@ -26,59 +34,141 @@ struct SectionList {
 pub(super) struct SectionData {
     pub(super) salt: Secret,
     pub(super) params: Secret,
+    pub(super) slots: Secret,
     pub(super) metadata: Secret,
     pub(super) mac: Secret,
+    #[cfg_attr(not(feature = "cli"), expect(dead_code, reason = "only rewriting a header in place or recovering one needs its reserved size"))]
+    pub(super) capacity: usize,
+}
+
+impl SectionData {
+    #[cfg(feature = "cli")]
+    pub(super) fn framed_len(&self) -> usize {
+        PREFIX_LEN.saturating_add(self.capacity)
+    }
 }
 
 pub(super) struct Section {
     compressor: Compression,
     encoder: Encoding,
+    replicas: Replicas,
 }
 
 impl Section {
     pub(super) fn new(compression_level: i32, original_count: usize, recovery_count: usize) -> Result<Self> {
         let compressor = Compression::new(compression_level).context("failed to initialize compression")?;
         let encoder = Encoding::new(original_count, recovery_count).context("failed to initialize encoder")?;
+        let replicas = Replicas::new(COMPACT_COPIES).context("failed to initialize replicas")?;
 
-        Ok(Self { compressor, encoder })
+        Ok(Self { compressor, encoder, replicas })
     }
 
-    pub(super) fn pack(&self, salt: &[u8], params: &[u8], metadata: &[u8], mac: &[u8]) -> Result<Vec<u8>> {
+    pub(super) fn pack(&self, salt: &[u8], params: &[u8], slots: &[u8], metadata: &[u8], mac: &[u8]) -> Result<Vec<u8>> {
         let encoded_section = SectionList {
-            salt: self.encoder.encode(salt).context("failed to encode salt")?,
-            params: self.encoder.encode(params).context("failed to encode params")?,
-            metadata: self.encoder.encode(metadata).context("failed to encode metadata")?,
-            mac: self.encoder.encode(mac).context("failed to encode mac")?,
+            salt: self.encode(salt).context("failed to encode salt")?,
+            params: self.encode(params).context("failed to encode params")?,
+            slots: self.encode(slots).context("failed to encode key slots")?,
+            metadata: self.encode(metadata).context("failed to encode metadata")?,
+            mac: self.encode(mac).context("failed to encode mac")?,
         };
 
         let serialized_section = postcard::to_allocvec(&encoded_section).context("failed to serialize section")?;
-        let compressed_section = self.compressor.compress(&serialized_section).context("failed to compress section")?;
-        let compressed_length = u32::try_from(compressed_section.len()).context("section too large")?;
 
-        let mut result = Vec::with_capacity(PREFIX_LEN.saturating_add(compressed_section.len()));
-        result.extend_from_slice(&compressed_length.to_le_bytes());
-        result.extend_from_slice(&compressed_section);
+        self.compressor.compress(&serialized_section).context("failed to compress section")
+    }
+
+    pub(super) fn frame(section: &[u8], capacity: usize) -> Result<Vec<u8>> {
+        if section.len() > capacity {
+            anyhow::bail!("section size {} exceeds reserved capacity {capacity}", section.len());
+        }
+
+        let section_length = u32::try_from(section.len()).context("section too large")?;
+        let capacity_length = u32::try_from(capacity).context("section capacity too large")?;
+        if capacity_length > MAX_SECTION_SIZE {
+            anyhow::bail!("section capacity {capacity_length} exceeds maximum {MAX_SECTION_SIZE}");
+        }
+
+        let framed_length = PREFIX_LEN.saturating_add(capacity);
+        let mut result = Vec::with_capacity(framed_length);
+        result.extend_from_slice(&section_length.to_le_bytes());
+        result.extend_from_slice(&capacity_length.to_le_bytes());
+        result.extend_from_slice(section);
+        result.resize(framed_length, 0);
 
         Ok(result)
     }
 
+    #[cfg(any(feature = "cli", feature = "fuzzing"))]
     pub(super) async fn unpack<R: AsyncRead + Unpin>(&self, reader: &mut R) -> Result<SectionData> {
         let buffer_size = reader.read_u32_le().await.context("failed to read section length")?;
-        if buffer_size > MAX_SECTION_SIZE {
-            anyhow::bail!("section size {buffer_size} exceeds maximum {MAX_SECTION_SIZE}");
-        }
+        let capacity = reader.read_u32_le().await.context("failed to read section capacity")?;
+        Self::check_frame(buffer_size, capacity)?;
 
-        let mut buffer = vec![0u8; buffer_size as usize];
+        let mut buffer = vec![0u8; capacity as usize];
         reader.read_exact(&mut buffer).await.context("failed to read section")?;
+        buffer.truncate(buffer_size as usize);
+
+        self.decode(&buffer, capacity as usize)
+    }
+
+    pub(super) fn unpack_bytes(&self, data: &[u8]) -> Result<(SectionData, usize)> {
+        let (buffer_size, rest) = data.split_first_chunk::<4>().context("failed to read section length")?;
+        let (capacity, rest) = rest.split_first_chunk::<4>().context("failed to read section capacity")?;
+        let (buffer_size, capacity) = (u32::from_le_bytes(*buffer_size), u32::from_le_bytes(*capacity));
+        Self::check_frame(buffer_size, capacity)?;
+
+        let frame = rest.get(..capacity as usize).context("failed to read section")?;
+        let section = self.decode(frame.get(..buffer_size as usize).unwrap_or_default(), capacity as usize)?;
 
-        let decompressed_section = self.compressor.decompress(&buffer).context("failed to decompress section")?;
+        Ok((section, (capacity as usize).saturating_add(PREFIX_LEN)))
+    }
+
+    fn check_frame(buffer_size: u32, capacity: u32) -> Result<()> {
+        if capacity > MAX_SECTION_SIZE {
+            anyhow::bail!("section capacity {capacity} exceeds maximum {MAX_SECTION_SIZE}");
+        }
+
+        if buffer_size > capacity {
+            anyhow::bail!("section size {buffer_size} exceeds reserved capacity {capacity}");
+        }
+
+        Ok(())
+    }
+
+    fn decode(&self, buffer: &[u8], capacity: usize) -> Result<SectionData> {
+        let decompressed_section = self.compressor.decompress(buffer, MAX_DECOMPRESSED_SECTION_SIZE).context("failed to decompress section")?;
         let encoded_section: SectionList = postcard::from_bytes(&decompressed_section).context("failed to deserialize section")?;
 
         Ok(SectionData {
-            salt: Secret::new(self.encoder.decode(&encoded_section.salt).context("failed to decode salt")?),
-            params: Secret::new(self.encoder.decode(&encoded_section.params).context("failed to decode params")?),
-            metadata: Secret::new(self.encoder.decode(&encoded_section.metadata).context("failed to decode metadata")?),
-            mac: Secret::new(self.encoder.decode(&encoded_section.mac).context("failed to decode mac")?),
+            salt: self.field("salt", &encoded_section.salt, ARGON2_SALT_LEN)?,
+            params: self.field("params", &encoded_section.params, MAX_PARAMS_SECTION_LEN)?,
+            slots: self.field("key slots", &encoded_section.slots, MAX_SLOTS_SECTION_LEN)?,
+            metadata: self.field("metadata", &encoded_section.metadata, MAX_METADATA_SECTION_LEN)?,
+            mac: self.field("mac", &encoded_section.mac, HEADER_MAC_LEN)?,
+            capacity,
         })
     }
+
+    /// Fields short enough that splitting them into shards would mostly add padding are stored as
+    /// copies instead.
+    fn encode(&self, field: &[u8]) -> Result<Vec<u8>> {
+        if field.len() <= COMPACT_SECTION_LEN {
+            return Ok(self.replicas.encode(field));
+        }
+
+        self.encoder.encode(field)
+    }
+
+    /// Decodes one header field after checking the size it claims against the most that kind of
+    /// field can hold.
+    fn field(&self, name: &str, encoded: &[u8], limit: usize) -> Result<Secret> {
+        let replicated = Replicas::matches(encoded);
+        let claimed = if replicated { self.replicas.decoded_len(encoded) } else { Encoding::decoded_len(encoded) }.with_context(|| format!("failed to read {name} length"))?;
+        if claimed > limit {
+            anyhow::bail!("{name} section claims {claimed} bytes, more than the {limit} allowed");
+        }
+
+        let decoded = if replicated { self.replicas.decode(encoded) } else { self.encoder.decode(encoded) };
+        Ok(Secret::new(decoded.with_context(|| format!("failed to decode {name}"))?))
+    }
 }
@@ -1,43 +1,173 @@
 use anyhow::{Context, Result};
 
+#[cfg(feature = "cli")]
+use super::section::PREFIX_LEN;
 use super::section::Section;
 use super::types::{Metadata, Parameters};
-use crate::cipher::Signer;
-use crate::config::{COMPRESSION_LEVEL, CURRENT_VERSION, MAGIC_BYTES, ORIGINAL_COUNT, RECOVERY_COUNT};
+use crate::cipher::{CipherMode, DerivedKeys, KdfParams, KeySlot, RandomSource, Signer, section_random};
+use crate::config::{COMPRESSION_LEVEL, CURRENT_VERSION, MAGIC_BYTES, MAX_KEY_SLOTS, MAX_SECTION_SIZE, ORIGINAL_COUNT, RECOVERY_COUNT, SLOT_RESERVE_LEN};
+use crate::files::Attributes;
+use crate::padding::PaddingScheme;
 use crate::secret::Secret;
+use crate::validation::KeySlots;
+#[cfg(feature = "cli")]
+use crate::validation::SessionSalt;
 
 pub(crate) struct Serializer {
     params: Parameters,
     metadata: Metadata,
+    headroom: usize,
 }
 
 impl Serializer {
-    pub(crate) fn new(name: impl Into<String>, size: u64, hash: Vec<u8>) -> Result<Self> {
-        let params = Parameters::new(MAGIC_BYTES, CURRENT_VERSION).context("failed to initialize params")?;
-        let metadata = Metadata::new(name, size, hash).context("failed to initialize metadata")?;
+    pub(crate) fn new(name: impl Into<String>, size: u64, hash: Vec<u8>, attributes: Attributes, mode: CipherMode, nonce_prefix: Vec<u8>, kdf: &KdfParams) -> Result<Self> {
+        let params = Parameters::new(MAGIC_BYTES, CURRENT_VERSION, mode.bits(), nonce_prefix, kdf).context("failed to initialize params")?;
+        let metadata = Metadata::new(name, size, hash, attributes).context("failed to initialize metadata")?;
 
-        Ok(Self { params, metadata })
+        Ok(Self { params, metadata, headroom: 0 })
     }
 
+    pub(crate) fn with_payload_mac(mut self, enabled: bool) -> Self {
+        self.params.payload_mac = enabled;
+        self
+    }
+
+    pub(crate) fn with_chunk_count(mut self, chunks: u64) -> Self {
+        self.params.chunk_count = chunks;
+        self
+    }
+
+    /// Plaintext bytes per chunk; every chunk but the last holds exactly this many, so a byte
+    /// offset maps to a chunk.
+    pub(crate) fn with_chunk_size(mut self, size: u32) -> Self {
+        self.params.chunk_size = size;
+        self
+    }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn with_chunk_index(mut self, enabled: bool) -> Self {
+        self.params.indexed = enabled;
+        self
+    }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn with_padding(mut self, enabled: bool) -> Self {
+        self.params.padded = enabled;
+        self
+    }
+
+    pub(crate) fn with_padding_scheme(mut self, scheme: PaddingScheme) -> Self {
+        self.params.padding_scheme = scheme;
+        self
+    }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn with_convergent(mut self, enabled: bool) -> Self {
+        self.params.convergent = enabled;
+        self
+    }
+
+    /// Records the salt of the session key the key slots were wrapped under, for files encrypted
+    /// with `session-kdf`.
+    #[cfg(feature = "cli")]
+    pub(crate) fn with_session_salt(mut self, salt: Option<&[u8]>) -> Result<Self> {
+        self.params.session_salt = salt.map(|salt| SessionSalt::try_new(salt.to_vec())).transpose().context("invalid session salt")?;
+        Ok(self)
+    }
+
+    /// Records that the key slots can only be opened together with the TOTP secret they were bound
+    /// to.
+    #[cfg(feature = "cli")]
+    pub(crate) fn with_second_factor(mut self, enabled: bool) -> Self {
+        self.params.second_factor = enabled;
+        self
+    }
+
+    /// Records that a copy of the header, framed by [`super::trailing_copy`], ends the file.
+    #[cfg(feature = "cli")]
+    pub(crate) fn with_header_copy(mut self, enabled: bool) -> Self {
+        self.params.copied = enabled;
+        self
+    }
+
+    /// Leaves `bytes` of spare capacity in the header frame, so it can be rewritten in place by a
+    /// slightly larger one.
+    #[cfg(feature = "cli")]
+    pub(crate) fn with_headroom(mut self, bytes: usize) -> Self {
+        self.headroom = bytes;
+        self
+    }
+
+    /// Replaces the recorded size and content hash, for a header written before the content had
+    /// been read.
+    #[cfg(feature = "cli")]
+    pub(crate) fn with_file_content(mut self, size: u64, hash: Vec<u8>) -> Result<Self> {
+        let name = self.metadata.name().to_owned();
+        self.metadata = Metadata::new(name, size, hash, self.metadata.attributes()).context("failed to initialize metadata")?;
+        Ok(self)
+    }
+
+    #[cfg(feature = "cli")]
     pub(crate) fn file_name(&self) -> &str {
         self.metadata.name()
     }
 
+    #[cfg(feature = "cli")]
     pub(crate) fn file_size(&self) -> u64 {
         self.metadata.size()
     }
 
+    #[cfg(feature = "cli")]
     pub(crate) fn file_hash(&self) -> &[u8] {
         self.metadata.hash()
     }
 
-    pub(crate) fn serialize(&self, salt: &[u8], signer_key: &Secret) -> Result<Vec<u8>> {
-        let params_bytes = postcard::to_allocvec(&self.params).context("failed to serialize params")?;
+    pub(crate) fn nonce_prefix(&self) -> &[u8] {
+        self.params.nonce_prefix()
+    }
+
+    /// Encrypts the metadata with the file's metadata key, so the name, size, and hash are only
+    /// readable after unlocking a key slot; the parameters and slots stay in the clear.
+    /// Convergent headers take the metadata nonce from the metadata itself, so copies that
+    /// differ only in name or mtime never share one.
+    pub(crate) fn serialize(&self, salt: &[u8], slots: Vec<KeySlot>, keys: &DerivedKeys, rng: &dyn RandomSource) -> Result<Vec<u8>> {
+        let reserve = MAX_KEY_SLOTS.saturating_sub(slots.len()).saturating_mul(SLOT_RESERVE_LEN).saturating_add(self.headroom);
+        let packed = self.pack(salt, slots, keys, rng)?;
+        let capacity = packed.len().saturating_add(reserve).min(MAX_SECTION_SIZE as usize);
+
+        Section::frame(&packed, capacity).context("failed to frame header")
+    }
+
+    /// Serializes into exactly `framed_len` bytes, so the result can overwrite an earlier header
+    /// without moving the payload behind it.
+    #[cfg(feature = "cli")]
+    pub(crate) fn serialize_to_len(&self, salt: &[u8], slots: Vec<KeySlot>, keys: &DerivedKeys, rng: &dyn RandomSource, framed_len: usize) -> Result<Vec<u8>> {
+        let packed = self.pack(salt, slots, keys, rng)?;
+        let capacity = framed_len.checked_sub(PREFIX_LEN).context("header length is shorter than its frame")?;
+
+        Section::frame(&packed, capacity).context("failed to frame header")
+    }
+
+    fn pack(&self, salt: &[u8], slots: Vec<KeySlot>, keys: &DerivedKeys, rng: &dyn RandomSource) -> Result<Vec<u8>> {
         let metadata_bytes = postcard::to_allocvec(&self.metadata).context("failed to serialize metadata")?;
-        let signer = Signer::new(signer_key).context("failed to initialize signer")?;
-        let mac = signer.compute_parts(&[salt, &params_bytes, &metadata_bytes]).context("failed to compute mac")?;
-        let section = Section::new(COMPRESSION_LEVEL, ORIGINAL_COUNT, RECOVERY_COUNT).context("failed to initialize section encoder")?;
+        let sealed = if self.params.convergent {
+            crate::cipher::seal(&keys.metadata_key, &metadata_bytes, &section_random(&keys.metadata_key, &metadata_bytes)?)
+        } else {
+            crate::cipher::seal(&keys.metadata_key, &metadata_bytes, rng)
+        }
+        .context("failed to encrypt metadata")?;
 
-        section.pack(salt, &params_bytes, &metadata_bytes, &mac).context("failed to pack header sections")
+        pack(salt, &self.params, slots, &sealed, &keys.signer_key)
     }
 }
+
+pub(super) fn pack(salt: &[u8], params: &Parameters, slots: Vec<KeySlot>, metadata: &[u8], signer_key: &Secret) -> Result<Vec<u8>> {
+    let slots = KeySlots::try_new(slots).context("invalid key slot count")?;
+    let params_bytes = postcard::to_allocvec(params).context("failed to serialize params")?;
+    let slots_bytes = postcard::to_allocvec(&slots).context("failed to serialize key slots")?;
+    let signer = Signer::new(signer_key).context("failed to initialize signer")?;
+    let mac = signer.compute_parts(&[salt, &params_bytes, &slots_bytes, metadata]).context("failed to compute mac")?;
+    let section = Section::new(COMPRESSION_LEVEL, ORIGINAL_COUNT, RECOVERY_COUNT).context("failed to initialize section encoder")?;
+
+    section.pack(salt, &params_bytes, &slots_bytes, metadata, &mac).context("failed to pack header sections")
+}
@@ -1,48 +1,155 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::validation::{FileHash, FileSize, Filename, Magic, Version};
+use crate::cipher::{Argon2Params, KdfParams, ScryptParams};
+use crate::files::Attributes;
+use crate::padding::PaddingScheme;
+use crate::validation::{AlgorithmFlags, FileHash, FileSize, Filename, KdfIterations, KdfMemory, KdfParallelism, Magic, NoncePrefix, ScryptBlockSize, ScryptLogN, SessionSalt, Version};
 
 #[derive(Serialize, Deserialize)]
 pub(super) struct Parameters {
     pub(super) magic: Magic,
     pub(super) version: Version,
+    pub(super) algorithm: AlgorithmFlags,
+    pub(super) nonce_prefix: NoncePrefix,
+    pub(super) kdf: KdfSpec,
+    pub(super) payload_mac: bool,
+    pub(super) chunk_count: u64,
+    pub(super) padded: bool,
+    pub(super) convergent: bool,
+    pub(super) chunk_size: u32,
+    pub(super) indexed: bool,
+    /// Set when the key-encryption keys come from a session key stretched under this salt rather
+    /// than from the password under the file's own salt.
+    pub(super) session_salt: Option<SessionSalt>,
+    /// Set when the key-encryption keys are also bound to a TOTP secret.
+    pub(super) second_factor: bool,
+    /// Set when a copy of the header ends the file, after the chunk index.
+    pub(super) copied: bool,
+    /// How each chunk was padded to whole blocks before it was encrypted.
+    pub(super) padding_scheme: PaddingScheme,
 }
 
 impl Parameters {
-    pub(super) fn new(magic: u32, version: u16) -> Result<Self> {
+    pub(super) fn new(magic: u32, version: u16, algorithm: u8, nonce_prefix: Vec<u8>, kdf: &KdfParams) -> Result<Self> {
         let magic = Magic::try_new(magic).context("invalid magic bytes")?;
         let version = Version::try_new(version).context("invalid version")?;
+        let algorithm = AlgorithmFlags::try_new(algorithm).context("invalid algorithm flags")?;
+        let nonce_prefix = NoncePrefix::try_new(nonce_prefix).context("invalid nonce prefix")?;
+        let kdf = KdfSpec::new(kdf)?;
 
-        Ok(Self { magic, version })
+        Ok(Self {
+            magic,
+            version,
+            algorithm,
+            nonce_prefix,
+            kdf,
+            payload_mac: false,
+            chunk_count: 0,
+            padded: false,
+            convergent: false,
+            chunk_size: 0,
+            indexed: false,
+            session_salt: None,
+            second_factor: false,
+            copied: false,
+            padding_scheme: PaddingScheme::default(),
+        })
     }
+
+    pub(super) fn algorithm(&self) -> u8 {
+        self.algorithm.into_inner()
+    }
+
+    pub(super) fn nonce_prefix(&self) -> &[u8] {
+        self.nonce_prefix.as_ref()
+    }
+
+    pub(super) fn session_salt(&self) -> Option<&[u8]> {
+        self.session_salt.as_ref().map(|salt| salt.as_ref().as_slice())
+    }
+
+    pub(super) fn kdf(&self) -> KdfParams {
+        match self.kdf {
+            KdfSpec::Argon2id { memory, iterations, parallelism } => {
+                KdfParams::Argon2id(Argon2Params { memory: memory.into_inner(), iterations: iterations.into_inner(), parallelism: parallelism.into_inner() })
+            }
+            KdfSpec::Scrypt { log_n, block_size, parallelism } => {
+                KdfParams::Scrypt(ScryptParams { log_n: log_n.into_inner(), block_size: block_size.into_inner(), parallelism: parallelism.into_inner() })
+            }
+        }
+    }
+}
+
+/// The KDF and its costs as the header records them; the variant tag names the algorithm.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub(super) enum KdfSpec {
+    Argon2id { memory: KdfMemory, iterations: KdfIterations, parallelism: KdfParallelism },
+    Scrypt { log_n: ScryptLogN, block_size: ScryptBlockSize, parallelism: KdfParallelism },
 }
 
+impl KdfSpec {
+    fn new(kdf: &KdfParams) -> Result<Self> {
+        match kdf {
+            KdfParams::Argon2id(params) => Ok(Self::Argon2id {
+                memory: KdfMemory::try_new(params.memory).context("invalid kdf memory cost")?,
+                iterations: KdfIterations::try_new(params.iterations).context("invalid kdf iteration count")?,
+                parallelism: KdfParallelism::try_new(params.parallelism).context("invalid kdf parallelism")?,
+            }),
+            KdfParams::Scrypt(params) => Ok(Self::Scrypt {
+                log_n: ScryptLogN::try_new(params.log_n).context("invalid scrypt cost")?,
+                block_size: ScryptBlockSize::try_new(params.block_size).context("invalid scrypt block size")?,
+                parallelism: KdfParallelism::try_new(params.parallelism).context("invalid kdf parallelism")?,
+            }),
+        }
+    }
+}
+
+/// Every section of a header as it decodes, before the Reed-Solomon and framing layers, and the
+/// reserved capacity that says how long the header is on disk. It is what a recovery code carries.
+#[cfg(feature = "cli")]
 #[derive(Serialize, Deserialize)]
-pub(super) struct Metadata {
+pub(super) struct RecoveryRecord {
+    pub(super) salt: Vec<u8>,
+    pub(super) params: Vec<u8>,
+    pub(super) slots: Vec<u8>,
+    pub(super) metadata: Vec<u8>,
+    pub(super) mac: Vec<u8>,
+    pub(super) capacity: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Metadata {
     name: Filename,
     size: FileSize,
     hash: FileHash,
+    attributes: Attributes,
 }
 
 impl Metadata {
-    pub(super) fn new(name: impl Into<String>, size: u64, hash: Vec<u8>) -> Result<Self> {
+    pub(super) fn new(name: impl Into<String>, size: u64, hash: Vec<u8>, attributes: Attributes) -> Result<Self> {
         let name = Filename::try_new(name.into()).context("invalid filename")?;
         let size = FileSize::try_new(size).context("invalid file size")?;
         let hash = FileHash::try_new(hash).context("invalid file hash")?;
 
-        Ok(Self { name, size, hash })
+        Ok(Self { name, size, hash, attributes })
     }
 
-    pub(super) fn name(&self) -> &str {
+    #[cfg(feature = "cli")]
+    pub(crate) fn name(&self) -> &str {
         self.name.as_ref()
     }
 
-    pub(super) fn size(&self) -> u64 {
+    pub(crate) fn size(&self) -> u64 {
         *self.size.as_ref()
     }
 
-    pub(super) fn hash(&self) -> &[u8] {
+    pub(crate) fn hash(&self) -> &[u8] {
         self.hash.as_ref()
     }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn attributes(&self) -> Attributes {
+        self.attributes
+    }
 }
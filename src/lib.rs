@@ -0,0 +1,79 @@
+//! Core of the `.swx` format: header, ciphers, Reed-Solomon encoding, padding, compression, an
+//! in-memory encrypt/decrypt path, `Read`/`Write` stream adapters, and output size estimates.
+//! Without the default `cli` feature this builds for `wasm32-unknown-unknown`.
+
+#[cfg(test)]
+mod allocations;
+mod api;
+#[cfg(feature = "cli")]
+mod app;
+#[cfg(feature = "cli")]
+mod archive;
+#[cfg(feature = "cli")]
+mod armor;
+#[cfg(feature = "cli")]
+mod calibrate;
+mod cipher;
+#[cfg(feature = "cli")]
+mod cli;
+mod compression;
+mod config;
+mod encoding;
+mod error;
+pub mod estimate;
+mod files;
+#[cfg(feature = "fuse")]
+mod fuse;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+mod header;
+#[cfg(feature = "cli")]
+mod manifest;
+mod memory;
+#[cfg(feature = "cli")]
+mod notify;
+mod padding;
+mod pipeline;
+#[cfg(feature = "cli")]
+mod recovery;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod run;
+mod secret;
+mod secure_temp;
+#[cfg(feature = "cli")]
+mod selftest;
+#[cfg(feature = "cli")]
+mod spec;
+#[cfg(feature = "cli")]
+mod state;
+#[cfg(feature = "cli")]
+mod stego;
+#[cfg(feature = "cli")]
+mod storage;
+mod stream;
+#[cfg(feature = "cli")]
+mod testvectors;
+#[cfg(feature = "cli")]
+mod ui;
+mod validation;
+
+use anyhow::Result;
+pub use api::{DecryptOptions, Decryptor, EncryptOptions, Encryptor, Header, OperationSummary};
+pub use cipher::CipherMode;
+pub use error::SweetByteError;
+#[cfg(feature = "async")]
+pub use pipeline::{Operation, StreamConfig, process_async};
+pub use pipeline::{ProgressEvent, ProgressSink, Stage};
+pub use stream::{DecryptingWriter, EncryptingReader};
+
+/// Encrypts `data` into a complete `.swx` file in memory, using the default cipher and settings.
+pub fn encrypt_bytes(data: &[u8], password: &[u8]) -> Result<Vec<u8>> {
+    Encryptor::default().encrypt(data, password).map(|(encrypted, _)| encrypted)
+}
+
+/// Decrypts a complete `.swx` file held in memory and checks it against the hash in its header.
+pub fn decrypt_bytes(data: &[u8], password: &[u8]) -> Result<Vec<u8>> {
+    Decryptor::default().decrypt(data, password).map(|(decrypted, _)| decrypted)
+}
@@ -0,0 +1,202 @@
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::app::Options;
+use crate::cipher::KdfParams;
+use crate::config::CURRENT_VERSION;
+use crate::error::SweetByteError;
+use crate::files::{Files, Metadata, TempFile};
+use crate::header::Deserializer;
+
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Entry {
+    original: PathBuf,
+    original_hash: String,
+    size: u64,
+    encrypted: PathBuf,
+    volumes: Vec<Volume>,
+    parameters: Parameters,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Volume {
+    path: PathBuf,
+    size: u64,
+    hash: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Parameters {
+    version: u16,
+    cipher: String,
+    chunk_size: usize,
+    payload_mac: bool,
+    #[serde(flatten)]
+    kdf: Kdf,
+}
+
+/// Costs of the KDF new files were stretched with; scrypt entries are told apart by their field
+/// names, so manifests written before scrypt existed still read as Argon2id.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Kdf {
+    Argon2id { kdf_memory: u32, kdf_iterations: u32, kdf_parallelism: u32 },
+    Scrypt { kdf_log_n: u8, kdf_block_size: u32, kdf_parallelism: u32 },
+}
+
+impl From<KdfParams> for Kdf {
+    fn from(kdf: KdfParams) -> Self {
+        match kdf {
+            KdfParams::Argon2id(params) => Self::Argon2id { kdf_memory: params.memory, kdf_iterations: params.iterations, kdf_parallelism: params.parallelism },
+            KdfParams::Scrypt(params) => Self::Scrypt { kdf_log_n: params.log_n, kdf_block_size: params.block_size, kdf_parallelism: params.parallelism },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Outcome {
+    Ok,
+    Mismatch,
+    Missing,
+}
+
+#[derive(Serialize)]
+pub(crate) struct EntryCheck {
+    pub(crate) encrypted: PathBuf,
+    pub(crate) outcome: Outcome,
+    pub(crate) detail: Option<String>,
+}
+
+impl Manifest {
+    pub(crate) async fn load(path: &Path) -> Result<Self> {
+        let contents = tokio::fs::read_to_string(path).await.with_context(|| format!("failed to read manifest {}", path.display()))?;
+
+        serde_json::from_str(&contents).with_context(|| format!("invalid manifest {}", path.display()))
+    }
+
+    pub(crate) async fn record(path: &Path, entry: Entry) -> Result<()> {
+        let mut manifest = if path.exists() { Self::load(path).await? } else { Self::default() };
+        manifest.entries.retain(|existing| existing.encrypted != entry.encrypted);
+        manifest.entries.push(entry);
+
+        let contents = serde_json::to_string_pretty(&manifest).context("failed to serialize manifest")?;
+        let temp = TempFile::new(path)?;
+        tokio::fs::write(temp.path(), contents).await.context("failed to write manifest")?;
+        temp.persist().await.context("failed to finalize manifest")
+    }
+
+    pub(crate) async fn verify(&self, threads: NonZeroUsize) -> Vec<EntryCheck> {
+        let mut checks = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let (outcome, detail) = match entry.verify(threads).await {
+                Ok(None) => (Outcome::Ok, None),
+                Ok(Some(detail)) => (Outcome::Missing, Some(detail)),
+                Err(error) => (Outcome::Mismatch, Some(format!("{error:#}"))),
+            };
+
+            checks.push(EntryCheck { encrypted: entry.encrypted.clone(), outcome, detail });
+        }
+
+        checks
+    }
+}
+
+impl Entry {
+    pub(crate) async fn new(source: &Files, target: &Files, metadata: &Metadata, options: &Options) -> Result<Self> {
+        let mut volumes = Vec::new();
+        for path in crate::files::spanning::volumes(target.path()) {
+            let volume = Files::new(&path);
+            volumes.push(Volume { size: volume.size().await?, hash: hex::encode(crate::files::hash::hash(&volume, options.settings.threads)?), path });
+        }
+
+        let parameters = Parameters {
+            version: CURRENT_VERSION,
+            cipher: options.cipher.to_possible_value().map(|value| value.get_name().to_owned()).unwrap_or_default(),
+            chunk_size: options.settings.chunk_size,
+            payload_mac: options.settings.payload_mac,
+            kdf: options.settings.kdf.into(),
+        };
+
+        Ok(Self { original: source.path().to_path_buf(), original_hash: hex::encode(&metadata.hash), size: metadata.size, encrypted: target.path().to_path_buf(), volumes, parameters })
+    }
+
+    async fn verify(&self, threads: NonZeroUsize) -> Result<Option<String>> {
+        for volume in &self.volumes {
+            let file = Files::new(&volume.path);
+            if !file.exists() {
+                anyhow::bail!("encrypted file {} is missing", volume.path.display());
+            }
+
+            if hex::encode(crate::files::hash::hash(&file, threads)?) != volume.hash {
+                anyhow::bail!("encrypted file {} does not match its recorded hash", volume.path.display());
+            }
+        }
+
+        let mut reader = crate::files::spanning::reader(&Files::new(&self.encrypted)).await.context("failed to open encrypted file")?;
+        // The original hash inside the header is encrypted, so without a password only its structure can be
+        // checked.
+        Deserializer::from_reader(reader.get_mut()).await.context(SweetByteError::CorruptHeader)?;
+
+        let original = Files::new(&self.original);
+        if !original.exists() {
+            return Ok(Some(format!("original file {} is missing", self.original.display())));
+        }
+
+        if hex::encode(crate::files::hash::hash(&original, threads)?) != self.original_hash {
+            anyhow::bail!("original file {} has changed since encryption", self.original.display());
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use tokio::fs;
+
+    use super::*;
+    use crate::cipher::SystemRandom;
+    use crate::config::Settings;
+    use crate::secret::Secret;
+
+    #[tokio::test]
+    async fn recorded_entries_verify_until_tampered() {
+        let dir = tempdir().unwrap();
+        let source = Files::new(dir.path().join("test.txt"));
+        let target = Files::new(dir.path().join("test.txt.swx"));
+        let manifest_path = dir.path().join("manifest.json");
+        fs::write(source.path(), b"audited content").await.unwrap();
+
+        let options = Options { manifest: Some(manifest_path.clone()), ..Options::new(Settings::default()) };
+        crate::app::encrypt(&source, &target, &[Secret::new(b"password".to_vec())], &options, &SystemRandom).await.unwrap();
+        crate::app::encrypt(&source, &target, &[Secret::new(b"password".to_vec())], &options, &SystemRandom).await.unwrap();
+
+        let manifest = Manifest::load(&manifest_path).await.unwrap();
+        assert_eq!(manifest.entries.len(), 1);
+        let checks = manifest.verify(options.settings.threads).await;
+        assert!(checks.iter().all(|check| check.outcome == Outcome::Ok));
+
+        fs::remove_file(source.path()).await.unwrap();
+        let checks = manifest.verify(options.settings.threads).await;
+        assert!(checks.iter().all(|check| check.outcome == Outcome::Missing));
+
+        let mut bytes = fs::read(target.path()).await.unwrap();
+        if let Some(last) = bytes.last_mut() {
+            *last ^= 0xFF;
+        }
+        fs::write(target.path(), bytes).await.unwrap();
+        let checks = manifest.verify(options.settings.threads).await;
+        assert!(checks.iter().all(|check| check.outcome == Outcome::Mismatch));
+    }
+}
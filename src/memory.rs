@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use subtle::ConstantTimeEq;
+
+use crate::cipher::{CipherMode, KeyDeriver, KeySlot, RandomSource};
+use crate::config::{ARGON2_SALT_LEN, CHUNK_INDEX_MARKER, COMPRESSION_LEVEL, MASTER_KEY_LEN, MAX_CHUNK_SIZE, NONCE_PREFIX_LEN, PADDING_MARKER, PAYLOAD_MAC_MARKER, Settings};
+use crate::error::SweetByteError;
+use crate::files::Attributes;
+use crate::header::{Deserializer, Serializer};
+#[cfg(any(feature = "cli", test))]
+use crate::pipeline::NoProgress;
+use crate::pipeline::{Operation, PayloadMac, Process, ProgressEvent, ProgressSink, Stage, Stats, TRAILER_BODY_LEN, Task, within_declared};
+use crate::secret::Secret;
+use crate::secure_temp::SecureBuffer;
+
+const LENGTH_PREFIX_LEN: usize = size_of::<u32>();
+
+/// Bytes encrypted in memory have no file name of their own, but the header has to record one.
+const NAME: &str = "data";
+
+#[cfg(any(feature = "cli", test))]
+pub(crate) fn encrypt_bytes(data: &[u8], secret: &Secret, mode: CipherMode, settings: &Settings, rng: &dyn RandomSource) -> Result<Vec<u8>> {
+    encrypt_counted(data, secret, mode, settings, rng, None, &NoProgress)
+}
+
+/// Like [`encrypt_bytes`], counting each chunk's sizes into `stats` when there is one and telling
+/// `progress` how far it has got.
+pub(crate) fn encrypt_counted(data: &[u8], secret: &Secret, mode: CipherMode, settings: &Settings, rng: &dyn RandomSource, stats: Option<Arc<Stats>>, progress: &dyn ProgressSink) -> Result<Vec<u8>> {
+    let salt = KeyDeriver::generate_salt(rng, ARGON2_SALT_LEN)?;
+    let mut master_key = vec![0u8; MASTER_KEY_LEN];
+    rng.fill(&mut master_key).context("failed to generate master key")?;
+    let master_key = Secret::new(master_key);
+
+    progress.on_event(&ProgressEvent::new(Stage::Deriving, 0));
+    let kek = KeyDeriver::new(secret)?.derive_kek(&salt, &settings.kdf)?;
+    progress.on_event(&ProgressEvent::new(Stage::Derived, 0));
+    let slot = KeySlot::wrap(&kek, &master_key, rng)?;
+    let keys = KeyDeriver::expand(&master_key, &salt)?;
+
+    let mut nonce_prefix = vec![0u8; NONCE_PREFIX_LEN];
+    rng.fill(&mut nonce_prefix).context("failed to generate nonce prefix")?;
+
+    let size = u64::try_from(data.len()).context("input too large")?;
+    let chunks = data.chunks(settings.chunk_size.max(1));
+    let chunk_count = u64::try_from(chunks.len()).context("too many chunks")?;
+    let header = Serializer::new(NAME, size, blake3::hash(data).as_bytes().to_vec(), Attributes::default(), mode, nonce_prefix, &settings.kdf)?
+        .with_payload_mac(settings.payload_mac)
+        .with_padding_scheme(settings.padding)
+        .with_chunk_count(chunk_count)
+        .with_chunk_size(u32::try_from(settings.chunk_size.max(1)).context("chunk size overflow")?);
+    let mut output = header.serialize(salt.expose_secret(), vec![slot], &keys, rng).context("failed to serialize header")?;
+
+    let mut process = Process::new(&keys.primary_key, &keys.secondary_key, Operation::Encryption, mode, header.nonce_prefix(), settings.compression_level)?.with_padding_scheme(settings.padding);
+    if let Some(stats) = stats {
+        process = process.with_stats(stats);
+    }
+    let mut mac = settings.payload_mac.then(|| PayloadMac::new(&keys.payload_key)).transpose()?;
+    progress.on_event(&ProgressEvent::new(Stage::Started, size));
+    let mut bytes_in = 0u64;
+    for (index, chunk) in (0u64..).zip(chunks) {
+        let result = process.process(&Task { data: SecureBuffer::locked(chunk.to_vec()), index })?;
+        let length = u32::try_from(result.data.len()).context("chunk too large")?;
+        if let Some(mac) = &mut mac {
+            mac.update(index, &result.data)?;
+        }
+
+        output.extend_from_slice(&length.to_le_bytes());
+        output.extend_from_slice(&result.data);
+
+        let chunk_plaintext = u64::try_from(chunk.len()).context("size overflow")?;
+        bytes_in = bytes_in.saturating_add(chunk_plaintext);
+        let bytes_out = u64::try_from(output.len()).context("size overflow")?;
+        progress.on_event(&ProgressEvent { stage: Stage::Chunk, chunk: index, chunk_plaintext, bytes_in, bytes_out, total: size });
+    }
+
+    if let Some(mac) = &mac {
+        output.extend_from_slice(&mac.trailer());
+    }
+
+    let bytes_out = u64::try_from(output.len()).context("size overflow")?;
+    progress.on_event(&ProgressEvent { stage: Stage::Finished, chunk: chunk_count, chunk_plaintext: 0, bytes_in, bytes_out, total: size });
+
+    Ok(output)
+}
+
+#[cfg(any(feature = "cli", test))]
+pub(crate) fn decrypt_bytes(data: &[u8], secret: &Secret) -> Result<Vec<u8>> {
+    decrypt_bounded(data, secret, u64::MAX, None, &NoProgress)
+}
+
+/// Like [`decrypt_bytes`], but refuses a file whose header records more than `max_size` plaintext
+/// bytes before any chunk is decrypted, counts each chunk's sizes into `stats` when there is one,
+/// and tells `progress` how far it has got.
+pub(crate) fn decrypt_bounded(data: &[u8], secret: &Secret, max_size: u64, stats: Option<Arc<Stats>>, progress: &dyn ProgressSink) -> Result<Vec<u8>> {
+    let (header, consumed) = Deserializer::from_bytes(data).context(SweetByteError::CorruptHeader)?;
+    if header.second_factor() {
+        anyhow::bail!("messages bound to a TOTP secret can't be decrypted in memory");
+    }
+
+    let deriver = KeyDeriver::new(secret)?;
+    progress.on_event(&ProgressEvent::new(Stage::Deriving, 0));
+    let kek = match header.session_salt() {
+        Some(session_salt) => KeyDeriver::session_kek(&deriver.derive_session_key(session_salt, &header.kdf())?, header.salt())?,
+        None => deriver.derive_kek(header.salt(), &header.kdf())?,
+    };
+    progress.on_event(&ProgressEvent::new(Stage::Derived, 0));
+    let (_, master_key) = header.find_slot(&kek).ok_or(SweetByteError::WrongPassword)?;
+    let keys = KeyDeriver::expand(&master_key, header.salt())?;
+    if !header.verify(&keys.signer_key)? {
+        return Err(SweetByteError::WrongPassword.into());
+    }
+    let metadata = header.metadata(&keys.metadata_key)?;
+    if metadata.size() > max_size {
+        anyhow::bail!("file holds {} bytes, more than the {max_size} allowed", metadata.size());
+    }
+
+    let mut process = Process::new(&keys.primary_key, &keys.secondary_key, Operation::Decryption, header.cipher_mode()?, header.nonce_prefix(), COMPRESSION_LEVEL)?
+        .with_padding_scheme(header.padding_scheme())
+        .with_chunk_limit(header.chunk_limit(metadata.size()));
+    if let Some(stats) = stats {
+        process = process.with_stats(stats);
+    }
+    let mut mac = header.payload_mac().then(|| PayloadMac::new(&keys.payload_key)).transpose()?;
+    let mut payload = data.get(consumed..).unwrap_or_default();
+    let mut plaintext = Vec::new();
+    let mut index = 0u64;
+    let mut authenticated = false;
+    progress.on_event(&ProgressEvent::new(Stage::Started, metadata.size()));
+
+    while let Some((prefix, rest)) = payload.split_first_chunk::<LENGTH_PREFIX_LEN>() {
+        let length = u32::from_le_bytes(*prefix);
+        if length == PAYLOAD_MAC_MARKER
+            && let Some(mac) = &mac
+        {
+            let (body, rest) = rest.split_first_chunk::<TRAILER_BODY_LEN>().context("payload authentication record is malformed")?;
+            let next = rest.first_chunk::<LENGTH_PREFIX_LEN>().map(|marker| u32::from_le_bytes(*marker));
+            let followed = (header.padded() && next == Some(PADDING_MARKER)) || (header.indexed() && next == Some(CHUNK_INDEX_MARKER));
+            if !rest.is_empty() && !followed {
+                anyhow::bail!("payload authentication record is malformed");
+            }
+            mac.verify(body)?;
+            authenticated = true;
+            payload = &[];
+            break;
+        }
+
+        if (length == PADDING_MARKER && header.padded()) || (length == CHUNK_INDEX_MARKER && header.indexed()) {
+            payload = &[];
+            break;
+        }
+
+        if length > MAX_CHUNK_SIZE {
+            anyhow::bail!("chunk size {length} exceeds maximum {MAX_CHUNK_SIZE}");
+        }
+
+        let length = usize::try_from(length).context("chunk length overflow")?;
+        let (chunk, rest) = rest.split_at_checked(length).context("payload truncated inside a chunk")?;
+        if let Some(mac) = &mut mac {
+            mac.update_record(index, chunk)?;
+        }
+
+        let decrypted = process.process(&Task { data: SecureBuffer::unlocked(chunk.to_vec()), index })?;
+        let written = u64::try_from(plaintext.len()).context("size overflow")?;
+        let kept = within_declared(index, written, u64::try_from(decrypted.data.len()).context("size overflow")?, metadata.size())?;
+        plaintext.extend_from_slice(decrypted.data.get(..usize::try_from(kept).context("size overflow")?).context("chunk shorter than its size")?);
+        payload = rest;
+
+        let bytes_in = u64::try_from(data.len().saturating_sub(payload.len())).context("size overflow")?;
+        let bytes_out = written.saturating_add(kept);
+        progress.on_event(&ProgressEvent { stage: Stage::Chunk, chunk: index, chunk_plaintext: kept, bytes_in, bytes_out, total: metadata.size() });
+        index = index.saturating_add(1);
+    }
+
+    if !payload.is_empty() {
+        anyhow::bail!("payload truncated inside a chunk length");
+    }
+
+    if index != header.chunk_count() {
+        anyhow::bail!("file truncated: expected {} chunks, found {index}", header.chunk_count());
+    }
+
+    if mac.is_some() && !authenticated {
+        anyhow::bail!("payload truncated: authentication record is missing");
+    }
+
+    plaintext.truncate(usize::try_from(metadata.size()).context("size overflow")?);
+    if !bool::from(blake3::hash(&plaintext).as_bytes().as_slice().ct_eq(metadata.hash())) {
+        anyhow::bail!("hash verification failed");
+    }
+
+    let bytes_in = u64::try_from(data.len()).context("size overflow")?;
+    progress.on_event(&ProgressEvent { stage: Stage::Finished, chunk: index, chunk_plaintext: 0, bytes_in, bytes_out: metadata.size(), total: metadata.size() });
+
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::SystemRandom;
+    use crate::config::MIN_CHUNK_SIZE;
+    use crate::padding::PaddingScheme;
+
+    #[test]
+    fn bytes_roundtrip_in_memory() {
+        let secret = Secret::new(b"password".to_vec());
+        let settings = Settings { chunk_size: MIN_CHUNK_SIZE, ..Settings::default() };
+        let plaintext: Vec<u8> = (0..MIN_CHUNK_SIZE * 2 + 17).map(|i| u8::try_from(i % 251).unwrap()).collect();
+
+        let encrypted = encrypt_bytes(&plaintext, &secret, CipherMode::Dual, &settings, &SystemRandom).unwrap();
+        assert_eq!(decrypt_bytes(&encrypted, &secret).unwrap(), plaintext);
+
+        decrypt_bytes(&encrypted, &Secret::new(b"wrong".to_vec())).unwrap_err();
+        decrypt_bytes(encrypted.split_last().unwrap().1, &secret).unwrap_err();
+
+        let (header, _) = Deserializer::from_bytes(&encrypted).unwrap();
+        assert!(header.metadata(&Secret::new(vec![0u8; 32])).is_err());
+    }
+
+    #[test]
+    fn the_header_names_the_padding_scheme() {
+        let secret = Secret::new(b"password".to_vec());
+        let settings = Settings { chunk_size: MIN_CHUNK_SIZE, padding: PaddingScheme::Iso7816, ..Settings::default() };
+        let plaintext = vec![3u8; MIN_CHUNK_SIZE + 5];
+
+        let encrypted = encrypt_bytes(&plaintext, &secret, CipherMode::Dual, &settings, &SystemRandom).unwrap();
+        assert_eq!(Deserializer::from_bytes(&encrypted).unwrap().0.padding_scheme(), PaddingScheme::Iso7816);
+        assert_eq!(decrypt_bytes(&encrypted, &secret).unwrap(), plaintext);
+    }
+}
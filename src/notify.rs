@@ -0,0 +1,121 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::config::{NOTIFY_TIMEOUT_SECS, Settings};
+use crate::ui::RunReport;
+
+/// Hands `report` to the `notify-cmd` and `notify-url` hooks, whichever are set. Both are tried
+/// even when the first fails, and the first failure is returned.
+pub(crate) async fn send(settings: &Settings, report: &RunReport) -> Result<()> {
+    if settings.notify_cmd.is_none() && settings.notify_url.is_none() {
+        return Ok(());
+    }
+    let body = serde_json::to_vec(report).context("failed to serialize run summary")?;
+
+    let command = match &settings.notify_cmd {
+        Some(command) => run(command, &body).await.with_context(|| format!("notify command {command:?} failed")),
+        None => Ok(()),
+    };
+    let webhook = match &settings.notify_url {
+        Some(url) => post(url, body).await.with_context(|| format!("failed to notify {url}")),
+        None => Ok(()),
+    };
+
+    command.and(webhook)
+}
+
+/// Runs `command` through the shell with `body` on its stdin. Its own output goes to stderr, so it
+/// never mixes with JSON results on stdout.
+async fn run(command: &str, body: &[u8]) -> Result<()> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::from(std::io::stderr()))
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to start")?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that doesn't read the summary closes the pipe early; that isn't its failure.
+        if let Err(error) = stdin.write_all(body).await
+            && error.kind() != std::io::ErrorKind::BrokenPipe
+        {
+            return Err(error).context("failed to write summary");
+        }
+    }
+
+    let status = tokio::time::timeout(Duration::from_secs(NOTIFY_TIMEOUT_SECS), child.wait())
+        .await
+        .with_context(|| format!("still running after {NOTIFY_TIMEOUT_SECS}s"))?
+        .context("failed to wait")?;
+    if !status.success() {
+        anyhow::bail!("exited with {status}");
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+/// Posts `body` to `url` as JSON. Anything but a 2xx answer counts as a failure.
+async fn post(url: &str, body: Vec<u8>) -> Result<()> {
+    let url = url.to_owned();
+
+    tokio::task::spawn_blocking(move || {
+        let agent: ureq::Agent = ureq::Agent::config_builder().timeout_global(Some(Duration::from_secs(NOTIFY_TIMEOUT_SECS))).build().into();
+        agent.post(&url).header("content-type", "application/json").send(body.as_slice()).map(drop).context("request failed")
+    })
+    .await
+    .context("webhook task failed")?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::{RunEntry, Status};
+
+    fn report() -> RunReport {
+        let files = vec![RunEntry { source: "a.txt".into(), status: Status::Success, error: None }];
+
+        RunReport::new("encrypt", files, Duration::from_millis(5), &Ok(()))
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn command_gets_the_summary_on_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("summary.json");
+        let settings = Settings { notify_cmd: Some(format!("cat > '{}'", out.display())), ..Settings::default() };
+
+        send(&settings, &report()).await.unwrap();
+
+        let summary: serde_json::Value = serde_json::from_slice(&std::fs::read(&out).unwrap()).unwrap();
+        assert_eq!(summary["operation"], "encrypt");
+        assert_eq!(summary["status"], "success");
+        assert_eq!(summary["succeeded"], 1);
+        assert_eq!(summary["files"][0]["source"], "a.txt");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn failing_command_is_an_error() {
+        let settings = Settings { notify_cmd: Some("exit 3".to_owned()), ..Settings::default() };
+
+        assert!(send(&settings, &report()).await.is_err());
+    }
+}
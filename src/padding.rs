@@ -1,87 +1,228 @@
 use anyhow::{Context, Result};
-use block_padding::array::typenum::{U16, U32, U64, U128, Unsigned};
-use block_padding::array::{Array, ArraySize};
-use block_padding::{PaddedData, Padding, Pkcs7};
+use serde::{Deserialize, Serialize};
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq, ConstantTimeLess};
 
-pub(crate) struct Pkcs7Padding {
+/// How a compressed chunk is padded to a whole number of blocks before it is encrypted, chosen with
+/// `padding` in the config file and recorded in the header.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, strum::EnumString, strum::Display)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub(crate) enum PaddingScheme {
+    /// Every padding byte holds the padding length.
+    #[default]
+    Pkcs7,
+    /// ISO/IEC 7816-4: a 0x80 byte, then zeros.
+    Iso7816,
+}
+
+pub(crate) struct BlockPadding {
+    scheme: PaddingScheme,
     block_size: usize,
 }
 
-impl Pkcs7Padding {
-    pub(crate) fn new(block_size: usize) -> Result<Self> {
+impl BlockPadding {
+    pub(crate) fn new(scheme: PaddingScheme, block_size: usize) -> Result<Self> {
         if !matches!(block_size, 16 | 32 | 64 | 128) {
             anyhow::bail!("invalid block size: {block_size}. must be 16, 32, 64, or 128.");
         }
 
-        Ok(Self { block_size })
+        Ok(Self { scheme, block_size })
+    }
+
+    pub(crate) fn with_scheme(mut self, scheme: PaddingScheme) -> Self {
+        self.scheme = scheme;
+        self
     }
 
+    /// Pads `buffer[start..]` where it lies, appending whole blocks of padding.
     #[inline]
-    pub(crate) fn pad(&self, data: &[u8]) -> Result<Vec<u8>> {
-        if data.is_empty() {
-            anyhow::bail!("data must not be empty");
-        }
+    pub(crate) fn pad_in_place(&self, buffer: &mut Vec<u8>, start: usize) -> Result<()> {
+        let len = buffer.len().checked_sub(start).filter(|&len| len > 0).context("data must not be empty")?;
+        let padding_len = self.block_size.saturating_sub(len.checked_rem(self.block_size).unwrap_or(0));
+        let padding_byte = u8::try_from(padding_len).context("padding too long")?;
 
-        match self.block_size {
-            16 => Self::pad_with::<U16>(data),
-            32 => Self::pad_with::<U32>(data),
-            64 => Self::pad_with::<U64>(data),
-            128 => Self::pad_with::<U128>(data),
-            other => anyhow::bail!("unsupported block size: {other}"),
+        match self.scheme {
+            PaddingScheme::Pkcs7 => buffer.resize(buffer.len().saturating_add(padding_len), padding_byte),
+            PaddingScheme::Iso7816 => {
+                buffer.push(0x80);
+                buffer.resize(buffer.len().saturating_add(padding_len).saturating_sub(1), 0);
+            }
         }
+
+        Ok(())
     }
 
+    /// The length of `data` without its padding, after checking every byte of the last block,
+    /// taking the same time whichever byte is wrong.
     #[inline]
-    pub(crate) fn unpad(&self, data: &[u8]) -> Result<Vec<u8>> {
-        if data.is_empty() {
-            anyhow::bail!("data must not be empty");
+    pub(crate) fn unpadded_len(&self, data: &[u8]) -> Result<usize> {
+        if data.is_empty() || data.len().checked_rem(self.block_size) != Some(0) {
+            anyhow::bail!("failed to unpad data: length is not a whole number of blocks");
         }
+        let tail = data.get(data.len().saturating_sub(self.block_size)..).unwrap_or_default();
 
-        match self.block_size {
-            16 => Self::unpad_with::<U16>(data),
-            32 => Self::unpad_with::<U32>(data),
-            64 => Self::unpad_with::<U64>(data),
-            128 => Self::unpad_with::<U128>(data),
-            other => anyhow::bail!("unsupported block size: {other}"),
+        let (valid, padding_len) = match self.scheme {
+            PaddingScheme::Pkcs7 => Self::check_pkcs7(tail),
+            PaddingScheme::Iso7816 => Self::check_iso7816(tail),
+        };
+        if !bool::from(valid) {
+            anyhow::bail!("failed to unpad data: invalid padding");
         }
+
+        Ok(data.len().saturating_sub(usize::from(padding_len)))
     }
 
-    #[inline]
-    fn pad_with<B: ArraySize>(data: &[u8]) -> Result<Vec<u8>> {
-        match Pkcs7::pad_detached::<B>(data) {
-            PaddedData::Pad { blocks, tail_block } => {
-                let total_len = blocks.len().saturating_mul(B::USIZE).saturating_add(B::USIZE);
-                let mut result = Vec::with_capacity(total_len);
-                for block in blocks {
-                    result.extend_from_slice(block.as_slice());
-                }
-                result.extend_from_slice(tail_block.as_slice());
-                Ok(result)
+    /// Valid when the last byte `n` is between 1 and the block size and the last `n` bytes all
+    /// equal it.
+    fn check_pkcs7(tail: &[u8]) -> (Choice, u8) {
+        let padding_len = tail.last().copied().unwrap_or(0);
+        let block_size = u8::try_from(tail.len()).unwrap_or(u8::MAX);
+        let mut valid = !padding_len.ct_eq(&0) & !block_size.ct_lt(&padding_len);
+
+        for (position, byte) in (0u8..).zip(tail.iter().rev()) {
+            let in_padding = position.ct_lt(&padding_len);
+            valid &= !in_padding | byte.ct_eq(&padding_len);
+        }
+
+        (valid, padding_len)
+    }
+
+    /// Valid when the last nonzero byte is 0x80; everything after it is padding, itself included.
+    fn check_iso7816(tail: &[u8]) -> (Choice, u8) {
+        let mut found = Choice::from(0);
+        let mut valid = Choice::from(1);
+        let mut padding_len = 0u8;
+
+        for (position, byte) in (1u8..).zip(tail.iter().rev()) {
+            let zero = byte.ct_eq(&0);
+            let marker = byte.ct_eq(&0x80);
+            valid &= found | zero | marker;
+            padding_len.conditional_assign(&position, !found & marker);
+            found |= marker | !zero;
+        }
+
+        (valid & found, padding_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use block_padding::array::ArraySize;
+    use block_padding::array::typenum::{U16, U32, U64, U128};
+    use block_padding::{Iso7816, PaddedData, Padding, Pkcs7};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// The copying padding the in-place functions replaced, kept as a reference to check them
+    /// against.
+    impl BlockPadding {
+        fn pad(&self, data: &[u8]) -> Result<Vec<u8>> {
+            if data.is_empty() {
+                anyhow::bail!("data must not be empty");
             }
-            PaddedData::NoPad { blocks } => {
-                let total_len = blocks.len().saturating_mul(B::USIZE);
-                let mut result = Vec::with_capacity(total_len);
-                for block in blocks {
-                    result.extend_from_slice(block.as_slice());
+
+            match (self.scheme, self.block_size) {
+                (PaddingScheme::Pkcs7, 16) => Self::pad_with::<Pkcs7, U16>(data),
+                (PaddingScheme::Pkcs7, 32) => Self::pad_with::<Pkcs7, U32>(data),
+                (PaddingScheme::Pkcs7, 64) => Self::pad_with::<Pkcs7, U64>(data),
+                (PaddingScheme::Pkcs7, 128) => Self::pad_with::<Pkcs7, U128>(data),
+                (PaddingScheme::Iso7816, 16) => Self::pad_with::<Iso7816, U16>(data),
+                (PaddingScheme::Iso7816, 32) => Self::pad_with::<Iso7816, U32>(data),
+                (PaddingScheme::Iso7816, 64) => Self::pad_with::<Iso7816, U64>(data),
+                (PaddingScheme::Iso7816, 128) => Self::pad_with::<Iso7816, U128>(data),
+                (_, other) => anyhow::bail!("unsupported block size: {other}"),
+            }
+        }
+
+        fn unpad(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.get(..self.unpadded_len(data)?).unwrap_or_default().to_vec())
+        }
+
+        fn pad_with<P: Padding, B: ArraySize>(data: &[u8]) -> Result<Vec<u8>> {
+            match P::pad_detached::<B>(data) {
+                PaddedData::Pad { blocks, tail_block } => {
+                    let total_len = blocks.len().saturating_mul(B::USIZE).saturating_add(B::USIZE);
+                    let mut result = Vec::with_capacity(total_len);
+                    for block in blocks {
+                        result.extend_from_slice(block.as_slice());
+                    }
+                    result.extend_from_slice(tail_block.as_slice());
+                    Ok(result)
+                }
+                PaddedData::NoPad { blocks } => {
+                    let total_len = blocks.len().saturating_mul(B::USIZE);
+                    let mut result = Vec::with_capacity(total_len);
+                    for block in blocks {
+                        result.extend_from_slice(block.as_slice());
+                    }
+                    Ok(result)
                 }
-                Ok(result)
+                PaddedData::Error => anyhow::bail!("invalid padding"),
             }
-            PaddedData::Error => anyhow::bail!("invalid padding"),
         }
     }
 
-    #[inline]
-    fn unpad_with<B: ArraySize + Unsigned>(data: &[u8]) -> Result<Vec<u8>> {
-        let num_blocks = data.len().checked_div(B::USIZE).unwrap_or(0);
-        let mut blocks = Vec::with_capacity(num_blocks);
-
-        for chunk in data.chunks_exact(B::USIZE) {
-            let mut arr = Array::default();
-            arr.copy_from_slice(chunk);
-            blocks.push(arr);
+    /// The padding length a plain, early-returning reading of each scheme accepts, or `None`.
+    fn reference(scheme: PaddingScheme, tail: &[u8]) -> Option<usize> {
+        match scheme {
+            PaddingScheme::Pkcs7 => {
+                let padding_len = usize::from(*tail.last()?);
+                (1..=tail.len())
+                    .contains(&padding_len)
+                    .then_some(padding_len)
+                    .filter(|&len| tail[tail.len().saturating_sub(len)..].iter().all(|&byte| usize::from(byte) == len))
+            }
+            PaddingScheme::Iso7816 => {
+                let position = tail.iter().rposition(|&byte| byte != 0)?;
+                (tail[position] == 0x80).then_some(tail.len().saturating_sub(position))
+            }
         }
-        let unpadded = Pkcs7::unpad_blocks::<B>(&blocks).context("failed to unpad data: invalid padding")?;
+    }
+
+    fn scheme() -> impl Strategy<Value = PaddingScheme> {
+        prop_oneof![Just(PaddingScheme::Pkcs7), Just(PaddingScheme::Iso7816)]
+    }
 
-        Ok(unpadded.to_vec())
+    proptest! {
+        #[test]
+        fn padding_roundtrips(scheme in scheme(), block_size in prop_oneof![Just(16usize), Just(32), Just(64), Just(128)], data in vec(any::<u8>(), 1..600)) {
+            let padding = BlockPadding::new(scheme, block_size).unwrap();
+            let padded = padding.pad(&data).unwrap();
+
+            prop_assert_eq!(padded.len().checked_rem(block_size), Some(0));
+            prop_assert!(padded.len() > data.len());
+            prop_assert_eq!(padding.unpad(&padded).unwrap(), data);
+        }
+
+        #[test]
+        fn padding_in_place_matches_padding(scheme in scheme(), block_size in prop_oneof![Just(16usize), Just(32), Just(64), Just(128)], prefix in vec(any::<u8>(), 0..40), data in vec(any::<u8>(), 1..600)) {
+            let padding = BlockPadding::new(scheme, block_size).unwrap();
+            let mut buffer = [prefix.as_slice(), &data].concat();
+            padding.pad_in_place(&mut buffer, prefix.len()).unwrap();
+
+            let padded = padding.pad(&data).unwrap();
+            prop_assert_eq!(&buffer[..prefix.len()], prefix.as_slice());
+            prop_assert_eq!(&buffer[prefix.len()..], padded.as_slice());
+        }
+
+        #[test]
+        fn unpad_accepts_exactly_well_formed_padding(scheme in scheme(), body in vec(any::<u8>(), 0..48), tail in vec(prop_oneof![Just(0u8), Just(0x80), 1..=16u8, any::<u8>()], 16)) {
+            let padding = BlockPadding::new(scheme, 16).unwrap();
+            let data = [body.chunks_exact(16).flatten().copied().collect(), tail.clone()].concat();
+
+            match reference(scheme, &tail) {
+                Some(padding_len) => prop_assert_eq!(padding.unpad(&data).unwrap(), &data[..data.len().saturating_sub(padding_len)]),
+                None => prop_assert!(padding.unpad(&data).is_err()),
+            }
+        }
+
+        #[test]
+        fn partial_blocks_are_rejected(scheme in scheme(), data in vec(any::<u8>(), 1..64)) {
+            prop_assume!(data.len() % 16 != 0);
+            prop_assert!(BlockPadding::new(scheme, 16).unwrap().unpad(&data).is_err());
+        }
     }
 }
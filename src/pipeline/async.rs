@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader, BufWriter};
+use tokio_util::sync::CancellationToken;
+
+use super::{Operation, Pipeline};
+use crate::api::EncryptOptions;
+use crate::config::IO_BUFFER_SIZE;
+use crate::secret::Secret;
+
+/// Keys and settings for [`process_async`]. The stream it reads or writes is the payload of a
+/// `.swx` file: length-prefixed chunks with no header, so both ends must already share the keys,
+/// the nonce prefix, and `options`.
+pub struct StreamConfig<'a> {
+    pub primary_key: &'a [u8; 32],
+    pub secondary_key: &'a [u8; 32],
+    /// 16 bytes every chunk nonce starts with; never reuse one under the same keys.
+    pub nonce_prefix: &'a [u8],
+    /// The cipher layers, chunk size, and compression level, and where progress goes. Decryption
+    /// has to be given the cipher the stream was sealed with.
+    pub options: EncryptOptions,
+}
+
+/// Encrypts or decrypts `input` into `output` on the threaded pipeline: chunks are read and written
+/// on the tokio runtime and sealed or opened on blocking threads, so an async service can run it
+/// without stalling its executor. Both streams are buffered here, so the pipeline's 4-byte length
+/// prefixes reach them as whole buffers rather than one small read or write each. Stops early once
+/// `cancel` fires.
+pub async fn process_async<R, W>(operation: Operation, input: R, output: W, config: &StreamConfig<'_>, cancel: &CancellationToken) -> Result<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (primary_key, secondary_key) = (Secret::new(config.primary_key.to_vec()), Secret::new(config.secondary_key.to_vec()));
+    let settings = config.options.settings()?;
+    let mut pipeline = Pipeline::new(&primary_key, &secondary_key, operation, config.options.cipher(), config.nonce_prefix, &settings)?;
+    if let Some(progress) = config.options.progress() {
+        pipeline = pipeline.with_progress(Arc::clone(progress));
+    }
+
+    pipeline
+        .process(BufReader::with_capacity(IO_BUFFER_SIZE, input), BufWriter::with_capacity(IO_BUFFER_SIZE, output), 0, cancel)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+
+    use tempfile::tempdir;
+    use tokio::fs::{self, File};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio_util::sync::CancellationToken;
+
+    use crate::cipher::{RandomSource, SeededRandom};
+    use crate::config::{IO_BUFFER_SIZE, MIN_CHUNK_SIZE};
+    // Everything through the crate root, as a dependent crate would reach it.
+    use crate::{CipherMode, EncryptOptions, Operation, ProgressEvent, Stage, StreamConfig, process_async};
+
+    /// A caller's unbuffered stream, counting the reads and writes that reach it.
+    struct Counted {
+        file: File,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Counted {
+        fn new(file: File) -> (Self, Arc<AtomicUsize>) {
+            let calls = Arc::new(AtomicUsize::new(0));
+            (Self { file, calls: Arc::clone(&calls) }, calls)
+        }
+
+        fn count<T>(&self, poll: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+            if poll.is_ready() {
+                self.calls.fetch_add(1, Ordering::Relaxed);
+            }
+            poll
+        }
+    }
+
+    impl AsyncRead for Counted {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            let poll = Pin::new(&mut this.file).poll_read(cx, buf);
+            this.count(poll)
+        }
+    }
+
+    impl AsyncWrite for Counted {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let poll = Pin::new(&mut this.file).poll_write(cx, buf);
+            this.count(poll)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().file).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().file).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn roundtrip_over_async_files() {
+        let dir = tempdir().unwrap();
+        let plain = dir.path().join("plain");
+        let encrypted = dir.path().join("encrypted");
+        let decrypted = dir.path().join("decrypted");
+        fs::write(&plain, vec![42u8; 300_000]).await.unwrap();
+
+        let plaintext = Arc::new(AtomicU64::new(0));
+        let finished = Arc::new(Mutex::new(None));
+        let sink = {
+            let (plaintext, finished) = (Arc::clone(&plaintext), Arc::clone(&finished));
+            move |event: &ProgressEvent| match event.stage {
+                Stage::Chunk => {
+                    plaintext.fetch_add(event.chunk_plaintext, Ordering::Relaxed);
+                }
+                Stage::Finished => *finished.lock().unwrap() = Some(*event),
+                Stage::Deriving | Stage::Derived | Stage::Started => {}
+            }
+        };
+        let options = EncryptOptions::new().with_cipher(CipherMode::Dual).with_progress(sink);
+        let config = StreamConfig { primary_key: &[1u8; 32], secondary_key: &[2u8; 32], nonce_prefix: &[9u8; 16], options };
+        let cancel = CancellationToken::new();
+
+        let (input, output) = (File::open(&plain).await.unwrap(), File::create(&encrypted).await.unwrap());
+        process_async(Operation::Encryption, input, output, &config, &cancel).await.unwrap();
+
+        let (input, output) = (File::open(&encrypted).await.unwrap(), File::create(&decrypted).await.unwrap());
+        process_async(Operation::Decryption, input, output, &config, &cancel).await.unwrap();
+
+        assert_eq!(fs::read(&decrypted).await.unwrap(), fs::read(&plain).await.unwrap());
+        assert_eq!(plaintext.load(Ordering::Relaxed), 600_000);
+
+        let finished = finished.lock().unwrap().unwrap();
+        assert_eq!(finished.bytes_out, 300_000);
+        assert_eq!(finished.bytes_in, fs::metadata(&encrypted).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn unbuffered_streams_see_whole_buffers() {
+        let dir = tempdir().unwrap();
+        let (plain, encrypted, decrypted) = (dir.path().join("plain"), dir.path().join("encrypted"), dir.path().join("decrypted"));
+        let mut content = vec![0u8; 2 * IO_BUFFER_SIZE];
+        SeededRandom::new(3).fill(&mut content).unwrap();
+        fs::write(&plain, &content).await.unwrap();
+
+        let options = EncryptOptions::new().with_chunk_size(MIN_CHUNK_SIZE);
+        let config = StreamConfig { primary_key: &[1u8; 32], secondary_key: &[2u8; 32], nonce_prefix: &[9u8; 16], options };
+        let cancel = CancellationToken::new();
+
+        let (output, writes) = Counted::new(File::create(&encrypted).await.unwrap());
+        process_async(Operation::Encryption, File::open(&plain).await.unwrap(), output, &config, &cancel).await.unwrap();
+        let (input, reads) = Counted::new(File::open(&encrypted).await.unwrap());
+        process_async(Operation::Decryption, input, File::create(&decrypted).await.unwrap(), &config, &cancel).await.unwrap();
+        assert_eq!(fs::read(&decrypted).await.unwrap(), content);
+
+        // 512 chunks, each a length prefix and a record: unbuffered, that is over a thousand calls.
+        let buffers = usize::try_from(fs::metadata(&encrypted).await.unwrap().len()).unwrap().div_ceil(IO_BUFFER_SIZE);
+        assert!(writes.load(Ordering::Relaxed) <= buffers + 1, "{} writes for {buffers} buffers", writes.load(Ordering::Relaxed));
+        assert!(reads.load(Ordering::Relaxed) <= buffers + 1, "{} reads for {buffers} buffers", reads.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,70 @@
+use std::num::NonZeroUsize;
+
+use anyhow::{Context, Result};
+use tokio::sync::watch;
+
+/// Number of chunks the executor may run ahead of the writer for a reorder limit given in bytes.
+/// Never fewer than one chunk per worker, so a tight limit still keeps every thread busy.
+pub(super) fn window_chunks(limit: usize, chunk_size: usize, threads: NonZeroUsize) -> u64 {
+    let chunks = limit.checked_div(chunk_size).unwrap_or_default().max(threads.get());
+
+    u64::try_from(chunks).unwrap_or(u64::MAX)
+}
+
+/// Splits a reorder window into the writer's side, which reports how many chunks are written, and
+/// the executor's side, which holds back new chunks until they fit in the window.
+pub(super) fn reorder_window(size: u64) -> (WindowProgress, WindowGate) {
+    let (sender, receiver) = watch::channel(0);
+
+    (WindowProgress { sender }, WindowGate { receiver, size })
+}
+
+pub(super) struct WindowProgress {
+    sender: watch::Sender<u64>,
+}
+
+impl WindowProgress {
+    pub(super) fn advance(&self, written: u64) {
+        self.sender.send_replace(written);
+    }
+}
+
+pub(super) struct WindowGate {
+    receiver: watch::Receiver<u64>,
+    size: u64,
+}
+
+impl WindowGate {
+    /// Waits until chunk `index` is within `size` chunks of the next one the writer needs. One slow
+    /// chunk then stalls new work instead of letting finished chunks pile up in the writer's
+    /// reorder buffer.
+    pub(super) async fn admit(&mut self, index: u64) -> Result<()> {
+        let size = self.size;
+        self.receiver
+            .wait_for(|&written| index < written.saturating_add(size))
+            .await
+            .context("writer stopped before the chunk could be scheduled")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn gate_waits_for_the_writer() {
+        let (progress, mut gate) = reorder_window(2);
+        gate.admit(1).await.unwrap();
+
+        tokio::time::timeout(Duration::from_millis(50), gate.admit(2)).await.unwrap_err();
+
+        progress.advance(1);
+        gate.admit(2).await.unwrap();
+        assert_eq!(window_chunks(1024, 256, NonZeroUsize::MIN), 4);
+        assert_eq!(window_chunks(0, 256, NonZeroUsize::new(8).unwrap()), 8);
+    }
+}
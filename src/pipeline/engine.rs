@@ -0,0 +1,228 @@
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::sync::CancellationToken;
+
+use super::buffer::{reorder_window, window_chunks};
+use super::executor::Executor;
+use super::losses::Losses;
+use super::reader::Reader;
+use super::trailer::{ChunkIndex, PadSize, PayloadMac};
+use super::writer::Writer;
+use super::{NoProgress, Operation, Process, ProgressEvent, ProgressSink, Stage, Stats, Task, TaskResult};
+use crate::cipher::CipherMode;
+use crate::config::Settings;
+use crate::error::SweetByteError;
+use crate::files::FsyncPolicy;
+use crate::padding::PaddingScheme;
+use crate::secret::Secret;
+
+pub(crate) struct Pipeline {
+    operation: Operation,
+    process: Process,
+    threads: NonZeroUsize,
+    chunk_size: usize,
+    fsync: FsyncPolicy,
+    window: u64,
+    progress: Arc<dyn ProgressSink>,
+    mac: Option<PayloadMac>,
+    expected_chunks: Option<u64>,
+    expected_size: Option<u64>,
+    stats: Option<Arc<Stats>>,
+    padding: PadSize,
+    offset: u64,
+    padded_input: bool,
+    chunk_index: Option<ChunkIndex>,
+    indexed_input: bool,
+    hidden: Vec<u8>,
+    header_copy: Vec<u8>,
+    losses: Option<Arc<Losses>>,
+    lost_chunk_size: u64,
+}
+
+impl Pipeline {
+    pub(crate) fn new(primary_key: &Secret, secondary_key: &Secret, operation: Operation, mode: CipherMode, nonce_prefix: &[u8], settings: &Settings) -> Result<Self> {
+        let process = Process::new(primary_key, secondary_key, operation, mode, nonce_prefix, settings.compression_level)
+            .context("failed to initialize process")?
+            .with_padding_scheme(settings.padding);
+
+        let window = window_chunks(settings.reorder_limit, settings.chunk_size, settings.threads);
+
+        Ok(Self {
+            operation,
+            process,
+            threads: settings.threads,
+            chunk_size: settings.chunk_size,
+            fsync: settings.fsync,
+            window,
+            progress: Arc::new(NoProgress),
+            mac: None,
+            expected_chunks: None,
+            expected_size: None,
+            stats: None,
+            padding: PadSize::None,
+            offset: 0,
+            padded_input: false,
+            chunk_index: None,
+            indexed_input: false,
+            hidden: Vec::new(),
+            header_copy: Vec::new(),
+            losses: None,
+            lost_chunk_size: 0,
+        })
+    }
+
+    pub(crate) fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub(crate) fn with_payload_mac(mut self, key: &Secret) -> Result<Self> {
+        self.mac = Some(PayloadMac::new(key).context("failed to initialize payload authentication")?);
+        Ok(self)
+    }
+
+    pub(crate) fn with_expected_chunks(mut self, chunks: u64) -> Self {
+        self.expected_chunks = Some(chunks);
+        self
+    }
+
+    /// Stops decryption once the plaintext outgrows `size`, the length the header declares, and
+    /// trims it to exactly that.
+    pub(crate) fn with_expected_size(mut self, size: u64) -> Self {
+        self.expected_size = Some(size);
+        self
+    }
+
+    pub(crate) fn with_chunk_limit(mut self, limit: usize) -> Self {
+        self.process = self.process.with_chunk_limit(limit);
+        self
+    }
+
+    /// Overrides the padding scheme from the settings, for decrypting a file whose header records
+    /// its own.
+    pub(crate) fn with_padding_scheme(mut self, scheme: PaddingScheme) -> Self {
+        self.process = self.process.with_padding_scheme(scheme);
+        self
+    }
+
+    pub(crate) fn with_stats(mut self, stats: Arc<Stats>) -> Self {
+        self.process = self.process.with_stats(Arc::clone(&stats));
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Pads encrypted output to `padding`; `offset` is how much was written before the payload,
+    /// i.e. the header.
+    pub(crate) fn with_padding(mut self, padding: PadSize, offset: u64) -> Self {
+        self.padding = padding;
+        self.offset = offset;
+        self
+    }
+
+    pub(crate) fn with_padded_input(mut self, padded: bool) -> Self {
+        self.padded_input = padded;
+        self
+    }
+
+    /// Appends a footer listing where every chunk starts, authenticated under `key`.
+    pub(crate) fn with_chunk_index(mut self, key: &Secret) -> Self {
+        self.chunk_index = Some(ChunkIndex::new(key));
+        self
+    }
+
+    pub(crate) fn with_indexed_input(mut self, indexed: bool) -> Self {
+        self.indexed_input = indexed;
+        self
+    }
+
+    /// Writes `hidden` at the end of the padding filler, where it can't be told apart from the
+    /// random bytes around it.
+    pub(crate) fn with_hidden(mut self, hidden: Vec<u8>) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    /// Ends the output with `header_copy`, a copy of the header framed to be found from the end of
+    /// the file.
+    pub(crate) fn with_header_copy(mut self, header_copy: Vec<u8>) -> Self {
+        self.header_copy = header_copy;
+        self
+    }
+
+    /// Decrypts past chunks that can't be recovered instead of failing: each is written as
+    /// `chunk_size` zeros, the file is filled with zeros to its full size if the payload ends
+    /// early, and every gap is recorded in `losses`.
+    pub(crate) fn with_keep_going(mut self, losses: Arc<Losses>, chunk_size: u64) -> Self {
+        self.losses = Some(losses);
+        self.lost_chunk_size = chunk_size;
+        self
+    }
+
+    pub(crate) async fn process<R, W>(self, input: R, output: W, total_size: u64, cancel: &CancellationToken) -> Result<()>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let started = Instant::now();
+        let channel_size = self.threads.get();
+        let keep_going = self.losses.is_some();
+        let executor = Executor::new(self.process, self.threads).context("failed to initialize executor")?.with_keep_going(keep_going);
+        let progress = self.progress;
+        progress.on_event(&ProgressEvent::new(Stage::Started, total_size));
+
+        let (task_tx, task_rx) = tokio::sync::mpsc::channel::<Task>(channel_size);
+        let (result_tx, result_rx) = tokio::sync::mpsc::channel::<TaskResult>(channel_size);
+
+        let (window_progress, window_gate) = reorder_window(self.window);
+
+        let (reader_mac, writer_mac) = if self.operation.is_encryption() { (None, self.mac) } else { (self.mac, None) };
+        let (reader_stats, writer_stats) = (self.stats.clone(), self.stats.clone());
+        let reader_cancel = cancel.clone();
+        let reader_handle = tokio::spawn(async move {
+            Reader::new(self.operation, self.chunk_size, reader_mac, self.expected_chunks, reader_stats)
+                .with_padding(self.padded_input)
+                .with_index(self.indexed_input)
+                .with_keep_going(keep_going)
+                .read_all(input, &task_tx, &reader_cancel)
+                .await
+        });
+        let writer_progress = Arc::clone(&progress);
+        let writer_handle = tokio::spawn(async move {
+            Writer::new(self.operation, self.fsync, total_size, writer_mac, window_progress, writer_stats)
+                .with_padding(self.padding, self.offset)
+                .with_chunk_index(self.chunk_index)
+                .with_hidden(self.hidden)
+                .with_header_copy(self.header_copy)
+                .with_losses(self.losses, self.lost_chunk_size)
+                .with_expected_size(self.expected_size)
+                .write_all(output, result_rx, writer_progress.as_ref())
+                .await
+        });
+        let executor_handle = tokio::spawn(async move { executor.execute(task_rx, result_tx, window_gate).await });
+
+        let (reader_result, executor_result, writer_result) = tokio::join!(reader_handle, executor_handle, writer_handle);
+
+        let reader_inner = reader_result.context("reader panicked")?;
+        reader_inner.context("failed to read")?;
+
+        let executor_inner = executor_result.context("executor panicked")?;
+        executor_inner.context("failed to execute")?;
+
+        let writer_inner = writer_result.context("writer panicked")?;
+        let finished = writer_inner.context("failed to write")?;
+        progress.on_event(&finished);
+        if let Some(stats) = &self.stats {
+            stats.record_pipeline(started.elapsed());
+        }
+
+        if cancel.is_cancelled() {
+            return Err(SweetByteError::Cancelled.into());
+        }
+
+        Ok(())
+    }
+}
@@ -1,24 +1,41 @@
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use tokio::sync::Semaphore;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::task::JoinSet;
 
+use super::buffer::WindowGate;
 use super::process::Process;
 use super::types::{Task, TaskResult};
 
 pub(super) struct Executor {
     process: Arc<Process>,
+    pool: Arc<ThreadPool>,
     concurrency: usize,
+    keep_going: bool,
 }
 
 impl Executor {
-    pub(super) fn new(process: Process, concurrency: usize) -> Self {
-        Self { process: Arc::new(process), concurrency }
+    pub(super) fn new(process: Process, threads: NonZeroUsize) -> Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads.get())
+            .thread_name(|index| format!("sweetbyte-worker-{index}"))
+            .build()
+            .context("failed to build worker pool")?;
+
+        Ok(Self { process: Arc::new(process), pool: Arc::new(pool), concurrency: threads.get(), keep_going: false })
+    }
+
+    /// Passes chunks that fail to process on as lost instead of failing the run.
+    pub(super) fn with_keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
     }
 
-    pub(super) async fn execute(&self, mut tasks: Receiver<Task>, results: Sender<TaskResult>) -> Result<()> {
+    pub(super) async fn execute(&self, mut tasks: Receiver<Task>, results: Sender<TaskResult>, mut window: WindowGate) -> Result<()> {
         let semaphore = Arc::new(Semaphore::new(self.concurrency));
         let mut workers: JoinSet<Result<()>> = JoinSet::new();
 
@@ -28,12 +45,18 @@ impl Executor {
                 worker_result.context("failed to process task")?;
             }
 
+            window.admit(task.index).await?;
             let permit = Arc::clone(&semaphore).acquire_owned().await.context("failed to acquire semaphore permit")?;
             let process = Arc::clone(&self.process);
+            let pool = Arc::clone(&self.pool);
             let results = results.clone();
+            let keep_going = self.keep_going;
 
             workers.spawn_blocking(move || {
-                let result = process.process(&task)?;
+                let result = match pool.install(|| process.process(&task)) {
+                    Err(_) if keep_going => TaskResult::lost(task.index, task.data.len().saturating_add(size_of::<u32>())),
+                    result => result?,
+                };
                 results.blocking_send(result).context("failed to send result")?;
 
                 drop(permit);
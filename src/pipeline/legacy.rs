@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+
+use super::progress::{ProgressEvent, ProgressSink, Stage};
+use super::trailer::within_declared;
+use crate::cipher::{DerivedKeys, LegacyCipher};
+use crate::compression::Compression;
+use crate::config::{BLOCK_SIZE, CHUNK_SIZE, COMPRESSION_LEVEL, MAX_CHUNK_SIZE, ORIGINAL_COUNT, RECOVERY_COUNT};
+use crate::encoding::Encoding;
+use crate::error::SweetByteError;
+use crate::padding::{BlockPadding, PaddingScheme};
+use crate::secure_temp::SecureBuffer;
+
+/// Decrypts the payload of a format 0x0002 file, one chunk after another. Its chunks run to the end
+/// of the file with no count, trailer, or payload MAC behind them, so only the hash in the header
+/// vouches for the whole; the caller checks it.
+pub(crate) struct LegacyPipeline {
+    cipher: LegacyCipher,
+    encoder: Encoding,
+    padding: BlockPadding,
+    compressor: Compression,
+    progress: Arc<dyn ProgressSink>,
+}
+
+impl LegacyPipeline {
+    pub(crate) fn new(keys: &DerivedKeys, progress: Arc<dyn ProgressSink>) -> Result<Self> {
+        let cipher = LegacyCipher::new(&keys.primary_key, &keys.secondary_key).context("failed to initialize cipher")?;
+        let encoder = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT).context("failed to initialize encoder")?;
+        let padding = BlockPadding::new(PaddingScheme::Pkcs7, BLOCK_SIZE).context("failed to initialize padding")?;
+        let compressor = Compression::new(COMPRESSION_LEVEL).context("failed to initialize compressor")?;
+
+        Ok(Self { cipher, encoder, padding, compressor, progress })
+    }
+
+    pub(crate) async fn process<R, W>(&self, mut reader: R, mut writer: W, size: u64, cancel: &CancellationToken) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        self.progress.on_event(&ProgressEvent::new(Stage::Started, size));
+        let (mut bytes_in, mut bytes_out) = (0u64, 0u64);
+
+        for index in 0u64.. {
+            if cancel.is_cancelled() {
+                return Err(SweetByteError::Cancelled.into());
+            }
+
+            let length = match reader.read_u32_le().await {
+                Ok(length) => length,
+                Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error).context("failed to read chunk length"),
+            };
+            if length > MAX_CHUNK_SIZE {
+                anyhow::bail!("chunk {index} claims {length} bytes, more than a chunk can hold");
+            }
+
+            let mut sealed = vec![0u8; length as usize];
+            reader.read_exact(&mut sealed).await.context("payload ends inside a chunk")?;
+            let plaintext = self.open(&sealed, index)?;
+
+            let kept = within_declared(index, bytes_out, u64::try_from(plaintext.len()).context("chunk length overflow")?, size)?;
+            let kept = plaintext.get(..usize::try_from(kept).context("chunk length overflow")?).unwrap_or_default();
+            writer.write_all(kept).await.context("failed to write output")?;
+
+            bytes_in = bytes_in.saturating_add(u64::from(length)).saturating_add(u64::try_from(size_of::<u32>()).unwrap_or(u64::MAX));
+            bytes_out = bytes_out.saturating_add(u64::try_from(kept.len()).unwrap_or(u64::MAX));
+            let mut event = ProgressEvent::new(Stage::Chunk, size);
+            event.chunk = index;
+            event.chunk_plaintext = u64::try_from(kept.len()).unwrap_or(u64::MAX);
+            event.bytes_in = bytes_in;
+            event.bytes_out = bytes_out;
+            self.progress.on_event(&event);
+        }
+
+        if bytes_out != size {
+            anyhow::bail!("payload holds {bytes_out} bytes but the header declares {size}");
+        }
+        writer.shutdown().await.context("failed to flush output")?;
+        self.progress.on_event(&ProgressEvent::new(Stage::Finished, size));
+
+        Ok(())
+    }
+
+    /// Rebuilds, opens, unpads, and decompresses one chunk.
+    fn open(&self, sealed: &[u8], index: u64) -> Result<SecureBuffer> {
+        let decoded = self.encoder.decode_legacy(sealed).with_context(|| format!("failed to decode chunk {index}"))?;
+        let padded = self.cipher.open(&decoded).with_context(|| format!("failed to decrypt chunk {index}"))?;
+        let compressed_len = self.padding.unpadded_len(&padded)?;
+        let data = self
+            .compressor
+            .decompress(padded.get(..compressed_len).unwrap_or_default(), CHUNK_SIZE)
+            .with_context(|| format!("failed to decompress chunk {index}"))?;
+
+        Ok(SecureBuffer::locked(data))
+    }
+}
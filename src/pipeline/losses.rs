@@ -0,0 +1,24 @@
+use std::ops::Range;
+use std::sync::{Mutex, PoisonError};
+
+/// Plaintext ranges written as zeros by a `--keep-going` decryption, because their chunks didn't
+/// decrypt or the payload ended before them. Adjacent ranges are merged, so each one is a run of
+/// lost bytes.
+#[derive(Default)]
+pub(crate) struct Losses {
+    ranges: Mutex<Vec<Range<u64>>>,
+}
+
+impl Losses {
+    pub(crate) fn record(&self, range: Range<u64>) {
+        let mut ranges = self.ranges.lock().unwrap_or_else(PoisonError::into_inner);
+        match ranges.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => ranges.push(range),
+        }
+    }
+
+    pub(crate) fn ranges(&self) -> Vec<Range<u64>> {
+        self.ranges.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
@@ -1,59 +1,44 @@
+#[cfg(feature = "async")]
+mod r#async;
+#[cfg(feature = "cli")]
+mod buffer;
+#[cfg(feature = "cli")]
+mod engine;
+#[cfg(feature = "cli")]
 mod executor;
+#[cfg(feature = "cli")]
+mod legacy;
+#[cfg(feature = "cli")]
+mod losses;
 mod process;
+mod progress;
+#[cfg(feature = "cli")]
 mod reader;
+mod stats;
+mod trailer;
 mod types;
+#[cfg(feature = "cli")]
 mod writer;
 
-use anyhow::{Context, Result};
-use executor::Executor;
-use process::Process;
-use reader::Reader;
-use tokio::io::{AsyncRead, AsyncWrite};
-pub(crate) use types::Operation;
-use types::{Task, TaskResult};
-use writer::Writer;
-
-use crate::secret::Secret;
-use crate::ui::Progress;
-
-pub(crate) struct Pipeline {
-    operation: Operation,
-    process: Process,
-}
-
-impl Pipeline {
-    pub(crate) fn new(primary_key: &Secret, secondary_key: &Secret, operation: Operation) -> Result<Self> {
-        let process = Process::new(primary_key, secondary_key, operation).context("failed to initialize process")?;
-
-        Ok(Self { operation, process })
-    }
-
-    pub(crate) async fn process<R, W>(self, input: R, output: W, total_size: u64) -> Result<()>
-    where
-        R: AsyncRead + Unpin + Send + 'static,
-        W: AsyncWrite + Unpin + Send + 'static,
-    {
-        let channel_size = std::thread::available_parallelism().map(std::num::NonZero::get).context("failed to get available parallelism")?;
-        let progress_bar = Progress::new(total_size, self.operation.label());
-
-        let (task_tx, task_rx) = tokio::sync::mpsc::channel::<Task>(channel_size);
-        let (result_tx, result_rx) = tokio::sync::mpsc::channel::<TaskResult>(channel_size);
-
-        let reader_handle = tokio::spawn(async move { Reader::new(self.operation).read_all(input, &task_tx).await });
-        let writer_handle = tokio::spawn(async move { Writer::new(self.operation).write_all(output, result_rx, &progress_bar).await });
-        let executor_handle = tokio::spawn(async move { Executor::new(self.process, channel_size).execute(task_rx, result_tx).await });
-
-        let (reader_result, executor_result, writer_result) = tokio::join!(reader_handle, executor_handle, writer_handle);
-
-        let reader_inner = reader_result.context("reader panicked")?;
-        reader_inner.context("failed to read")?;
-
-        let executor_inner = executor_result.context("executor panicked")?;
-        executor_inner.context("failed to execute")?;
-
-        let writer_inner = writer_result.context("writer panicked")?;
-        writer_inner.context("failed to write")?;
-
-        Ok(())
-    }
-}
+#[cfg(feature = "async")]
+pub use r#async::{StreamConfig, process_async};
+#[cfg(feature = "cli")]
+pub(crate) use engine::Pipeline;
+#[cfg(feature = "cli")]
+pub(crate) use legacy::LegacyPipeline;
+#[cfg(feature = "cli")]
+pub(crate) use losses::Losses;
+pub(crate) use process::Process;
+pub(crate) use progress::NoProgress;
+pub use progress::{ProgressEvent, ProgressSink, Stage};
+pub(crate) use stats::Stats;
+#[cfg(feature = "cli")]
+pub(crate) use stats::Throughput;
+pub use trailer::PadSize;
+#[cfg(feature = "cli")]
+pub(crate) use trailer::{ChunkIndex, IndexEntry};
+pub(crate) use trailer::{INDEX_TAIL_LEN, PayloadMac, TRAILER_BODY_LEN, within_declared};
+pub use types::Operation;
+pub(crate) use types::Task;
+#[cfg(feature = "cli")]
+pub(crate) use types::TaskResult;
@@ -1,33 +1,72 @@
+use std::cell::RefCell;
+use std::sync::{Arc, LazyLock};
+
 use anyhow::{Context, Result};
+use zeroize::Zeroize;
 
+use super::stats::{Stats, Step};
 use super::types::{Operation, Task, TaskResult};
-use crate::cipher::{Algorithm, Cipher};
+use crate::cipher::{Algorithm, Cipher, CipherMode};
 use crate::compression::Compression;
-use crate::config::{BLOCK_SIZE, COMPRESSION_LEVEL, ORIGINAL_COUNT, RECOVERY_COUNT};
-use crate::encoding::Encoding;
-use crate::padding::Pkcs7Padding;
+use crate::config::{BLOCK_SIZE, CHUNK_SIZE, ORIGINAL_COUNT, RECOVERY_COUNT};
+use crate::encoding::{Encoding, Shards};
+use crate::padding::{BlockPadding, PaddingScheme};
 use crate::secret::Secret;
+use crate::secure_temp::SecureBuffer;
+
+/// The most a chunk takes up while it is sealed: every layer's nonce and tag around the largest
+/// chunk compressed without shrinking, plus a block of padding.
+static SCRATCH_LEN: LazyLock<usize> = LazyLock::new(|| zstd::zstd_safe::compress_bound(CHUNK_SIZE).saturating_add(BLOCK_SIZE).saturating_add(CipherMode::Dual.overhead()));
+
+thread_local! {
+    /// The buffer each worker thread seals its chunks in, kept between chunks so that sealing one allocates only the
+    /// encoded result and zstd's context. It is locked, and sized for the largest chunk up front so it never moves.
+    static SCRATCH: RefCell<SecureBuffer> = RefCell::new(SecureBuffer::with_capacity(*SCRATCH_LEN));
+}
 
-pub(super) struct Process {
+pub(crate) struct Process {
     cipher: Cipher,
     encoder: Encoding,
     compressor: Compression,
-    padding: Pkcs7Padding,
+    padding: BlockPadding,
     operation: Operation,
+    mode: CipherMode,
+    chunk_limit: usize,
+    stats: Option<Arc<Stats>>,
 }
 
 impl Process {
-    pub(super) fn new(primary_key: &Secret, secondary_key: &Secret, operation: Operation) -> Result<Self> {
-        let cipher = Cipher::new(primary_key, secondary_key).context("failed to initialize cipher")?;
+    pub(crate) fn new(primary_key: &Secret, secondary_key: &Secret, operation: Operation, mode: CipherMode, nonce_prefix: &[u8], compression_level: i32) -> Result<Self> {
+        let cipher = Cipher::new(primary_key, secondary_key, nonce_prefix).context("failed to initialize cipher")?;
         let encoder = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT).context("failed to initialize encoder")?;
-        let compressor = Compression::new(COMPRESSION_LEVEL).context("failed to initialize compressor")?;
-        let padding = Pkcs7Padding::new(BLOCK_SIZE).context("failed to initialize padding")?;
+        let compressor = Compression::new(compression_level).context("failed to initialize compressor")?;
+        let padding = BlockPadding::new(PaddingScheme::default(), BLOCK_SIZE).context("failed to initialize padding")?;
+
+        Ok(Self { cipher, encoder, compressor, padding, operation, mode, chunk_limit: CHUNK_SIZE, stats: None })
+    }
+
+    /// Pads chunks with `scheme` before encrypting them, or expects it when decrypting; the header
+    /// records which.
+    pub(crate) fn with_padding_scheme(mut self, scheme: PaddingScheme) -> Self {
+        self.padding = self.padding.with_scheme(scheme);
+        self
+    }
+
+    /// Fails a chunk that decompresses to more than `limit` bytes instead of holding all of it in
+    /// memory. Without a limit, a chunk may hold as much as the largest chunk size an encryptor
+    /// writes.
+    pub(crate) fn with_chunk_limit(mut self, limit: usize) -> Self {
+        self.chunk_limit = limit;
+        self
+    }
 
-        Ok(Self { cipher, encoder, compressor, padding, operation })
+    pub(crate) fn with_stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = Some(stats);
+        self
     }
 
     #[inline]
-    pub(super) fn process(&self, task: &Task) -> Result<TaskResult> {
+    pub(crate) fn process(&self, task: &Task) -> Result<TaskResult> {
         match self.operation {
             Operation::Encryption => self.encrypt(task),
             Operation::Decryption => self.decrypt(task),
@@ -36,29 +75,204 @@ impl Process {
 
     #[inline]
     fn encrypt(&self, task: &Task) -> Result<TaskResult> {
-        self.compressor
-            .compress(&task.data)
-            .and_then(|data| self.padding.pad(&data))
-            .and_then(|data| self.cipher.encrypt(&Algorithm::Aes256Gcm, &data))
-            .and_then(|data| self.cipher.encrypt(&Algorithm::ChaCha20Poly1305, &data))
-            .and_then(|data| self.encoder.encode(&data))
-            .map(|data| {
-                let size = task.data.len();
-                TaskResult::new(task.index, data, size)
-            })
+        if task.data.len() > CHUNK_SIZE {
+            anyhow::bail!("chunk {} holds {} bytes, more than the {CHUNK_SIZE} a chunk can", task.index, task.data.len());
+        }
+
+        SCRATCH.with_borrow_mut(|buffer| {
+            let buffer = buffer.as_mut_vec();
+            let result = self.seal(task, buffer);
+            // Sealing encrypts the compressed chunk where it lies, but one that fails partway can leave it in
+            // the clear.
+            if result.is_err() {
+                buffer.zeroize();
+            }
+            result
+        })
+    }
+
+    /// Compresses, pads and encrypts the chunk in `buffer`, leaving room in front for every layer's
+    /// nonce so that nothing has to be copied between stages.
+    #[inline]
+    fn seal(&self, task: &Task, buffer: &mut Vec<u8>) -> Result<TaskResult> {
+        let mut start = self.mode.nonce_room();
+        buffer.clear();
+        buffer.resize(start, 0);
+
+        self.timed(Step::Compression, || self.compressor.compress_into(&task.data, buffer))?;
+        let compressed_len = buffer.len().saturating_sub(start);
+        self.timed(Step::Padding, || self.padding.pad_in_place(buffer, start))?;
+        self.timed(Step::Encryption, || {
+            for algorithm in [Algorithm::Aes256Gcm, Algorithm::ChaCha20Poly1305] {
+                if self.mode.uses(&algorithm) {
+                    start = self.cipher.encrypt_in_place(&algorithm, buffer, start, task.index)?;
+                }
+            }
+            Ok(())
+        })?;
+        let sealed = buffer.get(start..).context("sealed chunk missing")?;
+        let encoded = self.timed(Step::Encoding, || self.encoder.encode(sealed))?;
+
+        let size = task.data.len();
+        if let Some(stats) = &self.stats {
+            stats.count(size, compressed_len, sealed.len(), encoded.len());
+        }
+
+        Ok(TaskResult::new(task.index, SecureBuffer::unlocked(encoded), size, size))
     }
 
     #[inline]
     fn decrypt(&self, task: &Task) -> Result<TaskResult> {
-        self.encoder
-            .decode(&task.data)
-            .and_then(|data| self.cipher.decrypt(&Algorithm::ChaCha20Poly1305, &data))
-            .and_then(|data| self.cipher.decrypt(&Algorithm::Aes256Gcm, &data))
-            .and_then(|data| self.padding.unpad(&data))
-            .and_then(|data| self.compressor.decompress(&data))
-            .map(|data| {
-                let size = data.len();
-                TaskResult::new(task.index, data, size)
-            })
+        let shards = self.timed(Step::Encoding, || self.encoder.shards(&task.data))?;
+        let mut sealed = self.timed(Step::Encoding, || self.encoder.reconstruct(&shards, None))?;
+        let sealed_len = sealed.len();
+        let start = match self.timed(Step::Encryption, || self.open(&mut sealed, task.index)) {
+            Ok(start) => start,
+            Err(error) => {
+                sealed.zeroize();
+                let (retried, start) = self.retry(&shards, task.index).ok_or(error)?;
+                sealed = retried;
+                start
+            }
+        };
+
+        let result = self.inflate(task, sealed.get(start..).unwrap_or_default(), sealed_len);
+        sealed.zeroize();
+        result
+    }
+
+    /// Strips the padding from the opened chunk and decompresses what is left.
+    #[inline]
+    fn inflate(&self, task: &Task, padded: &[u8], sealed_len: usize) -> Result<TaskResult> {
+        let compressed_len = self.timed(Step::Padding, || self.padding.unpadded_len(padded))?;
+        let compressed = padded.get(..compressed_len).unwrap_or_default();
+        let data = self
+            .timed(Step::Compression, || self.compressor.decompress(compressed, self.chunk_limit))
+            .with_context(|| format!("failed to decompress chunk {}", task.index))?;
+
+        let size = data.len();
+        if let Some(stats) = &self.stats {
+            stats.count(size, compressed_len, sealed_len, task.data.len());
+        }
+
+        Ok(TaskResult::new(task.index, SecureBuffer::locked(data), size, task.data.len().saturating_add(size_of::<u32>())))
+    }
+
+    /// Opens every layer of `sealed` in place, outermost first; returns where the padded plaintext
+    /// begins.
+    #[inline]
+    fn open(&self, sealed: &mut Vec<u8>, index: u64) -> Result<usize> {
+        let mut start = 0;
+        for algorithm in [Algorithm::ChaCha20Poly1305, Algorithm::Aes256Gcm] {
+            if self.mode.uses(&algorithm) {
+                start = self.cipher.decrypt_in_place(&algorithm, sealed, start, index)?;
+            }
+        }
+
+        Ok(start)
+    }
+
+    /// A CRC-32 can still match a damaged shard, and then the chunk rebuilt from it fails to
+    /// authenticate. Rebuilds it without each shard it may have relied on in turn, and keeps
+    /// the first version that authenticates.
+    fn retry(&self, shards: &Shards<'_>, index: u64) -> Option<(Vec<u8>, usize)> {
+        self.encoder.suspects(shards).into_iter().find_map(|suspect| {
+            let mut sealed = self.timed(Step::Encoding, || self.encoder.reconstruct(shards, Some(suspect))).ok()?;
+            match self.timed(Step::Encryption, || self.open(&mut sealed, index)) {
+                Ok(start) => Some((sealed, start)),
+                Err(_) => {
+                    sealed.zeroize();
+                    None
+                }
+            }
+        })
+    }
+
+    #[inline]
+    fn timed<T>(&self, step: Step, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        match &self.stats {
+            Some(stats) => stats.time(step, f),
+            None => f(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::{RandomSource, SeededRandom};
+    use crate::config::{COMPRESSION_LEVEL, KEY_LEN, NONCE_PREFIX_LEN};
+
+    #[test]
+    fn a_shard_with_a_forged_checksum_is_left_out_until_the_chunk_authenticates() {
+        let (primary, secondary) = (Secret::new(vec![1u8; KEY_LEN]), Secret::new(vec![2u8; KEY_LEN]));
+        let process = |operation| Process::new(&primary, &secondary, operation, CipherMode::default(), &[3u8; NONCE_PREFIX_LEN], COMPRESSION_LEVEL).unwrap();
+        let mut plaintext = vec![0u8; 5000];
+        SeededRandom::new(4).fill(&mut plaintext).unwrap();
+        let encoded = process(Operation::Encryption)
+            .process(&Task { data: SecureBuffer::unlocked(plaintext.clone()), index: 7 })
+            .unwrap()
+            .data
+            .to_vec();
+
+        // Damage an original shard and fix up its checksum, so only authentication can tell.
+        let encoder = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT).unwrap();
+        let mut forged = encoded.clone();
+        let span = encoder.shard_spans(forged.len())[1].clone();
+        let (crc, shard) = forged[span].split_at_mut(4);
+        shard[0] ^= 0xFF;
+        crc.copy_from_slice(&crc32fast::hash(shard).to_le_bytes());
+        assert_ne!(encoder.decode(&forged).unwrap(), encoder.decode(&encoded).unwrap());
+
+        let decrypted = process(Operation::Decryption).process(&Task { data: SecureBuffer::unlocked(forged), index: 7 }).unwrap();
+        assert_eq!(&decrypted.data[..], &plaintext[..]);
+    }
+
+    #[test]
+    fn every_mode_roundtrips_through_the_reused_buffer() {
+        let (primary, secondary) = (Secret::new(vec![1u8; KEY_LEN]), Secret::new(vec![2u8; KEY_LEN]));
+        for mode in [CipherMode::Aes, CipherMode::Chacha, CipherMode::Dual] {
+            let process = |operation| Process::new(&primary, &secondary, operation, mode, &[3u8; NONCE_PREFIX_LEN], COMPRESSION_LEVEL).unwrap();
+            let (encryptor, decryptor) = (process(Operation::Encryption), process(Operation::Decryption));
+
+            // A large chunk first, so the smaller ones after it are sealed in a buffer still holding its bytes.
+            for (index, len) in (0u64..).zip([CHUNK_SIZE, 1, 4095, 16, 70_000]) {
+                let mut plaintext = vec![0u8; len];
+                SeededRandom::new(index).fill(&mut plaintext).unwrap();
+                let encoded = encryptor.process(&Task { data: SecureBuffer::unlocked(plaintext.clone()), index }).unwrap().data.to_vec();
+                let sealed = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT).unwrap().decode(&encoded).unwrap();
+                let framing = mode.framing(&sealed, &[3u8; NONCE_PREFIX_LEN], index).unwrap();
+                assert!(framing.nonce_matches && framing.tags_present);
+
+                let decrypted = decryptor.process(&Task { data: SecureBuffer::unlocked(encoded), index }).unwrap();
+                assert_eq!(&decrypted.data[..], &plaintext[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_past_the_limit_are_refused() {
+        let (primary, secondary) = (Secret::new(vec![1u8; KEY_LEN]), Secret::new(vec![2u8; KEY_LEN]));
+        let process = |operation| Process::new(&primary, &secondary, operation, CipherMode::default(), &[3u8; NONCE_PREFIX_LEN], COMPRESSION_LEVEL).unwrap();
+        let task = |data: Vec<u8>| Task { data: SecureBuffer::unlocked(data), index: 0 };
+        let encoded = process(Operation::Encryption).process(&task(vec![0u8; 4096])).unwrap().data.to_vec();
+
+        assert_eq!(process(Operation::Decryption).with_chunk_limit(4096).process(&task(encoded.clone())).unwrap().data.len(), 4096);
+        let error = process(Operation::Decryption).with_chunk_limit(4095).process(&task(encoded)).err().unwrap();
+        assert!(format!("{error:#}").contains("decompressed data exceeds 4095 bytes"));
+    }
+
+    #[test]
+    fn corrupt_frames_are_not_reported_as_too_large() {
+        let process =
+            Process::new(&Secret::new(vec![1u8; KEY_LEN]), &Secret::new(vec![2u8; KEY_LEN]), Operation::Decryption, CipherMode::default(), &[3u8; NONCE_PREFIX_LEN], COMPRESSION_LEVEL).unwrap();
+        let mut padded = process.compressor.compress(&[7u8; 4096]).unwrap();
+        padded.truncate(8);
+        process.padding.pad_in_place(&mut padded, 0).unwrap();
+
+        let task = Task { data: SecureBuffer::unlocked(Vec::new()), index: 5 };
+        let error = process.inflate(&task, &padded, padded.len()).err().unwrap();
+        assert!(format!("{error:#}").starts_with("failed to decompress chunk 5"));
+        assert!(!format!("{error:#}").contains("exceeds"));
     }
 }
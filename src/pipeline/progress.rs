@@ -0,0 +1,50 @@
+/// Where a run has got to when a [`ProgressEvent`] is sent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stage {
+    /// The password is being stretched, which can take a few seconds at the default costs.
+    Deriving,
+    Derived,
+    Started,
+    /// One more chunk has been written out.
+    Chunk,
+    Finished,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProgressEvent {
+    pub stage: Stage,
+    /// Index of the chunk just written; only meaningful for [`Stage::Chunk`].
+    pub chunk: u64,
+    /// Plaintext bytes that chunk held.
+    pub chunk_plaintext: u64,
+    /// Bytes read so far.
+    pub bytes_in: u64,
+    /// Bytes written so far.
+    pub bytes_out: u64,
+    /// Plaintext bytes the whole run covers, or zero before that is known.
+    pub total: u64,
+}
+
+impl ProgressEvent {
+    pub(crate) fn new(stage: Stage, total: u64) -> Self {
+        Self { stage, chunk: 0, chunk_plaintext: 0, bytes_in: 0, bytes_out: 0, total }
+    }
+}
+
+/// Receives a run's [`ProgressEvent`]s as they happen, for drawing a progress bar of one's own.
+/// Any `Fn(&ProgressEvent)` closure is one.
+pub trait ProgressSink: Send + Sync {
+    fn on_event(&self, event: &ProgressEvent);
+}
+
+impl<F: Fn(&ProgressEvent) + Send + Sync> ProgressSink for F {
+    fn on_event(&self, event: &ProgressEvent) {
+        self(event);
+    }
+}
+
+pub(crate) struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn on_event(&self, _event: &ProgressEvent) {}
+}
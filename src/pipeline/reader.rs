@@ -1,42 +1,80 @@
+use std::sync::Arc;
+use std::time::Instant;
+
 use anyhow::{Context, Result};
-use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
 
+use super::stats::{Stats, Step};
+use super::trailer::{PayloadMac, TRAILER_BODY_LEN};
 use super::types::{Operation, Task};
-use crate::config::{CHUNK_SIZE, MAX_CHUNK_SIZE};
+use crate::config::{CHUNK_INDEX_MARKER, MAX_CHUNK_SIZE, PADDING_MARKER, PAYLOAD_MAC_MARKER};
+use crate::secure_temp::SecureBuffer;
 
 pub(super) struct Reader {
     index: u64,
-    buffer: Vec<u8>,
     operation: Operation,
+    chunk_size: usize,
+    mac: Option<PayloadMac>,
+    expected_chunks: Option<u64>,
+    stats: Option<Arc<Stats>>,
+    padded: bool,
+    indexed: bool,
+    keep_going: bool,
 }
 
 impl Reader {
-    pub(super) fn new(operation: Operation) -> Self {
-        Self { index: 0, buffer: Vec::with_capacity(CHUNK_SIZE), operation }
+    pub(super) fn new(operation: Operation, chunk_size: usize, mac: Option<PayloadMac>, expected_chunks: Option<u64>, stats: Option<Arc<Stats>>) -> Self {
+        Self { index: 0, operation, chunk_size, mac, expected_chunks, stats, padded: false, indexed: false, keep_going: false }
+    }
+
+    /// Accepts a padding marker after the last record and discards everything behind it.
+    pub(super) fn with_padding(mut self, padded: bool) -> Self {
+        self.padded = padded;
+        self
+    }
+
+    /// Accepts a chunk index footer after the last record. It is skipped; the payload is
+    /// authenticated without it.
+    pub(super) fn with_index(mut self, indexed: bool) -> Self {
+        self.indexed = indexed;
+        self
+    }
+
+    /// Stops at the first record that can't be read, or a payload that fails to authenticate,
+    /// without failing the run. The writer fills whatever the payload no longer holds.
+    pub(super) fn with_keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
     }
 
-    pub(super) async fn read_all<R: AsyncRead + Unpin>(&mut self, input: R, sender: &Sender<Task>) -> Result<()> {
+    /// Reads `input` as it is given. Decryption reads each record length on its own, so its input
+    /// should already be buffered, as every reader from `files` is.
+    pub(super) async fn read_all<R: AsyncRead + Unpin>(&mut self, mut input: R, sender: &Sender<Task>, cancel: &CancellationToken) -> Result<()> {
         self.index = 0;
-        self.buffer.clear();
-        let mut reader = BufReader::new(input);
 
         match self.operation {
-            Operation::Encryption => self.read_fixed_chunks(&mut reader, sender).await,
-            Operation::Decryption => self.read_length_prefixed(&mut reader, sender).await,
+            Operation::Encryption => self.read_fixed_chunks(&mut input, sender, cancel).await,
+            Operation::Decryption => match self.read_length_prefixed(&mut input, sender, cancel).await {
+                Err(_) if self.keep_going => Ok(()),
+                read => read,
+            },
         }
     }
 
-    async fn read_fixed_chunks<R: AsyncRead + Unpin>(&mut self, reader: &mut R, sender: &Sender<Task>) -> Result<()> {
-        loop {
-            self.buffer.clear();
-            let bytes_read = reader.take(CHUNK_SIZE as u64).read_to_end(&mut self.buffer).await.context("failed to read chunk")?;
+    async fn read_fixed_chunks<R: AsyncRead + Unpin>(&mut self, reader: &mut R, sender: &Sender<Task>, cancel: &CancellationToken) -> Result<()> {
+        while !cancel.is_cancelled() {
+            let mut data = SecureBuffer::zeroed(self.chunk_size);
+            let started = Instant::now();
+            let bytes_read = Self::fill(reader, &mut data).await?;
+            self.record(started);
 
             if bytes_read == 0 {
                 break;
             }
 
-            let data = std::mem::take(&mut self.buffer);
+            data.truncate(bytes_read);
             sender.send(Task { data, index: self.index }).await.context("failed to send chunk")?;
             self.index = self.index.saturating_add(1);
         }
@@ -44,23 +82,182 @@ impl Reader {
         Ok(())
     }
 
-    async fn read_length_prefixed<R: AsyncRead + Unpin>(&mut self, reader: &mut R, sender: &Sender<Task>) -> Result<()> {
-        loop {
+    async fn read_length_prefixed<R: AsyncRead + Unpin>(&mut self, reader: &mut R, sender: &Sender<Task>, cancel: &CancellationToken) -> Result<()> {
+        while !cancel.is_cancelled() {
             match reader.read_u32_le().await {
+                Ok(PAYLOAD_MAC_MARKER) if self.mac.is_some() => {
+                    self.check_complete()?;
+                    return self.verify_trailer(reader).await;
+                }
+                Ok(PADDING_MARKER) if self.padded => {
+                    self.check_complete()?;
+                    if self.mac.is_some() {
+                        anyhow::bail!("payload truncated: authentication record is missing");
+                    }
+                    return Self::discard(reader).await;
+                }
+                Ok(CHUNK_INDEX_MARKER) if self.indexed => {
+                    self.check_complete()?;
+                    if self.mac.is_some() {
+                        anyhow::bail!("payload truncated: authentication record is missing");
+                    }
+                    return Self::discard(reader).await;
+                }
                 Ok(chunk_len) => {
                     if chunk_len > MAX_CHUNK_SIZE {
                         anyhow::bail!("chunk size {chunk_len} exceeds maximum {MAX_CHUNK_SIZE}");
                     }
-                    let mut data = vec![0u8; chunk_len as usize];
-                    reader.read_exact(&mut data).await.context("failed to read chunk")?;
+                    if let Some(expected) = self.expected_chunks.filter(|&expected| self.index >= expected) {
+                        anyhow::bail!("file has more chunks than the {expected} recorded in the header");
+                    }
+                    let mut data = SecureBuffer::unlocked(vec![0u8; chunk_len as usize]);
+                    let started = Instant::now();
+                    let read = reader.read_exact(&mut data).await;
+                    self.record(started);
+                    if let Err(e) = read {
+                        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                            self.check_complete()?;
+                        }
+                        return Err(e).context("failed to read chunk");
+                    }
+                    if let Some(mac) = &mut self.mac {
+                        mac.update_record(self.index, &data)?;
+                    }
                     sender.send(Task { data, index: self.index }).await.context("failed to send chunk")?;
                     self.index = self.index.saturating_add(1);
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    self.check_complete()?;
+                    if self.mac.is_some() {
+                        anyhow::bail!("payload truncated: authentication record is missing");
+                    }
+                    break;
+                }
                 Err(e) => return Err(e).context("failed to read chunk length"),
             }
         }
 
         Ok(())
     }
+
+    /// Reads into `buffer` until it is full or the input ends, without growing it.
+    async fn fill<R: AsyncRead + Unpin>(reader: &mut R, buffer: &mut [u8]) -> Result<usize> {
+        let mut filled = 0;
+        while let Some(rest) = buffer.get_mut(filled..).filter(|rest| !rest.is_empty()) {
+            match reader.read(rest).await.context("failed to read chunk")? {
+                0 => break,
+                read => filled = filled.saturating_add(read),
+            }
+        }
+
+        Ok(filled)
+    }
+
+    async fn discard<R: AsyncRead + Unpin>(reader: &mut R) -> Result<()> {
+        tokio::io::copy(reader, &mut tokio::io::sink()).await.context("failed to read padding")?;
+
+        Ok(())
+    }
+
+    fn record(&self, started: Instant) {
+        if let Some(stats) = &self.stats {
+            stats.record(Step::Read, started.elapsed());
+        }
+    }
+
+    fn check_complete(&self) -> Result<()> {
+        match self.expected_chunks {
+            Some(expected) if self.index < expected => anyhow::bail!("file truncated: expected {expected} chunks, found {}", self.index),
+            _ => Ok(()),
+        }
+    }
+
+    async fn verify_trailer<R: AsyncRead + Unpin>(&self, reader: &mut R) -> Result<()> {
+        let mut body = [0u8; TRAILER_BODY_LEN];
+        reader.read_exact(&mut body).await.context("payload truncated: authentication record is incomplete")?;
+        self.mac.as_ref().context("payload authentication is not enabled")?.verify(&body)?;
+
+        if self.padded {
+            if reader.read_u32_le().await.context("payload truncated: padding record is missing")? != PADDING_MARKER {
+                anyhow::bail!("unexpected data after payload authentication record");
+            }
+            return Self::discard(reader).await;
+        }
+
+        if self.indexed {
+            if reader.read_u32_le().await.context("payload truncated: chunk index is missing")? != CHUNK_INDEX_MARKER {
+                anyhow::bail!("unexpected data after payload authentication record");
+            }
+            return Self::discard(reader).await;
+        }
+
+        if reader.read(&mut [0u8; 1]).await.context("failed to read past authentication record")? != 0 {
+            anyhow::bail!("unexpected data after payload authentication record");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::{BufReader, ReadBuf};
+
+    use super::*;
+    use crate::config::{CHUNK_SIZE, IO_BUFFER_SIZE};
+
+    const RECORDS: usize = 256;
+
+    /// The caller's source, counting the reads that reach it.
+    struct Source<'a> {
+        data: &'a [u8],
+        reads: usize,
+    }
+
+    impl AsyncRead for Source<'_> {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            this.reads = this.reads.saturating_add(1);
+            Pin::new(&mut this.data).poll_read(cx, buf)
+        }
+    }
+
+    fn payload() -> Vec<u8> {
+        let len = u32::try_from(CHUNK_SIZE).unwrap().to_le_bytes();
+        (0..RECORDS).flat_map(|_| len.into_iter().chain(std::iter::repeat_n(0xa5, CHUNK_SIZE))).collect()
+    }
+
+    async fn read<R: AsyncRead + Unpin>(input: R) {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(RECORDS);
+        Reader::new(Operation::Decryption, CHUNK_SIZE, None, None, None)
+            .read_all(input, &sender, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        drop(sender);
+        let mut received = 0usize;
+        while let Some(task) = receiver.recv().await {
+            assert_eq!(task.data.len(), CHUNK_SIZE);
+            received = received.saturating_add(1);
+        }
+        assert_eq!(received, RECORDS);
+    }
+
+    #[tokio::test]
+    async fn records_are_read_through_the_callers_buffer() {
+        let payload = payload();
+        let (mut double, mut single) = (Source { data: &payload, reads: 0 }, Source { data: &payload, reads: 0 });
+        read(BufReader::new(BufReader::new(&mut double))).await;
+        read(BufReader::with_capacity(IO_BUFFER_SIZE, &mut single)).await;
+        let (double, single) = (double.reads, single.reads);
+
+        // One read per buffer, plus the empty one that finds the end.
+        let buffers = payload.len().div_ceil(IO_BUFFER_SIZE);
+        assert!(single <= buffers.saturating_add(1), "{single} reads for {buffers} buffers");
+        assert!(single < double, "{single} reads buffered once, {double} double-buffered");
+    }
 }
@@ -0,0 +1,155 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Step {
+    Read,
+    Compression,
+    Padding,
+    Encryption,
+    Encoding,
+    Write,
+}
+
+/// Per-step timers and byte counters, shared by the reader, every worker, and the writer. Times are
+/// summed across threads, so with several workers a step can add up to more than the wall time.
+#[derive(Default)]
+pub(crate) struct Stats {
+    read: AtomicU64,
+    compression: AtomicU64,
+    padding: AtomicU64,
+    encryption: AtomicU64,
+    encoding: AtomicU64,
+    write: AtomicU64,
+    pipeline: AtomicU64,
+    plaintext_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+    sealed_bytes: AtomicU64,
+    encoded_bytes: AtomicU64,
+}
+
+impl Stats {
+    pub(crate) fn time<T>(&self, step: Step, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.record(step, started.elapsed());
+
+        result
+    }
+
+    pub(crate) fn record(&self, step: Step, elapsed: Duration) {
+        self.timer(step).fetch_add(nanos(elapsed), Ordering::Relaxed);
+    }
+
+    #[cfg(any(feature = "cli", test))]
+    pub(crate) fn record_pipeline(&self, elapsed: Duration) {
+        self.pipeline.fetch_add(nanos(elapsed), Ordering::Relaxed);
+    }
+
+    /// Counts one chunk's size at each step: before compression, after compression, after the
+    /// cipher layers, and after Reed-Solomon encoding.
+    pub(crate) fn count(&self, plaintext: usize, compressed: usize, sealed: usize, encoded: usize) {
+        for (counter, bytes) in [(&self.plaintext_bytes, plaintext), (&self.compressed_bytes, compressed), (&self.sealed_bytes, sealed), (&self.encoded_bytes, encoded)] {
+            counter.fetch_add(u64::try_from(bytes).unwrap_or(u64::MAX), Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn summary(&self, elapsed: Duration) -> Throughput {
+        let plaintext = self.plaintext_bytes.load(Ordering::Relaxed);
+        let pipeline = self.pipeline.load(Ordering::Relaxed);
+        let sealed = self.sealed_bytes.load(Ordering::Relaxed);
+
+        Throughput {
+            elapsed_ms: millis(nanos(elapsed)),
+            mb_per_s: ratio(plaintext.saturating_mul(1_000), pipeline),
+            compression_ratio: ratio(self.compressed_bytes.load(Ordering::Relaxed), plaintext),
+            rs_overhead: ratio(self.encoded_bytes.load(Ordering::Relaxed).saturating_sub(sealed), sealed),
+            stages: StageTimes {
+                read_ms: self.millis(Step::Read),
+                compression_ms: self.millis(Step::Compression),
+                padding_ms: self.millis(Step::Padding),
+                encryption_ms: self.millis(Step::Encryption),
+                encoding_ms: self.millis(Step::Encoding),
+                write_ms: self.millis(Step::Write),
+            },
+        }
+    }
+
+    fn timer(&self, step: Step) -> &AtomicU64 {
+        match step {
+            Step::Read => &self.read,
+            Step::Compression => &self.compression,
+            Step::Padding => &self.padding,
+            Step::Encryption => &self.encryption,
+            Step::Encoding => &self.encoding,
+            Step::Write => &self.write,
+        }
+    }
+
+    fn millis(&self, step: Step) -> u64 {
+        millis(self.timer(step).load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Clone, Copy, Serialize)]
+pub(crate) struct Throughput {
+    pub(crate) elapsed_ms: u64,
+    /// Plaintext megabytes (10^6 bytes) per second of pipeline time, excluding key derivation and
+    /// prompts.
+    pub(crate) mb_per_s: f64,
+    /// Compressed size over plaintext size; below 1.0 means compression helped.
+    pub(crate) compression_ratio: f64,
+    /// Reed-Solomon parity and checksums as a fraction of the ciphertext they protect.
+    pub(crate) rs_overhead: f64,
+    pub(crate) stages: StageTimes,
+}
+
+#[derive(Clone, Copy, Serialize)]
+pub(crate) struct StageTimes {
+    pub(crate) read_ms: u64,
+    pub(crate) compression_ms: u64,
+    pub(crate) padding_ms: u64,
+    pub(crate) encryption_ms: u64,
+    pub(crate) encoding_ms: u64,
+    pub(crate) write_ms: u64,
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+fn millis(nanos: u64) -> u64 {
+    nanos.checked_div(1_000_000).unwrap_or_default()
+}
+
+#[expect(clippy::cast_precision_loss, reason = "byte counts and nanoseconds only feed a human-readable ratio")]
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        return 0.0;
+    }
+
+    numerator as f64 / denominator as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_reports_ratios() {
+        let stats = Stats::default();
+        stats.count(1_000, 250, 300, 1_050);
+        stats.record_pipeline(Duration::from_millis(1));
+        stats.record(Step::Compression, Duration::from_millis(3));
+
+        let summary = stats.summary(Duration::from_millis(5));
+
+        assert_eq!(summary.elapsed_ms, 5);
+        assert_eq!(summary.stages.compression_ms, 3);
+        assert!((summary.compression_ratio - 0.25).abs() < f64::EPSILON);
+        assert!((summary.rs_overhead - 2.5).abs() < f64::EPSILON);
+        assert!((summary.mb_per_s - 1.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,257 @@
+use anyhow::{Context, Result};
+#[cfg(feature = "cli")]
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use crate::config::{CHUNK_INDEX_MAGIC, MAX_OUTPUT_OVERRUN, ORIGINAL_COUNT, PAYLOAD_MAC_LEN, PAYLOAD_MAC_MARKER, RECOVERY_COUNT};
+#[cfg(feature = "cli")]
+use crate::config::{CHUNK_INDEX_MARKER, MAX_CHUNK_INDEX_LEN};
+use crate::encoding::Encoding;
+use crate::secret::Secret;
+
+pub(crate) const TRAILER_BODY_LEN: usize = size_of::<u64>() + PAYLOAD_MAC_LEN;
+
+/// The last bytes of an indexed file: the encoded index length and a magic, so the footer can be
+/// found from the end.
+pub(crate) const INDEX_TAIL_LEN: usize = size_of::<u32>() + CHUNK_INDEX_MAGIC.len();
+
+/// How many of the `size` bytes chunk `index` decrypted to lie within the `expected` bytes the
+/// header declares, given `written` bytes before it. Fails once the output would run more than
+/// `MAX_OUTPUT_OVERRUN` past the end.
+pub(crate) fn within_declared(index: u64, written: u64, size: u64, expected: u64) -> Result<u64> {
+    let overrun = written.saturating_add(size).saturating_sub(expected);
+    if overrun > MAX_OUTPUT_OVERRUN {
+        anyhow::bail!("chunk {index} decrypts past the {expected} bytes the header declares");
+    }
+
+    Ok(size.saturating_sub(overrun))
+}
+
+/// Target size for the finished file. The writer reaches it by appending a padding marker and
+/// random filler after the last record; decryption discards everything after the marker.
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
+pub enum PadSize {
+    #[default]
+    None,
+    PowerOfTwo,
+    Multiple(u64),
+}
+
+impl PadSize {
+    pub(crate) fn is_enabled(self) -> bool {
+        self != Self::None
+    }
+
+    /// Filler bytes to write after the marker so a file that is `len` bytes long before the marker
+    /// hits the target.
+    pub(crate) fn filler(self, len: u64) -> u64 {
+        let marked = len.saturating_add(size_of::<u32>() as u64);
+        let target = match self {
+            Self::None => return 0,
+            Self::PowerOfTwo => marked.checked_next_power_of_two().unwrap_or(marked),
+            Self::Multiple(step) => marked.div_ceil(step.max(1)).saturating_mul(step.max(1)),
+        };
+
+        target.saturating_sub(marked)
+    }
+}
+
+pub(crate) struct PayloadMac {
+    hasher: blake3::Hasher,
+    chunks: u64,
+}
+
+impl PayloadMac {
+    pub(crate) fn new(key: &Secret) -> Result<Self> {
+        let key: &[u8; blake3::KEY_LEN] = key.expose_secret().try_into().context("payload key must be 32 bytes")?;
+
+        Ok(Self { hasher: blake3::Hasher::new_keyed(key), chunks: 0 })
+    }
+
+    pub(crate) fn update(&mut self, index: u64, chunk: &[u8]) -> Result<()> {
+        let chunk_len = u64::try_from(chunk.len()).context("chunk length overflow")?;
+        self.hasher.update(&index.to_le_bytes());
+        self.hasher.update(&chunk_len.to_le_bytes());
+        self.hasher.update(chunk);
+        self.chunks = self.chunks.saturating_add(1);
+
+        Ok(())
+    }
+
+    /// Feeds a chunk record as it was read back. A record with shards that fail their checksum is
+    /// rebuilt and encoded again first, so damage Reed-Solomon can repair doesn't also fail
+    /// authentication; one that can't be rebuilt is fed as it is.
+    pub(crate) fn update_record(&mut self, index: u64, record: &[u8]) -> Result<()> {
+        let encoding = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT)?;
+        if encoding.intact_shards(record).is_ok_and(|intact| intact == encoding.total_count()) {
+            return self.update(index, record);
+        }
+
+        match encoding.decode(record).and_then(|sealed| encoding.encode(&sealed)) {
+            Ok(canonical) => self.update(index, &canonical),
+            Err(_) => self.update(index, record),
+        }
+    }
+
+    pub(crate) fn trailer(&self) -> Vec<u8> {
+        let mut trailer = Vec::with_capacity(size_of::<u32>().saturating_add(TRAILER_BODY_LEN));
+        trailer.extend_from_slice(&PAYLOAD_MAC_MARKER.to_le_bytes());
+        trailer.extend_from_slice(&self.chunks.to_le_bytes());
+        trailer.extend_from_slice(self.tag().as_bytes());
+        trailer
+    }
+
+    pub(crate) fn verify(&self, body: &[u8; TRAILER_BODY_LEN]) -> Result<()> {
+        let (chunks, tag) = body.split_at(size_of::<u64>());
+        let chunks = u64::from_le_bytes(chunks.try_into().context("invalid chunk count")?);
+        if chunks != self.chunks {
+            anyhow::bail!("payload authentication failed: expected {chunks} chunks, found {}", self.chunks);
+        }
+
+        if !bool::from(self.tag().as_bytes().as_slice().ct_eq(tag)) {
+            anyhow::bail!("payload authentication failed: chunks were modified, removed, or reordered");
+        }
+
+        Ok(())
+    }
+
+    fn tag(&self) -> blake3::Hash {
+        let mut hasher = self.hasher.clone();
+        hasher.update(&self.chunks.to_le_bytes());
+        hasher.finalize()
+    }
+}
+
+#[cfg(feature = "cli")]
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct IndexEntry {
+    /// Where the chunk's length prefix starts, counted from the end of the header.
+    pub(crate) offset: u64,
+    /// Where the chunk's first byte sits in the original.
+    pub(crate) plaintext_offset: u64,
+}
+
+/// Table of chunk positions, written as a footer behind everything else so it can be read from the
+/// end of the file without touching the payload. It is authenticated under its own key and
+/// Reed-Solomon encoded like a chunk.
+#[cfg(feature = "cli")]
+pub(crate) struct ChunkIndex {
+    key: Secret,
+    entries: Vec<IndexEntry>,
+}
+
+#[cfg(feature = "cli")]
+impl ChunkIndex {
+    pub(crate) fn new(key: &Secret) -> Self {
+        Self { key: Secret::new(key.expose_secret().to_vec()), entries: Vec::new() }
+    }
+
+    pub(crate) fn push(&mut self, offset: u64, plaintext_offset: u64) {
+        self.entries.push(IndexEntry { offset, plaintext_offset });
+    }
+
+    /// Marker, encoded table, and tail, ready to be appended to the file.
+    pub(crate) fn footer(&self) -> Result<Vec<u8>> {
+        let mut body = postcard::to_allocvec(&self.entries).context("failed to serialize chunk index")?;
+        let tag = Self::tag(&self.key, &body)?;
+        body.extend_from_slice(tag.as_bytes());
+
+        let encoded = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT)?.encode(&body).context("failed to encode chunk index")?;
+        let encoded_len = u32::try_from(encoded.len()).context("chunk index too large")?;
+
+        let mut footer = Vec::with_capacity(size_of::<u32>().saturating_add(encoded.len()).saturating_add(INDEX_TAIL_LEN));
+        footer.extend_from_slice(&CHUNK_INDEX_MARKER.to_le_bytes());
+        footer.extend_from_slice(&encoded);
+        footer.extend_from_slice(&encoded_len.to_le_bytes());
+        footer.extend_from_slice(&CHUNK_INDEX_MAGIC);
+        Ok(footer)
+    }
+
+    /// Length of the encoded table that precedes `tail`.
+    pub(crate) fn encoded_len(tail: &[u8; INDEX_TAIL_LEN]) -> Result<u32> {
+        let (length, magic) = tail.split_first_chunk::<{ size_of::<u32>() }>().context("invalid chunk index tail")?;
+        if magic != CHUNK_INDEX_MAGIC {
+            anyhow::bail!("chunk index is missing from the end of the file");
+        }
+
+        let length = u32::from_le_bytes(*length);
+        if length > MAX_CHUNK_INDEX_LEN {
+            anyhow::bail!("chunk index claims {length} bytes, more than an index can hold");
+        }
+
+        Ok(length)
+    }
+
+    /// Repairs and authenticates an encoded table.
+    pub(crate) fn open(key: &Secret, encoded: &[u8]) -> Result<Vec<IndexEntry>> {
+        let body = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT)?.decode(encoded).context("chunk index is damaged beyond repair")?;
+        let split = body.len().checked_sub(PAYLOAD_MAC_LEN).context("chunk index is too short")?;
+        let (entries, tag) = body.split_at_checked(split).context("chunk index is too short")?;
+
+        if !bool::from(Self::tag(key, entries)?.as_bytes().as_slice().ct_eq(tag)) {
+            anyhow::bail!("chunk index authentication failed");
+        }
+
+        postcard::from_bytes(entries).context("failed to parse chunk index")
+    }
+
+    fn tag(key: &Secret, entries: &[u8]) -> Result<blake3::Hash> {
+        let key: &[u8; blake3::KEY_LEN] = key.expose_secret().try_into().context("chunk index key must be 32 bytes")?;
+
+        Ok(blake3::keyed_hash(key, entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filler_reaches_the_target_size() {
+        assert_eq!(PadSize::None.filler(1_000), 0);
+        assert_eq!(PadSize::PowerOfTwo.filler(1_000), 1_024 - 1_004);
+        assert_eq!(PadSize::PowerOfTwo.filler(1_020), 0);
+        assert_eq!(PadSize::Multiple(4_096).filler(5_000), 8_192 - 5_004);
+    }
+
+    #[test]
+    fn output_is_held_to_the_declared_size() {
+        assert_eq!(within_declared(0, 0, 4_096, 10_000).unwrap(), 4_096);
+        assert_eq!(within_declared(2, 8_192, 1_900, 10_000).unwrap(), 1_808);
+        within_declared(2, 8_192, 4_096, 10_000).unwrap_err();
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn chunk_index_survives_damage_but_not_tampering() {
+        let key = Secret::new(vec![7u8; 32]);
+        let mut index = ChunkIndex::new(&key);
+        index.push(0, 0);
+        index.push(1_000, 4_096);
+
+        let footer = index.footer().unwrap();
+        let (rest, tail) = footer.split_last_chunk::<INDEX_TAIL_LEN>().unwrap();
+        let encoded_len = usize::try_from(ChunkIndex::encoded_len(tail).unwrap()).unwrap();
+        let mut encoded = rest[rest.len().saturating_sub(encoded_len)..].to_vec();
+
+        encoded[8] ^= 0xFF;
+        let entries = ChunkIndex::open(&key, &encoded).unwrap();
+        assert_eq!(entries.iter().map(|entry| (entry.offset, entry.plaintext_offset)).collect::<Vec<_>>(), [(0, 0), (1_000, 4_096)]);
+
+        assert!(ChunkIndex::open(&Secret::new(vec![8u8; 32]), &encoded).is_err());
+    }
+
+    #[test]
+    fn repairable_damage_still_authenticates() {
+        let key = Secret::new(vec![7u8; 32]);
+        let record = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT).unwrap().encode(&[3u8; 1_000]).unwrap();
+        let mut damaged = record.clone();
+        damaged[8] ^= 0xFF;
+
+        let (mut written, mut read) = (PayloadMac::new(&key).unwrap(), PayloadMac::new(&key).unwrap());
+        written.update(0, &record).unwrap();
+        read.update_record(0, &damaged).unwrap();
+        let trailer = written.trailer();
+        read.verify(trailer[size_of::<u32>()..].try_into().unwrap()).unwrap();
+    }
+}
@@ -1,14 +1,20 @@
-use strum::{Display, EnumIter, IntoEnumIterator, IntoStaticStr};
+#[cfg(feature = "cli")]
+use strum::IntoEnumIterator;
+use strum::{Display, EnumIter, IntoStaticStr};
 
+use crate::secure_temp::SecureBuffer;
+
+/// Whether a run encrypts or decrypts.
 #[non_exhaustive]
 #[derive(Display, Clone, Copy, Eq, PartialEq, EnumIter, IntoStaticStr)]
-pub(crate) enum Operation {
+pub enum Operation {
     #[strum(to_string = "Encrypt")]
     Encryption,
     #[strum(to_string = "Decrypt")]
     Decryption,
 }
 
+#[cfg(feature = "cli")]
 impl Operation {
     pub(crate) fn iter() -> impl Iterator<Item = Self> {
         <Self as IntoEnumIterator>::iter()
@@ -26,19 +32,28 @@ impl Operation {
     }
 }
 
-pub(super) struct Task {
-    pub(super) data: Vec<u8>,
-    pub(super) index: u64,
+pub(crate) struct Task {
+    pub(crate) data: SecureBuffer,
+    pub(crate) index: u64,
 }
 
-pub(super) struct TaskResult {
-    pub(super) index: u64,
-    pub(super) data: Vec<u8>,
-    pub(super) size: usize,
+#[cfg_attr(not(feature = "cli"), expect(dead_code, reason = "only the file pipeline reorders results and reads their sizes"))]
+pub(crate) struct TaskResult {
+    pub(crate) index: u64,
+    pub(crate) data: SecureBuffer,
+    pub(crate) size: usize,
+    pub(crate) input_len: usize,
+    /// The chunk didn't decrypt; the writer leaves zeros in its place.
+    pub(crate) lost: bool,
 }
 
 impl TaskResult {
-    pub(super) fn new(index: u64, data: Vec<u8>, size: usize) -> Self {
-        Self { index, data, size }
+    pub(crate) fn new(index: u64, data: SecureBuffer, size: usize, input_len: usize) -> Self {
+        Self { index, data, size, input_len, lost: false }
+    }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn lost(index: u64, input_len: usize) -> Self {
+        Self { index, data: SecureBuffer::unlocked(Vec::new()), size: 0, input_len, lost: true }
     }
 }
@@ -1,27 +1,117 @@
 use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::{Context, Result};
-use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::sync::mpsc::Receiver;
 
+use super::buffer::WindowProgress;
+use super::losses::Losses;
+use super::progress::{ProgressEvent, ProgressSink, Stage};
+use super::stats::{Stats, Step};
+use super::trailer::{ChunkIndex, PadSize, PayloadMac, within_declared};
 use super::types::{Operation, TaskResult};
-use crate::ui::Progress;
+use crate::cipher::{RandomSource, SystemRandom};
+use crate::config::{FILLER_BLOCK_LEN, PADDING_MARKER};
+use crate::files::FsyncPolicy;
 
 pub(super) struct Writer {
     index: u64,
     pending: VecDeque<Option<TaskResult>>,
     operation: Operation,
+    fsync: FsyncPolicy,
+    total: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    mac: Option<PayloadMac>,
+    window: WindowProgress,
+    stats: Option<Arc<Stats>>,
+    padding: PadSize,
+    offset: u64,
+    chunk_index: Option<ChunkIndex>,
+    hidden: Vec<u8>,
+    header_copy: Vec<u8>,
+    losses: Option<Arc<Losses>>,
+    chunk_size: u64,
+    plaintext: u64,
+    expected_size: Option<u64>,
 }
 
 impl Writer {
-    pub(super) fn new(operation: Operation) -> Self {
-        Self { index: 0, pending: VecDeque::new(), operation }
+    pub(super) fn new(operation: Operation, fsync: FsyncPolicy, total: u64, mac: Option<PayloadMac>, window: WindowProgress, stats: Option<Arc<Stats>>) -> Self {
+        Self {
+            index: 0,
+            pending: VecDeque::new(),
+            operation,
+            fsync,
+            total,
+            bytes_in: 0,
+            bytes_out: 0,
+            mac,
+            window,
+            stats,
+            padding: PadSize::None,
+            offset: 0,
+            chunk_index: None,
+            hidden: Vec::new(),
+            header_copy: Vec::new(),
+            losses: None,
+            chunk_size: 0,
+            plaintext: 0,
+            expected_size: None,
+        }
+    }
+
+    /// Pads the output to `padding`, counting the `offset` bytes written ahead of the pipeline.
+    pub(super) fn with_padding(mut self, padding: PadSize, offset: u64) -> Self {
+        self.padding = padding;
+        self.offset = offset;
+        self
+    }
+
+    /// Records where each chunk starts and appends the table as a footer.
+    pub(super) fn with_chunk_index(mut self, chunk_index: Option<ChunkIndex>) -> Self {
+        self.chunk_index = chunk_index;
+        self
+    }
+
+    /// Ends the padding filler with `hidden` instead of random bytes; it must fit in the filler the
+    /// padding needs.
+    pub(super) fn with_hidden(mut self, hidden: Vec<u8>) -> Self {
+        self.hidden = hidden;
+        self
     }
 
-    pub(super) async fn write_all<W: AsyncWrite + Unpin>(&mut self, output: W, mut receiver: Receiver<TaskResult>, progress: &Progress) -> Result<()> {
+    /// Ends the file with `header_copy`, after the chunk index.
+    pub(super) fn with_header_copy(mut self, header_copy: Vec<u8>) -> Self {
+        self.header_copy = header_copy;
+        self
+    }
+
+    /// Writes `chunk_size` zeros for each lost chunk, and zeros up to `total` if the payload ends
+    /// early, recording every gap in `losses`.
+    pub(super) fn with_losses(mut self, losses: Option<Arc<Losses>>, chunk_size: u64) -> Self {
+        self.losses = losses;
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Fails once decrypted output runs more than `MAX_OUTPUT_OVERRUN` past `expected_size`, and
+    /// drops whatever smaller excess the last chunk carries.
+    pub(super) fn with_expected_size(mut self, expected_size: Option<u64>) -> Self {
+        self.expected_size = expected_size;
+        self
+    }
+
+    /// Writes to `writer` as it is given. Encryption writes each record length on its own, so its
+    /// output should already be buffered, as every writer from `files` is.
+    pub(super) async fn write_all<W: AsyncWrite + Unpin>(&mut self, mut writer: W, mut receiver: Receiver<TaskResult>, progress: &dyn ProgressSink) -> Result<ProgressEvent> {
         self.index = 0;
         self.pending.clear();
-        let mut writer = BufWriter::new(output);
+        self.bytes_in = 0;
+        self.bytes_out = 0;
+        self.plaintext = 0;
 
         while let Some(result) = receiver.recv().await {
             let delta = result.index.checked_sub(self.index).context("chunk index behind writer")?;
@@ -37,23 +127,182 @@ impl Writer {
                 let Some(result) = slot.take() else { break };
                 self.pending.pop_front();
 
+                let started = Instant::now();
                 self.write_result(&mut writer, &result, progress).await?;
+                if let Some(stats) = &self.stats {
+                    stats.record(Step::Write, started.elapsed());
+                }
                 self.index = self.index.saturating_add(1);
+                self.window.advance(self.index);
+
+                if self.fsync == FsyncPolicy::Always {
+                    writer.flush().await.context("failed to sync chunk")?;
+                }
             }
         }
 
-        writer.flush().await.context("failed to flush")
+        if self.losses.is_some() && self.plaintext < self.total {
+            let gap = self.write_gap(&mut writer, u64::MAX).await?;
+            self.bytes_out = self.bytes_out.saturating_add(gap);
+        }
+
+        if let Some(mac) = &self.mac {
+            let trailer = mac.trailer();
+            writer.write_all(&trailer).await.context("failed to write payload authentication record")?;
+            self.bytes_out = self.bytes_out.saturating_add(u64::try_from(trailer.len()).context("size overflow")?);
+        }
+
+        let footer = self.chunk_index.as_ref().map(ChunkIndex::footer).transpose()?;
+        let footer_len = u64::try_from(footer.as_ref().map_or(0, Vec::len)).context("size overflow")?;
+        let copy_len = u64::try_from(self.header_copy.len()).context("size overflow")?;
+        if self.padding.is_enabled() {
+            self.write_padding(&mut writer, footer_len.saturating_add(copy_len)).await?;
+        } else if !self.hidden.is_empty() {
+            anyhow::bail!("a hidden payload needs padding to sit in");
+        }
+        if let Some(footer) = &footer {
+            writer.write_all(footer).await.context("failed to write chunk index")?;
+            self.bytes_out = self.bytes_out.saturating_add(footer_len);
+        }
+        if !self.header_copy.is_empty() {
+            writer.write_all(&self.header_copy).await.context("failed to write header copy")?;
+            self.bytes_out = self.bytes_out.saturating_add(copy_len);
+        }
+
+        writer.flush().await.context("failed to flush")?;
+
+        Ok(self.event(Stage::Finished, self.index, 0))
     }
 
-    async fn write_result<W: AsyncWrite + Unpin>(&self, writer: &mut W, result: &TaskResult, progress_bar: &Progress) -> Result<()> {
+    async fn write_result<W: AsyncWrite + Unpin>(&mut self, writer: &mut W, result: &TaskResult, progress: &dyn ProgressSink) -> Result<()> {
+        let mut written = result.data.len();
         if self.operation.is_encryption() {
             let data_len = u32::try_from(result.data.len()).context("chunk length overflow")?;
+            if let Some(chunk_index) = &mut self.chunk_index {
+                chunk_index.push(self.bytes_out, self.bytes_in);
+            }
             writer.write_all(&data_len.to_le_bytes()).await.context("failed to write chunk")?;
+            written = written.saturating_add(size_of::<u32>());
+            if let Some(mac) = &mut self.mac {
+                mac.update(result.index, &result.data)?;
+            }
+        }
+
+        let plaintext = if result.lost {
+            let gap = self.write_gap(writer, self.chunk_size).await?;
+            written = usize::try_from(gap).context("size overflow")?;
+            gap
+        } else {
+            let (data, size) = self.bounded(result)?;
+            writer.write_all(data).await.context("failed to write chunk")?;
+            if !self.operation.is_encryption() {
+                written = data.len();
+            }
+            self.plaintext = self.plaintext.saturating_add(size);
+            size
+        };
+
+        self.bytes_in = self.bytes_in.saturating_add(u64::try_from(result.input_len).context("size overflow")?);
+        self.bytes_out = self.bytes_out.saturating_add(u64::try_from(written).context("size overflow")?);
+        progress.on_event(&self.event(Stage::Chunk, result.index, plaintext));
+
+        Ok(())
+    }
+
+    /// The part of a decrypted chunk that fits within the expected size, and how much plaintext
+    /// that is.
+    fn bounded<'a>(&self, result: &'a TaskResult) -> Result<(&'a [u8], u64)> {
+        let size = u64::try_from(result.size).context("size overflow")?;
+        let Some(expected) = self.expected_size.filter(|_| !self.operation.is_encryption()) else {
+            return Ok((&result.data, size));
+        };
+
+        let kept = within_declared(result.index, self.plaintext, size, expected)?;
+        let data = result.data.get(..usize::try_from(kept).context("size overflow")?).context("chunk shorter than its size")?;
+        Ok((data, kept))
+    }
+
+    /// Writes zeros for up to `len` bytes of plaintext that couldn't be recovered, stopping at the
+    /// end of the file, and records where they went. Returns how many were written.
+    async fn write_gap<W: AsyncWrite + Unpin>(&mut self, writer: &mut W, len: u64) -> Result<u64> {
+        let losses = self.losses.as_ref().context("a chunk was lost without --keep-going")?;
+        let len = len.min(self.total.saturating_sub(self.plaintext));
+        let start = self.plaintext;
+        self.plaintext = start.saturating_add(len);
+        losses.record(start..self.plaintext);
+
+        let block = vec![0u8; FILLER_BLOCK_LEN];
+        let mut remaining = len;
+        while remaining > 0 {
+            let zeros = block.get(..usize::try_from(remaining).unwrap_or(usize::MAX).min(FILLER_BLOCK_LEN)).context("gap block out of range")?;
+            writer.write_all(zeros).await.context("failed to write lost chunk")?;
+            remaining = remaining.saturating_sub(u64::try_from(zeros.len()).context("size overflow")?);
         }
 
-        writer.write_all(&result.data).await.context("failed to write chunk")?;
-        progress_bar.add(u64::try_from(result.size).context("size overflow")?);
+        Ok(len)
+    }
+
+    /// Writes the padding record, leaving room for `trailing` bytes that follow it. A hidden
+    /// payload takes the end of the filler, so the file is the same size with or without one.
+    async fn write_padding<W: AsyncWrite + Unpin>(&mut self, writer: &mut W, trailing: u64) -> Result<()> {
+        let filler = self.padding.filler(self.offset.saturating_add(self.bytes_out).saturating_add(trailing));
+        let hidden_len = u64::try_from(self.hidden.len()).context("size overflow")?;
+        let Some(mut remaining) = filler.checked_sub(hidden_len) else {
+            anyhow::bail!("the hidden payload needs {hidden_len} bytes of padding but only {filler} are free; use a larger --pad-size");
+        };
+        writer.write_all(&PADDING_MARKER.to_le_bytes()).await.context("failed to write padding marker")?;
+        self.bytes_out = self.bytes_out.saturating_add(u64::try_from(size_of::<u32>()).context("size overflow")?).saturating_add(filler);
+
+        let mut block = vec![0u8; FILLER_BLOCK_LEN];
+        while remaining > 0 {
+            let len = usize::try_from(remaining).unwrap_or(usize::MAX).min(FILLER_BLOCK_LEN);
+            let filler = block.get_mut(..len).context("filler block out of range")?;
+            SystemRandom.fill(filler).context("failed to generate padding")?;
+            writer.write_all(filler).await.context("failed to write padding")?;
+            remaining = remaining.saturating_sub(u64::try_from(len).context("size overflow")?);
+        }
+        writer.write_all(&self.hidden).await.context("failed to write padding")?;
 
         Ok(())
     }
+
+    fn event(&self, stage: Stage, chunk: u64, chunk_plaintext: u64) -> ProgressEvent {
+        ProgressEvent { stage, chunk, chunk_plaintext, bytes_in: self.bytes_in, bytes_out: self.bytes_out, total: self.total }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::pipeline::NoProgress;
+    use crate::pipeline::buffer::reorder_window;
+    use crate::secure_temp::SecureBuffer;
+
+    async fn decrypt(expected_size: u64, chunks: &[usize]) -> Result<Vec<u8>> {
+        let (window, _gate) = reorder_window(u64::MAX);
+        let mut writer = Writer::new(Operation::Decryption, FsyncPolicy::Never, expected_size, None, window, None).with_expected_size(Some(expected_size));
+        let (sender, receiver) = mpsc::channel(chunks.len().max(1));
+        for (index, &len) in (0u64..).zip(chunks) {
+            sender.send(TaskResult::new(index, SecureBuffer::unlocked(vec![7u8; len]), len, len)).await.unwrap();
+        }
+        drop(sender);
+
+        let mut output = Vec::new();
+        writer.write_all(&mut output, receiver, &NoProgress).await?;
+        Ok(output)
+    }
+
+    #[tokio::test]
+    async fn output_is_trimmed_to_the_declared_size() {
+        assert_eq!(decrypt(1000, &[600, 400]).await.unwrap().len(), 1000);
+        assert_eq!(decrypt(1000, &[600, 410]).await.unwrap().len(), 1000);
+    }
+
+    #[tokio::test]
+    async fn output_far_past_the_declared_size_is_refused() {
+        let error = decrypt(1000, &[600, 600]).await.unwrap_err();
+        assert!(error.to_string().contains("past the 1000 bytes"));
+    }
 }
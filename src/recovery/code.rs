@@ -0,0 +1,106 @@
+use anyhow::{Context, Result};
+
+/// Crockford's base32, which leaves out I, L, O, and U so a code copied by hand can't mix up
+/// look-alike characters.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const PREFIX: &str = "SWXR1";
+const GROUP_LEN: usize = 5;
+const GROUPS_PER_LINE: usize = 8;
+
+/// Writes a header's recovery record as a printable code: a version tag, then base32 in groups of
+/// five, eight groups to a line, with a CRC-32 of the record encoded after it.
+pub(crate) fn encode(record: &[u8]) -> String {
+    let mut data = record.to_vec();
+    data.extend_from_slice(&crc32fast::hash(record).to_be_bytes());
+
+    let mut code = String::from(PREFIX);
+    for (index, group) in to_base32(&data).chunks(GROUP_LEN).enumerate() {
+        code.push(if index.is_multiple_of(GROUPS_PER_LINE) { '\n' } else { ' ' });
+        code.push_str(&String::from_utf8_lossy(group));
+    }
+    code.push('\n');
+
+    code
+}
+
+/// Reads a code back into the record it was made from. Case, spacing, and line breaks don't matter,
+/// and `O`, `I`, and `L` are read as the digits they look like.
+pub(crate) fn decode(code: &str) -> Result<Vec<u8>> {
+    let compact: String = code.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+    let body = compact
+        .get(..PREFIX.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(PREFIX))
+        .and_then(|_| compact.get(PREFIX.len()..))
+        .context("not a sweetbyte recovery code")?;
+
+    let data = from_base32(body.bytes()).context("recovery code has a character that isn't base32")?;
+    let (record, checksum) = data.split_last_chunk::<4>().context("recovery code is cut short")?;
+    if u32::from_be_bytes(*checksum) != crc32fast::hash(record) {
+        anyhow::bail!("recovery code checksum does not match; check it for typos");
+    }
+
+    Ok(record.to_vec())
+}
+
+fn to_base32(data: &[u8]) -> Vec<u8> {
+    let symbol = |value: u32| usize::try_from(value & 31).ok().and_then(|value| ALPHABET.get(value)).copied().unwrap_or(b'0');
+
+    let mut symbols = Vec::with_capacity(data.len().saturating_mul(8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for byte in data {
+        buffer = (buffer.wrapping_shl(8) | u32::from(*byte)) & 0xffff;
+        bits = bits.saturating_add(8);
+        while bits >= 5 {
+            bits = bits.saturating_sub(5);
+            symbols.push(symbol(buffer.wrapping_shr(bits)));
+        }
+    }
+    if bits > 0 {
+        symbols.push(symbol(buffer.wrapping_shl(5u32.saturating_sub(bits))));
+    }
+
+    symbols
+}
+
+fn from_base32(symbols: impl Iterator<Item = u8>) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for symbol in symbols {
+        let value = match symbol.to_ascii_uppercase() {
+            b'O' => 0,
+            b'I' | b'L' => 1,
+            other => ALPHABET.iter().position(|candidate| *candidate == other)?,
+        };
+        buffer = (buffer.wrapping_shl(5) | u32::try_from(value).ok()?) & 0xffff;
+        bits = bits.saturating_add(5);
+        if bits >= 8 {
+            bits = bits.saturating_sub(8);
+            decoded.push(u8::try_from(buffer.wrapping_shr(bits) & 0xff).ok()?);
+        }
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_roundtrip_and_catch_typos() {
+        let record: Vec<u8> = (0..=255).collect();
+        let code = encode(&record);
+        assert!(code.starts_with(PREFIX));
+        assert!(code.lines().skip(1).all(|line| line.split(' ').count() <= GROUPS_PER_LINE));
+
+        assert_eq!(decode(&code).unwrap(), record);
+        let (prefix, body) = code.split_at(PREFIX.len());
+        let copied = format!("{prefix}{}", body.to_lowercase().replace('0', "o").replace('1', "l"));
+        assert_eq!(decode(&copied).unwrap(), record);
+
+        let typo = code.replacen('A', "B", 1);
+        decode(&typo).unwrap_err();
+        decode("SWXR1").unwrap_err();
+        decode("not a code").unwrap_err();
+    }
+}
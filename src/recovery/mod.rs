@@ -0,0 +1,5 @@
+mod code;
+mod scan;
+
+pub(crate) use code::{decode, encode};
+pub(crate) use scan::{Candidate, scan};
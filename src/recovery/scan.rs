@@ -0,0 +1,162 @@
+use anyhow::{Context, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, SeekFrom};
+
+use crate::config::{HEADER_COPY_MAGIC, MAX_SECTION_SIZE};
+use crate::header::{COPY_TAIL_LEN, Deserializer, MAX_FRAMED_LEN};
+
+/// zstd's frame magic. Every header section is compressed, so it follows the frame prefix of every
+/// header.
+const SECTION_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+/// The section length and capacity in front of every header.
+const FRAME_PREFIX_LEN: usize = 2 * size_of::<u32>();
+const SIGNATURE_LEN: usize = FRAME_PREFIX_LEN + SECTION_MAGIC.len();
+const SCAN_BLOCK_LEN: usize = 4 * 1024 * 1024;
+
+/// A header found in the scanned stream.
+pub(crate) struct Candidate {
+    /// Where the header starts; its payload follows it directly.
+    pub(crate) offset: u64,
+    pub(crate) header: Deserializer,
+    /// Whether this is the copy `header-copy` leaves at the end of a file, with no payload behind
+    /// it.
+    pub(crate) copy: bool,
+}
+
+/// Scans `stream` from start to end for headers, wherever they sit: a frame prefix with a plausible
+/// length and capacity, then a zstd frame, that decodes in full. Nothing here needs a password, so
+/// every `.swx` file whose header survives is found, whether or not it can be opened.
+pub(crate) async fn scan<R: AsyncRead + AsyncSeek + Unpin>(stream: &mut R) -> Result<Vec<Candidate>> {
+    let offsets = signatures(stream).await?;
+
+    let mut found = Vec::new();
+    let mut covered = 0u64;
+    for offset in offsets {
+        if offset < covered {
+            continue;
+        }
+        let Some((header, len)) = parse_at(stream, offset).await? else {
+            continue;
+        };
+        let end = offset.saturating_add(u64::try_from(len).context("header length overflow")?);
+        let copy = header.copied() && is_copy(stream, end, len).await?;
+
+        covered = end;
+        found.push(Candidate { offset, header, copy });
+    }
+
+    Ok(found)
+}
+
+/// Every offset whose bytes look like the start of a header frame, read a block at a time so a disk
+/// image never has to fit in memory.
+async fn signatures<R: AsyncRead + AsyncSeek + Unpin>(stream: &mut R) -> Result<Vec<u64>> {
+    stream.rewind().await.context("failed to rewind input")?;
+
+    let mut offsets = Vec::new();
+    let mut window: Vec<u8> = Vec::with_capacity(SCAN_BLOCK_LEN.saturating_add(SIGNATURE_LEN));
+    let mut window_start = 0u64;
+    loop {
+        let kept = window.len();
+        window.resize(kept.saturating_add(SCAN_BLOCK_LEN), 0);
+        let read = stream.read(window.get_mut(kept..).unwrap_or_default()).await.context("failed to read input")?;
+        window.truncate(kept.saturating_add(read));
+
+        for (position, candidate) in window.windows(SIGNATURE_LEN).enumerate() {
+            if looks_like_header(candidate) {
+                offsets.push(window_start.saturating_add(u64::try_from(position).context("offset overflow")?));
+            }
+        }
+        if read == 0 {
+            return Ok(offsets);
+        }
+
+        // Keep the bytes a signature could still start in, since its rest arrives with the next block.
+        let keep_from = window.len().saturating_sub(SIGNATURE_LEN.saturating_sub(1));
+        window_start = window_start.saturating_add(u64::try_from(keep_from).context("offset overflow")?);
+        window.drain(..keep_from);
+    }
+}
+
+fn looks_like_header(bytes: &[u8]) -> bool {
+    let Some((length, rest)) = bytes.split_first_chunk::<4>() else {
+        return false;
+    };
+    let Some((capacity, magic)) = rest.split_first_chunk::<4>() else {
+        return false;
+    };
+    let (length, capacity) = (u32::from_le_bytes(*length), u32::from_le_bytes(*capacity));
+
+    length > 0 && length <= capacity && capacity <= MAX_SECTION_SIZE && magic == SECTION_MAGIC
+}
+
+/// The header starting at `offset` and how many bytes it takes, or `None` when what's there doesn't
+/// decode.
+async fn parse_at<R: AsyncRead + AsyncSeek + Unpin>(stream: &mut R, offset: u64) -> Result<Option<(Deserializer, usize)>> {
+    stream.seek(SeekFrom::Start(offset)).await.context("failed to seek in input")?;
+    let mut frame = Vec::with_capacity(MAX_FRAMED_LEN);
+    (&mut *stream).take(MAX_FRAMED_LEN as u64).read_to_end(&mut frame).await.context("failed to read input")?;
+
+    Ok(Deserializer::from_bytes(&frame).ok())
+}
+
+/// Whether the `len`-byte header ending at `end` is followed by a header copy's tail, which makes
+/// it the copy.
+async fn is_copy<R: AsyncRead + AsyncSeek + Unpin>(stream: &mut R, end: u64, len: usize) -> Result<bool> {
+    stream.seek(SeekFrom::Start(end)).await.context("failed to seek in input")?;
+    let mut tail = [0u8; COPY_TAIL_LEN];
+    if stream.read_exact(&mut tail).await.is_err() {
+        return Ok(false);
+    }
+
+    let (copy_len, magic) = tail.split_at(size_of::<u32>());
+    Ok(u32::from_le_bytes(copy_len.try_into().context("invalid header copy tail")?) == u32::try_from(len).context("header length overflow")? && magic == HEADER_COPY_MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::cipher::{CipherMode, KdfParams, KeyDeriver, KeySlot, RandomSource, SeededRandom};
+    use crate::config::{ARGON2_SALT_LEN, KEY_LEN, MASTER_KEY_LEN, NONCE_PREFIX_LEN};
+    use crate::files::Attributes;
+    use crate::header::Serializer;
+    use crate::secret::Secret;
+
+    fn header(name: &str, rng: &SeededRandom) -> Vec<u8> {
+        let salt = KeyDeriver::generate_salt(rng, ARGON2_SALT_LEN).unwrap();
+        let master_key = Secret::new(vec![5u8; MASTER_KEY_LEN]);
+        let keys = KeyDeriver::expand(&master_key, &salt).unwrap();
+        let slot = KeySlot::wrap(&Secret::new(vec![9u8; KEY_LEN]), &master_key, rng).unwrap();
+
+        Serializer::new(name, 1, vec![0u8; blake3::OUT_LEN], Attributes::default(), CipherMode::default(), vec![2u8; NONCE_PREFIX_LEN], &KdfParams::default())
+            .unwrap()
+            .with_header_copy(true)
+            .serialize(salt.expose_secret(), vec![slot], &keys, rng)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn headers_are_found_anywhere_in_a_stream() {
+        let rng = SeededRandom::new(8);
+        let (first, second) = (header("a.txt", &rng), header("b.txt", &rng));
+
+        // Enough noise between them that the second one's signature straddles two scan blocks.
+        let mut noise = vec![0u8; SCAN_BLOCK_LEN];
+        rng.fill(&mut noise).unwrap();
+        let mut image = noise[..1000].to_vec();
+        image.extend_from_slice(&first);
+        let gap = SCAN_BLOCK_LEN - 5 - image.len();
+        image.extend_from_slice(&noise[..gap]);
+        let second_at = image.len();
+        image.extend_from_slice(&second);
+        image.extend_from_slice(&crate::header::trailing_copy(&second).unwrap());
+        image.extend_from_slice(&noise[..300]);
+
+        let found = scan(&mut Cursor::new(&image)).await.unwrap();
+        let offsets: Vec<(u64, bool)> = found.iter().map(|candidate| (candidate.offset, candidate.copy)).collect();
+        let copy_at = (second_at + second.len()) as u64;
+        assert_eq!(offsets, [(1000, false), (second_at as u64, false), (copy_at, true)]);
+        assert_eq!(found[1].header.encoded_len(), second.len());
+    }
+}
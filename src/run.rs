@@ -0,0 +1,885 @@
+//! The command line behind the `sweetbyte-rs` binary.
+
+use std::cell::RefCell;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
+use tokio_util::sync::CancellationToken;
+use zeroize::Zeroizing;
+
+use crate::app::{Destination, Hidden, Options};
+use crate::cipher::{KdfSession, RandomSource, SecondFactor, SeededRandom, Share, SystemRandom, Threshold, sss};
+use crate::cli::{CalibrateArgs, Cli, Command, DecryptArgs, Docs, EncryptArgs, EncryptTextArgs, FuzzCorruptArgs, MigrateArgs, WatchArgs};
+use crate::config::{KEY_LEN, PASSWORD_LEN, Settings};
+use crate::error::SweetByteError;
+use crate::files::{Discover, Files};
+use crate::manifest::Manifest;
+use crate::pipeline::Operation;
+use crate::secret::Secret;
+use crate::ui::{
+    BatchProgress, CalibrateReport, CheckReport, ChunksReport, CorruptReport, HistoryStore, Input, KeyReport, ManifestReport, OutputFormat, PasswordCache, Progress, ProgressStyle, RecoverReport,
+    Report, RunEntry, RunReport, SelftestReport, Status, TextReport, WatchReport,
+};
+
+/// The exit status of a `decrypt --keep-going` that wrote the file with some of it lost.
+const PARTIAL_RECOVERY_EXIT: u8 = 3;
+
+/// Parses the arguments and runs the command they name until it finishes or is interrupted.
+pub async fn main() -> Result<ExitCode> {
+    CompleteEnv::with_factory(Cli::command).var(crate::cli::COMPLETE_VAR).complete();
+    let cli = Cli::parse();
+    crate::files::temp::install_panic_hook();
+    let cancel = CancellationToken::new();
+
+    let result = tokio::select! {
+        result = run(&cli, &cancel) => result,
+        signal = interrupted(&cancel) => match signal {
+            Ok(()) => {
+                crate::files::temp::cleanup();
+                Err(SweetByteError::Cancelled).context("operation interrupted")
+            }
+            Err(error) => Err(error).context("failed to listen for interrupt"),
+        },
+    };
+    if let Err(error) = &result {
+        crate::ui::display::failure(error, cli.output_format)?;
+    }
+
+    match result {
+        Err(error) if matches!(SweetByteError::classify(&error), SweetByteError::PartialRecovery { .. }) => {
+            eprintln!("Error: {error:?}");
+            Ok(ExitCode::from(PARTIAL_RECOVERY_EXIT))
+        }
+        result => result.map(|()| ExitCode::SUCCESS),
+    }
+}
+
+/// Cancels on the first Ctrl-C, unless the abort key already has, and resolves on the next.
+async fn interrupted(cancel: &CancellationToken) -> std::io::Result<()> {
+    tokio::select! {
+        signal = tokio::signal::ctrl_c() => {
+            signal?;
+            cancel.cancel();
+        }
+        () = cancel.cancelled() => {}
+    }
+    tokio::signal::ctrl_c().await
+}
+
+async fn run(cli: &Cli, cancel: &CancellationToken) -> Result<()> {
+    let mut options = Options::new(cli.settings()?);
+    options.cancel = cancel.clone();
+    options.allow_weak = cli.allow_weak;
+    let terminal = cli.interactive();
+    if cli.output_format == OutputFormat::Json {
+        options.settings.progress = ProgressStyle::Hidden;
+    } else if !terminal && matches!(options.settings.progress, ProgressStyle::Bar) {
+        options.settings.progress = ProgressStyle::Plain;
+    }
+
+    let mut input = Input::new(PASSWORD_LEN, true).with_terminal(terminal).with_assume_yes(cli.yes);
+    if let Some(path) = &cli.password_file {
+        input = input.with_password_file(path)?;
+    }
+    let format = cli.output_format;
+
+    match &cli.command {
+        Some(Command::Encrypt(args)) => {
+            let source = Files::new(&args.input);
+            if source.is_stdin() {
+                check_stdin(args, cli.password_file.as_deref())?;
+            }
+            let target = match &args.output {
+                Some(output) => Files::new(output),
+                None if args.hide_name => Files::new(source.hidden_output_path(&SystemRandom)?),
+                None => Files::new(source.output_path(Operation::Encryption)),
+            };
+            if args.split_key.is_some() && args.share_dir.is_none() && format == OutputFormat::Json {
+                anyhow::bail!("--split-key with --output-format json needs --share-dir to put the shares in");
+            }
+            let (share_key, shares) = split_key(args.split_key)?;
+            let share_target = target.path().to_path_buf();
+            let options = Options {
+                cipher: args.cipher.unwrap_or(options.cipher),
+                split_size: args.split_size,
+                chunk_store: args.chunk_store.clone(),
+                manifest: args.emit_manifest.clone(),
+                if_changed: args.if_changed,
+                tar: args.tar,
+                pad_size: args.pad_size.unwrap_or_default(),
+                convergent: args.convergent,
+                preflight: !args.no_preflight,
+                paranoid: args.paranoid,
+                verify_after: args.verify_after,
+                second_factor: second_factor(&input, cli.totp_secret.as_deref())?,
+                hidden: hidden(&input, &source, args.hidden.as_deref())?,
+                carrier: args.carrier.clone(),
+                share_key,
+                ..options
+            };
+            process(&input, Operation::Encryption, &source, &Destination::File(target), args.passwords, &options, format).await?;
+            if !shares.is_empty() {
+                save_shares(&shares, &share_target, args.share_dir.as_deref())?;
+            }
+            if args.delete_source {
+                delete_source(&source, options.settings.shred_passes, format).await?;
+            }
+            Ok(())
+        }
+        Some(Command::Decrypt(args)) => {
+            let input = match key_shares(&args.key_share)? {
+                Some(key) => input.with_secret(key),
+                None => input,
+            };
+            let source = Files::new(&args.input);
+            let destination = if args.output.as_deref() == Some(Path::new("-")) {
+                check_stdout(args, format)?;
+                Destination::Stdout
+            } else if args.untar {
+                Destination::Extract(args.output.clone().unwrap_or_else(|| source.output_path(Operation::Decryption)))
+            } else if args.restore_name || (args.output.is_none() && source.has_hidden_name()) {
+                let dir = args.output_dir.clone().or_else(|| source.path().parent().map(Path::to_path_buf)).unwrap_or_default();
+                Destination::Restore(dir)
+            } else {
+                Destination::File(Files::new(args.output.clone().unwrap_or_else(|| source.output_path(Operation::Decryption))))
+            };
+            let options = Options {
+                preserve_attrs: args.preserve_attrs,
+                preserve_special_bits: args.preserve_special_bits,
+                range: args.range.clone(),
+                keep_going: args.keep_going,
+                preflight: !args.no_preflight,
+                second_factor: second_factor(&input, cli.totp_secret.as_deref())?,
+                recovery: args.recovery_code.as_deref().map(recovery_code).transpose()?,
+                ..options
+            };
+            process(&input, Operation::Decryption, &source, &destination, 1, &options, format).await?;
+            if args.delete_source {
+                delete_source(&source, options.settings.shred_passes, format).await?;
+            }
+            Ok(())
+        }
+        Some(Command::EncryptText(args)) => {
+            let text = message(args.text.as_deref(), cli.password_file.as_deref())?;
+            encrypt_text(&input, &text, args, &options, format)
+        }
+        Some(Command::DecryptText(args)) => {
+            let text = message(args.text.as_deref(), cli.password_file.as_deref())?;
+            decrypt_text(&input, &text, format)
+        }
+        Some(Command::CheckPassword(args)) => {
+            let options = Options { second_factor: second_factor(&input, cli.totp_secret.as_deref())?, ..options };
+            check_password(&input, &Files::new(&args.input), &options, format).await
+        }
+        Some(Command::ExportRecovery(args)) => export_recovery(&Files::new(&args.input), format).await,
+        Some(Command::AddKey(args)) => add_key(&input, &Files::new(&args.input), &options, format).await,
+        Some(Command::RemoveKey(args)) => remove_key(&input, &Files::new(&args.input), &options, format).await,
+        Some(Command::Chunks(args)) => chunks(&input, &Files::new(&args.input), args.decrypt, &options, format).await,
+        Some(Command::Recover(args)) => recover(&input, &Files::new(&args.input), args.output_dir.as_deref(), &options, format).await,
+        #[cfg(feature = "fuse")]
+        Some(Command::Mount(args)) => mount(&input, &Files::new(&args.input), &args.mountpoint, args.cache_size, &options).await,
+        Some(Command::Watch(args)) => watch(&input, args, options, format).await,
+        Some(Command::Migrate(args)) => {
+            let options = Options { cipher: args.cipher.unwrap_or(options.cipher), second_factor: second_factor(&input, cli.totp_secret.as_deref())?, drop_extras: args.drop_extras, ..options };
+            migrate(&input, args, &options, format).await
+        }
+        Some(Command::VerifyManifest(args)) => verify_manifest(&args.input, &options, format).await,
+        Some(Command::Selftest) => selftest(format),
+        Some(Command::Calibrate(args)) => calibrate(args, cli.config_path(), format),
+        Some(Command::Completions(args)) => crate::cli::completions(args.shell, &mut std::io::stdout()),
+        Some(Command::Docs(Docs::Man)) => crate::cli::man(&mut std::io::stdout()),
+        Some(Command::Docs(Docs::Formats)) => {
+            print!("{}", crate::spec::format()?);
+            Ok(())
+        }
+        Some(Command::FuzzCorrupt(args)) => fuzz_corrupt(&input, args, &options, format).await,
+        Some(Command::GenVectors(args)) => {
+            let generated = crate::testvectors::generate(&args.output).await?;
+            crate::ui::display::generated(generated, format)
+        }
+        None => interactive(&input, options, cli.config_path()).await,
+    }
+}
+
+async fn interactive(input: &Input, mut options: Options, config: Option<&Path>) -> Result<()> {
+    crate::ui::display::clear()?;
+    crate::ui::display::banner()?;
+
+    let operation = input.operation_mode()?;
+    let output_dir = session_options(input, operation, &mut options, config)?;
+    let history = HistoryStore::open(options.settings.history, crate::config::layer::config_dir(), input)?;
+    let (recent_dirs, recent_files) = history.as_ref().map(|(_, history)| (history.directories(), history.files().to_vec())).unwrap_or_default();
+
+    let root = input.directory(&recent_dirs)?;
+    let files: Vec<Files> = Discover::new(root.to_string_lossy(), operation, &options.settings.exclude).run().into_iter().map(Files::new).collect();
+    if files.is_empty() {
+        anyhow::bail!("no files available for processing");
+    }
+
+    crate::ui::display::files(&files).await?;
+
+    let sources: Vec<Files> = input.files(&files, &recent_files)?.into_iter().map(Files::new).collect();
+    let settings = options.settings.clone();
+    let hide_name = operation == Operation::Encryption && input.hide_name()?;
+    let shred_passes = options.settings.shred_passes;
+    let processed = match sources.as_slice() {
+        [source] => {
+            process(input, operation, source, &destination(source, operation, hide_name, output_dir.as_deref())?, 1, &options, OutputFormat::Text).await?;
+            vec![source]
+        }
+        _ => batch(input, operation, &sources, hide_name, output_dir.as_deref(), options).await?,
+    };
+
+    if let Some((store, mut history)) = history {
+        history.record(&processed);
+        store.save(&history, &settings).context("failed to save history")?;
+    }
+
+    for source in &processed {
+        if input.delete(source, operation)? {
+            delete_source(source, shred_passes, OutputFormat::Text).await?;
+        }
+    }
+
+    if processed.len() < sources.len() {
+        anyhow::bail!("{} of {} files failed", sources.len().saturating_sub(processed.len()), sources.len());
+    }
+
+    crate::ui::display::exit()
+}
+
+/// Reads the `--totp-secret` file and checks it against the code the authenticator shows, so a
+/// wrong or stale secret is caught before anything is encrypted to it.
+fn second_factor(input: &Input, path: Option<&Path>) -> Result<Option<SecondFactor>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+
+    let encoded = Zeroizing::new(std::fs::read_to_string(path).with_context(|| format!("failed to read TOTP secret {}", path.display()))?);
+    let factor = SecondFactor::from_base32(&encoded)?;
+    if !factor.verify_now(&input.totp_code()?)? {
+        anyhow::bail!("TOTP code does not match the secret; check the secret file and the system clock");
+    }
+
+    Ok(Some(factor))
+}
+
+/// Asks for the password of the file `--hidden` names. It's asked for ahead of the decoy's, and
+/// must differ from it.
+fn hidden(input: &Input, source: &Files, path: Option<&Path>) -> Result<Option<Hidden>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    if source.path().is_dir() {
+        anyhow::bail!("--hidden needs a single file to use as the decoy");
+    }
+
+    let secret = input.new_password("Enter the hidden file's password")?;
+
+    Ok(Some(Hidden { source: Files::new(path), secret }))
+}
+
+/// A random key for the `--split-key` slot, and the shares it's split into.
+fn split_key(threshold: Option<Threshold>) -> Result<(Option<Secret>, Vec<Share>)> {
+    let Some(threshold) = threshold else {
+        return Ok((None, Vec::new()));
+    };
+
+    let mut key = vec![0u8; KEY_LEN];
+    SystemRandom.fill(&mut key).context("failed to generate share key")?;
+    let key = Secret::new(key);
+    let shares = sss::split(&key, threshold, &SystemRandom)?;
+
+    Ok((Some(key), shares))
+}
+
+/// Writes each share to `<output>.share-<n>` in `dir`, or shows them when there is no directory.
+fn save_shares(shares: &[Share], target: &Path, dir: Option<&Path>) -> Result<()> {
+    let encoded: Vec<String> = shares.iter().map(Share::encode).collect();
+    let Some(dir) = dir else {
+        return crate::ui::display::shares(&encoded);
+    };
+
+    let name = target.file_name().context("output has no file name")?.to_string_lossy();
+    for (number, share) in (1u32..).zip(&encoded) {
+        let path = dir.join(format!("{name}.share-{number}"));
+        std::fs::write(&path, format!("{share}\n")).with_context(|| format!("failed to write key share {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Rebuilds the key `--key-share` values were split from; each is a share itself or a file holding
+/// one.
+fn key_shares(values: &[String]) -> Result<Option<Secret>> {
+    if values.is_empty() {
+        return Ok(None);
+    }
+
+    let shares = values
+        .iter()
+        .map(|value| {
+            if !Path::new(value).is_file() {
+                return Share::decode(value);
+            }
+            let text = Zeroizing::new(std::fs::read_to_string(value).with_context(|| format!("failed to read key share {value}"))?);
+            Share::decode(&text)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    sss::combine(&shares).map(Some)
+}
+
+/// Deletes a source whose output has been written and verified, overwriting it first when shredding
+/// is configured.
+async fn delete_source(source: &Files, shred_passes: u32, format: OutputFormat) -> Result<()> {
+    source.delete(shred_passes).await.context("failed to delete source file")?;
+    if format == OutputFormat::Text {
+        crate::ui::display::deleted(source)?;
+    }
+
+    Ok(())
+}
+
+fn destination(source: &Files, operation: Operation, hide_name: bool, output_dir: Option<&Path>) -> Result<Destination> {
+    if operation == Operation::Decryption && source.has_hidden_name() {
+        return Ok(Destination::Restore(output_dir.or_else(|| source.path().parent()).map(Path::to_path_buf).unwrap_or_default()));
+    }
+
+    let output = if hide_name { source.hidden_output_path(&SystemRandom)? } else { source.output_path(operation) };
+    match (output_dir, output.file_name()) {
+        (Some(dir), Some(name)) => Ok(Destination::File(Files::new(dir.join(name)))),
+        _ => Ok(Destination::File(Files::new(output))),
+    }
+}
+
+/// Processes several files under the cached passwords, with an overall bar above each file's own
+/// bar. A failed file is reported after the run instead of stopping it; only the files that
+/// succeeded are returned.
+async fn batch<'a>(input: &Input, operation: Operation, sources: &'a [Files], hide_name: bool, output_dir: Option<&Path>, options: Options) -> Result<Vec<&'a Files>> {
+    let mut jobs = Vec::with_capacity(sources.len());
+    let mut total = 0u64;
+    for source in sources {
+        ensure_input(source)?;
+        let destination = destination(source, operation, hide_name, output_dir)?;
+        if let Destination::File(target) = &destination
+            && (target.exists() || crate::files::spanning::is_spanned(target.path()))
+            && !input.overwrite(target)?
+        {
+            continue;
+        }
+
+        let size = source.size().await?;
+        total = total.saturating_add(size);
+        jobs.push((source, destination, size));
+    }
+
+    let ask_again = options.settings.password_cache.is_some();
+    let mut cache = PasswordCache::new(options.settings.password_cache);
+    cache.insert(input.password(operation)?);
+
+    // Reading always goes through the session so files written with `session-kdf` share a derivation;
+    // writing only does when it's turned on.
+    let session = match operation {
+        Operation::Encryption if !options.settings.session_kdf => None,
+        Operation::Encryption | Operation::Decryption => Some(KdfSession::new(&SystemRandom)?),
+    };
+    let started = Instant::now();
+    let progress = Arc::new(BatchProgress::new(jobs.len(), total, options.settings.progress));
+    let options = Options { batch: Some(Arc::clone(&progress)), session, ..options };
+    let mut succeeded = Vec::with_capacity(jobs.len());
+    let mut failed = Vec::new();
+    for (index, (source, destination, size)) in jobs.into_iter().enumerate() {
+        // The first file uses the password just asked for, however short the timeout.
+        if index > 0
+            && cache.expire()
+            && let Some(session) = &options.session
+        {
+            session.forget();
+        }
+
+        let result = match (operation, &destination) {
+            (Operation::Encryption, Destination::File(target)) => {
+                let secret = encryption_password(input, &mut cache)?;
+                crate::app::encrypt(source, target, std::slice::from_ref(secret), &options, &SystemRandom).await.map(drop)
+            }
+            (Operation::Encryption, Destination::Restore(_) | Destination::Extract(_) | Destination::Stdout) => anyhow::bail!("encryption requires an output path"),
+            (Operation::Decryption, destination) => decrypt_cached(input, &mut cache, ask_again, source, destination, &options).await,
+        };
+
+        progress.complete(size, result.is_ok());
+        match result {
+            Ok(()) => succeeded.push(source),
+            Err(error) => failed.push((source, error)),
+        }
+    }
+    let settings = options.settings.clone();
+    drop(options);
+    drop(progress);
+
+    crate::ui::display::batch(&succeeded, &failed)?;
+
+    let files = succeeded
+        .iter()
+        .map(|source| RunEntry { source: source.path().to_path_buf(), status: Status::Success, error: None })
+        .chain(
+            failed
+                .iter()
+                .map(|(source, error)| RunEntry { source: source.path().to_path_buf(), status: Status::Failed, error: Some(format!("{error:#}")) }),
+        )
+        .collect();
+    notify(&settings, &RunReport::new(Report::operation_name(operation), files, started.elapsed(), &Ok(()))).await;
+
+    Ok(succeeded)
+}
+
+/// The batch's encryption password, asked for again once the cache has forgotten it.
+fn encryption_password<'c>(input: &Input, cache: &'c mut PasswordCache) -> Result<&'c Secret> {
+    if cache.passwords().is_empty() {
+        cache.insert(input.password(Operation::Encryption)?);
+    }
+
+    cache.passwords().first().context("no password provided")
+}
+
+/// Decrypts `source` with the first cached password that opens it. With `ask_again`, a file none of
+/// them opens asks for its own password, which is then tried on the files after it as well.
+async fn decrypt_cached(input: &Input, cache: &mut PasswordCache, ask_again: bool, source: &Files, destination: &Destination, options: &Options) -> Result<()> {
+    for secret in cache.passwords() {
+        let result = crate::app::decrypt(source, destination, secret, options).await.map(drop);
+        let wrong_password = matches!(&result, Err(error) if SweetByteError::classify(error) == SweetByteError::WrongPassword);
+        if !wrong_password || !ask_again {
+            return result;
+        }
+    }
+
+    let secret = input.prompt_password(&format!("Enter decryption password for {}", source.path().display()))?;
+    crate::app::decrypt(source, destination, &secret, options).await?;
+    cache.insert(secret);
+
+    Ok(())
+}
+
+fn session_options(input: &Input, operation: Operation, options: &mut Options, config: Option<&Path>) -> Result<Option<PathBuf>> {
+    if !input.adjust_options()? {
+        return Ok(None);
+    }
+
+    if operation == Operation::Encryption {
+        options.settings.compression_level = input.compression_level(options.settings.compression_level)?;
+    }
+    options.settings.shred_passes = input.shred_passes(options.settings.shred_passes)?;
+    options.settings.history = input.history_mode(options.settings.history)?;
+    let output_dir = input.output_dir()?;
+
+    if input.save_options()? {
+        let path = options.settings.save(config)?;
+        crate::ui::display::saved(&path)?;
+    }
+
+    Ok(output_dir)
+}
+
+async fn process(input: &Input, operation: Operation, source: &Files, destination: &Destination, passwords: usize, options: &Options, format: OutputFormat) -> Result<()> {
+    if operation == Operation::Decryption || !source.is_stdin() {
+        ensure_input(source)?;
+    }
+
+    let mut status = Status::Success;
+    let mut entered = None;
+    if let Destination::File(target) = destination {
+        let target_exists = target.exists() || crate::files::spanning::is_spanned(target.path());
+        if target_exists && operation == Operation::Encryption && options.if_changed {
+            let secrets = input.passwords(passwords)?;
+            let started = Instant::now();
+            let progress = Progress::new(0, "Checking...", options.settings.progress);
+            let secret = secrets.first().context("no password provided")?;
+            if let Some(header) = crate::app::unchanged(source, target, secret, options.second_factor.as_ref(), &progress, options.settings.threads).await? {
+                let report = Report {
+                    operation: Report::operation_name(operation),
+                    status: Status::Skipped,
+                    source: source.path().to_path_buf(),
+                    output: target.path().to_path_buf(),
+                    restored: false,
+                    name: header.name,
+                    size: header.size,
+                    hash: hex::encode(&header.hash),
+                    duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+                    throughput: None,
+                };
+
+                return crate::ui::display::report(&report, operation, format);
+            }
+
+            status = Status::Updated;
+            entered = Some(secrets);
+        } else if target_exists && !input.overwrite(target)? {
+            anyhow::bail!("operation canceled");
+        }
+    }
+
+    let secrets = match (entered, operation) {
+        (Some(secrets), _) => secrets,
+        (None, Operation::Encryption) => input.passwords(passwords)?,
+        (None, Operation::Decryption) => vec![input.password(operation)?],
+    };
+    let started = Instant::now();
+    let (header, output) = match (operation, destination) {
+        (Operation::Encryption, Destination::File(target)) if options.tar => {
+            (crate::app::encrypt_directory(source.path(), target, &secrets, options, &SystemRandom).await?, target.path().to_path_buf())
+        }
+        (Operation::Encryption, Destination::File(target)) => (crate::app::encrypt(source, target, &secrets, options, &SystemRandom).await?, target.path().to_path_buf()),
+        (Operation::Encryption, Destination::Restore(_) | Destination::Extract(_) | Destination::Stdout) => anyhow::bail!("encryption requires an output path"),
+        (Operation::Decryption, destination) => crate::app::decrypt(source, destination, secrets.first().context("no password provided")?, options).await?,
+    };
+    if let (Operation::Encryption, Destination::File(target)) = (operation, destination)
+        && options.verify_after
+    {
+        let secret = secrets.first().context("no password provided")?;
+        crate::app::read_back(target, secret, &header, options).await.context("read-back verification failed")?;
+    }
+
+    let report = Report {
+        operation: Report::operation_name(operation),
+        status,
+        source: source.path().to_path_buf(),
+        output,
+        restored: matches!(destination, Destination::Restore(_)),
+        name: header.name,
+        size: header.size,
+        hash: hex::encode(&header.hash),
+        duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+        throughput: Some(options.stats.summary(started.elapsed())),
+    };
+
+    crate::ui::display::report(&report, operation, format)
+}
+
+/// The message given on the command line, or everything on stdin when there isn't one.
+fn message(text: Option<&str>, password_file: Option<&Path>) -> Result<Zeroizing<Vec<u8>>> {
+    if let Some(text) = text {
+        return Ok(Zeroizing::new(text.as_bytes().to_vec()));
+    }
+    if password_file == Some(Path::new("-")) {
+        anyhow::bail!("stdin already holds the passwords; pass the message as an argument");
+    }
+
+    let mut contents = Zeroizing::new(Vec::new());
+    std::io::stdin().read_to_end(&mut contents).context("failed to read message from stdin")?;
+    if contents.is_empty() {
+        anyhow::bail!("no message given");
+    }
+
+    Ok(contents)
+}
+
+fn encrypt_text(input: &Input, text: &[u8], args: &EncryptTextArgs, options: &Options, format: OutputFormat) -> Result<()> {
+    let secret = input.password(Operation::Encryption)?;
+    let sealed = crate::memory::encrypt_bytes(text, &secret, args.cipher.unwrap_or(options.cipher), &options.settings, &SystemRandom)?;
+    let report = TextReport { operation: "encrypt-text", status: Status::Success, text: crate::armor::armor(&sealed) };
+
+    #[cfg(feature = "qr")]
+    {
+        if args.qr {
+            crate::ui::qr::show(&report.text)?;
+        }
+        if let Some(path) = &args.qr_png {
+            crate::ui::qr::save(&report.text, path)?;
+        }
+    }
+
+    crate::ui::display::text(&report, format)
+}
+
+fn decrypt_text(input: &Input, text: &[u8], format: OutputFormat) -> Result<()> {
+    let sealed = crate::armor::dearmor(std::str::from_utf8(text).context("armored message is not valid UTF-8")?)?;
+    let secret = input.password(Operation::Decryption)?;
+    let plaintext = crate::memory::decrypt_bytes(&sealed, &secret)?;
+    let report = TextReport { operation: "decrypt-text", status: Status::Success, text: String::from_utf8(plaintext).context("decrypted message is not text; use decrypt for binary files")? };
+
+    crate::ui::display::text(&report, format)
+}
+
+async fn export_recovery(source: &Files, format: OutputFormat) -> Result<()> {
+    ensure_input(source)?;
+
+    let report = TextReport { operation: "export-recovery", status: Status::Success, text: crate::app::recovery_code(source).await? };
+    crate::ui::display::text(&report, format)
+}
+
+/// The header record in the recovery code file at `path`.
+fn recovery_code(path: &Path) -> Result<Vec<u8>> {
+    let code = std::fs::read_to_string(path).with_context(|| format!("failed to read recovery code {}", path.display()))?;
+
+    crate::recovery::decode(&code)
+}
+
+async fn check_password(input: &Input, source: &Files, options: &Options, format: OutputFormat) -> Result<()> {
+    ensure_input(source)?;
+
+    let secret = input.password(Operation::Decryption)?;
+    let started = Instant::now();
+    let progress = Progress::new(0, "Checking...", options.settings.progress);
+    let header = crate::app::check_password(source, &secret, options.second_factor.as_ref(), &progress).await?;
+
+    let report = CheckReport {
+        operation: "check-password",
+        status: Status::Success,
+        source: source.path().to_path_buf(),
+        name: header.name,
+        size: header.size,
+        hash: hex::encode(&header.hash),
+        duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+    };
+
+    crate::ui::display::checked(&report, format)
+}
+
+async fn add_key(input: &Input, source: &Files, options: &Options, format: OutputFormat) -> Result<()> {
+    ensure_input(source)?;
+
+    let secret = input.prompt_password("Enter an existing password")?;
+    let new_secret = input.new_password("Enter the password to add")?;
+    let started = Instant::now();
+    let progress = Progress::new(0, "Adding key...", options.settings.progress);
+    let slots = crate::app::add_key(source, &secret, &new_secret, &progress, &SystemRandom).await?;
+
+    crate::ui::display::keys(&key_report("add-key", source, slots, started), format)
+}
+
+async fn remove_key(input: &Input, source: &Files, options: &Options, format: OutputFormat) -> Result<()> {
+    ensure_input(source)?;
+
+    let secret = input.prompt_password("Enter the password to remove")?;
+    let started = Instant::now();
+    let progress = Progress::new(0, "Removing key...", options.settings.progress);
+    let slots = crate::app::remove_key(source, &secret, &progress).await?;
+
+    crate::ui::display::keys(&key_report("remove-key", source, slots, started), format)
+}
+
+async fn chunks(input: &Input, source: &Files, decrypt: bool, options: &Options, format: OutputFormat) -> Result<()> {
+    ensure_input(source)?;
+
+    let secret = if decrypt { Some(input.password(Operation::Decryption)?) } else { None };
+    let progress = Progress::new(0, "Inspecting...", options.settings.progress);
+    let layout = crate::app::chunks(source, secret.as_ref(), &progress).await?;
+    let report = ChunksReport::new(source.path().to_path_buf(), layout);
+    crate::ui::display::chunks(&report, format)?;
+
+    if report.failed() {
+        anyhow::bail!("damaged or missing chunks found");
+    }
+
+    Ok(())
+}
+
+async fn recover(input: &Input, source: &Files, output_dir: Option<&Path>, options: &Options, format: OutputFormat) -> Result<()> {
+    ensure_input(source)?;
+
+    let dir = output_dir.or_else(|| source.path().parent()).map(Path::to_path_buf).unwrap_or_default();
+    tokio::fs::create_dir_all(&dir).await.with_context(|| format!("failed to create {}", dir.display()))?;
+
+    let secret = input.password(Operation::Decryption)?;
+    let progress = Progress::new(0, "Scanning...", options.settings.progress);
+    let recovered = crate::app::recover(source, &secret, &dir, options, &progress).await?;
+    let report = RecoverReport::new(source.path().to_path_buf(), recovered);
+    crate::ui::display::recovered(&report, format)?;
+
+    if report.failed() {
+        anyhow::bail!("not every encrypted file found could be recovered whole");
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "fuse")]
+async fn mount(input: &Input, source: &Files, mountpoint: &Path, cache_size: u64, options: &Options) -> Result<()> {
+    ensure_input(source)?;
+
+    let secret = input.password(Operation::Decryption)?;
+    let progress = Progress::new(0, "Mounting...", options.settings.progress);
+    let session = crate::app::mount(source, mountpoint, &secret, cache_size, &progress).await?;
+    crate::ui::display::mounted(mountpoint)?;
+
+    options.cancel.cancelled().await;
+    drop(session);
+
+    Ok(())
+}
+
+/// Encrypts what `args.dir` already holds and then whatever is added or changed there, under one
+/// password asked for up front, until interrupted.
+async fn watch(input: &Input, args: &WatchArgs, options: Options, format: OutputFormat) -> Result<()> {
+    if !args.dir.is_dir() {
+        anyhow::bail!("not a directory: {}", args.dir.display());
+    }
+
+    let secret = input.password(Operation::Encryption)?;
+    let session = if options.settings.session_kdf { Some(KdfSession::new(&SystemRandom)?) } else { None };
+    let mut options = Options { cipher: args.cipher.unwrap_or(options.cipher), session, ..options };
+    options.settings.progress = ProgressStyle::Hidden;
+
+    let watch_options = crate::app::WatchOptions {
+        settle: Duration::from_secs(args.settle),
+        once: args.once,
+        since_last_run: args.since_last_run,
+        state_dir: args.state_dir.clone().or_else(crate::state::default_dir),
+    };
+
+    if format == OutputFormat::Text && !args.once {
+        crate::ui::display::watching(&args.dir, &args.output_dir)?;
+    }
+    let files = RefCell::new(Vec::new());
+    let log = |watched| {
+        let report = WatchReport::new(watched);
+        crate::ui::display::watched(&report, format).unwrap_or_default();
+        files.borrow_mut().push(RunEntry::from(report));
+    };
+    let started = Instant::now();
+    let result = crate::app::watch(&args.dir, &args.output_dir, &secret, &watch_options, &options, &log).await;
+
+    notify(&options.settings, &RunReport::new("watch", files.into_inner(), started.elapsed(), &result)).await;
+    result
+}
+
+/// Hands a finished run's summary to the notify hooks. A hook that fails is reported without
+/// failing the run.
+async fn notify(settings: &Settings, report: &RunReport) {
+    if let Err(error) = crate::notify::send(settings, report).await {
+        crate::ui::display::notify_failed(&error).unwrap_or_default();
+    }
+}
+
+async fn migrate(input: &Input, args: &MigrateArgs, options: &Options, format: OutputFormat) -> Result<()> {
+    let source = Files::new(&args.input);
+    ensure_input(&source)?;
+    let target = match &args.output {
+        Some(output) => Files::new(output),
+        None if crate::files::spanning::is_spanned(source.path()) || crate::storage::is_index(source.path()) => {
+            anyhow::bail!("split volumes and chunk-store indexes can't be migrated in place; pass --output")
+        }
+        None => Files::new(source.path()),
+    };
+    if !args.in_place && (target.exists() || crate::files::spanning::is_spanned(target.path())) && !input.overwrite(&target)? {
+        anyhow::bail!("operation canceled");
+    }
+
+    let secret = input.password(Operation::Decryption)?;
+    let started = Instant::now();
+    let metadata = crate::app::migrate(&source, &target, &secret, options, &SystemRandom).await?;
+
+    let report = Report {
+        operation: "migrate",
+        status: Status::Success,
+        source: source.path().to_path_buf(),
+        output: target.path().to_path_buf(),
+        restored: false,
+        name: metadata.name,
+        size: metadata.size,
+        hash: hex::encode(&metadata.hash),
+        duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+        throughput: Some(options.stats.summary(started.elapsed())),
+    };
+
+    crate::ui::display::migrated(&report, format)
+}
+
+async fn fuzz_corrupt(input: &Input, args: &FuzzCorruptArgs, options: &Options, format: OutputFormat) -> Result<()> {
+    let source = Files::new(&args.input);
+    ensure_input(&source)?;
+
+    let seed = match args.seed {
+        Some(seed) => seed,
+        None => {
+            let mut bytes = [0u8; 8];
+            SystemRandom.fill(&mut bytes)?;
+            u64::from_le_bytes(bytes)
+        }
+    };
+    let damage = crate::app::Damage { flip_bits: args.flip_bits, wipe_shards: args.wipe_shards };
+
+    let secret = input.password(Operation::Decryption)?;
+    let trials = crate::app::corrupt(&source, &secret, damage, args.trials, &SeededRandom::new(seed), &options.cancel).await?;
+
+    crate::ui::display::corrupted(&CorruptReport::new(source.path().to_path_buf(), seed, damage, trials), format)
+}
+
+async fn verify_manifest(path: &Path, options: &Options, format: OutputFormat) -> Result<()> {
+    let manifest = Manifest::load(path).await?;
+    let report = ManifestReport::new(path.to_path_buf(), manifest.verify(options.settings.threads).await);
+    crate::ui::display::manifest(&report, format)?;
+
+    if report.failed() {
+        anyhow::bail!("manifest verification failed");
+    }
+
+    Ok(())
+}
+
+fn selftest(format: OutputFormat) -> Result<()> {
+    let report = SelftestReport::new(crate::selftest::run());
+    crate::ui::display::selftest(&report, format)?;
+
+    if report.failed() {
+        anyhow::bail!("self-test failed");
+    }
+
+    Ok(())
+}
+
+fn calibrate(args: &CalibrateArgs, config: Option<&Path>, format: OutputFormat) -> Result<()> {
+    let max_memory = u32::try_from(args.max_memory.checked_div(1024).unwrap_or(0)).unwrap_or(u32::MAX);
+    let parallelism = args.parallelism.unwrap_or_else(crate::calibrate::default_parallelism);
+    let calibration = crate::calibrate::calibrate(Duration::from_millis(args.target_ms), max_memory, parallelism)?;
+
+    let written = if args.write { Some(Settings::save_kdf(calibration.params, config)?) } else { None };
+    crate::ui::display::calibrated(&CalibrateReport::new(&calibration, args.target_ms, written), format)
+}
+
+/// Rejects the encrypt options that need to read the source before or after the single pass over
+/// stdin.
+fn check_stdin(args: &EncryptArgs, password_file: Option<&Path>) -> Result<()> {
+    if password_file == Some(Path::new("-")) {
+        anyhow::bail!("stdin already holds the passwords; pass a password file path");
+    }
+    if args.output.is_none() && !args.hide_name {
+        anyhow::bail!("encrypting stdin needs --output");
+    }
+    if args.if_changed || args.tar || args.convergent || args.paranoid || args.delete_source || args.chunk_store.is_some() || args.emit_manifest.is_some() {
+        anyhow::bail!("stdin can't be combined with --if-changed, --tar, --convergent, --paranoid, --delete-source, --chunk-store, or --emit-manifest");
+    }
+
+    Ok(())
+}
+
+/// Rejects the decrypt options that need a real output file, or that would print onto the
+/// plaintext.
+fn check_stdout(args: &DecryptArgs, format: OutputFormat) -> Result<()> {
+    if args.untar || args.preserve_attrs || args.range.is_some() {
+        anyhow::bail!("--output - can't be combined with --untar, --preserve-attrs, or --range");
+    }
+    if format == OutputFormat::Json {
+        anyhow::bail!("--output - writes the plaintext to stdout, so the report can't be JSON");
+    }
+
+    Ok(())
+}
+
+fn ensure_input(source: &Files) -> Result<()> {
+    if !source.exists() && !crate::files::spanning::is_spanned(source.path()) {
+        anyhow::bail!("input file not found: {}", source.path().display());
+    }
+
+    Ok(())
+}
+
+fn key_report(operation: &'static str, source: &Files, slots: usize, started: Instant) -> KeyReport {
+    KeyReport { operation, status: Status::Success, source: source.path().to_path_buf(), slots, duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX) }
+}
@@ -0,0 +1,117 @@
+use std::ops::{Deref, DerefMut};
+
+use zeroize::Zeroize;
+
+/// A chunk buffer that is wiped on drop and, when requested, locked into RAM so it can't be written
+/// to swap.
+///
+/// Locking is best effort: if the platform refuses (no support, or `RLIMIT_MEMLOCK` is used up by
+/// other buffers in flight) the buffer works the same, it just stays swappable.
+pub(crate) struct SecureBuffer {
+    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg_attr(not(test), expect(dead_code, reason = "held so the region stays locked until the buffer is dropped"))]
+    lock: Option<region::LockGuard>,
+    data: Vec<u8>,
+}
+
+impl SecureBuffer {
+    /// Takes ownership of `data` and tries to lock its allocation. Used for plaintext.
+    pub(crate) fn locked(data: Vec<u8>) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let lock = match data.capacity() {
+            0 => None,
+            capacity => region::lock(data.as_ptr(), capacity).ok(),
+        };
+
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            lock,
+            data,
+        }
+    }
+
+    /// Takes ownership of `data` without locking it. Used for ciphertext, which is still wiped on
+    /// drop.
+    pub(crate) fn unlocked(data: Vec<u8>) -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            lock: None,
+            data,
+        }
+    }
+
+    /// Allocates a locked buffer of `len` zero bytes, to be filled in place and then truncated.
+    pub(crate) fn zeroed(len: usize) -> Self {
+        Self::locked(vec![0u8; len])
+    }
+
+    /// Allocates an empty locked buffer with room for `capacity` bytes, to be filled through
+    /// [`Self::as_mut_vec`].
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self::locked(Vec::with_capacity(capacity))
+    }
+
+    /// The buffer as a `Vec`, to fill in place. Only the allocation it had when it was locked is
+    /// locked and wiped, so the caller must keep it within its capacity.
+    pub(crate) fn as_mut_vec(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+
+    /// Shortens the buffer without reallocating, so the locked region stays valid.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if let Some(tail) = self.data.get_mut(len..) {
+            tail.zeroize();
+        }
+        self.data.truncate(len);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_locked(&self) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        let locked = self.lock.is_some();
+        #[cfg(target_arch = "wasm32")]
+        let locked = false;
+
+        locked
+    }
+}
+
+impl Deref for SecureBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for SecureBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl Drop for SecureBuffer {
+    fn drop(&mut self) {
+        // Wipes the whole capacity, then the lock guard (declared first) unlocks before the allocation is
+        // freed.
+        self.data.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_keeps_the_allocation() {
+        let mut buffer = SecureBuffer::zeroed(64);
+        buffer.copy_from_slice(&[7u8; 64]);
+        let address = buffer.as_ptr();
+
+        buffer.truncate(10);
+
+        assert_eq!(&*buffer, &[7u8; 10]);
+        assert_eq!(buffer.as_ptr(), address);
+        assert!(!SecureBuffer::unlocked(vec![1, 2, 3]).is_locked());
+    }
+}
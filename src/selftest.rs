@@ -0,0 +1,168 @@
+use aead::{Aead, AeadCore, KeyInit, Nonce};
+use aes_gcm::Aes256Gcm;
+use anyhow::{Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::XChaCha20Poly1305;
+use serde::Serialize;
+
+use crate::cipher::{Argon2Params, CipherMode, KdfParams, SeededRandom};
+use crate::config::{MIN_CHUNK_SIZE, MIN_KDF_MEMORY, ORIGINAL_COUNT, RECOVERY_COUNT, Settings};
+use crate::encoding::Encoding;
+use crate::secret::Secret;
+
+/// AES-256-GCM test case 14 from the GCM specification: zero key, zero IV, one zero block.
+const AES_GCM_EXPECTED: &str = "cea7403d4d606b6e074ec5d3baf39d18d0d1c8a799996bf0265b98b5d48ab919";
+
+/// XChaCha20-Poly1305 over one zero block with a zero key and nonce.
+const XCHACHA_EXPECTED: &str = "789e9689e5208d7fd9e1f3c5b5341f483959fc0b770c8e6d6116830dcb630cc5";
+
+/// Argon2id v1.3 of "password" / "somesalt" with m=32 KiB, t=3, p=1 and a 32-byte tag.
+const ARGON2ID_EXPECTED: &str = "6d4c5fa26a057c23e3a4f72ae34c64e71398c851f2c79464e3e670ed41b543f9";
+
+/// scrypt of an empty password and salt with N=16, r=1, p=1 and a 64-byte output, the first RFC
+/// 7914 test vector.
+const SCRYPT_EXPECTED: &str = "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906";
+
+/// BLAKE3 of the empty input.
+const BLAKE3_EXPECTED: &str = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+
+/// A primitive's name and the known-answer test that exercises it.
+type KnownAnswer = (&'static str, fn() -> Result<()>);
+
+#[derive(Serialize)]
+pub(crate) struct Check {
+    pub(crate) name: &'static str,
+    pub(crate) passed: bool,
+    pub(crate) detail: Option<String>,
+}
+
+pub(crate) fn run() -> Vec<Check> {
+    let checks: [KnownAnswer; 7] = [
+        ("AES-256-GCM", aes256_gcm),
+        ("XChaCha20-Poly1305", xchacha20_poly1305),
+        ("Argon2id", argon2id),
+        ("scrypt", scrypt_kdf),
+        ("BLAKE3", blake3_digest),
+        ("Reed-Solomon", reed_solomon),
+        ("Roundtrip", roundtrip),
+    ];
+
+    checks
+        .into_iter()
+        .map(|(name, check)| match check() {
+            Ok(()) => Check { name, passed: true, detail: None },
+            Err(error) => Check { name, passed: false, detail: Some(format!("{error:#}")) },
+        })
+        .collect()
+}
+
+fn aes256_gcm() -> Result<()> {
+    aead_known_answer::<Aes256Gcm>(AES_GCM_EXPECTED)
+}
+
+fn xchacha20_poly1305() -> Result<()> {
+    aead_known_answer::<XChaCha20Poly1305>(XCHACHA_EXPECTED)
+}
+
+fn aead_known_answer<Cipher>(expected: &str) -> Result<()>
+where
+    Cipher: Aead + AeadCore + KeyInit,
+{
+    let cipher = Cipher::new_from_slice(&[0u8; 32]).context("failed to setup key")?;
+    let nonce = Nonce::<Cipher>::default();
+    let plaintext = [0u8; 16];
+
+    let ciphertext = cipher.encrypt(&nonce, plaintext.as_slice()).context("failed to encrypt")?;
+    ensure_hex(&ciphertext, expected)?;
+
+    let decrypted = cipher.decrypt(&nonce, ciphertext.as_slice()).context("failed to decrypt")?;
+    if decrypted != plaintext {
+        anyhow::bail!("decrypted block differs from the plaintext");
+    }
+
+    let mut tampered = ciphertext;
+    if let Some(byte) = tampered.first_mut() {
+        *byte ^= 1;
+    }
+    if cipher.decrypt(&nonce, tampered.as_slice()).is_ok() {
+        anyhow::bail!("tampered ciphertext was accepted");
+    }
+
+    Ok(())
+}
+
+fn argon2id() -> Result<()> {
+    let params = Params::new(32, 3, 1, Some(32)).context("invalid argon2 parameters")?;
+    let mut tag = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(b"password", b"somesalt", &mut tag)
+        .context("failed to hash password")?;
+
+    ensure_hex(&tag, ARGON2ID_EXPECTED)
+}
+
+fn scrypt_kdf() -> Result<()> {
+    let params = scrypt::Params::new(4, 1, 1, 64).map_err(|error| anyhow::anyhow!("invalid scrypt parameters: {error}"))?;
+    let mut output = [0u8; 64];
+    scrypt::scrypt(b"", b"", &params, &mut output).map_err(|error| anyhow::anyhow!("failed to hash password: {error}"))?;
+
+    ensure_hex(&output, SCRYPT_EXPECTED)
+}
+
+fn blake3_digest() -> Result<()> {
+    ensure_hex(blake3::hash(b"").as_bytes(), BLAKE3_EXPECTED)
+}
+
+fn reed_solomon() -> Result<()> {
+    let encoding = Encoding::new(ORIGINAL_COUNT, RECOVERY_COUNT)?;
+    let data: Vec<u8> = (0..=u8::MAX).collect();
+    let mut encoded = encoding.encode(&data)?;
+
+    // Wipe every original shard (and its checksum) so decoding has to rebuild them from recovery
+    // shards.
+    let (_, shards) = encoded.split_at_mut_checked(4).context("encoded data too short")?;
+    let shard_len = shards.len().checked_div(ORIGINAL_COUNT.saturating_add(RECOVERY_COUNT)).context("invalid shard count")?;
+    for shard in shards.chunks_mut(shard_len).take(ORIGINAL_COUNT) {
+        shard.fill(0);
+    }
+
+    if encoding.decode(&encoded)? != data {
+        anyhow::bail!("recovered data differs from the original");
+    }
+
+    Ok(())
+}
+
+fn roundtrip() -> Result<()> {
+    let secret = Secret::new(b"selftest".to_vec());
+    let settings = Settings { chunk_size: MIN_CHUNK_SIZE, kdf: KdfParams::Argon2id(Argon2Params { memory: MIN_KDF_MEMORY, iterations: 1, parallelism: 1 }), ..Settings::default() };
+    let plaintext: Vec<u8> = (0..=u8::MAX).cycle().take(MIN_CHUNK_SIZE.saturating_mul(2).saturating_add(1)).collect();
+
+    let encrypted = crate::memory::encrypt_bytes(&plaintext, &secret, CipherMode::Dual, &settings, &SeededRandom::new(0))?;
+    if crate::memory::decrypt_bytes(&encrypted, &secret)? != plaintext {
+        anyhow::bail!("decrypted data differs from the original");
+    }
+
+    Ok(())
+}
+
+fn ensure_hex(actual: &[u8], expected: &str) -> Result<()> {
+    let actual = hex::encode(actual);
+    if actual != expected {
+        anyhow::bail!("expected {expected}, got {actual}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_check_passes() {
+        for check in run() {
+            assert!(check.passed, "{}: {:?}", check.name, check.detail);
+        }
+    }
+}
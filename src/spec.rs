@@ -0,0 +1,160 @@
+use std::fmt::Write;
+
+use anyhow::Result;
+
+use crate::config::{
+    ARGON2_KEY_LEN, ARGON2_M_COST, ARGON2_P_COST, ARGON2_SALT_LEN, ARGON2_T_COST, BLOCK_SIZE, CHUNK_INDEX_INFO, CHUNK_INDEX_MAGIC, CHUNK_INDEX_MARKER, COMPACT_COPIES, COMPACT_SECTION_LEN,
+    COMPRESSION_LEVEL, CONVERGENT_INFO, CONVERGENT_SALT, CURRENT_VERSION, HEADER_COPY_MAGIC, HEADER_MAC_LEN, HIDDEN_INFO, KDF_INFO, KEK_INFO, MAGIC_BYTES, MAX_CHUNK_INDEX_LEN, MAX_CHUNK_SIZE,
+    MAX_DECOMPRESSED_SECTION_SIZE, MAX_KEY_SLOTS, MAX_METADATA_SECTION_LEN, MAX_PARAMS_SECTION_LEN, MAX_SECTION_SIZE, MAX_SLOTS_SECTION_LEN, METADATA_KEY_INFO, NONCE_PREFIX_LEN, ORIGINAL_COUNT,
+    PADDING_MARKER, PAYLOAD_MAC_INFO, PAYLOAD_MAC_LEN, PAYLOAD_MAC_MARKER, RECOVERY_COUNT, SCRYPT_BLOCK_SIZE, SCRYPT_LOG_N, SCRYPT_P_COST, SECOND_FACTOR_INFO, SESSION_INFO, SLOT_NONCE_LEN,
+    VOLUME_HEADER_LEN, WRAPPED_KEY_LEN,
+};
+use crate::encoding::REPLICA_MARKER;
+use crate::files::Attributes;
+use crate::files::spanning::{VOLUME_ID_LEN, VOLUME_MAGIC};
+use crate::header::{COPY_TAIL_LEN, field_names};
+use crate::pipeline::{INDEX_TAIL_LEN, IndexEntry, TRAILER_BODY_LEN};
+
+/// Renders the on-disk format as Markdown. Every constant, marker, and field list comes from the
+/// code that reads and writes files, so the document changes whenever the format does.
+pub(crate) fn format() -> Result<String> {
+    let mut out = String::new();
+
+    writeln!(out, "# SweetByte file format, version 0x{CURRENT_VERSION:04X}")?;
+    writeln!(out)?;
+    writeln!(out, "Generated by sweetbyte-rs {}. Integers are little-endian. Structures are postcard-encoded, fields in the order listed.", env!("CARGO_PKG_VERSION"))?;
+    writeln!(out)?;
+    writeln!(out, "## Layout")?;
+    writeln!(out)?;
+    writeln!(out, "1. Header frame")?;
+    writeln!(out, "2. Payload records, one per chunk")?;
+    writeln!(out, "3. Payload MAC trailer, if `payload_mac` is set")?;
+    writeln!(out, "4. Padding, if `padded` is set")?;
+    writeln!(out, "5. Chunk index footer, if `indexed` is set")?;
+    writeln!(out)?;
+
+    writeln!(out, "## Header frame")?;
+    writeln!(out)?;
+    writeln!(out, "| Offset | Size | Field |")?;
+    writeln!(out, "|---|---|---|")?;
+    writeln!(out, "| 0 | 4 | section length (u32) |")?;
+    writeln!(out, "| 4 | 4 | reserved capacity (u32, at most {MAX_SECTION_SIZE}) |")?;
+    writeln!(out, "| 8 | capacity | section, zero-padded to the reserved capacity |")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "The section is zstd-compressed (level {COMPRESSION_LEVEL}). Inside it, each field is stored as a base64 string. Fields of at most {COMPACT_SECTION_LEN} bytes are the marker 0x{REPLICA_MARKER:08X} followed by {COMPACT_COPIES} copies, each a CRC-32 then the field; larger ones are Reed-Solomon encoded ({ORIGINAL_COUNT} data + {RECOVERY_COUNT} parity shards) like a payload chunk."
+    )?;
+    writeln!(out)?;
+    for (name, fields) in crate::header::structures() {
+        writeln!(out, "- **{name}**: {}", list(fields))?;
+    }
+    writeln!(out, "- **attributes** (in metadata): {}", list(field_names::<Attributes>()))?;
+    writeln!(out)?;
+    writeln!(out, "- `magic` is 0x{MAGIC_BYTES:08X} and `version` is 0x{CURRENT_VERSION:04X}.")?;
+    writeln!(out, "- `nonce_prefix` is {NONCE_PREFIX_LEN} bytes. Each chunk nonce is the prefix followed by the u64 chunk number.")?;
+    writeln!(out, "- `padding_scheme` pads each compressed chunk to {BLOCK_SIZE}-byte blocks before encryption: variant 0 is PKCS#7, variant 1 is ISO/IEC 7816-4.")?;
+    writeln!(out, "- `salt` is {ARGON2_SALT_LEN} bytes.")?;
+    writeln!(out, "- `slots` holds 1 to {MAX_KEY_SLOTS} key slots: a {SLOT_NONCE_LEN}-byte nonce and the master key wrapped with XChaCha20-Poly1305 ({WRAPPED_KEY_LEN} bytes).")?;
+    writeln!(out, "- `metadata` is sealed with XChaCha20-Poly1305 under the metadata key: a {SLOT_NONCE_LEN}-byte nonce followed by the ciphertext.")?;
+    writeln!(out, "- `mac` is HMAC-SHA256 over the salt, parameters, slots, and sealed metadata.")?;
+    writeln!(
+        out,
+        "- Decoded field limits: salt {ARGON2_SALT_LEN}, params {MAX_PARAMS_SECTION_LEN}, slots {MAX_SLOTS_SECTION_LEN}, metadata {MAX_METADATA_SECTION_LEN}, mac {HEADER_MAC_LEN} bytes. The decompressed section is at most {MAX_DECOMPRESSED_SECTION_SIZE} bytes."
+    )?;
+    writeln!(out)?;
+
+    writeln!(out, "## Payload")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "A Reed-Solomon block is the u32 data length, then {ORIGINAL_COUNT} data and {RECOVERY_COUNT} parity shards of equal even size, each behind its CRC-32. The data shards hold the u32 length again, the data, and zero padding. Readers trim to the length inside the shards; the one in front only bounds the size before decoding."
+    )?;
+    writeln!(out)?;
+    writeln!(out, "Each record is a u32 length of at most {MAX_CHUNK_SIZE}, then that many bytes of Reed-Solomon encoded, encrypted chunk. Larger values are markers:")?;
+    writeln!(out)?;
+    writeln!(out, "| Marker | Meaning | Followed by |")?;
+    writeln!(out, "|---|---|---|")?;
+    writeln!(out, "| 0x{PAYLOAD_MAC_MARKER:08X} | payload MAC trailer | {TRAILER_BODY_LEN} bytes: u64 chunk count, {PAYLOAD_MAC_LEN}-byte keyed BLAKE3 tag |")?;
+    writeln!(out, "| 0x{PADDING_MARKER:08X} | padding | random filler up to the chunk index or the end of the file |")?;
+    writeln!(
+        out,
+        "| 0x{CHUNK_INDEX_MARKER:08X} | chunk index | Reed-Solomon encoded entries and a {PAYLOAD_MAC_LEN}-byte keyed BLAKE3 tag (at most {MAX_CHUNK_INDEX_LEN} bytes), then a {INDEX_TAIL_LEN}-byte tail: u32 encoded length, magic `{}` |",
+        String::from_utf8_lossy(&CHUNK_INDEX_MAGIC)
+    )?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "A hidden payload, when there is one, takes the end of the padding filler and is not marked: a sealed postcard `(name, data)`, then a sealed little-endian u64 giving that length, each a {SLOT_NONCE_LEN}-byte nonce and XChaCha20-Poly1305 ciphertext under the hidden key."
+    )?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "When `copied` is set, the chunk index is followed by the header again, byte for byte, then a {COPY_TAIL_LEN}-byte tail: u32 header length, magic `{}`. Readers fall back to it when the header at the start is unreadable.",
+        String::from_utf8_lossy(&HEADER_COPY_MAGIC)
+    )?;
+    writeln!(out)?;
+    writeln!(out, "- **index entry**: {}", list(field_names::<IndexEntry>()))?;
+    writeln!(out)?;
+
+    writeln!(out, "## Keys")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Argon2id (defaults: m={ARGON2_M_COST} KiB, t={ARGON2_T_COST}, p={ARGON2_P_COST}; the values used are in the parameters) derives {ARGON2_KEY_LEN} bytes from the password and salt."
+    )?;
+    writeln!(
+        out,
+        "`kdf` is a postcard enum: variant 0 is Argon2id (`memory`, `iterations`, `parallelism`), variant 1 is scrypt (`log_n`, `block_size`, `parallelism`; defaults: N=2^{SCRYPT_LOG_N}, r={SCRYPT_BLOCK_SIZE}, p={SCRYPT_P_COST}), which takes Argon2id's place wherever it appears below."
+    )?;
+    writeln!(out, "HKDF-SHA256 expands them into the key-encryption key, and the master key into the file keys, with these info strings:")?;
+    writeln!(out)?;
+    for info in [KEK_INFO, PAYLOAD_MAC_INFO, METADATA_KEY_INFO, CHUNK_INDEX_INFO, CONVERGENT_INFO, SESSION_INFO, SECOND_FACTOR_INFO, HIDDEN_INFO] {
+        writeln!(out, "- `{}`", String::from_utf8_lossy(info))?;
+    }
+    for info in KDF_INFO {
+        writeln!(out, "- `{}`", String::from_utf8_lossy(&info))?;
+    }
+    writeln!(out)?;
+    writeln!(out, "Convergent files derive their stream key with Argon2id under the fixed salt `{}`.", String::from_utf8_lossy(CONVERGENT_SALT))?;
+    writeln!(
+        out,
+        "When `session_salt` is set, Argon2id runs under it instead, HKDF expands the result into a session key, and each key-encryption key is expanded from the session key with the file's `salt` as HKDF salt."
+    )?;
+    writeln!(
+        out,
+        "When `second_factor` is set, each key-encryption key is replaced by HKDF-SHA256 over it followed by the raw TOTP secret, with the file's `salt` as HKDF salt and the second-factor info string."
+    )?;
+    writeln!(
+        out,
+        "The hidden key is HKDF-SHA256 over the key-encryption key the hidden password gives under the file's `salt` (never a session key), with that `salt` as HKDF salt and the hidden info string."
+    )?;
+    writeln!(out)?;
+
+    writeln!(out, "## Split volumes")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Volumes are named `.swx.001`, `.swx.002`, and so on. Each starts with {VOLUME_HEADER_LEN} bytes: magic 0x{VOLUME_MAGIC:08X}, the u32 volume number, counting from 1, and {VOLUME_ID_LEN} random bytes that every volume of the file shares. A volume whose bytes differ from the first one's is rejected. The rest of each volume, joined in order, is the file."
+    )?;
+
+    Ok(out)
+}
+
+fn list(fields: &[&str]) -> String {
+    fields.iter().map(|field| format!("`{field}`")).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_every_header_field() {
+        let spec = format().unwrap();
+        for (_, fields) in crate::header::structures() {
+            assert!(!fields.is_empty());
+            assert!(fields.iter().all(|field| spec.contains(&format!("`{field}`"))));
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CONFIG_DIR, JOURNAL_FILE, RUN_LOCK_FILE};
+use crate::files::{Metadata, TempFile};
+
+/// Where unattended runs keep their state when `--state-dir` isn't given:
+/// `$XDG_STATE_HOME/sweetbyte`, or `~/.local/state/sweetbyte`.
+pub(crate) fn default_dir() -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from);
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|dir| !dir.is_empty())
+        .map(|home| PathBuf::from(home).join(".local").join("state"));
+
+    state_home.or(home).map(|dir| dir.join(CONFIG_DIR))
+}
+
+/// The state kept for one watched directory: a lock that stops two runs working on it at once, held
+/// until this is dropped, and the journal of what earlier runs encrypted.
+pub(crate) struct State {
+    journal_path: PathBuf,
+    journal: Journal,
+    _lock: File,
+}
+
+impl State {
+    /// Opens the state `root` holds for `source`, which should be canonical so every run finds the
+    /// same state. Fails straight away if another run already holds it.
+    pub(crate) fn open(root: &Path, source: &Path) -> Result<Self> {
+        let id = blake3::hash(source.as_os_str().as_encoded_bytes()).to_hex();
+        let dir = root.join(id.get(..16).unwrap_or_default());
+        std::fs::create_dir_all(&dir).with_context(|| format!("failed to create state directory {}", dir.display()))?;
+
+        let lock_path = dir.join(RUN_LOCK_FILE);
+        let lock = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .with_context(|| format!("failed to open run lock {}", lock_path.display()))?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => anyhow::bail!("another run is already working on {}", source.display()),
+            Err(TryLockError::Error(error)) => return Err(error).with_context(|| format!("failed to lock {}", lock_path.display())),
+        }
+
+        let journal_path = dir.join(JOURNAL_FILE);
+        let journal = if journal_path.exists() {
+            let contents = std::fs::read(&journal_path).with_context(|| format!("failed to read journal {}", journal_path.display()))?;
+            serde_json::from_slice(&contents).with_context(|| format!("invalid journal {}", journal_path.display()))?
+        } else {
+            Journal { source: source.to_path_buf(), files: BTreeMap::new() }
+        };
+
+        Ok(Self { journal_path, journal, _lock: lock })
+    }
+
+    pub(crate) fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub(crate) fn journal_mut(&mut self) -> &mut Journal {
+        &mut self.journal
+    }
+
+    /// Writes the journal through a temporary file, so a run killed halfway leaves the previous one
+    /// intact.
+    pub(crate) fn save(&self) -> Result<()> {
+        let contents = serde_json::to_vec_pretty(&self.journal).context("failed to serialize journal")?;
+        let temp = TempFile::new(&self.journal_path)?;
+        std::fs::write(temp.path(), contents).context("failed to write journal")?;
+        temp.persist_blocking().context("failed to finalize journal")
+    }
+}
+
+/// Every file encrypted out of one directory, by its path.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Journal {
+    source: PathBuf,
+    files: BTreeMap<PathBuf, JournalEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalEntry {
+    hash: String,
+    size: u64,
+    /// The file's modification time when it was read, before it was encrypted.
+    modified: Option<SystemTime>,
+    output: PathBuf,
+    encrypted_at: SystemTime,
+}
+
+impl Journal {
+    /// Whether `file` was last encrypted to `output` as it is now: the output is still there and
+    /// the file has the size and modification time it had then. A file whose modification time
+    /// can't be read never counts.
+    pub(crate) fn is_current(&self, file: &Path, output: &Path) -> bool {
+        let Some(entry) = self.files.get(file) else { return false };
+        let Ok(metadata) = std::fs::metadata(file) else { return false };
+
+        entry.output == output && output.exists() && entry.size == metadata.len() && entry.modified.is_some() && entry.modified == metadata.modified().ok()
+    }
+
+    /// Records that `file`, with the modification time `modified` it had before it was read, is now
+    /// in `output`.
+    pub(crate) fn record(&mut self, file: &Path, modified: Option<SystemTime>, output: &Path, metadata: &Metadata) {
+        let entry = JournalEntry { hash: hex::encode(&metadata.hash), size: metadata.size, modified, output: output.to_path_buf(), encrypted_at: SystemTime::now() };
+        self.files.insert(file.to_path_buf(), entry);
+    }
+}
+
+/// `path`'s modification time, to be taken before it's read and passed to `Journal::record`.
+pub(crate) fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::files::Attributes;
+
+    fn metadata(size: u64) -> Metadata {
+        Metadata { name: "a.txt".to_owned(), size, hash: vec![7u8; 32], attributes: Attributes::default() }
+    }
+
+    #[test]
+    fn second_run_on_the_same_directory_fails() {
+        let dir = tempdir().unwrap();
+        let state = State::open(dir.path(), Path::new("/data/photos")).unwrap();
+
+        let error = State::open(dir.path(), Path::new("/data/photos")).err().unwrap();
+        assert!(error.to_string().contains("another run is already working"));
+        State::open(dir.path(), Path::new("/data/music")).unwrap();
+
+        drop(state);
+        State::open(dir.path(), Path::new("/data/photos")).unwrap();
+    }
+
+    #[test]
+    fn journal_survives_between_runs_and_notices_changes() {
+        let dir = tempdir().unwrap();
+        let (file, output) = (dir.path().join("a.txt"), dir.path().join("a.txt.swx"));
+        std::fs::write(&file, b"first").unwrap();
+        std::fs::write(&output, b"encrypted").unwrap();
+
+        let mut state = State::open(&dir.path().join("state"), dir.path()).unwrap();
+        assert!(!state.journal().is_current(&file, &output));
+        state.journal_mut().record(&file, modified(&file), &output, &metadata(5));
+        state.save().unwrap();
+        drop(state);
+
+        let state = State::open(&dir.path().join("state"), dir.path()).unwrap();
+        assert!(state.journal().is_current(&file, &output));
+        assert!(!state.journal().is_current(&file, &dir.path().join("elsewhere.swx")));
+
+        std::fs::write(&file, b"second, longer").unwrap();
+        assert!(!state.journal().is_current(&file, &output));
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+/// Ancillary, private, and safe to copy: viewers skip it, and editors that keep unknown chunks
+/// carry it along.
+const PNG_CHUNK: [u8; 4] = *b"swXd";
+const PNG_END: [u8; 4] = *b"IEND";
+const PNG_CHUNK_LEN: usize = 1024 * 1024;
+/// Length, type, and CRC around each PNG chunk's data.
+const PNG_FRAMING_LEN: usize = 12;
+
+const RIFF: [u8; 4] = *b"RIFF";
+const WAVE: [u8; 4] = *b"WAVE";
+const WAV_CHUNK: [u8; 4] = *b"swxd";
+/// `RIFF`, the size, and `WAVE`.
+const RIFF_HEADER_LEN: usize = 12;
+
+/// How many leading bytes [`is_carrier`] needs to recognize a carrier.
+pub(crate) const SNIFF_LEN: usize = RIFF_HEADER_LEN;
+
+#[derive(Clone, Copy)]
+enum Format {
+    Png,
+    Wav,
+}
+
+impl Format {
+    fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&PNG_SIGNATURE) {
+            return Some(Self::Png);
+        }
+
+        (data.get(..4)? == RIFF && data.get(8..RIFF_HEADER_LEN)? == WAVE).then_some(Self::Wav)
+    }
+}
+
+/// Whether `prefix`, the start of a file, is a PNG image or WAV file that may carry a payload.
+pub(crate) fn is_carrier(prefix: &[u8]) -> bool {
+    Format::detect(prefix).is_some()
+}
+
+/// Hides a complete `.swx` file in a copy of `carrier`: in private `swXd` chunks before the end of
+/// a PNG, or in a `swxd` chunk at the end of a WAV file. The image or sound is left untouched, so
+/// the result opens as before.
+pub(crate) fn embed(carrier: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    match Format::detect(carrier).context("carrier must be a PNG image or a WAV file")? {
+        Format::Png => embed_png(carrier, payload),
+        Format::Wav => embed_wav(carrier, payload),
+    }
+}
+
+/// Recovers the `.swx` file [`embed`] hid in `data`.
+pub(crate) fn extract(data: &[u8]) -> Result<Vec<u8>> {
+    let payload = match Format::detect(data).context("not a PNG or WAV carrier")? {
+        Format::Png => extract_png(data)?,
+        Format::Wav => extract_wav(data)?,
+    };
+
+    payload.context("carrier holds no encrypted payload")
+}
+
+struct PngChunk<'a> {
+    offset: usize,
+    kind: [u8; 4],
+    data: &'a [u8],
+    crc: u32,
+}
+
+/// Every chunk up to and including `IEND`.
+fn png_chunks(data: &[u8]) -> Result<Vec<PngChunk<'_>>> {
+    let mut chunks = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+    loop {
+        let rest = data.get(offset..).context("PNG ends before its IEND chunk")?;
+        let (len, rest) = rest.split_first_chunk::<4>().context("PNG chunk is truncated")?;
+        let (kind, rest) = rest.split_first_chunk::<4>().context("PNG chunk is truncated")?;
+        let len = usize::try_from(u32::from_be_bytes(*len)).context("PNG chunk too large")?;
+        let (body, rest) = rest.split_at_checked(len).context("PNG chunk is truncated")?;
+        let (crc, _) = rest.split_first_chunk::<4>().context("PNG chunk is truncated")?;
+
+        chunks.push(PngChunk { offset, kind: *kind, data: body, crc: u32::from_be_bytes(*crc) });
+        if *kind == PNG_END {
+            return Ok(chunks);
+        }
+        offset = offset.checked_add(len).and_then(|offset| offset.checked_add(PNG_FRAMING_LEN)).context("PNG chunk too large")?;
+    }
+}
+
+fn png_crc(kind: &[u8; 4], data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+fn embed_png(carrier: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    let chunks = png_chunks(carrier)?;
+    if chunks.iter().any(|chunk| chunk.kind == PNG_CHUNK) {
+        anyhow::bail!("carrier already holds an encrypted payload");
+    }
+    let end = chunks.last().map(|chunk| chunk.offset).context("PNG has no IEND chunk")?;
+    let (head, tail) = carrier.split_at_checked(end).context("PNG chunk out of range")?;
+
+    let framing = payload.len().div_ceil(PNG_CHUNK_LEN).saturating_mul(PNG_FRAMING_LEN);
+    let mut embedded = Vec::with_capacity(carrier.len().saturating_add(payload.len()).saturating_add(framing));
+    embedded.extend_from_slice(head);
+    for part in payload.chunks(PNG_CHUNK_LEN) {
+        let len = u32::try_from(part.len()).context("PNG chunk too large")?;
+        embedded.extend_from_slice(&len.to_be_bytes());
+        embedded.extend_from_slice(&PNG_CHUNK);
+        embedded.extend_from_slice(part);
+        embedded.extend_from_slice(&png_crc(&PNG_CHUNK, part).to_be_bytes());
+    }
+    embedded.extend_from_slice(tail);
+
+    Ok(embedded)
+}
+
+fn extract_png(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut payload: Option<Vec<u8>> = None;
+    for chunk in png_chunks(data)?.iter().filter(|chunk| chunk.kind == PNG_CHUNK) {
+        if png_crc(&chunk.kind, chunk.data) != chunk.crc {
+            anyhow::bail!("embedded payload fails its PNG chunk CRC");
+        }
+        payload.get_or_insert_default().extend_from_slice(chunk.data);
+    }
+
+    Ok(payload)
+}
+
+/// The chunks the RIFF header's size covers, and whatever follows them.
+fn riff_body(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let size = data.get(4..8).and_then(|size| <[u8; 4]>::try_from(size).ok()).context("WAV header is truncated")?;
+    let end = usize::try_from(u32::from_le_bytes(size)).ok().and_then(|size| size.checked_add(8)).context("WAV file too large")?;
+    let body = data.get(RIFF_HEADER_LEN..end).context("WAV file is shorter than its header says")?;
+    let trailing = data.get(end..).unwrap_or_default();
+
+    Ok((body, trailing))
+}
+
+fn wav_chunks(mut body: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut chunks = Vec::new();
+    while !body.is_empty() {
+        let (id, rest) = body.split_first_chunk::<4>().context("WAV chunk is truncated")?;
+        let (len, rest) = rest.split_first_chunk::<4>().context("WAV chunk is truncated")?;
+        let len = usize::try_from(u32::from_le_bytes(*len)).context("WAV chunk too large")?;
+        let (data, rest) = rest.split_at_checked(len).context("WAV chunk is truncated")?;
+
+        chunks.push((*id, data));
+        // Chunks are padded to an even length.
+        body = rest.get(len & 1..).unwrap_or_default();
+    }
+
+    Ok(chunks)
+}
+
+fn embed_wav(carrier: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    let (body, trailing) = riff_body(carrier)?;
+    if wav_chunks(body)?.iter().any(|(id, _)| *id == WAV_CHUNK) {
+        anyhow::bail!("carrier already holds an encrypted payload");
+    }
+
+    let len = u32::try_from(payload.len()).context("payload too large for a WAV carrier")?;
+    let pad = payload.len() & 1;
+    let size = body
+        .len()
+        .checked_add(WAVE.len())
+        .and_then(|size| size.checked_add(8))
+        .and_then(|size| size.checked_add(payload.len()))
+        .and_then(|size| size.checked_add(pad));
+    let size = size.and_then(|size| u32::try_from(size).ok()).context("payload too large for a WAV carrier")?;
+
+    let mut embedded = Vec::with_capacity(carrier.len().saturating_add(payload.len()).saturating_add(8).saturating_add(pad));
+    embedded.extend_from_slice(&RIFF);
+    embedded.extend_from_slice(&size.to_le_bytes());
+    embedded.extend_from_slice(&WAVE);
+    embedded.extend_from_slice(body);
+    embedded.extend_from_slice(&WAV_CHUNK);
+    embedded.extend_from_slice(&len.to_le_bytes());
+    embedded.extend_from_slice(payload);
+    embedded.resize(embedded.len().saturating_add(pad), 0);
+    embedded.extend_from_slice(trailing);
+
+    Ok(embedded)
+}
+
+fn extract_wav(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let (body, _) = riff_body(data)?;
+
+    Ok(wav_chunks(body)?.into_iter().find(|(id, _)| *id == WAV_CHUNK).map(|(_, payload)| payload.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png() -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        for (kind, data) in [(*b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0][..]), (*b"IDAT", &[1, 2, 3][..]), (PNG_END, &[][..])] {
+            png.extend_from_slice(&u32::try_from(data.len()).unwrap().to_be_bytes());
+            png.extend_from_slice(&kind);
+            png.extend_from_slice(data);
+            png.extend_from_slice(&png_crc(&kind, data).to_be_bytes());
+        }
+        png
+    }
+
+    fn wav() -> Vec<u8> {
+        let mut wav = RIFF.to_vec();
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&WAVE);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&3u32.to_le_bytes());
+        wav.extend_from_slice(&[1, 2, 3, 0]);
+        wav
+    }
+
+    #[test]
+    fn payloads_roundtrip_through_png_and_wav() {
+        let payload: Vec<u8> = (0..=255).cycle().take(PNG_CHUNK_LEN + 7).collect();
+        for carrier in [png(), wav()] {
+            assert!(is_carrier(&carrier));
+            extract(&carrier).unwrap_err();
+
+            let embedded = embed(&carrier, &payload).unwrap();
+            assert_eq!(extract(&embedded).unwrap(), payload);
+            embed(&embedded, b"again").unwrap_err();
+        }
+
+        let embedded = embed(&png(), b"odd").unwrap();
+        assert!(embedded.ends_with(&png()[png().len() - PNG_FRAMING_LEN..]));
+        assert!(!is_carrier(b"plain text file"));
+        embed(b"plain text file", b"payload").unwrap_err();
+    }
+
+    #[test]
+    fn damaged_png_payloads_are_rejected() {
+        let mut embedded = embed(&png(), b"payload").unwrap();
+        let position = embedded.windows(7).position(|window| window == b"payload").unwrap();
+        embedded[position] ^= 0xFF;
+
+        extract(&embedded).unwrap_err();
+    }
+}
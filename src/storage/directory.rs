@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use super::ObjectStore;
+use crate::files::TempFile;
+
+const OBJECTS_DIR: &str = "objects";
+const ID_LEN: usize = 64;
+const FANOUT_LEN: usize = 2;
+
+pub(crate) struct DirectoryStore {
+    root: PathBuf,
+}
+
+impl DirectoryStore {
+    pub(crate) fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn object_path(&self, id: &str) -> Result<PathBuf> {
+        if id.len() != ID_LEN || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            anyhow::bail!("invalid object id {id:?}");
+        }
+
+        let fanout = id.get(..FANOUT_LEN).context("invalid object id")?;
+
+        Ok(self.root.join(OBJECTS_DIR).join(fanout).join(id))
+    }
+}
+
+impl ObjectStore for DirectoryStore {
+    fn put(&self, data: &[u8]) -> Result<String> {
+        let id = blake3::hash(data).to_hex().to_string();
+        let path = self.object_path(&id)?;
+        if path.exists() {
+            return Ok(id);
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+
+        let temp = TempFile::new(&path)?;
+        std::fs::write(temp.path(), data).context("failed to write object")?;
+        temp.persist_blocking().context("failed to store object")?;
+
+        Ok(id)
+    }
+
+    fn get(&self, id: &str) -> Result<Vec<u8>> {
+        let path = self.object_path(id)?;
+        let data = std::fs::read(&path).with_context(|| format!("failed to read object {id}"))?;
+        if blake3::hash(&data).to_hex().as_str() != id {
+            anyhow::bail!("object {id} is corrupted");
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn identical_objects_are_stored_once() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryStore::new(dir.path());
+
+        let first = store.put(b"chunk").unwrap();
+        let second = store.put(b"chunk").unwrap();
+        assert_eq!(first, second);
+        assert_eq!(store.get(&first).unwrap(), b"chunk");
+
+        std::fs::write(store.object_path(&first).unwrap(), b"tampered").unwrap();
+        store.get(&first).unwrap_err();
+    }
+}
@@ -0,0 +1,59 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{DirectoryStore, ObjectStore};
+use crate::files::TempFile;
+
+const INDEX_MAGIC: &str = "sweetbyte-chunk-index";
+const INDEX_PREFIX: &[u8] = b"{\"magic\":\"sweetbyte-chunk-index\"";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Index {
+    magic: String,
+    pub(crate) store: PathBuf,
+    pub(crate) header: Option<String>,
+    pub(crate) chunks: Vec<String>,
+    pub(crate) trailer: Option<String>,
+}
+
+impl Index {
+    pub(crate) fn new(store: PathBuf) -> Self {
+        Self { magic: INDEX_MAGIC.to_owned(), store, header: None, chunks: Vec::new(), trailer: None }
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path).with_context(|| format!("failed to read chunk index {}", path.display()))?;
+        let index: Self = serde_json::from_slice(&contents).with_context(|| format!("invalid chunk index {}", path.display()))?;
+        if index.magic != INDEX_MAGIC {
+            anyhow::bail!("not a sweetbyte chunk index: {}", path.display());
+        }
+
+        Ok(index)
+    }
+
+    pub(crate) async fn save(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_vec(self).context("failed to serialize chunk index")?;
+        let temp = TempFile::new(path)?;
+        tokio::fs::write(temp.path(), contents).await.context("failed to write chunk index")?;
+        temp.persist().await.context("failed to finalize chunk index")
+    }
+
+    pub(crate) fn open_store(&self) -> DirectoryStore {
+        DirectoryStore::new(&self.store)
+    }
+
+    pub(crate) async fn replace_header(path: &Path, header: &[u8]) -> Result<()> {
+        let mut index = Self::load(path)?;
+        index.header = Some(index.open_store().put(header).context("failed to store header object")?);
+        index.save(path).await
+    }
+}
+
+pub(crate) fn is_index(path: &Path) -> bool {
+    let mut prefix = [0u8; INDEX_PREFIX.len()];
+
+    std::fs::File::open(path).and_then(|mut file| file.read_exact(&mut prefix)).is_ok_and(|()| prefix == INDEX_PREFIX)
+}
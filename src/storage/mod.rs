@@ -0,0 +1,16 @@
+mod directory;
+mod index;
+mod reader;
+mod writer;
+
+use anyhow::Result;
+pub(crate) use directory::DirectoryStore;
+pub(crate) use index::{Index, is_index};
+pub(crate) use reader::StoreReader;
+pub(crate) use writer::{StoreOutput, StoreWriter};
+
+pub(crate) trait ObjectStore: Send + Sync {
+    fn put(&self, data: &[u8]) -> Result<String>;
+
+    fn get(&self, id: &str) -> Result<Vec<u8>>;
+}
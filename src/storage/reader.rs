@@ -0,0 +1,70 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use super::{Index, ObjectStore};
+use crate::config::PAYLOAD_MAC_MARKER;
+
+pub(crate) struct StoreReader {
+    store: Box<dyn ObjectStore>,
+    index: Index,
+    next: usize,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl StoreReader {
+    pub(crate) fn new(index: Index) -> Self {
+        let store = Box::new(index.open_store());
+        Self { store, index, next: 0, buffer: Vec::new(), position: 0 }
+    }
+
+    fn load_next(&mut self) -> Result<bool> {
+        let chunks = self.index.chunks.len();
+        let record = match self.next {
+            0 => {
+                let id = self.index.header.as_deref().context("chunk index has no header")?;
+                self.store.get(id)?
+            }
+            n if n <= chunks => {
+                let id = self.index.chunks.get(n.saturating_sub(1)).context("chunk index out of range")?;
+                let chunk = self.store.get(id)?;
+                let length = u32::try_from(chunk.len()).context("chunk too large")?;
+                [length.to_le_bytes().as_slice(), &chunk].concat()
+            }
+            n if n == chunks.saturating_add(1) => match self.index.trailer.as_deref() {
+                Some(id) => [PAYLOAD_MAC_MARKER.to_le_bytes().as_slice(), &self.store.get(id)?].concat(),
+                None => return Ok(false),
+            },
+            _ => return Ok(false),
+        };
+
+        self.next = self.next.saturating_add(1);
+        self.buffer = record;
+        self.position = 0;
+
+        Ok(true)
+    }
+}
+
+impl AsyncRead for StoreReader {
+    fn poll_read(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        while this.position >= this.buffer.len() {
+            if !this.load_next().map_err(io::Error::other)? {
+                return Poll::Ready(Ok(()));
+            }
+        }
+
+        let pending = this.buffer.get(this.position..).unwrap_or_default();
+        let count = pending.len().min(buf.remaining());
+        buf.put_slice(pending.get(..count).unwrap_or_default());
+        this.position = this.position.saturating_add(count);
+
+        Poll::Ready(Ok(()))
+    }
+}
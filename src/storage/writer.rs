@@ -0,0 +1,129 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context as TaskContext, Poll};
+
+use anyhow::{Context, Result};
+use tokio::io::AsyncWrite;
+
+use super::{Index, ObjectStore};
+use crate::config::PAYLOAD_MAC_MARKER;
+use crate::pipeline::TRAILER_BODY_LEN;
+
+const PREFIX_LEN: usize = size_of::<u32>();
+const HEADER_PREFIX_LEN: usize = PREFIX_LEN * 2;
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum State {
+    Header,
+    Chunks,
+    Done,
+}
+
+struct Shared {
+    index: Index,
+    partial: bool,
+}
+
+pub(crate) struct StoreOutput {
+    target: PathBuf,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl StoreOutput {
+    pub(crate) async fn persist(self) -> Result<()> {
+        let index = {
+            let shared = self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+            if shared.partial || shared.index.header.is_none() {
+                anyhow::bail!("encrypted stream ended in the middle of a record");
+            }
+
+            shared.index.clone()
+        };
+
+        index.save(&self.target).await
+    }
+}
+
+pub(crate) struct StoreWriter {
+    store: Arc<dyn ObjectStore>,
+    buffer: Vec<u8>,
+    state: State,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl StoreWriter {
+    pub(crate) fn create(target: &Path, store_root: PathBuf, store: Arc<dyn ObjectStore>) -> (StoreOutput, Self) {
+        let shared = Arc::new(Mutex::new(Shared { index: Index::new(store_root), partial: false }));
+        let output = StoreOutput { target: target.to_path_buf(), shared: Arc::clone(&shared) };
+
+        (output, Self { store, buffer: Vec::new(), state: State::Header, shared })
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        while let Some(consumed) = self.next_record()? {
+            self.buffer.drain(..consumed);
+        }
+
+        self.shared.lock().unwrap_or_else(PoisonError::into_inner).partial = !self.buffer.is_empty();
+
+        Ok(())
+    }
+
+    fn next_record(&mut self) -> Result<Option<usize>> {
+        let Some(prefix) = self.buffer.get(..PREFIX_LEN) else { return Ok(None) };
+        let length = u32::from_le_bytes(prefix.try_into().context("invalid record prefix")?);
+
+        match self.state {
+            State::Header => {
+                let Some(capacity) = self.buffer.get(PREFIX_LEN..HEADER_PREFIX_LEN) else { return Ok(None) };
+                let capacity = usize::try_from(u32::from_le_bytes(capacity.try_into().context("invalid header capacity")?)).context("header capacity overflow")?;
+                let Some(header) = self.buffer.get(..HEADER_PREFIX_LEN.saturating_add(capacity)) else { return Ok(None) };
+
+                let id = self.store.put(header).context("failed to store header object")?;
+                self.shared.lock().unwrap_or_else(PoisonError::into_inner).index.header = Some(id);
+                self.state = State::Chunks;
+
+                Ok(Some(header.len()))
+            }
+            State::Chunks if length == PAYLOAD_MAC_MARKER => {
+                let Some(body) = self.buffer.get(PREFIX_LEN..PREFIX_LEN.saturating_add(TRAILER_BODY_LEN)) else { return Ok(None) };
+
+                let id = self.store.put(body).context("failed to store trailer object")?;
+                self.shared.lock().unwrap_or_else(PoisonError::into_inner).index.trailer = Some(id);
+                self.state = State::Done;
+
+                Ok(Some(PREFIX_LEN.saturating_add(TRAILER_BODY_LEN)))
+            }
+            State::Chunks => {
+                let length = usize::try_from(length).context("chunk length overflow")?;
+                let Some(chunk) = self.buffer.get(PREFIX_LEN..PREFIX_LEN.saturating_add(length)) else { return Ok(None) };
+
+                let id = self.store.put(chunk).context("failed to store chunk object")?;
+                self.shared.lock().unwrap_or_else(PoisonError::into_inner).index.chunks.push(id);
+
+                Ok(Some(PREFIX_LEN.saturating_add(length)))
+            }
+            State::Done => anyhow::bail!("unexpected data after payload authentication record"),
+        }
+    }
+}
+
+impl AsyncWrite for StoreWriter {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.buffer.extend_from_slice(buf);
+        this.drain().map_err(io::Error::other)?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,282 @@
+//! Adapters that encrypt or decrypt a stream of unknown length, such as a socket or a tar stream,
+//! without a `.swx` header. A stream starts with the cipher, a random salt, and a nonce prefix, and
+//! ends with the payload MAC record, so dropping whole chunks from its end is caught.
+
+use std::io::{self, Read, Write};
+
+use anyhow::{Context, Result};
+
+use crate::api::EncryptOptions;
+use crate::cipher::{CipherMode, DerivedKeys, KeyDeriver, RandomSource, SystemRandom};
+use crate::config::{ARGON2_SALT_LEN, COMPRESSION_LEVEL, MAX_CHUNK_SIZE, NONCE_PREFIX_LEN, PAYLOAD_MAC_MARKER};
+use crate::padding::PaddingScheme;
+use crate::pipeline::{Operation, PayloadMac, Process, TRAILER_BODY_LEN, Task};
+use crate::secret::Secret;
+use crate::secure_temp::SecureBuffer;
+
+/// Start of a stream, ahead of its cipher flags, salt, and nonce prefix.
+const STREAM_MAGIC: [u8; 4] = *b"SWXS";
+const LENGTH_PREFIX_LEN: usize = 4;
+const PREAMBLE_LEN: usize = STREAM_MAGIC.len() + 1 + ARGON2_SALT_LEN + NONCE_PREFIX_LEN;
+
+/// Reads `inner` and yields it encrypted under `key`, a chunk at a time.
+///
+/// The output is read back with a [`DecryptingWriter`] holding the same key. Every stream gets its
+/// own salt and nonce prefix, so one key can seal many streams.
+pub struct EncryptingReader<R> {
+    inner: R,
+    process: Process,
+    mac: PayloadMac,
+    chunk_size: usize,
+    index: u64,
+    frame: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> EncryptingReader<R> {
+    /// Takes the cipher, chunk size, and compression level from `options`. The stream always ends
+    /// with a payload MAC, whatever [`EncryptOptions::with_payload_mac`] says, since nothing else
+    /// marks where it ends.
+    pub fn new(inner: R, key: &[u8; 32], options: EncryptOptions) -> Result<Self> {
+        let settings = options.settings()?;
+        let mut preamble = Vec::with_capacity(PREAMBLE_LEN);
+        preamble.extend_from_slice(&STREAM_MAGIC);
+        preamble.push(options.cipher().bits());
+        let mut random = [0u8; ARGON2_SALT_LEN + NONCE_PREFIX_LEN];
+        SystemRandom.fill(&mut random).context("failed to generate stream salt")?;
+        preamble.extend_from_slice(&random);
+
+        let (salt, nonce_prefix) = random.split_at(ARGON2_SALT_LEN);
+        let keys = stream_keys(&Secret::new(key.to_vec()), salt)?;
+        let process = Process::new(&keys.primary_key, &keys.secondary_key, Operation::Encryption, options.cipher(), nonce_prefix, settings.compression_level)
+            .context("failed to initialize process")?
+            .with_padding_scheme(settings.padding);
+
+        Ok(Self { inner, process, mac: PayloadMac::new(&keys.payload_key)?, chunk_size: settings.chunk_size, index: 0, frame: preamble, position: 0, finished: false })
+    }
+
+    fn next_frame(&mut self) -> io::Result<()> {
+        let mut data = SecureBuffer::zeroed(self.chunk_size);
+        let mut filled = 0;
+        while let Some(rest) = data.get_mut(filled..).filter(|rest| !rest.is_empty()) {
+            match self.inner.read(rest) {
+                Ok(0) => break,
+                Ok(read) => filled = filled.saturating_add(read),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.position = 0;
+        if filled == 0 {
+            self.frame = self.mac.trailer();
+            self.finished = true;
+            return Ok(());
+        }
+        data.truncate(filled);
+
+        let result = self.process.process(&Task { data, index: self.index }).map_err(io::Error::other)?;
+        let len = u32::try_from(result.data.len()).map_err(io::Error::other)?;
+        self.mac.update(self.index, &result.data).map_err(io::Error::other)?;
+
+        self.frame.clear();
+        self.frame.extend_from_slice(&len.to_le_bytes());
+        self.frame.extend_from_slice(&result.data);
+        self.index = self.index.saturating_add(1);
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for EncryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.frame.len() {
+            if self.finished {
+                return Ok(0);
+            }
+
+            self.next_frame()?;
+        }
+
+        let pending = self.frame.get(self.position..).unwrap_or_default();
+        let len = pending.len().min(buf.len());
+        buf.get_mut(..len).unwrap_or_default().copy_from_slice(pending.get(..len).unwrap_or_default());
+        self.position = self.position.saturating_add(len);
+
+        Ok(len)
+    }
+}
+
+/// Takes a stream written by an [`EncryptingReader`] and writes it decrypted to `inner`.
+///
+/// Each chunk is authenticated before it's written, but only [`Self::finish`] can tell that the
+/// stream wasn't cut short, so a caller has to treat what was written as incomplete until it
+/// returns.
+pub struct DecryptingWriter<W> {
+    inner: W,
+    key: Secret,
+    opened: Option<(Process, PayloadMac)>,
+    index: u64,
+    buffer: Vec<u8>,
+    ended: bool,
+}
+
+impl<W: Write> DecryptingWriter<W> {
+    pub fn new(inner: W, key: &[u8; 32]) -> Self {
+        Self { inner, key: Secret::new(key.to_vec()), opened: None, index: 0, buffer: Vec::new(), ended: false }
+    }
+
+    /// Checks that the stream reached its payload MAC and hands back the writer.
+    pub fn finish(mut self) -> Result<W> {
+        if !self.ended {
+            anyhow::bail!("stream ended after {} chunks, before its payload MAC", self.index);
+        }
+
+        self.inner.flush().context("failed to flush output")?;
+
+        Ok(self.inner)
+    }
+
+    fn open(&mut self) -> io::Result<bool> {
+        let Some(preamble) = self.buffer.first_chunk::<PREAMBLE_LEN>() else { return Ok(false) };
+        let (magic, rest) = preamble.split_at(STREAM_MAGIC.len());
+        if magic != STREAM_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a sweetbyte stream"));
+        }
+
+        let (bits, rest) = rest.split_first().ok_or_else(|| io::Error::other("stream preamble is truncated"))?;
+        let mode = CipherMode::from_bits(*bits).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let (salt, nonce_prefix) = rest.split_at(ARGON2_SALT_LEN);
+        let keys = stream_keys(&self.key, salt).map_err(io::Error::other)?;
+        let process = Process::new(&keys.primary_key, &keys.secondary_key, Operation::Decryption, mode, nonce_prefix, COMPRESSION_LEVEL)
+            .map_err(io::Error::other)?
+            .with_padding_scheme(PaddingScheme::default());
+
+        self.opened = Some((process, PayloadMac::new(&keys.payload_key).map_err(io::Error::other)?));
+        self.buffer.drain(..PREAMBLE_LEN);
+
+        Ok(true)
+    }
+
+    fn drain_frames(&mut self) -> io::Result<()> {
+        if self.opened.is_none() && !self.open()? {
+            return Ok(());
+        }
+
+        loop {
+            if self.ended && !self.buffer.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "data after the end of the stream"));
+            }
+            let Some(prefix) = self.buffer.first_chunk::<LENGTH_PREFIX_LEN>() else { return Ok(()) };
+            let Some((process, mac)) = self.opened.as_mut() else { return Ok(()) };
+
+            let chunk_len = u32::from_le_bytes(*prefix);
+            if chunk_len == PAYLOAD_MAC_MARKER {
+                let Some(body) = self.buffer.get(LENGTH_PREFIX_LEN..).and_then(<[u8]>::first_chunk::<TRAILER_BODY_LEN>) else { return Ok(()) };
+                mac.verify(body).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+                self.buffer.drain(..LENGTH_PREFIX_LEN.saturating_add(TRAILER_BODY_LEN));
+                self.ended = true;
+                continue;
+            }
+            if chunk_len > MAX_CHUNK_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("chunk size {chunk_len} exceeds maximum {MAX_CHUNK_SIZE}")));
+            }
+
+            let frame_len = usize::try_from(chunk_len).map_err(io::Error::other)?.saturating_add(LENGTH_PREFIX_LEN);
+            let Some(data) = self.buffer.get(LENGTH_PREFIX_LEN..frame_len) else { return Ok(()) };
+
+            mac.update_record(self.index, data).map_err(io::Error::other)?;
+            let task = Task { data: SecureBuffer::unlocked(data.to_vec()), index: self.index };
+            let result = process.process(&task).map_err(io::Error::other)?;
+            self.inner.write_all(&result.data)?;
+            self.buffer.drain(..frame_len);
+            self.index = self.index.saturating_add(1);
+        }
+    }
+}
+
+impl<W: Write> Write for DecryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        self.drain_frames()?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The stream's cipher and MAC keys, expanded from the caller's key under the stream's salt.
+fn stream_keys(key: &Secret, salt: &[u8]) -> Result<DerivedKeys> {
+    KeyDeriver::expand(key, &Secret::new(salt.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [1u8; 32];
+
+    fn encrypt(plaintext: &[u8], options: EncryptOptions) -> Vec<u8> {
+        let mut encrypted = Vec::new();
+        EncryptingReader::new(plaintext, &KEY, options).unwrap().read_to_end(&mut encrypted).unwrap();
+        encrypted
+    }
+
+    fn decrypt(encrypted: &[u8]) -> Result<Vec<u8>> {
+        let mut writer = DecryptingWriter::new(Vec::new(), &KEY);
+        writer.write_all(encrypted)?;
+        writer.finish()
+    }
+
+    #[test]
+    fn roundtrip_through_adapters() {
+        let plaintext: Vec<u8> = (0..20_000u32).map(|i| u8::try_from(i % 251).unwrap()).collect();
+        let encrypted = encrypt(&plaintext, EncryptOptions::new().with_chunk_size(4096));
+
+        let mut writer = DecryptingWriter::new(Vec::new(), &KEY);
+        for piece in encrypted.chunks(1000) {
+            writer.write_all(piece).unwrap();
+        }
+
+        assert_eq!(writer.finish().unwrap(), plaintext);
+        assert_eq!(decrypt(&encrypt(b"", EncryptOptions::new())).unwrap(), b"");
+    }
+
+    #[test]
+    fn truncated_stream_is_rejected() {
+        let mut encrypted = encrypt(b"hello stream", EncryptOptions::new().with_cipher(CipherMode::Aes));
+        encrypted.pop();
+
+        decrypt(&encrypted).unwrap_err();
+    }
+
+    #[test]
+    fn dropped_frames_are_rejected() {
+        let plaintext = vec![7u8; 4096 * 3];
+        let encrypted = encrypt(&plaintext, EncryptOptions::new().with_chunk_size(4096));
+
+        let mut frames = Vec::new();
+        let mut rest = &encrypted[PREAMBLE_LEN..];
+        while let Some((prefix, _)) = rest.split_first_chunk::<LENGTH_PREFIX_LEN>() {
+            let len = match u32::from_le_bytes(*prefix) {
+                PAYLOAD_MAC_MARKER => TRAILER_BODY_LEN,
+                len => usize::try_from(len).unwrap(),
+            };
+            let (frame, tail) = rest.split_at(LENGTH_PREFIX_LEN + len);
+            frames.push(frame);
+            rest = tail;
+        }
+        assert_eq!(frames.len(), 4);
+
+        let without_last_chunk = [&encrypted[..PREAMBLE_LEN], frames[0], frames[1], frames[3]].concat();
+        let error = decrypt(&without_last_chunk).unwrap_err();
+        assert!(format!("{error:#}").contains("expected 3 chunks, found 2"));
+
+        let without_trailer = [&encrypted[..PREAMBLE_LEN], frames[0], frames[1], frames[2]].concat();
+        let error = decrypt(&without_trailer).unwrap_err();
+        assert!(format!("{error:#}").contains("before its payload MAC"));
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::app::Options;
+use crate::cipher::{CipherMode, RandomSource, SeededRandom};
+use crate::config::{CHUNK_SIZE, Settings};
+use crate::files::{Attributes, Files};
+use crate::secret::Secret;
+
+pub(crate) const PASSWORD: &[u8] = b"sweetbyte-test-vector";
+const MODE: u32 = 0o644;
+
+pub(crate) struct Vector {
+    pub(crate) name: &'static str,
+    pub(crate) seed: u64,
+    pub(crate) mode: CipherMode,
+    pub(crate) len: usize,
+}
+
+pub(crate) const VECTORS: &[Vector] = &[
+    Vector { name: "small-dual", seed: 1, mode: CipherMode::Dual, len: 13 },
+    Vector { name: "small-aes", seed: 2, mode: CipherMode::Aes, len: 13 },
+    Vector { name: "small-chacha", seed: 3, mode: CipherMode::Chacha, len: 13 },
+    Vector { name: "multi-chunk-dual", seed: 4, mode: CipherMode::Dual, len: CHUNK_SIZE * 2 + 17 },
+];
+
+impl Vector {
+    pub(crate) fn plaintext(&self) -> Result<Vec<u8>> {
+        let mut plaintext = vec![0u8; self.len];
+        SeededRandom::new(!self.seed).fill(&mut plaintext)?;
+
+        Ok(plaintext)
+    }
+
+    pub(crate) fn plaintext_name(&self) -> String {
+        format!("{}.bin", self.name)
+    }
+
+    pub(crate) fn encrypted_name(&self) -> String {
+        format!("{}.bin.swx", self.name)
+    }
+}
+
+pub(crate) async fn generate(dir: &Path) -> Result<Vec<PathBuf>> {
+    tokio::fs::create_dir_all(dir).await.context("failed to create vector directory")?;
+    let secret = Secret::new(PASSWORD.to_vec());
+    let mut generated = Vec::with_capacity(VECTORS.len());
+
+    for vector in VECTORS {
+        let source = Files::new(dir.join(vector.plaintext_name()));
+        let target = Files::new(dir.join(vector.encrypted_name()));
+        tokio::fs::write(source.path(), vector.plaintext()?).await.context("failed to write vector plaintext")?;
+        Attributes::new(Some(0), Some(MODE)).apply(source.path(), false)?;
+
+        let rng = SeededRandom::new(vector.seed);
+        let options = Options { cipher: vector.mode, ..Options::new(Settings::default()) };
+        crate::app::encrypt(&source, &target, std::slice::from_ref(&secret), &options, &rng)
+            .await
+            .with_context(|| format!("failed to generate vector {}", vector.name))?;
+        generated.push(target.path().to_path_buf());
+    }
+
+    Ok(generated)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use tokio::fs;
+
+    use super::*;
+    use crate::app::Destination;
+
+    #[tokio::test]
+    async fn generation_is_deterministic() {
+        let first = tempdir().unwrap();
+        let second = tempdir().unwrap();
+
+        generate(first.path()).await.unwrap();
+        generate(second.path()).await.unwrap();
+
+        for vector in VECTORS {
+            let left = fs::read(first.path().join(vector.encrypted_name())).await.unwrap();
+            let right = fs::read(second.path().join(vector.encrypted_name())).await.unwrap();
+            assert_eq!(left, right, "vector {} is not deterministic", vector.name);
+        }
+    }
+
+    #[tokio::test]
+    async fn golden_files_match() {
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("vectors");
+        let dir = tempdir().unwrap();
+        generate(dir.path()).await.unwrap();
+
+        for vector in VECTORS {
+            let golden_path = golden.join(vector.encrypted_name());
+            let expected = fs::read(&golden_path).await.unwrap_or_else(|error| panic!("missing golden file {}: {error}", golden_path.display()));
+            let actual = fs::read(dir.path().join(vector.encrypted_name())).await.unwrap();
+            assert_eq!(expected, actual, "vector {} diverged from golden file", vector.name);
+
+            let decrypted = Destination::File(Files::new(dir.path().join(vector.plaintext_name()).with_extension("out")));
+            let options = Options::new(Settings::default());
+            let (_, output) = crate::app::decrypt(&Files::new(&golden_path), &decrypted, &Secret::new(PASSWORD.to_vec()), &options).await.unwrap();
+            assert_eq!(fs::read(output).await.unwrap(), vector.plaintext().unwrap());
+        }
+    }
+}
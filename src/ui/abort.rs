@@ -0,0 +1,103 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use cliclack::ProgressBar;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use tokio_util::sync::CancellationToken;
+
+/// How long the listener waits for a key before checking whether it should stop.
+const POLL: Duration = Duration::from_millis(100);
+
+const PROMPT: &str = "abort? y stops and discards the partial output, any other key carries on";
+
+/// Listens for `q` or Esc while a transfer draws its bar, and cancels it once `y` confirms. The
+/// terminal is in raw mode meanwhile, so Ctrl-C arrives as a key rather than a signal and cancels
+/// straight away.
+pub(super) struct AbortKey {
+    stop: Arc<AtomicBool>,
+    confirming: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl AbortKey {
+    /// Starts listening, or returns `None` if the terminal can't be put in raw mode.
+    pub(super) fn start(cancel: CancellationToken, bar: Arc<ProgressBar>, message: String) -> Option<Self> {
+        terminal::enable_raw_mode().ok()?;
+
+        let (stop, confirming) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)));
+        let listener = Listener { stop: Arc::clone(&stop), confirming: Arc::clone(&confirming), cancel, bar, message };
+        match thread::Builder::new().name("abort-key".into()).spawn(move || listener.run()) {
+            Ok(handle) => Some(Self { stop, confirming, handle: Some(handle) }),
+            Err(_) => {
+                terminal::disable_raw_mode().unwrap_or_default();
+                None
+            }
+        }
+    }
+
+    /// Whether the bar is showing the prompt, which progress updates shouldn't overwrite.
+    pub(super) fn confirming(&self) -> bool {
+        self.confirming.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for AbortKey {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap_or_default();
+        }
+        terminal::disable_raw_mode().unwrap_or_default();
+    }
+}
+
+struct Listener {
+    stop: Arc<AtomicBool>,
+    confirming: Arc<AtomicBool>,
+    cancel: CancellationToken,
+    bar: Arc<ProgressBar>,
+    message: String,
+}
+
+impl Listener {
+    fn run(&self) {
+        while !self.stop.load(Ordering::Relaxed) {
+            match event::poll(POLL) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => return,
+            }
+            let Ok(Event::Key(key)) = event::read() else { continue };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            let confirming = self.confirming.load(Ordering::Relaxed);
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return self.abort(),
+                KeyCode::Char('y' | 'Y') if confirming => return self.abort(),
+                KeyCode::Char('q' | 'Q') | KeyCode::Esc if !confirming => {
+                    self.confirming.store(true, Ordering::Relaxed);
+                    self.bar.set_message(format!("{} ({PROMPT})", self.message));
+                }
+                _ if confirming => {
+                    self.confirming.store(false, Ordering::Relaxed);
+                    self.bar.set_message(&self.message);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Cancels the transfer and leaves raw mode at once, so a further Ctrl-C is a signal again and
+    /// exits immediately.
+    fn abort(&self) {
+        terminal::disable_raw_mode().unwrap_or_default();
+        self.confirming.store(true, Ordering::Relaxed);
+        self.bar.set_message(format!("{} (aborting...)", self.message));
+        self.cancel.cancel();
+    }
+}
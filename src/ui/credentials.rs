@@ -0,0 +1,64 @@
+use std::time::{Duration, Instant};
+
+use crate::secret::Secret;
+
+/// Passwords entered during an interactive batch. They live only in memory and are wiped when
+/// dropped, which happens at the end of the batch or once `timeout` passes without them being used.
+pub(crate) struct PasswordCache {
+    timeout: Option<Duration>,
+    passwords: Vec<Secret>,
+    last_used: Instant,
+}
+
+impl PasswordCache {
+    /// A cache that keeps its passwords for the whole batch when `timeout` is `None`.
+    pub(crate) fn new(timeout: Option<Duration>) -> Self {
+        Self { timeout, passwords: Vec::new(), last_used: Instant::now() }
+    }
+
+    /// Forgets every password if the cache has sat unused for longer than its timeout; returns
+    /// whether it did.
+    pub(crate) fn expire(&mut self) -> bool {
+        let idle = self.last_used.elapsed();
+        if self.passwords.is_empty() || self.timeout.is_none_or(|timeout| idle < timeout) {
+            return false;
+        }
+
+        self.passwords.clear();
+        true
+    }
+
+    /// The passwords held, oldest first.
+    pub(crate) fn passwords(&mut self) -> &[Secret] {
+        self.last_used = Instant::now();
+        &self.passwords
+    }
+
+    pub(crate) fn insert(&mut self, password: Secret) {
+        self.last_used = Instant::now();
+        self.passwords.push(password);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_expire_only_with_a_timeout() {
+        let mut kept = PasswordCache::new(None);
+        kept.insert(Secret::new(b"password".to_vec()));
+        assert!(!kept.expire());
+        assert_eq!(kept.passwords().len(), 1);
+
+        let mut expiring = PasswordCache::new(Some(Duration::ZERO));
+        assert!(!expiring.expire());
+        expiring.insert(Secret::new(b"password".to_vec()));
+        assert!(expiring.expire());
+        assert!(expiring.passwords().is_empty());
+
+        let mut fresh = PasswordCache::new(Some(Duration::from_secs(3600)));
+        fresh.insert(Secret::new(b"password".to_vec()));
+        assert!(!fresh.expire());
+    }
+}
@@ -1,10 +1,17 @@
+use std::path::{Path, PathBuf};
+
 use anyhow::{Context, Result};
 use comfy_table::modifiers::UTF8_ROUND_CORNERS;
 use comfy_table::presets::UTF8_FULL;
 use comfy_table::{Cell, Color, ContentArrangement, Table};
 
+use super::report::{
+    CalibrateReport, CheckReport, ChunksReport, CorruptReport, ErrorReport, GeneratedReport, KeyReport, ManifestReport, OutputFormat, RecoverReport, Report, SelftestReport, Status, TextReport,
+    WatchReport,
+};
 use crate::files::Files;
-use crate::pipeline::Operation;
+use crate::manifest::Outcome;
+use crate::pipeline::{Operation, Throughput};
 
 pub(crate) async fn files(items: &[Files]) -> Result<()> {
     if items.is_empty() {
@@ -27,7 +34,7 @@ pub(crate) async fn files(items: &[Files]) -> Result<()> {
     cliclack::note(format!("Found {} file(s)", items.len()), table.to_string()).context("failed to display files")
 }
 
-pub(crate) fn success(operation: Operation, file: &Files) -> Result<()> {
+fn success(operation: Operation, file: &Files) -> Result<()> {
     let process = match operation {
         Operation::Encryption => "encrypted",
         Operation::Decryption => "decrypted",
@@ -36,11 +43,50 @@ pub(crate) fn success(operation: Operation, file: &Files) -> Result<()> {
     cliclack::log::success(format!("File {process} successfully: {}", file.name())).context("failed to display success message")
 }
 
+fn restored(path: &Path) -> Result<()> {
+    cliclack::log::info(format!("Restored original name: {}", path.display())).context("failed to display restored path")
+}
+
+pub(crate) fn saved(path: &Path) -> Result<()> {
+    cliclack::log::info(format!("Options saved to {}", path.display())).context("failed to display saved options")
+}
+
+#[cfg(feature = "fuse")]
+pub(crate) fn mounted(path: &Path) -> Result<()> {
+    cliclack::log::info(format!("Mounted read-only at {}; press Ctrl-C to unmount", path.display())).context("failed to display mount point")
+}
+
+pub(crate) fn watching(dir: &Path, output_dir: &Path) -> Result<()> {
+    cliclack::log::info(format!("Watching {} and encrypting into {}; press Ctrl-C to stop", dir.display(), output_dir.display())).context("failed to display watch")
+}
+
+/// Logs one file a watch handled as it happens, so a long-running watch leaves a record of what it
+/// did.
+pub(crate) fn watched(report: &WatchReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    let (source, output) = (report.source.display(), report.output.display());
+    match (&report.status, &report.error) {
+        (Status::Skipped, _) => cliclack::log::info(format!("Unchanged, skipped: {source}")),
+        (Status::Failed, Some(error)) => cliclack::log::error(format!("{source}: {error}")),
+        _ => cliclack::log::success(format!("Encrypted {source} -> {output}")),
+    }
+    .context("failed to display watched file")
+}
+
+/// Shows the `--split-key` shares once, to be written down or handed out; they aren't stored
+/// anywhere else.
+pub(crate) fn shares(shares: &[String]) -> Result<()> {
+    cliclack::note("Key shares (keep each one separately)", shares.join("\n")).context("failed to display key shares")
+}
+
 pub(crate) fn deleted(file: &Files) -> Result<()> {
     cliclack::log::success(format!("Source file deleted: {}", file.name())).context("failed to display deletion message")
 }
 
-pub(crate) fn header(file_name: &str, file_size: u64, file_hash: &str) -> Result<()> {
+fn header(file_name: &str, file_size: u64, file_hash: &str) -> Result<()> {
     let mut table = Table::new();
     table.load_preset(UTF8_FULL).apply_modifier(UTF8_ROUND_CORNERS).set_content_arrangement(ContentArrangement::Dynamic);
 
@@ -52,6 +98,256 @@ pub(crate) fn header(file_name: &str, file_size: u64, file_hash: &str) -> Result
     cliclack::note("Header Information", table.to_string()).context("failed to display header")
 }
 
+pub(crate) fn report(report: &Report, operation: Operation, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    if matches!(report.status, Status::Skipped) {
+        return cliclack::log::info(format!("Unchanged, skipped: {}", report.source.display())).context("failed to display skipped file");
+    }
+
+    success(operation, &Files::new(&report.output))?;
+    if report.restored {
+        restored(&report.output)?;
+    }
+
+    header(&report.name, report.size, &report.hash)?;
+    match &report.throughput {
+        Some(throughput) => summary(throughput),
+        None => Ok(()),
+    }
+}
+
+fn summary(throughput: &Throughput) -> Result<()> {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL).apply_modifier(UTF8_ROUND_CORNERS).set_content_arrangement(ContentArrangement::Dynamic);
+
+    let stages = &throughput.stages;
+    let rows = [
+        ("Elapsed", format!("{} ms", throughput.elapsed_ms)),
+        ("Throughput", format!("{:.2} MB/s", throughput.mb_per_s)),
+        ("Compression Ratio", format!("{:.3}", throughput.compression_ratio)),
+        ("Reed-Solomon Overhead", format!("{:.1}%", throughput.rs_overhead * 100.0)),
+        ("Read", format!("{} ms", stages.read_ms)),
+        ("Compression", format!("{} ms", stages.compression_ms)),
+        ("Padding", format!("{} ms", stages.padding_ms)),
+        ("Encryption", format!("{} ms", stages.encryption_ms)),
+        ("Encoding", format!("{} ms", stages.encoding_ms)),
+        ("Write", format!("{} ms", stages.write_ms)),
+    ];
+    for (label, value) in rows {
+        table.add_row([Cell::new(label).fg(Color::Green), Cell::new(value).fg(Color::White)]);
+    }
+
+    cliclack::note("Summary", table.to_string()).context("failed to display summary")
+}
+
+pub(crate) fn batch(succeeded: &[&Files], failed: &[(&Files, anyhow::Error)]) -> Result<()> {
+    for (file, error) in failed {
+        cliclack::log::error(format!("{}: {error:#}", file.name())).context("failed to display failed file")?;
+    }
+
+    let total = succeeded.len().saturating_add(failed.len());
+    cliclack::log::success(format!("Processed {} of {total} file(s)", succeeded.len())).context("failed to display batch result")
+}
+
+pub(crate) fn migrated(report: &Report, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    cliclack::log::success(format!("Migrated {} -> {}", report.source.display(), report.output.display())).context("failed to display migration result")?;
+    header(&report.name, report.size, &report.hash)?;
+    match &report.throughput {
+        Some(throughput) => summary(throughput),
+        None => Ok(()),
+    }
+}
+
+/// A file opened with `--allow-weak` despite falling short of the pinned policy.
+pub(crate) fn weak(source: &Path, shortfalls: &[String]) -> Result<()> {
+    cliclack::log::warning(format!("{} falls short of the pinned policy: {}", source.display(), shortfalls.join(", "))).context("failed to display policy warning")
+}
+
+/// A file migrated with `--drop-extras`, and what it lost.
+pub(crate) fn dropped(source: &Path, dropped: &[String]) -> Result<()> {
+    cliclack::log::warning(format!("Migrating {} dropped {}", source.display(), dropped.join(" and "))).context("failed to display migration warning")
+}
+
+/// A notify hook that failed; the run it reports on is unaffected.
+pub(crate) fn notify_failed(error: &anyhow::Error) -> Result<()> {
+    cliclack::log::warning(format!("Notification failed: {error:#}")).context("failed to display notification failure")
+}
+
+pub(crate) fn checked(report: &CheckReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    cliclack::log::success(format!("Password is correct for {}", report.source.display())).context("failed to display check result")?;
+    header(&report.name, report.size, &report.hash)
+}
+
+/// Prints the armored or decrypted message by itself on stdout, so it can be piped or copied as is.
+pub(crate) fn text(report: &TextReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    print!("{}", report.text);
+
+    Ok(())
+}
+
+pub(crate) fn keys(report: &KeyReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    cliclack::log::success(format!("Updated key slots for {} ({} in use)", report.source.display(), report.slots)).context("failed to display key result")
+}
+
+pub(crate) fn manifest(report: &ManifestReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    for entry in &report.entries {
+        let message = match &entry.detail {
+            Some(detail) => format!("{}: {detail}", entry.encrypted.display()),
+            None => format!("{}: verified", entry.encrypted.display()),
+        };
+
+        match entry.outcome {
+            Outcome::Ok => cliclack::log::success(message),
+            Outcome::Missing => cliclack::log::warning(message),
+            Outcome::Mismatch => cliclack::log::error(message),
+        }
+        .context("failed to display manifest entry")?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn chunks(report: &ChunksReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL).apply_modifier(UTF8_ROUND_CORNERS).set_content_arrangement(ContentArrangement::Dynamic);
+    table.set_header(["Chunk", "Offset", "Length", "Shards", "Nonce", "Tags", "Decrypts"].map(|h| Cell::new(h).fg(Color::White)));
+
+    let mark = |ok: bool| if ok { Cell::new("ok").fg(Color::Green) } else { Cell::new("bad").fg(Color::Red) };
+    for chunk in &report.layout.chunks {
+        let shards = Cell::new(format!("{}/{}", chunk.shards_intact, chunk.shards_total)).fg(if chunk.recoverable { Color::Green } else { Color::Red });
+        let decrypts = chunk.decrypts.map_or_else(|| Cell::new("-"), mark);
+        table.add_row([Cell::new(chunk.index), Cell::new(chunk.offset), Cell::new(chunk.length), shards, mark(chunk.nonce_matches), mark(chunk.tags_present), decrypts]);
+    }
+
+    let layout = &report.layout;
+    cliclack::note(format!("{} chunk(s) after a {}-byte header", layout.chunks.len(), layout.header_len), table.to_string()).context("failed to display chunks")?;
+
+    if let Some(problem) = &layout.problem {
+        cliclack::log::error(problem).context("failed to display chunk problem")?;
+    }
+    if u64::try_from(layout.chunks.len()).is_ok_and(|found| found != layout.expected) {
+        cliclack::log::error(format!("header expects {} chunks, found {}", layout.expected, layout.chunks.len())).context("failed to display chunk count")?;
+    }
+    if layout.trailer {
+        cliclack::log::info("Payload authentication record present").context("failed to display trailer")?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn recovered(report: &RecoverReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+    if report.files.is_empty() {
+        return cliclack::log::warning("No encrypted files found").context("failed to display recovery");
+    }
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL).apply_modifier(UTF8_ROUND_CORNERS).set_content_arrangement(ContentArrangement::Dynamic);
+    table.set_header(["Offset", "Name", "Chunks", "Lost", "Result"].map(|h| Cell::new(h).fg(Color::White)));
+
+    for file in &report.files {
+        let result = match (&file.problem, &file.output) {
+            (Some(problem), _) => Cell::new(problem).fg(Color::Red),
+            (None, Some(output)) if file.complete => Cell::new(output.display()).fg(Color::Green),
+            (None, output) => Cell::new(output.as_deref().map(Path::display).map(|path| format!("{path} (partial)")).unwrap_or_default()).fg(Color::Yellow),
+        };
+        table.add_row([Cell::new(file.offset), Cell::new(file.name.as_deref().unwrap_or("-")), Cell::new(file.chunks), Cell::new(file.lost), result]);
+    }
+
+    let complete = report.files.iter().filter(|file| file.complete).count();
+    cliclack::note(format!("{complete} of {} file(s) recovered whole", report.files.len()), table.to_string()).context("failed to display recovery")
+}
+
+pub(crate) fn corrupted(report: &CorruptReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    for trial in &report.trials {
+        match &trial.error {
+            Some(error) => cliclack::log::error(format!("Copy {}: {error}", trial.trial)),
+            None => cliclack::log::success(format!("Copy {}: recovered", trial.trial)),
+        }
+        .context("failed to display trial")?;
+    }
+
+    let damage = format!("{} flipped bit(s), {} wiped shard(s) per chunk", report.flip_bits, report.wipe_shards);
+    cliclack::log::info(format!("Recovered {} of {} copies with {damage} (seed {})", report.recovered, report.trials.len(), report.seed)).context("failed to display corruption summary")
+}
+
+pub(crate) fn selftest(report: &SelftestReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    for check in &report.checks {
+        match &check.detail {
+            Some(detail) => cliclack::log::error(format!("{}: {detail}", check.name)),
+            None if check.passed => cliclack::log::success(format!("{}: passed", check.name)),
+            None => cliclack::log::error(format!("{}: failed", check.name)),
+        }
+        .context("failed to display self-test result")?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn calibrated(report: &CalibrateReport, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(report);
+    }
+
+    let memory = humansize::format_size(u64::from(report.memory).saturating_mul(1024), humansize::BINARY);
+    cliclack::log::success(format!("Argon2id took {} ms against a {} ms target with {memory}", report.measured_ms, report.target_ms)).context("failed to display calibration result")?;
+    let snippet = format!("[kdf]\nalgorithm = \"argon2id\"\nmemory = {}\niterations = {}\nparallelism = {}", report.memory, report.iterations, report.parallelism);
+    match &report.config {
+        Some(path) => cliclack::log::info(format!("Wrote to {}:\n{snippet}", path.display())),
+        None => cliclack::log::info(format!("Add to your config file, or pass --write:\n{snippet}")),
+    }
+    .context("failed to display calibration result")
+}
+
+pub(crate) fn generated(paths: Vec<PathBuf>, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(&GeneratedReport { status: Status::Success, files: paths });
+    }
+
+    for path in &paths {
+        cliclack::log::success(format!("Generated {}", path.display())).context("failed to display generated file")?;
+    }
+
+    Ok(())
+}
+
 pub(crate) fn banner() -> Result<()> {
     let app_name = env!("CARGO_PKG_NAME");
     let version = option_env!("SWEETBYTE_BUILD_VERSION").unwrap_or(env!("CARGO_PKG_VERSION"));
@@ -66,3 +362,13 @@ pub(crate) fn exit() -> Result<()> {
 pub(crate) fn clear() -> Result<()> {
     cliclack::clear_screen().context("failed to clear screen")
 }
+
+/// Prints why the command failed as a JSON object on stdout. In text mode there's nothing to add to
+/// the error `main` prints on stderr.
+pub(crate) fn failure(error: &anyhow::Error, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Json {
+        return super::report::json(&ErrorReport::new(error));
+    }
+
+    Ok(())
+}
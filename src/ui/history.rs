@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::Input;
+use crate::cipher::{CipherMode, SystemRandom};
+use crate::config::{HISTORY_ENCRYPTED_FILE, HISTORY_FILE, HISTORY_LEN, Settings};
+use crate::files::Files;
+use crate::secret::Secret;
+
+/// Whether the interactive menu remembers what it processed, and how the list is kept on disk.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Deserialize, strum::EnumString, strum::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub(crate) enum HistoryMode {
+    #[default]
+    Off,
+    Plain,
+    Encrypted,
+}
+
+/// Recently processed files and the directories they were in, newest first.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct History {
+    directories: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl History {
+    /// Directories that still exist, for the quick pick of where to look for files.
+    pub(crate) fn directories(&self) -> Vec<PathBuf> {
+        self.directories.iter().filter(|dir| dir.is_dir()).cloned().collect()
+    }
+
+    pub(crate) fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    pub(crate) fn record(&mut self, processed: &[&Files]) {
+        for file in processed {
+            let Ok(path) = file.path().canonicalize() else { continue };
+            if let Some(dir) = path.parent() {
+                remember(&mut self.directories, dir.to_path_buf());
+            }
+            remember(&mut self.files, path);
+        }
+    }
+}
+
+fn remember(list: &mut Vec<PathBuf>, path: PathBuf) {
+    list.retain(|existing| *existing != path);
+    list.insert(0, path);
+    list.truncate(HISTORY_LEN);
+}
+
+/// Where the history lives under the config directory, and the password it's sealed with in
+/// encrypted mode.
+pub(crate) struct HistoryStore {
+    dir: PathBuf,
+    mode: HistoryMode,
+    secret: Option<Secret>,
+}
+
+impl HistoryStore {
+    /// Loads the history for `mode`, asking for its password first when it is kept encrypted.
+    /// `None` when history is off or there's no config directory to keep it in.
+    pub(crate) fn open(mode: HistoryMode, dir: Option<PathBuf>, input: &Input) -> Result<Option<(Self, History)>> {
+        let Some(dir) = dir else {
+            return Ok(None);
+        };
+
+        let plain = dir.join(HISTORY_FILE);
+        let encrypted = dir.join(HISTORY_ENCRYPTED_FILE);
+        let (secret, history) = match mode {
+            HistoryMode::Off => return Ok(None),
+            HistoryMode::Plain if plain.exists() => {
+                let contents = std::fs::read(&plain).with_context(|| format!("failed to read history {}", plain.display()))?;
+                (None, serde_json::from_slice(&contents).context("invalid history file")?)
+            }
+            HistoryMode::Plain => (None, History::default()),
+            HistoryMode::Encrypted if encrypted.exists() => {
+                let secret = input.prompt_password("History password")?;
+                let contents = std::fs::read(&encrypted).with_context(|| format!("failed to read history {}", encrypted.display()))?;
+                let contents = crate::memory::decrypt_bytes(&contents, &secret).context("failed to open history; wrong password?")?;
+                (Some(secret), serde_json::from_slice(&contents).context("invalid history file")?)
+            }
+            HistoryMode::Encrypted => (Some(input.new_password("New history password")?), History::default()),
+        };
+
+        Ok(Some((Self { dir, mode, secret }, history)))
+    }
+
+    /// Writes the history in the current mode and removes any copy left over from the other one.
+    pub(crate) fn save(&self, history: &History, settings: &Settings) -> Result<()> {
+        let contents = serde_json::to_vec_pretty(history).context("failed to serialize history")?;
+        let (path, stale, contents) = match (&self.secret, self.mode) {
+            (Some(secret), HistoryMode::Encrypted) => {
+                let sealed = crate::memory::encrypt_bytes(&contents, secret, CipherMode::default(), settings, &SystemRandom).context("failed to encrypt history")?;
+                (self.dir.join(HISTORY_ENCRYPTED_FILE), self.dir.join(HISTORY_FILE), sealed)
+            }
+            _ => (self.dir.join(HISTORY_FILE), self.dir.join(HISTORY_ENCRYPTED_FILE), contents),
+        };
+
+        std::fs::create_dir_all(&self.dir).with_context(|| format!("failed to create config directory {}", self.dir.display()))?;
+        std::fs::write(&path, contents).with_context(|| format!("failed to write history {}", path.display()))?;
+        if stale.exists() {
+            std::fs::remove_file(&stale).with_context(|| format!("failed to remove old history {}", stale.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `path` is one of the recently processed files.
+pub(crate) fn is_recent(recent: &[PathBuf], path: &Path) -> bool {
+    path.canonicalize().is_ok_and(|path| recent.contains(&path))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn newest_entries_come_first_without_duplicates() {
+        let dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = (0..=HISTORY_LEN).map(|index| dir.path().join(format!("file-{index}.txt"))).collect();
+        for path in &paths {
+            std::fs::write(path, b"content").unwrap();
+        }
+
+        let mut history = History::default();
+        for path in &paths {
+            history.record(&[&Files::new(path)]);
+        }
+        history.record(&[&Files::new(&paths[1])]);
+
+        assert_eq!(history.files().len(), HISTORY_LEN);
+        assert_eq!(history.files()[0], paths[1].canonicalize().unwrap());
+        assert!(!is_recent(history.files(), &paths[0]));
+        assert_eq!(history.directories(), vec![dir.path().canonicalize().unwrap()]);
+    }
+}
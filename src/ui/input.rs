@@ -1,7 +1,12 @@
-use std::path::PathBuf;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 
 use anyhow::{Context, Result};
+use zeroize::Zeroizing;
 
+use super::HistoryMode;
 use crate::files::Files;
 use crate::pipeline::Operation;
 use crate::secret::Secret;
@@ -11,34 +16,129 @@ pub(crate) struct Input {
     default_overwrite: bool,
     default_delete: bool,
     filter_mode: bool,
+    terminal: bool,
+    assume_yes: bool,
+    supplied: Option<Mutex<VecDeque<Secret>>>,
 }
 
 impl Input {
     pub(crate) fn new(min_password_len: usize, filter_mode: bool) -> Self {
-        Self { min_password_len, default_overwrite: false, default_delete: false, filter_mode }
+        Self { min_password_len, default_overwrite: false, default_delete: false, filter_mode, terminal: true, assume_yes: false, supplied: None }
+    }
+
+    /// Without a terminal every prompt fails with an error instead of waiting for input that can't
+    /// arrive.
+    pub(crate) fn with_terminal(mut self, terminal: bool) -> Self {
+        self.terminal = terminal;
+        self
+    }
+
+    /// Answers yes to overwriting an existing output without asking, with or without a terminal.
+    pub(crate) fn with_assume_yes(mut self, assume_yes: bool) -> Self {
+        self.assume_yes = assume_yes;
+        self
+    }
+
+    /// Takes passwords from `path`, one per line, in the order the command would otherwise prompt
+    /// for them; confirmations are skipped. `-` reads them from stdin.
+    pub(crate) fn with_password_file(mut self, path: &Path) -> Result<Self> {
+        let mut contents = Zeroizing::new(Vec::new());
+        if path == Path::new("-") {
+            std::io::stdin().read_to_end(&mut contents).context("failed to read passwords from stdin")?;
+        } else {
+            std::fs::File::open(path)
+                .and_then(|mut file| file.read_to_end(&mut contents))
+                .with_context(|| format!("failed to read password file {}", path.display()))?;
+        }
+
+        let lines = contents
+            .split(|byte| *byte == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty());
+        self.supplied = Some(Mutex::new(lines.map(|line| Secret::new(line.to_vec())).collect()));
+
+        Ok(self)
+    }
+
+    /// Answers the next password prompt with `secret`, such as the key `--key-share` rebuilds.
+    pub(crate) fn with_secret(mut self, secret: Secret) -> Self {
+        self.supplied = Some(Mutex::new(VecDeque::from([secret])));
+        self
+    }
+
+    fn next_supplied(&self, supplied: &Mutex<VecDeque<Secret>>) -> Result<Secret> {
+        let secret = supplied.lock().unwrap_or_else(PoisonError::into_inner).pop_front().context("password file has no more passwords")?;
+        if secret.expose_secret().len() < self.min_password_len {
+            anyhow::bail!("password must be at least {} characters", self.min_password_len);
+        }
+
+        Ok(secret)
     }
 
     pub(crate) fn password(&self, operation: Operation) -> Result<Secret> {
+        match operation {
+            Operation::Encryption => self.new_password("Enter encryption password"),
+            Operation::Decryption => self.prompt_password("Enter decryption password"),
+        }
+    }
+
+    pub(crate) fn passwords(&self, count: usize) -> Result<Vec<Secret>> {
+        if count <= 1 {
+            return self.password(Operation::Encryption).map(|secret| vec![secret]);
+        }
+
+        (1..=count).map(|n| self.new_password(&format!("Enter password {n} of {count}"))).collect()
+    }
+
+    pub(crate) fn new_password(&self, message: &str) -> Result<Secret> {
+        if let Some(supplied) = &self.supplied {
+            return self.next_supplied(supplied);
+        }
+
+        let password = self.prompt_password(message)?;
+        let confirmed = self.prompt_password("Confirm password")?;
+        if password.expose_secret() != confirmed.expose_secret() {
+            anyhow::bail!("passwords do not match");
+        }
+
+        Ok(password)
+    }
+
+    pub(crate) fn prompt_password(&self, message: &str) -> Result<Secret> {
+        if let Some(supplied) = &self.supplied {
+            return self.next_supplied(supplied);
+        }
+        if !self.terminal {
+            anyhow::bail!("no terminal to ask for a password; pass --password-file");
+        }
+
         let min = self.min_password_len;
         let validate = move |s: &String| (s.len() >= min).then_some(()).ok_or_else(|| format!("password must be at least {min} characters"));
 
-        let (message, confirm_message) = match operation {
-            Operation::Encryption => ("Enter encryption password", Some("Confirm password")),
-            Operation::Decryption => ("Enter decryption password", None),
-        };
-
         let password = cliclack::password(message).validate(validate).interact().context("failed to read password")?;
-        if let Some(message) = confirm_message {
-            let confirmed = cliclack::password(message).validate(validate).interact().context("failed to confirm password")?;
-            if password != confirmed {
-                anyhow::bail!("passwords do not match");
-            }
-        }
 
         Ok(Secret::new(password.as_bytes().to_vec()))
     }
 
+    /// The code the authenticator shows right now, for `--totp-secret`.
+    pub(crate) fn totp_code(&self) -> Result<String> {
+        if !self.terminal {
+            anyhow::bail!("no terminal to ask for a TOTP code");
+        }
+
+        let validate = |s: &String| {
+            (!s.trim().is_empty() && s.trim().bytes().all(|byte| byte.is_ascii_digit()))
+                .then_some(())
+                .ok_or("the code is digits only")
+        };
+
+        cliclack::input("Enter the current TOTP code").validate(validate).interact().context("failed to read TOTP code")
+    }
+
     pub(crate) fn operation_mode(&self) -> Result<Operation> {
+        if !self.terminal {
+            anyhow::bail!("interactive mode needs a terminal; run a subcommand such as encrypt or decrypt");
+        }
         let mut select = cliclack::select("Select operation");
         for m in Operation::iter() {
             select = select.item(m, m.to_string(), "");
@@ -51,9 +151,28 @@ impl Input {
         select.interact().context("failed to select operation")
     }
 
-    pub(crate) fn file(&self, files: &[Files]) -> Result<PathBuf> {
-        let mut select = cliclack::select("Select file");
-        for f in files {
+    /// Offers the current directory and the recently used ones as places to look for files.
+    pub(crate) fn directory(&self, recent: &[PathBuf]) -> Result<PathBuf> {
+        if recent.is_empty() {
+            return Ok(PathBuf::from("."));
+        }
+
+        let mut select = cliclack::select("Where to look for files?").item(PathBuf::from("."), "Current directory", "");
+        for dir in recent {
+            select = select.item(dir.clone(), dir.display(), "recent");
+        }
+
+        select.interact().context("failed to select directory")
+    }
+
+    /// Lists recently processed files first, marked as such.
+    pub(crate) fn files(&self, files: &[Files], recent: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let (recent_files, other): (Vec<&Files>, Vec<&Files>) = files.iter().partition(|f| super::history::is_recent(recent, f.path()));
+        let mut select = cliclack::multiselect("Select files").required(true);
+        for f in recent_files {
+            select = select.item(f.path().to_path_buf(), f.name(), "recent");
+        }
+        for f in other {
             select = select.item(f.path().to_path_buf(), f.name(), "");
         }
 
@@ -61,10 +180,95 @@ impl Input {
             select = select.filter_mode();
         }
 
-        select.interact().context("failed to select file")
+        select.interact().context("failed to select files")
+    }
+
+    pub(crate) fn adjust_options(&self) -> Result<bool> {
+        cliclack::confirm("Adjust options for this session?")
+            .initial_value(false)
+            .interact()
+            .context("failed to confirm options")
+    }
+
+    pub(crate) fn compression_level(&self, current: i32) -> Result<i32> {
+        let range = zstd::compression_level_range();
+        let validate = move |s: &String| match s.trim().parse::<i32>() {
+            Ok(level) if range.contains(&level) => Ok(()),
+            _ => Err(format!("compression level must be between {} and {}", range.start(), range.end())),
+        };
+
+        cliclack::input("Compression level")
+            .default_input(&current.to_string())
+            .validate(validate)
+            .interact()
+            .context("failed to read compression level")
+    }
+
+    pub(crate) fn shred_passes(&self, current: u32) -> Result<u32> {
+        let shred = cliclack::confirm("Shred the source file when it is deleted?")
+            .initial_value(current > 0)
+            .interact()
+            .context("failed to confirm shredding")?;
+        if !shred {
+            return Ok(0);
+        }
+
+        let validate = |s: &String| s.trim().parse::<u32>().ok().filter(|passes| *passes > 0).map(|_| ()).ok_or("shred passes must be a positive number");
+
+        cliclack::input("Shred passes")
+            .default_input(&current.max(1).to_string())
+            .validate(validate)
+            .interact()
+            .context("failed to read shred passes")
+    }
+
+    pub(crate) fn output_dir(&self) -> Result<Option<PathBuf>> {
+        let validate = |s: &String| match s.trim() {
+            "" => Ok(()),
+            dir if Path::new(dir).is_dir() => Ok(()),
+            _ => Err("output directory must exist"),
+        };
+
+        let dir: String = cliclack::input("Output directory (leave empty to write next to the source)")
+            .required(false)
+            .validate(validate)
+            .interact()
+            .context("failed to read output directory")?;
+
+        Ok(Some(dir.trim()).filter(|dir| !dir.is_empty()).map(PathBuf::from))
+    }
+
+    pub(crate) fn history_mode(&self, current: HistoryMode) -> Result<HistoryMode> {
+        cliclack::select("Remember recently used files and directories?")
+            .initial_value(current)
+            .item(HistoryMode::Off, "No", "")
+            .item(HistoryMode::Plain, "Yes, in a plain file", "")
+            .item(HistoryMode::Encrypted, "Yes, encrypted under a history password", "")
+            .interact()
+            .context("failed to select history mode")
+    }
+
+    pub(crate) fn save_options(&self) -> Result<bool> {
+        cliclack::confirm("Save these options to the config file?")
+            .initial_value(false)
+            .interact()
+            .context("failed to confirm saving options")
+    }
+
+    pub(crate) fn hide_name(&self) -> Result<bool> {
+        cliclack::confirm("Hide the original filename in the output name?")
+            .initial_value(false)
+            .interact()
+            .context("failed to confirm hidden name")
     }
 
     pub(crate) fn overwrite(&self, file: &Files) -> Result<bool> {
+        if self.assume_yes {
+            return Ok(true);
+        }
+        if !self.terminal {
+            anyhow::bail!("output file {} already exists; pass --yes to overwrite it", file.path().display());
+        }
         cliclack::confirm(format!("Output file {} already exists. Overwrite?", file.name()))
             .initial_value(self.default_overwrite)
             .interact()
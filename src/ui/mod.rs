@@ -1,6 +1,17 @@
+mod abort;
+mod credentials;
 pub(crate) mod display;
+mod history;
 mod input;
 mod progress;
+#[cfg(feature = "qr")]
+pub(crate) mod qr;
+mod report;
 
+pub(crate) use credentials::PasswordCache;
+pub(crate) use history::{HistoryMode, HistoryStore};
 pub(crate) use input::Input;
-pub(crate) use progress::Progress;
+pub(crate) use progress::{BatchProgress, Progress, ProgressStyle};
+pub(crate) use report::{
+    CalibrateReport, CheckReport, ChunksReport, CorruptReport, KeyReport, ManifestReport, OutputFormat, RecoverReport, Report, RunEntry, RunReport, SelftestReport, Status, TextReport, WatchReport,
+};
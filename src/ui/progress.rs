@@ -1,27 +1,406 @@
-use cliclack::ProgressBar;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cliclack::{MultiProgress, ProgressBar};
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+
+use super::abort::AbortKey;
+use crate::pipeline::{ProgressEvent, ProgressSink, Stage};
 
 const TEMPLATE: &str = "{msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})";
 
+/// A single file's bar, whose rate and ETA are smoothed here and shown in the message instead.
+const FILE_TEMPLATE: &str = "{msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes}";
+
+/// How long a transfer can go without finishing a chunk before it's reported as stalled.
+const STALL_AFTER: Duration = Duration::from_secs(30);
+
+/// The shortest span the smoothed rate takes a sample over, so that chunks finishing together don't
+/// skew it.
+const SAMPLE_MILLIS: u64 = 500;
+
+/// Each sample moves the smoothed rate a quarter of the way towards it.
+const SMOOTHING: u64 = 4;
+
+#[derive(Clone, Copy, Default, Deserialize, strum::EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub(crate) enum ProgressStyle {
+    #[default]
+    Bar,
+    Plain,
+    Hidden,
+}
+
 pub(crate) struct Progress {
-    bar: ProgressBar,
+    bar: Option<Arc<ProgressBar>>,
+    spinner: Option<ProgressBar>,
+    message: String,
+    lines: Option<Lines>,
+    started: AtomicBool,
+    finished: AtomicBool,
+    since: OnceLock<Instant>,
+    rate: Smoothed,
+    total: u64,
+    done: AtomicU64,
+    watchdog: OnceLock<Sender<Pulse>>,
+    cancel: Option<CancellationToken>,
+    abort: Mutex<Option<AbortKey>>,
 }
 
 impl Progress {
-    pub(crate) fn new(total: u64, message: impl Into<String>) -> Self {
-        let bar = cliclack::progress_bar(total).with_template(TEMPLATE);
-        bar.start(message.into());
+    pub(crate) fn new(total: u64, message: impl Into<String>, style: ProgressStyle) -> Self {
+        let (bar, spinner, lines) = match style {
+            ProgressStyle::Bar => (Some(cliclack::progress_bar(total).with_template(FILE_TEMPLATE)), Some(cliclack::spinner()), None),
+            ProgressStyle::Plain => (None, None, Some(Lines::new(total))),
+            ProgressStyle::Hidden => (None, None, None),
+        };
 
-        Self { bar }
+        Self::from_parts(total, bar, spinner, message.into(), lines)
     }
 
-    pub(crate) fn add(&self, delta: u64) {
-        self.bar.inc(delta);
+    fn from_parts(total: u64, bar: Option<ProgressBar>, spinner: Option<ProgressBar>, message: String, lines: Option<Lines>) -> Self {
+        Self {
+            bar: bar.map(Arc::new),
+            spinner,
+            message,
+            lines,
+            started: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            since: OnceLock::new(),
+            rate: Smoothed::default(),
+            total,
+            done: AtomicU64::new(0),
+            watchdog: OnceLock::new(),
+            cancel: None,
+            abort: Mutex::new(None),
+        }
+    }
+
+    /// Lets `q` or Esc cancel the transfer through `cancel` while its bar is drawn, after asking to
+    /// be sure.
+    pub(crate) fn with_abort(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    fn listen(&self) {
+        let (Some(bar), Some(cancel)) = (&self.bar, &self.cancel) else { return };
+        if let Ok(mut abort) = self.abort.lock()
+            && abort.is_none()
+        {
+            *abort = AbortKey::start(cancel.clone(), Arc::clone(bar), self.message.clone());
+        }
+    }
+
+    /// Stops listening for the abort key, handing the terminal back before anything else is drawn
+    /// or asked.
+    fn stop_listening(&self) {
+        let abort = self.abort.lock().ok().and_then(|mut abort| abort.take());
+        drop(abort);
+    }
+
+    fn confirming_abort(&self) -> bool {
+        self.abort.lock().ok().is_some_and(|abort| abort.as_ref().is_some_and(AbortKey::confirming))
+    }
+
+    /// Starts a thread that reports a stall on the bar or as a line whenever no chunk has finished
+    /// for `STALL_AFTER`, which usually means a disk or network mount has hung. It ends with
+    /// the transfer.
+    fn watch(&self) {
+        if (self.bar.is_none() && self.lines.is_none()) || self.watchdog.get().is_some() {
+            return;
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let (bar, message) = (self.bar.clone(), self.message.clone());
+        if thread::Builder::new()
+            .name("progress-watchdog".into())
+            .spawn(move || watch(&receiver, bar.as_deref(), &message))
+            .is_ok()
+        {
+            self.watchdog.get_or_init(|| sender);
+        }
+    }
+
+    /// The smoothed plaintext rate and the ETA it gives, then the bytes read and written.
+    fn status(&self, event: &ProgressEvent, elapsed: Duration) -> String {
+        let done = self.done.fetch_add(event.chunk_plaintext, Ordering::Relaxed).saturating_add(event.chunk_plaintext);
+        let rate = self.rate.update(event.chunk_plaintext);
+        let eta = self
+            .total
+            .saturating_sub(done)
+            .checked_div(rate)
+            .filter(|_| self.total > 0)
+            .map_or_else(|| "--".to_owned(), |secs| eta(Duration::from_secs(secs)));
+
+        format!("{}/s, ETA {eta}; {}", humansize::format_size(rate, humansize::DECIMAL), transfer(event, elapsed))
+    }
+}
+
+impl ProgressSink for Progress {
+    fn on_event(&self, event: &ProgressEvent) {
+        if event.stage == Stage::Started {
+            self.since.get_or_init(Instant::now);
+            self.watch();
+        }
+        let elapsed = self.since.get().map_or(Duration::ZERO, Instant::elapsed);
+        let status = (event.stage == Stage::Chunk).then(|| self.status(event, elapsed));
+        if let Some(watchdog) = self.watchdog.get() {
+            let pulse = if event.stage == Stage::Finished { Pulse::Finished } else { Pulse::Chunk };
+            watchdog.send(pulse).unwrap_or_default();
+        }
+
+        if let Some(lines) = &self.lines {
+            lines.on_event(&self.message, event, status.as_deref().unwrap_or_default());
+        }
+
+        match (event.stage, &self.bar, &self.spinner) {
+            (Stage::Deriving, _, Some(spinner)) => spinner.start("Deriving key..."),
+            (Stage::Derived, _, Some(spinner)) => spinner.stop("Key derived"),
+            (Stage::Started, Some(bar), _) => {
+                bar.start(&self.message);
+                self.started.store(true, Ordering::Relaxed);
+                self.listen();
+            }
+            (Stage::Chunk, Some(bar), _) => {
+                bar.inc(event.chunk_plaintext);
+                if !self.confirming_abort() {
+                    bar.set_message(format!("{} ({})", self.message, status.unwrap_or_default()));
+                }
+            }
+            (Stage::Finished, _, _) => {
+                self.stop_listening();
+                self.finished.store(true, Ordering::Relaxed);
+            }
+            _ => {}
+        }
     }
 }
 
 impl Drop for Progress {
     fn drop(&mut self) {
+        self.stop_listening();
+
         #[cfg(not(debug_assertions))]
-        self.bar.stop("Done");
+        if let Some(bar) = &self.bar
+            && self.started.load(Ordering::Relaxed)
+        {
+            if self.finished.load(Ordering::Relaxed) {
+                bar.stop("Done");
+            } else {
+                bar.error("Failed");
+            }
+        }
+    }
+}
+
+/// Progress as plain lines on stderr for logs and CI: one when key derivation starts, then one per
+/// tenth of the plaintext.
+struct Lines {
+    total: u64,
+    done: AtomicU64,
+    reported: AtomicU64,
+}
+
+impl Lines {
+    fn new(total: u64) -> Self {
+        Self { total, done: AtomicU64::new(0), reported: AtomicU64::new(0) }
+    }
+
+    fn on_event(&self, message: &str, event: &ProgressEvent, status: &str) {
+        match event.stage {
+            Stage::Deriving => eprintln!("Deriving key..."),
+            Stage::Started => eprintln!("{message}"),
+            Stage::Chunk => {
+                let done = self.done.fetch_add(event.chunk_plaintext, Ordering::Relaxed).saturating_add(event.chunk_plaintext);
+                let tenths = done.saturating_mul(10).checked_div(self.total).unwrap_or(10).min(10);
+                if self.reported.fetch_max(tenths, Ordering::Relaxed) < tenths {
+                    let total = humansize::format_size(self.total, humansize::DECIMAL);
+                    eprintln!("{message} {}% ({} of {total}; {status})", tenths.saturating_mul(10), humansize::format_size(done, humansize::DECIMAL));
+                }
+            }
+            Stage::Derived | Stage::Finished => {}
+        }
+    }
+}
+
+enum Pulse {
+    Chunk,
+    Finished,
+}
+
+/// Waits on `pulses` and reports every `STALL_AFTER` that passes without one, until the transfer
+/// finishes or its progress is dropped.
+fn watch(pulses: &Receiver<Pulse>, bar: Option<&ProgressBar>, message: &str) {
+    let mut stalled = Duration::ZERO;
+    loop {
+        match pulses.recv_timeout(STALL_AFTER) {
+            Ok(Pulse::Chunk) => stalled = Duration::ZERO,
+            Ok(Pulse::Finished) | Err(RecvTimeoutError::Disconnected) => return,
+            Err(RecvTimeoutError::Timeout) => {
+                stalled = stalled.saturating_add(STALL_AFTER);
+                let warning = format!("stalled: no chunk has finished for {}s; the disk or network may have hung", stalled.as_secs());
+                match bar {
+                    Some(bar) => bar.set_message(format!("{message} ({warning})")),
+                    None => eprintln!("{message} {warning}"),
+                }
+            }
+        }
+    }
+}
+
+/// Plaintext throughput as an exponential moving average, so the ETA doesn't jump around with every
+/// chunk.
+#[derive(Default)]
+struct Smoothed {
+    state: Mutex<Sample>,
+}
+
+#[derive(Default)]
+struct Sample {
+    since: Option<Instant>,
+    pending: u64,
+    rate: u64,
+}
+
+impl Smoothed {
+    /// Counts `bytes` towards the current sample, folds it in once it spans `SAMPLE_MILLIS`, and
+    /// returns the smoothed rate in bytes per second, or zero until the first sample is in.
+    fn update(&self, bytes: u64) -> u64 {
+        let Ok(mut sample) = self.state.lock() else { return 0 };
+        let now = Instant::now();
+        let since = *sample.since.get_or_insert(now);
+        sample.pending = sample.pending.saturating_add(bytes);
+
+        let millis = u64::try_from(now.duration_since(since).as_millis()).unwrap_or(u64::MAX);
+        if millis >= SAMPLE_MILLIS {
+            let rate = sample.pending.saturating_mul(1_000).checked_div(millis).unwrap_or(0);
+            sample.rate = smooth(sample.rate, rate);
+            sample.since = Some(now);
+            sample.pending = 0;
+        }
+
+        sample.rate
+    }
+}
+
+fn smooth(previous: u64, rate: u64) -> u64 {
+    if previous == 0 {
+        return rate;
+    }
+
+    let step = previous.abs_diff(rate).checked_div(SMOOTHING).unwrap_or(0);
+    if rate > previous { previous.saturating_add(step) } else { previous.saturating_sub(step) }
+}
+
+fn eta(remaining: Duration) -> String {
+    let secs = remaining.as_secs();
+    let (hours, minutes, seconds) = (secs.checked_div(3600).unwrap_or(0), secs.checked_rem(3600).unwrap_or(0).checked_div(60).unwrap_or(0), secs.checked_rem(60).unwrap_or(0));
+    match (hours, minutes) {
+        (0, 0) => format!("{seconds}s"),
+        (0, _) => format!("{minutes}m {seconds:02}s"),
+        _ => format!("{hours}h {minutes:02}m"),
+    }
+}
+
+/// The bytes read and written so far and the rate of each. They differ by the error correction and
+/// encryption overhead, which the plaintext the bar counts leaves out.
+fn transfer(event: &ProgressEvent, elapsed: Duration) -> String {
+    let millis = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+    let side = |bytes: u64| {
+        let rate = bytes.saturating_mul(1_000).checked_div(millis).unwrap_or(0);
+        format!("{} at {}/s", humansize::format_size(bytes, humansize::DECIMAL), humansize::format_size(rate, humansize::DECIMAL))
+    };
+
+    format!("read {}, wrote {}", side(event.bytes_in), side(event.bytes_out))
+}
+
+/// Overall bar for a multi-file run, counting files and bytes, with each file's own bar drawn
+/// underneath it.
+pub(crate) struct BatchProgress {
+    multi: Option<MultiProgress>,
+    overall: Option<ProgressBar>,
+    files: usize,
+    completed: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+impl BatchProgress {
+    pub(crate) fn new(files: usize, bytes: u64, style: ProgressStyle) -> Self {
+        let (multi, overall) = match style {
+            ProgressStyle::Bar => {
+                let multi = cliclack::multi_progress(format!("Processing {files} file(s)"));
+                let overall = multi.add(cliclack::progress_bar(bytes).with_template(TEMPLATE));
+                overall.start(format!("0/{files} files"));
+                (Some(multi), Some(overall))
+            }
+            ProgressStyle::Plain | ProgressStyle::Hidden => (None, None),
+        };
+
+        Self { multi, overall, files, completed: AtomicUsize::new(0), failed: AtomicUsize::new(0) }
+    }
+
+    /// Progress for the next file, drawn under the overall bar. Key derivation gets no spinner and
+    /// empty transfers, such as unlocking a header, get no bar, so nothing is left behind
+    /// between files.
+    pub(crate) fn file(&self, total: u64, message: impl Into<String>) -> Progress {
+        let bar = self
+            .multi
+            .as_ref()
+            .filter(|_| total > 0)
+            .map(|multi| multi.add(cliclack::progress_bar(total).with_template(FILE_TEMPLATE)));
+
+        Progress::from_parts(total, bar, None, message.into(), None)
+    }
+
+    pub(crate) fn complete(&self, bytes: u64, succeeded: bool) {
+        if !succeeded {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let done = self.completed.fetch_add(1, Ordering::Relaxed).saturating_add(1);
+        if let Some(overall) = &self.overall {
+            overall.inc(bytes);
+            overall.set_message(format!("{done}/{} files", self.files));
+        }
+    }
+}
+
+impl Drop for BatchProgress {
+    fn drop(&mut self) {
+        if let Some(overall) = &self.overall {
+            match self.failed.load(Ordering::Relaxed) {
+                0 => overall.stop(format!("{} file(s) done", self.files)),
+                failed => overall.error(format!("{failed} of {} file(s) failed", self.files)),
+            }
+        }
+
+        if let Some(multi) = &self.multi {
+            multi.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_moves_a_quarter_of_the_way_to_each_sample() {
+        assert_eq!(smooth(0, 1_000), 1_000);
+        assert_eq!(smooth(1_000, 2_000), 1_250);
+        assert_eq!(smooth(1_000, 0), 750);
+    }
+
+    #[test]
+    fn eta_is_shown_in_its_two_largest_units() {
+        assert_eq!(eta(Duration::from_secs(42)), "42s");
+        assert_eq!(eta(Duration::from_secs(65)), "1m 05s");
+        assert_eq!(eta(Duration::from_secs(7_320)), "2h 02m");
     }
 }
@@ -0,0 +1,33 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::Luma;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::{EcLevel, QrCode};
+
+const PNG_MIN_SIZE: u32 = 512;
+
+/// Draws `text` on stderr as a QR code of half-block characters, light on dark so it scans from a
+/// terminal with a dark background, followed by a note on how to read it back. Stdout is left to
+/// the armored text itself.
+pub(crate) fn show(text: &str) -> Result<()> {
+    let code = encode(text)?;
+    let drawn = code.render::<Dense1x2>().dark_color(Dense1x2::Light).light_color(Dense1x2::Dark).build();
+    eprintln!("{drawn}");
+
+    cliclack::log::info("Scan the code as text and pass it to decrypt-text, or paste it into a file and pipe that in").context("failed to display QR code guidance")
+}
+
+/// Saves `text` as a QR code PNG at `path`, scaled up to at least 512 pixels a side.
+pub(crate) fn save(text: &str, path: &Path) -> Result<()> {
+    let image = encode(text)?.render::<Luma<u8>>().min_dimensions(PNG_MIN_SIZE, PNG_MIN_SIZE).build();
+    image.save(path).with_context(|| format!("failed to write QR code to {}", path.display()))?;
+
+    cliclack::log::info(format!("QR code saved to {}", path.display())).context("failed to display QR code path")
+}
+
+/// Medium error correction still survives a smudged screen or print, and holds about 2 KB, which is
+/// plenty for an armored key or password but not for a document.
+fn encode(text: &str) -> Result<QrCode> {
+    QrCode::with_error_correction_level(text.as_bytes(), EcLevel::M).context("message is too long for a QR code; keep QR output to short secrets")
+}
@@ -0,0 +1,308 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::app::{ChunkLayout, Damage, Recovered, Trial, WatchOutcome, Watched};
+use crate::calibrate::Calibration;
+use crate::error::SweetByteError;
+use crate::manifest::{EntryCheck, Outcome};
+use crate::pipeline::{Operation, Throughput};
+use crate::selftest::Check;
+
+#[derive(Clone, Copy, Default, Eq, PartialEq, clap::ValueEnum)]
+pub(crate) enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Status {
+    Success,
+    Skipped,
+    Updated,
+    Failed,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Report {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) source: PathBuf,
+    pub(crate) output: PathBuf,
+    pub(crate) restored: bool,
+    pub(crate) name: String,
+    pub(crate) size: u64,
+    pub(crate) hash: String,
+    pub(crate) duration_ms: u64,
+    pub(crate) throughput: Option<Throughput>,
+}
+
+impl Report {
+    pub(crate) fn operation_name(operation: Operation) -> &'static str {
+        match operation {
+            Operation::Encryption => "encrypt",
+            Operation::Decryption => "decrypt",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct CheckReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) source: PathBuf,
+    pub(crate) name: String,
+    pub(crate) size: u64,
+    pub(crate) hash: String,
+    pub(crate) duration_ms: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct TextReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) text: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct KeyReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) source: PathBuf,
+    pub(crate) slots: usize,
+    pub(crate) duration_ms: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ManifestReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) manifest: PathBuf,
+    pub(crate) entries: Vec<EntryCheck>,
+}
+
+impl ManifestReport {
+    pub(crate) fn new(manifest: PathBuf, entries: Vec<EntryCheck>) -> Self {
+        let status = if entries.iter().any(|entry| entry.outcome == Outcome::Mismatch) { Status::Failed } else { Status::Success };
+
+        Self { operation: "verify-manifest", status, manifest, entries }
+    }
+
+    pub(crate) fn failed(&self) -> bool {
+        matches!(self.status, Status::Failed)
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct ChunksReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) source: PathBuf,
+    #[serde(flatten)]
+    pub(crate) layout: ChunkLayout,
+}
+
+impl ChunksReport {
+    pub(crate) fn new(source: PathBuf, layout: ChunkLayout) -> Self {
+        let complete = layout.problem.is_none() && u64::try_from(layout.chunks.len()).is_ok_and(|found| found == layout.expected);
+        let status = if complete && layout.chunks.iter().all(|chunk| chunk.healthy()) { Status::Success } else { Status::Failed };
+
+        Self { operation: "chunks", status, source, layout }
+    }
+
+    pub(crate) fn failed(&self) -> bool {
+        matches!(self.status, Status::Failed)
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct RecoverReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) source: PathBuf,
+    pub(crate) files: Vec<Recovered>,
+}
+
+impl RecoverReport {
+    /// Succeeds only when every file found came back whole.
+    pub(crate) fn new(source: PathBuf, files: Vec<Recovered>) -> Self {
+        let status = if !files.is_empty() && files.iter().all(|file| file.complete) { Status::Success } else { Status::Failed };
+
+        Self { operation: "recover", status, source, files }
+    }
+
+    pub(crate) fn failed(&self) -> bool {
+        matches!(self.status, Status::Failed)
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct CorruptReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) source: PathBuf,
+    pub(crate) seed: u64,
+    pub(crate) flip_bits: usize,
+    pub(crate) wipe_shards: usize,
+    pub(crate) recovered: usize,
+    pub(crate) trials: Vec<Trial>,
+}
+
+impl CorruptReport {
+    pub(crate) fn new(source: PathBuf, seed: u64, damage: Damage, trials: Vec<Trial>) -> Self {
+        let recovered = trials.iter().filter(|trial| trial.recovered).count();
+
+        Self { operation: "fuzz-corrupt", status: Status::Success, source, seed, flip_bits: damage.flip_bits, wipe_shards: damage.wipe_shards, recovered, trials }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct SelftestReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) checks: Vec<Check>,
+}
+
+impl SelftestReport {
+    pub(crate) fn new(checks: Vec<Check>) -> Self {
+        let status = if checks.iter().all(|check| check.passed) { Status::Success } else { Status::Failed };
+
+        Self { operation: "selftest", status, checks }
+    }
+
+    pub(crate) fn failed(&self) -> bool {
+        matches!(self.status, Status::Failed)
+    }
+}
+
+/// The Argon2id costs `calibrate` settled on, and where they were written if `--write` was given.
+#[derive(Serialize)]
+pub(crate) struct CalibrateReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) target_ms: u64,
+    pub(crate) measured_ms: u64,
+    /// KiB.
+    pub(crate) memory: u32,
+    pub(crate) iterations: u32,
+    pub(crate) parallelism: u32,
+    pub(crate) config: Option<PathBuf>,
+}
+
+impl CalibrateReport {
+    pub(crate) fn new(calibration: &Calibration, target_ms: u64, config: Option<PathBuf>) -> Self {
+        Self {
+            operation: "calibrate",
+            status: if config.is_some() { Status::Updated } else { Status::Success },
+            target_ms,
+            measured_ms: u64::try_from(calibration.measured.as_millis()).unwrap_or(u64::MAX),
+            memory: calibration.params.memory,
+            iterations: calibration.params.iterations,
+            parallelism: calibration.params.parallelism,
+            config,
+        }
+    }
+}
+
+/// One file a watch handled; in JSON, each is printed as it happens.
+#[derive(Serialize)]
+pub(crate) struct WatchReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) source: PathBuf,
+    pub(crate) output: PathBuf,
+    pub(crate) duration_ms: u64,
+    pub(crate) error: Option<String>,
+}
+
+impl WatchReport {
+    pub(crate) fn new(watched: Watched) -> Self {
+        let (status, error) = match watched.outcome {
+            WatchOutcome::Encrypted => (Status::Success, None),
+            WatchOutcome::Unchanged => (Status::Skipped, None),
+            WatchOutcome::Failed(error) => (Status::Failed, Some(format!("{error:#}"))),
+        };
+
+        Self { operation: "watch", status, source: watched.source, output: watched.output, duration_ms: u64::try_from(watched.elapsed.as_millis()).unwrap_or(u64::MAX), error }
+    }
+}
+
+/// How a batch or watch ended, handed to the notify hooks once it's over.
+#[derive(Serialize)]
+pub(crate) struct RunReport {
+    pub(crate) operation: &'static str,
+    pub(crate) status: Status,
+    pub(crate) succeeded: usize,
+    pub(crate) skipped: usize,
+    pub(crate) failed: usize,
+    pub(crate) files: Vec<RunEntry>,
+    pub(crate) duration_ms: u64,
+    /// Why the run itself stopped early, apart from any file that failed.
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RunEntry {
+    pub(crate) source: PathBuf,
+    pub(crate) status: Status,
+    pub(crate) error: Option<String>,
+}
+
+impl RunReport {
+    /// Failed when the run stopped with `result` an error or any of its files failed.
+    pub(crate) fn new(operation: &'static str, files: Vec<RunEntry>, elapsed: Duration, result: &Result<()>) -> Self {
+        let count = |wanted: fn(&Status) -> bool| files.iter().filter(|entry| wanted(&entry.status)).count();
+        let (skipped, failed) = (count(|status| matches!(status, Status::Skipped)), count(|status| matches!(status, Status::Failed)));
+        let error = result.as_ref().err().map(|error| format!("{error:#}"));
+        let status = if error.is_some() || failed > 0 { Status::Failed } else { Status::Success };
+
+        Self {
+            operation,
+            status,
+            succeeded: files.len().saturating_sub(skipped).saturating_sub(failed),
+            skipped,
+            failed,
+            files,
+            duration_ms: u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+            error,
+        }
+    }
+}
+
+impl From<WatchReport> for RunEntry {
+    fn from(report: WatchReport) -> Self {
+        Self { source: report.source, status: report.status, error: report.error }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct GeneratedReport {
+    pub(crate) status: Status,
+    pub(crate) files: Vec<PathBuf>,
+}
+
+/// A command that failed: the kind of failure for scripts to branch on, and the full message for
+/// people.
+#[derive(Serialize)]
+pub(crate) struct ErrorReport {
+    pub(crate) status: Status,
+    pub(crate) error: SweetByteError,
+    pub(crate) message: String,
+}
+
+impl ErrorReport {
+    pub(crate) fn new(error: &anyhow::Error) -> Self {
+        Self { status: Status::Failed, error: SweetByteError::classify(error), message: format!("{error:#}") }
+    }
+}
+
+pub(crate) fn json<T: Serialize>(report: &T) -> Result<()> {
+    let json = serde_json::to_string(report).context("failed to serialize report")?;
+    println!("{json}");
+
+    Ok(())
+}
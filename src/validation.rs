@@ -1,4 +1,8 @@
-use crate::config::{CURRENT_VERSION, KEY_LEN, MAGIC_BYTES, MAX_FILENAME_LEN};
+use crate::cipher::KeySlot;
+use crate::config::{
+    ALGORITHM_AES, ALGORITHM_CHACHA, ARGON2_SALT_LEN, CURRENT_VERSION, KEY_LEN, MAGIC_BYTES, MAX_FILENAME_LEN, MAX_KDF_ITERATIONS, MAX_KDF_MEMORY, MAX_KDF_PARALLELISM, MAX_KEY_SLOTS,
+    MAX_SCRYPT_BLOCK_SIZE, MAX_SCRYPT_LOG_N, MIN_KDF_MEMORY, MIN_SCRYPT_LOG_N, NONCE_PREFIX_LEN, SLOT_NONCE_LEN, WRAPPED_KEY_LEN,
+};
 use crate::secret::Secret;
 
 #[nutype::nutype(validate(not_empty, len_char_max = MAX_FILENAME_LEN), derive(AsRef, Serialize, Deserialize))]
@@ -16,6 +20,39 @@ pub(crate) struct Magic(u32);
 #[nutype::nutype(validate(predicate = |&v| v == CURRENT_VERSION), derive(Serialize, Deserialize))]
 pub(crate) struct Version(u16);
 
+#[nutype::nutype(validate(predicate = |&a| a != 0 && a & !(ALGORITHM_AES | ALGORITHM_CHACHA) == 0), derive(Clone, Copy, Serialize, Deserialize))]
+pub(crate) struct AlgorithmFlags(u8);
+
+#[nutype::nutype(validate(predicate = |p| p.len() == NONCE_PREFIX_LEN), derive(AsRef, Serialize, Deserialize))]
+pub(crate) struct NoncePrefix(Vec<u8>);
+
+#[nutype::nutype(validate(predicate = |s| s.len() == ARGON2_SALT_LEN), derive(AsRef, Serialize, Deserialize))]
+pub(crate) struct SessionSalt(Vec<u8>);
+
+#[nutype::nutype(validate(greater_or_equal = MIN_KDF_MEMORY, less_or_equal = MAX_KDF_MEMORY), derive(Clone, Copy, Serialize, Deserialize))]
+pub(crate) struct KdfMemory(u32);
+
+#[nutype::nutype(validate(greater_or_equal = 1, less_or_equal = MAX_KDF_ITERATIONS), derive(Clone, Copy, Serialize, Deserialize))]
+pub(crate) struct KdfIterations(u32);
+
+#[nutype::nutype(validate(greater_or_equal = 1, less_or_equal = MAX_KDF_PARALLELISM), derive(Clone, Copy, Serialize, Deserialize))]
+pub(crate) struct KdfParallelism(u32);
+
+#[nutype::nutype(validate(greater_or_equal = MIN_SCRYPT_LOG_N, less_or_equal = MAX_SCRYPT_LOG_N), derive(Clone, Copy, Serialize, Deserialize))]
+pub(crate) struct ScryptLogN(u8);
+
+#[nutype::nutype(validate(greater_or_equal = 1, less_or_equal = MAX_SCRYPT_BLOCK_SIZE), derive(Clone, Copy, Serialize, Deserialize))]
+pub(crate) struct ScryptBlockSize(u32);
+
+#[nutype::nutype(validate(predicate = |n| n.len() == SLOT_NONCE_LEN), derive(Clone, AsRef, Serialize, Deserialize))]
+pub(crate) struct SlotNonce(Vec<u8>);
+
+#[nutype::nutype(validate(predicate = |w| w.len() == WRAPPED_KEY_LEN), derive(Clone, AsRef, Serialize, Deserialize))]
+pub(crate) struct WrappedKey(Vec<u8>);
+
+#[nutype::nutype(validate(predicate = |s| !s.is_empty() && s.len() <= MAX_KEY_SLOTS), derive(AsRef, Serialize, Deserialize))]
+pub(crate) struct KeySlots(Vec<KeySlot>);
+
 #[nutype::nutype(validate(predicate = |b| b.len() == KEY_LEN))]
 pub(crate) struct KeyBytes(Vec<u8>);
 